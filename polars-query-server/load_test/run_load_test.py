@@ -5,7 +5,7 @@ from statistics import mean
 from time import perf_counter
 from pathlib import Path
 
-URL = "http://127.0.0.1:3000/run-query"
+URL = "http://127.0.0.1:3000/run-query?wait=true"
 QUERIES = [
     "df = pl.read_parquet('data/sample_0.parquet')\ndf = df.select(['name','age'])"
 ] * 10
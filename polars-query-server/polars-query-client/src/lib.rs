@@ -0,0 +1,23 @@
+//! Official async Rust client for `polars-query-server`.
+//!
+//! Downstream services submit queries and collect their results as Polars
+//! DataFrames without decoding responses themselves: inline base64/zstd/IPC
+//! payloads and results stored as files on the server are handled alike.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), polars_query_client::Error> {
+//! let client = polars_query_client::Client::new("http://127.0.0.1:3000");
+//! let job = client.submit("df = pl.read_parquet(\"data/sample_0.parquet\")").await?;
+//! let job = client.poll(job.job_id).await?;
+//! let df = client.fetch_result_as_dataframe(job.job_id).await?;
+//! println!("{}", df);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The client is the one the `rdata` command line tool is built on, from the
+//! `rdata-client` crate, so both decode results the same way.
+
+pub use rdata_client::{
+    decode_inline, Client, Error, JobResponse, Output, OutputPart, PartManifest,
+};
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use polars::prelude::*;
+use polars_query_client::{Client, Error, Output};
+use polars_query_server::{Config, QueryServer};
+
+/// A client of an in-process server writing its results to `output_dir`,
+/// stored rather than inline when `stored` is set.
+fn serve(output_dir: &Path, stored: bool) -> Client {
+    let mut config = Config::default();
+    config.storage.output_dir = output_dir.to_path_buf();
+    if stored {
+        config.storage.output.inline_limit = 0;
+    }
+    let server = QueryServer::builder()
+        .with_config(config)
+        .with_background_tasks(false)
+        .build()
+        .unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = server.router().into_make_service();
+    tokio::spawn(async move { axum::Server::from_tcp(listener).unwrap().serve(app).await });
+    Client::new(format!("http://{}", addr)).with_poll_interval(Duration::from_millis(10))
+}
+
+fn people(dir: &Path) -> (DataFrame, String) {
+    let mut df = df!["name" => ["a", "b", "c"], "age" => [20, 30, 40]].unwrap();
+    let path = dir.join("people.parquet");
+    ParquetWriter::new(File::create(&path).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    (df, path.to_string_lossy().to_string())
+}
+
+#[tokio::test]
+async fn sql_jobs_are_polled_by_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = serve(dir.path(), false);
+    let (_, path) = people(dir.path());
+
+    let sql = format!("SELECT name FROM \"{}\" WHERE age > 25", path);
+    let job = client.submit_sql(&sql).await.unwrap();
+    let job = client.poll(job.job_id).await.unwrap();
+    assert!(job.is_finished());
+    let df = client.fetch_dataframe(&job).await.unwrap();
+    assert!(df.frame_equal(&df!["name" => ["b", "c"]].unwrap()));
+
+    let err = client.submit_sql("DELETE FROM people").await.unwrap_err();
+    assert!(matches!(err, Error::Query(_)), "{}", err);
+}
+
+#[tokio::test]
+async fn results_are_fetched_by_job_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let (df, path) = people(dir.path());
+    let query = format!("df = pl.read_parquet(\"{}\")", path);
+
+    for stored in [false, true] {
+        let client = serve(&dir.path().join("output"), stored);
+        let job = client.submit(&query).await.unwrap();
+        let finished = client.poll(job.job_id).await.unwrap();
+        let downloaded =
+            matches!(&finished.output, Some(Output::Single(url)) if url.starts_with('/'));
+        assert_eq!(downloaded, stored);
+        let fetched = client.fetch_result_as_dataframe(job.job_id).await.unwrap();
+        assert!(fetched.frame_equal(&df), "stored: {}", stored);
+    }
+}
+
+#[tokio::test]
+async fn failed_jobs_are_query_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = serve(dir.path(), false);
+    let missing = dir.path().join("missing.parquet");
+
+    let query = format!("df = pl.read_parquet(\"{}\")", missing.display());
+    let job = client.submit(&query).await.unwrap();
+    let err = client.poll(job.job_id).await.unwrap_err();
+    assert!(matches!(err, Error::Query(_)), "{}", err);
+    let err = client
+        .fetch_result_as_dataframe(job.job_id)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Query(_)), "{}", err);
+}
@@ -0,0 +1,254 @@
+"""Python client for polars-query-server.
+
+Example::
+
+    from rdata_client import Client
+
+    client = Client("http://127.0.0.1:3000", user="alice")
+    df = client.query('df = pl.read_parquet("data/sample_0.parquet")')
+"""
+
+import base64
+import io
+import time
+from dataclasses import dataclass
+from typing import Iterator, Optional
+
+import httpx
+import polars as pl
+import zstandard
+
+USER_HEADER = "x-user-id"
+
+FINISHED_STATUSES = ("completed", "failed", "cancelled", "timeout", "rejected", "expired")
+
+
+ARROW_STREAM = "application/vnd.apache.arrow.stream"
+
+
+class QueryError(Exception):
+    """Raised when the server reports a failed job.
+
+    Errors carry the server's error ``kind`` (such as ``invalid_query``,
+    ``access_denied`` or ``execution_failed``) and the job id when one was
+    assigned; errors from a request also carry the HTTP status.
+    """
+
+    def __init__(
+        self,
+        message: str,
+        kind: Optional[str] = None,
+        job_id: Optional[int] = None,
+        status: Optional[int] = None,
+    ):
+        super().__init__(message)
+        self.message = message
+        self.kind = kind
+        self.job_id = job_id
+        self.status = status
+
+    def __repr__(self) -> str:
+        return f"QueryError(kind={self.kind!r}, job_id={self.job_id!r}, message={self.message!r})"
+
+    def _repr_html_(self) -> str:
+        """Rendering in Jupyter."""
+        from html import escape
+
+        job = f" (job {self.job_id})" if self.job_id is not None else ""
+        return (
+            f"<b>QueryError</b> <code>{escape(str(self.kind))}</code>{job}"
+            f"<pre>{escape(self.message)}</pre>"
+        )
+
+    @classmethod
+    def from_response(cls, resp: httpx.Response) -> "QueryError":
+        try:
+            error = resp.json()["error"]
+        except (ValueError, KeyError, TypeError):
+            return cls(resp.text or resp.reason_phrase, status=resp.status_code)
+        if isinstance(error, str):
+            return cls(error, status=resp.status_code)
+        return cls(
+            error.get("message", ""),
+            kind=error.get("kind"),
+            job_id=error.get("job_id"),
+            status=error.get("status", resp.status_code),
+        )
+
+
+@dataclass
+class JobResponse:
+    job_id: int
+    status: str
+    duration_ms: Optional[int] = None
+    cost: Optional[int] = None
+    output: object = None
+    error: Optional[str] = None
+    error_kind: Optional[str] = None
+
+    @classmethod
+    def from_json(cls, data: dict) -> "JobResponse":
+        return cls(
+            job_id=data["job_id"],
+            status=data["status"],
+            duration_ms=data.get("duration_ms"),
+            cost=data.get("cost"),
+            output=data.get("output"),
+            error=data.get("error"),
+            error_kind=data.get("error_kind"),
+        )
+
+    @property
+    def finished(self) -> bool:
+        return (
+            self.status in FINISHED_STATUSES
+            or self.output is not None
+            or self.error is not None
+            or self.duration_ms is not None
+        )
+
+
+def decode_inline(payload: str) -> pl.DataFrame:
+    """Decode a base64, zstd-compressed Arrow IPC payload."""
+    compressed = base64.b64decode(payload, validate=True)
+    ipc = zstandard.ZstdDecompressor().decompressobj().decompress(compressed)
+    return pl.read_ipc(io.BytesIO(ipc))
+
+
+class Client:
+    """Client for a single polars-query-server instance."""
+
+    def __init__(
+        self,
+        base_url: str,
+        user: Optional[str] = None,
+        timeout: float = 300.0,
+        poll_interval: float = 0.2,
+    ):
+        headers = {USER_HEADER: user} if user else {}
+        self._http = httpx.Client(base_url=base_url.rstrip("/"), headers=headers, timeout=timeout)
+        self._timeout = timeout
+        self._poll_interval = poll_interval
+
+    def close(self) -> None:
+        self._http.close()
+
+    def __enter__(self) -> "Client":
+        return self
+
+    def __exit__(self, *exc) -> None:
+        self.close()
+
+    def submit(self, query: str) -> JobResponse:
+        """Submit a query and return its job id and initial status."""
+        resp = self._http.post("/run-query", content=query)
+        if resp.is_client_error:
+            raise QueryError.from_response(resp)
+        resp.raise_for_status()
+        return JobResponse.from_json(resp.json())
+
+    def status(self, job_id: int) -> JobResponse:
+        """Return the current status of a job, with its result once finished."""
+        resp = self._http.get(f"/jobs/{job_id}")
+        resp.raise_for_status()
+        return JobResponse.from_json(resp.json())
+
+    def cancel(self, job_id: int) -> None:
+        """Cancel a job: removed if still queued, signalled to stop if running."""
+        resp = self._http.delete(f"/jobs/{job_id}")
+        resp.raise_for_status()
+
+    def wait(self, job: JobResponse) -> JobResponse:
+        """Poll a job until it finishes, raising ``QueryError`` if it failed
+        or did not finish within the client's timeout."""
+        deadline = time.monotonic() + self._timeout
+        while not job.finished:
+            if time.monotonic() > deadline:
+                raise QueryError(
+                    f"job {job.job_id} did not finish in {self._timeout}s",
+                    job_id=job.job_id,
+                )
+            time.sleep(self._poll_interval)
+            job = self.status(job.job_id)
+        if job.error is not None:
+            raise QueryError(job.error, kind=job.error_kind, job_id=job.job_id)
+        return job
+
+    def iter_frames(self, job: JobResponse) -> Iterator[pl.DataFrame]:
+        """Yield the result as DataFrames, one per part file."""
+        output = job.output
+        if output is None:
+            return
+        if isinstance(output, dict):
+            for part in output["parts"]:
+                yield self.download(part["url"])
+            return
+        # Download URLs start with a slash, inline zstd payloads never do.
+        if output.startswith("/"):
+            yield self.download(output)
+        else:
+            yield decode_inline(output)
+
+    def download(self, url: str) -> pl.DataFrame:
+        """Download a stored result file from ``url``, relative to the server."""
+        resp = self._http.get(url)
+        if resp.is_client_error:
+            raise QueryError.from_response(resp)
+        resp.raise_for_status()
+        return pl.read_ipc(io.BytesIO(resp.content))
+
+    def fetch(self, job: JobResponse) -> pl.DataFrame:
+        """Return a finished job's result as a single Polars DataFrame."""
+        frames = list(self.iter_frames(job))
+        if not frames:
+            raise QueryError("job produced no output")
+        return pl.concat(frames) if len(frames) > 1 else frames[0]
+
+    def query(self, query: str) -> pl.DataFrame:
+        """Submit a query, wait for it and return the result."""
+        return self.fetch(self.wait(self.submit(query)))
+
+    def _arrow_stream(self, query: str) -> bytes:
+        resp = self._http.post("/run-query/arrow", content=query)
+        if resp.status_code != 200 or not resp.headers.get("content-type", "").startswith(ARROW_STREAM):
+            raise QueryError.from_response(resp)
+        return resp.content
+
+    def query_arrow(self, query: str) -> pl.DataFrame:
+        """Run a query, receiving the result as a single Arrow IPC stream.
+
+        Unlike ``query`` this needs no access to the server's result files, so
+        it suits notebooks on other machines. Failures raise ``QueryError``
+        with the error's ``kind`` and ``job_id`` set.
+        """
+        return pl.read_ipc_stream(io.BytesIO(self._arrow_stream(query)))
+
+    def query_pyarrow(self, query: str):
+        """Like ``query_arrow`` but returns a ``pyarrow.Table`` (requires pyarrow)."""
+        import pyarrow.ipc
+
+        return pyarrow.ipc.open_stream(self._arrow_stream(query)).read_all()
+
+    def append(self, dataset: str, df: pl.DataFrame) -> dict:
+        """Append ``df`` to ``dataset`` as an Arrow stream.
+
+        Rows are buffered by the server and become queryable once flushed,
+        usually within seconds. Returns the server's append report.
+        """
+        buf = io.BytesIO()
+        df.write_ipc_stream(buf)
+        resp = self._http.post(
+            f"/datasets/{dataset}/append",
+            content=buf.getvalue(),
+            headers={"content-type": ARROW_STREAM},
+        )
+        if resp.status_code != 202:
+            raise QueryError.from_response(resp)
+        return resp.json()
+
+    def query_pandas(self, query: str):
+        """Like ``query`` but returns a pandas DataFrame (requires pyarrow)."""
+        return self.query(query).to_pandas()
+
+
+__all__ = ["ARROW_STREAM", "Client", "JobResponse", "QueryError", "decode_inline"]
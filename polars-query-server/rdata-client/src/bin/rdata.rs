@@ -0,0 +1,101 @@
+use clap::{Parser, ValueEnum};
+use polars::prelude::*;
+use rdata_client::{Client, Error};
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Submit a query to polars-query-server and write the result.
+#[derive(Debug, Parser)]
+#[command(name = "rdata", version)]
+struct Args {
+    /// File containing the query; reads stdin when omitted or `-`.
+    query: Option<PathBuf>,
+    /// Server base URL.
+    #[arg(long, env = "RDATA_URL", default_value = "http://127.0.0.1:3000")]
+    url: String,
+    /// Submit on behalf of this user.
+    #[arg(long, env = "RDATA_USER")]
+    user: Option<String>,
+    /// Write the result to this file instead of stdout.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Output format; inferred from the output file extension by default.
+    #[arg(long, short, value_enum)]
+    format: Option<Format>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Table,
+    Csv,
+    Parquet,
+}
+
+impl Args {
+    fn format(&self) -> Format {
+        if let Some(format) = self.format {
+            return format;
+        }
+        match self
+            .output
+            .as_ref()
+            .and_then(|p| p.extension())
+            .and_then(|e| e.to_str())
+        {
+            Some("parquet") => Format::Parquet,
+            Some("csv") => Format::Csv,
+            _ => Format::Table,
+        }
+    }
+
+    fn read_query(&self) -> std::io::Result<String> {
+        let mut query = String::new();
+        match &self.query {
+            Some(path) if path.as_os_str() != "-" => {
+                File::open(path)?.read_to_string(&mut query)?;
+            }
+            _ => {
+                std::io::stdin().read_to_string(&mut query)?;
+            }
+        }
+        Ok(query)
+    }
+}
+
+fn write_result(mut df: DataFrame, format: Format, output: Option<&PathBuf>) -> Result<(), Error> {
+    let mut sink: Box<dyn std::io::Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    match format {
+        Format::Table => writeln!(sink, "{}", df)?,
+        Format::Csv => CsvWriter::new(&mut sink).finish(&mut df)?,
+        Format::Parquet => {
+            ParquetWriter::new(&mut sink).finish(&mut df)?;
+        }
+    }
+    Ok(())
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    let mut client = Client::new(&args.url);
+    if let Some(user) = &args.user {
+        client = client.with_user(user);
+    }
+    let query = args.read_query()?;
+    let job = client.submit(&query).await?;
+    eprintln!("submitted job {} ({})", job.job_id, job.status);
+    let job = client.wait(job).await?;
+    let df = client.fetch_dataframe(&job).await?;
+    write_result(df, args.format(), args.output.as_ref())
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+/// Errors returned by the client.
+#[derive(Debug)]
+pub enum Error {
+    /// The HTTP request failed or the server returned an error status.
+    Http(reqwest::Error),
+    /// The job ran but failed on the server.
+    Query(String),
+    /// The job finished without producing output.
+    NoOutput,
+    /// Reading or decompressing the result failed.
+    Io(std::io::Error),
+    /// Decoding the result into a DataFrame failed.
+    Polars(polars::prelude::PolarsError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request failed: {}", e),
+            Error::Query(msg) => write!(f, "query failed: {}", msg),
+            Error::NoOutput => write!(f, "job produced no output"),
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::Polars(e) => write!(f, "failed to decode result: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<polars::prelude::PolarsError> for Error {
+    fn from(e: polars::prelude::PolarsError) -> Self {
+        Error::Polars(e)
+    }
+}
@@ -0,0 +1,317 @@
+//! Async client for `polars-query-server`.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), rdata_client::Error> {
+//! let client = rdata_client::Client::new("http://127.0.0.1:3000");
+//! let job = client.submit("df = pl.read_parquet(\"data/sample_0.parquet\")").await?;
+//! let job = client.wait(job).await?;
+//! let df = client.fetch_dataframe(&job).await?;
+//! println!("{}", df);
+//!
+//! // Or, knowing only the id of a job submitted elsewhere:
+//! let df = client.fetch_result_as_dataframe(job.job_id).await?;
+//! # let _ = df;
+//! # Ok(())
+//! # }
+//! ```
+
+use base64::engine::general_purpose::STANDARD as B64_ENGINE;
+use base64::Engine;
+use futures::stream::{self, Stream, StreamExt};
+use polars::prelude::*;
+use serde::Deserialize;
+use std::io::Cursor;
+use std::time::Duration;
+
+mod error;
+
+pub use error::Error;
+
+/// Header the server uses to attribute jobs to a user.
+pub const USER_HEADER: &str = "x-user-id";
+
+/// Default time between status requests while waiting for a job.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A file of a result split into several parts.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputPart {
+    /// Download URL of the part, relative to the server.
+    pub url: String,
+    #[serde(default)]
+    pub rows: usize,
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+/// Manifest of a multi-part result.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PartManifest {
+    #[serde(default)]
+    pub total_rows: usize,
+    pub parts: Vec<OutputPart>,
+}
+
+/// Result payload as returned by the server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Output {
+    /// Base64 zstd-compressed IPC bytes, or the download URL of a Feather
+    /// file relative to the server.
+    Single(String),
+    /// Row-ordered part files.
+    Parts(PartManifest),
+}
+
+/// Response to a job submission, or the status of a job.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobResponse {
+    pub job_id: u64,
+    pub status: String,
+    pub duration_ms: Option<u64>,
+    pub cost: Option<u64>,
+    pub output: Option<Output>,
+    pub error: Option<String>,
+    /// What went wrong when the job failed, such as `execution_failed`.
+    pub error_kind: Option<String>,
+}
+
+impl JobResponse {
+    /// Whether the job has reached a terminal state.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "completed" | "failed" | "cancelled" | "timeout" | "rejected" | "expired"
+        ) || self.output.is_some()
+            || self.error.is_some()
+            || self.duration_ms.is_some()
+    }
+}
+
+/// [`Error::Query`] from the structured error a rejected query is answered
+/// with.
+async fn query_error(resp: reqwest::Response) -> Error {
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| v["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| format!("{}: {}", status, body));
+    Error::Query(message)
+}
+
+/// Client for a single server.
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    user: Option<String>,
+    poll_interval: Duration,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// Create a client talking to the server at `base_url`, e.g. `http://127.0.0.1:3000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Client {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            user: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Submit jobs on behalf of `user`.
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Time between status requests while waiting for a job.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Submit a query and return the server's response, which carries the
+    /// job id and its initial status.
+    pub async fn submit(&self, query: &str) -> Result<JobResponse, Error> {
+        self.post_query("/run-query", query).await
+    }
+
+    /// Submit a SQL `SELECT` statement, which the server translates to a
+    /// query, and return its response as [`Client::submit`] does.
+    pub async fn submit_sql(&self, query: &str) -> Result<JobResponse, Error> {
+        self.post_query("/run-sql", query).await
+    }
+
+    async fn post_query(&self, path: &str, query: &str) -> Result<JobResponse, Error> {
+        let mut req = self.http.post(self.url(path)).body(query.to_string());
+        if let Some(user) = &self.user {
+            req = req.header(USER_HEADER, user);
+        }
+        let resp = req.send().await?;
+        if resp.status().is_client_error() {
+            return Err(query_error(resp).await);
+        }
+        Ok(resp.error_for_status()?.json().await?)
+    }
+
+    /// Fetch the current status of job `id`, with its result once finished.
+    pub async fn status(&self, id: u64) -> Result<JobResponse, Error> {
+        let mut req = self.http.get(self.url(&format!("/jobs/{}", id)));
+        if let Some(user) = &self.user {
+            req = req.header(USER_HEADER, user);
+        }
+        let resp = req.send().await?.error_for_status()?;
+        Ok(resp.json().await?)
+    }
+
+    /// Cancel job `id`: the server removes it if queued and signals it to
+    /// stop if running.
+    pub async fn cancel(&self, id: u64) -> Result<(), Error> {
+        let mut req = self.http.delete(self.url(&format!("/jobs/{}", id)));
+        if let Some(user) = &self.user {
+            req = req.header(USER_HEADER, user);
+        }
+        req.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Poll job `id` until it finishes, turning a failed job into
+    /// [`Error::Query`]. Unlike [`Client::wait`] this needs only the id, as
+    /// kept by a process other than the one that submitted the job.
+    pub async fn poll(&self, id: u64) -> Result<JobResponse, Error> {
+        let job = self.status(id).await?;
+        self.wait(job).await
+    }
+
+    /// Poll a submitted job until it finishes, turning a failed job into
+    /// [`Error::Query`].
+    pub async fn wait(&self, mut job: JobResponse) -> Result<JobResponse, Error> {
+        while !job.is_finished() {
+            tokio::time::sleep(self.poll_interval).await;
+            job = self.status(job.job_id).await?;
+        }
+        if let Some(message) = &job.error {
+            return Err(Error::Query(message.clone()));
+        }
+        Ok(job)
+    }
+
+    /// Submit a query, wait for it and return the result as a DataFrame.
+    pub async fn query(&self, query: &str) -> Result<DataFrame, Error> {
+        let job = self.submit(query).await?;
+        let job = self.wait(job).await?;
+        self.fetch_dataframe(&job).await
+    }
+
+    /// Wait for job `id` to finish if it hasn't and return its result as a
+    /// single DataFrame, however the server returned it.
+    pub async fn fetch_result_as_dataframe(&self, id: u64) -> Result<DataFrame, Error> {
+        let job = self.poll(id).await?;
+        self.fetch_dataframe(&job).await
+    }
+
+    /// Decode a finished job's result into a single DataFrame.
+    pub async fn fetch_dataframe(&self, job: &JobResponse) -> Result<DataFrame, Error> {
+        let mut frames = std::pin::pin!(self.fetch_stream(job));
+        let mut out: Option<DataFrame> = None;
+        while let Some(frame) = frames.next().await {
+            let frame = frame?;
+            match out.as_mut() {
+                Some(df) => {
+                    df.vstack_mut(&frame)?;
+                }
+                None => out = Some(frame),
+            }
+        }
+        out.ok_or(Error::NoOutput)
+    }
+
+    /// Stream a finished job's result as DataFrames, one per part file, so
+    /// large results can be processed without holding them in memory at once.
+    pub fn fetch_stream<'a>(
+        &'a self,
+        job: &'a JobResponse,
+    ) -> impl Stream<Item = Result<DataFrame, Error>> + 'a {
+        let sources: Vec<Source> = match &job.output {
+            None => vec![],
+            Some(Output::Single(s)) => vec![Source::Single(s.clone())],
+            Some(Output::Parts(manifest)) => manifest
+                .parts
+                .iter()
+                .map(|p| Source::File(p.url.clone()))
+                .collect(),
+        };
+        stream::iter(sources).then(move |source| self.load(source))
+    }
+
+    async fn load(&self, source: Source) -> Result<DataFrame, Error> {
+        match source {
+            // Download URLs start with a slash, inline zstd payloads never do.
+            Source::Single(s) if !s.starts_with('/') => {
+                let compressed = B64_ENGINE
+                    .decode(s.as_bytes())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                decode_inline(&compressed)
+            }
+            Source::Single(url) | Source::File(url) => self.download(&url).await,
+        }
+    }
+
+    /// Download a stored result file from `url`, relative to the server, and
+    /// read it as Feather.
+    pub async fn download(&self, url: &str) -> Result<DataFrame, Error> {
+        let mut req = self.http.get(self.url(url));
+        if let Some(user) = &self.user {
+            req = req.header(USER_HEADER, user);
+        }
+        let bytes = req.send().await?.error_for_status()?.bytes().await?;
+        Ok(IpcReader::new(Cursor::new(bytes)).finish()?)
+    }
+}
+
+enum Source {
+    Single(String),
+    File(String),
+}
+
+/// Decode zstd-compressed IPC bytes returned inline by the server.
+pub fn decode_inline(compressed: &[u8]) -> Result<DataFrame, Error> {
+    let ipc = zstd::decode_all(Cursor::new(compressed))?;
+    Ok(IpcReader::new(Cursor::new(ipc)).finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_inline_output() {
+        let mut df = df!["val" => [1, 2, 3]].unwrap();
+        let mut ipc = Vec::new();
+        IpcWriter::new(&mut ipc).finish(&mut df).unwrap();
+        let compressed = zstd::encode_all(Cursor::new(ipc), 0).unwrap();
+
+        let decoded = decode_inline(&compressed).unwrap();
+        assert!(decoded.frame_equal(&df));
+    }
+
+    #[test]
+    fn parse_part_manifest_response() {
+        let body = r#"{"job_id": 1, "status": "running", "duration_ms": 5, "cost": 10,
+            "output": {"total_rows": 3, "parts": [{"url": "/jobs/1/download?part=0", "rows": 3, "bytes": 10}]},
+            "error": null}"#;
+        let job: JobResponse = serde_json::from_str(body).unwrap();
+        assert!(job.is_finished());
+        assert!(matches!(job.output, Some(Output::Parts(ref m)) if m.parts.len() == 1));
+
+        let body = r#"{"job_id": 2, "status": "queued", "warnings": []}"#;
+        let job: JobResponse = serde_json::from_str(body).unwrap();
+        assert!(!job.is_finished());
+    }
+}
@@ -1,56 +1,2289 @@
-use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
-use base64::engine::general_purpose::STANDARD as B64_ENGINE;
-use base64::Engine;
-use serde_json::json;
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{
+        rejection::QueryRejection, BodyStream, DefaultBodyLimit, MatchedPath, Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::StreamExt;
+use tokio_util::io::ReaderStream;
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{info, Instrument};
 
-use crate::scheduler::Scheduler;
+use crate::access::{Grant, Permission};
+use crate::audit::AuditEvent;
+use crate::catalog::DatasetSpec;
+use crate::cluster::{self, Role, WorkResult};
+use crate::compaction;
+use crate::config::Config;
+use crate::convert::ConvertFormat;
+use crate::discovery;
+use crate::download;
+use crate::explain::ExplainFormat;
+use crate::ingest::{self, IngestFormat, IngestOptions};
+use crate::introspect::SchemaRequest;
+use crate::jobs::{self, JobFilter};
+use crate::lineage::Lineage;
+use crate::lint::LintWarning;
+use crate::masking::ColumnPolicy;
+use crate::parser::{self, QueryPlan};
+use crate::partition::{self, PartitionStats};
+use crate::quality::CheckSpec;
+use crate::ratelimit::Throttled;
+use crate::scheduler::{JobError, JobOptions, JobPriority, JobResult, Scheduler};
+use crate::server::QueryServer;
+use crate::sql;
+use crate::streaming::{self, AppendReport, BUFFER_FULL};
+use crate::subscriptions::{Subscription, SubscriptionSpec};
+use crate::systemd;
+use crate::telemetry;
+use crate::templates::SavedQuerySpec;
+use crate::utils::{self, OutputFormat, PreparedOutput};
+use crate::views::{ViewSpec, ViewStatus};
+
+/// Header identifying the caller for per-user accounting.
+pub const USER_HEADER: &str = "x-user-id";
+
+/// Header carrying the secret of a trusted proxy vouching for [`USER_HEADER`].
+pub const PROXY_TOKEN_HEADER: &str = "x-proxy-token";
+
+/// Header carrying the token required by the `/admin` endpoints.
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// Header naming a temporary dataset to register a query's result as.
+pub const REGISTER_HEADER: &str = "x-register-as";
+
+/// Header carrying the TTL in seconds of a dataset named by [`REGISTER_HEADER`].
+pub const REGISTER_TTL_HEADER: &str = "x-register-ttl";
+
+/// Build [`JobOptions`] from request headers. The caller named by
+/// [`USER_HEADER`] is only believed when the access settings trust it, see
+/// [`crate::access::AccessControl::trusts_caller`]; otherwise the job runs
+/// as the anonymous user.
+fn job_options(state: &AppState, headers: &HeaderMap) -> JobOptions {
+    let mut options = JobOptions::default();
+    let proxy_token = headers
+        .get(PROXY_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !state.scheduler.access().trusts_caller(proxy_token) {
+        return options;
+    }
+    if let Some(user) = headers.get(USER_HEADER).and_then(|v| v.to_str().ok()) {
+        if !user.is_empty() {
+            options.user = user.to_string();
+        }
+    }
+    options
+}
 
 #[derive(Clone)]
 pub struct AppState {
     pub scheduler: Scheduler,
 }
 
-/// Handler for `/run-query` which logs the incoming body and
-/// returns a simple JSON status response.
-async fn run_query(State(state): State<Arc<AppState>>, body: String) -> impl IntoResponse {
+/// Query parameters of `POST /run-query` and `POST /queries/:name/run`.
+#[derive(Debug, Default, Deserialize)]
+struct RunParams {
+    /// Answer once the job has finished instead of right away.
+    #[serde(default)]
+    wait: bool,
+    /// Longest the job may run, in milliseconds, overriding the server's
+    /// default.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// Explain the query as `POST /explain` does instead of running it.
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    priority: Option<JobPriority>,
+    /// Run on Polars' streaming engine, overriding the server's default.
+    #[serde(default)]
+    streaming: Option<bool>,
+    /// Attempts at the job when it fails with transient I/O errors,
+    /// overriding the server's default.
+    #[serde(default)]
+    max_attempts: Option<u32>,
+}
+
+/// JSON body of `POST /run-query`, sent instead of the bare query text.
+#[derive(Debug, Deserialize)]
+struct RunRequest {
+    query: String,
+    /// Language the query is written in, the DSL unless given.
+    #[serde(default)]
+    dialect: Option<Dialect>,
+    #[serde(flatten)]
+    options: RequestOptions,
+}
+
+/// Language of a submitted query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Dialect {
+    /// The Python-like DSL.
+    #[default]
+    Dsl,
+    /// A SQL `SELECT` statement, translated to the DSL before it is run.
+    Sql,
+}
+
+/// Job options of a [`RunRequest`].
+#[derive(Debug, Default, Deserialize)]
+struct RequestOptions {
+    /// Serialization of the result, overriding the `Accept` header.
+    #[serde(default)]
+    format: Option<OutputFormat>,
+    /// Largest compressed result returned inline, up to the server's limit.
+    #[serde(default)]
+    inline_limit: Option<usize>,
+    /// Longest the job may run, in milliseconds, overriding `?timeout_ms=`.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// Overrides `?priority=`.
+    #[serde(default)]
+    priority: Option<JobPriority>,
+    /// Overrides `?streaming=`.
+    #[serde(default)]
+    streaming: Option<bool>,
+    /// Overrides `?max_attempts=`.
+    #[serde(default)]
+    max_attempts: Option<u32>,
+}
+
+/// Handler for `/run-query`: submit a query, given as text or as a
+/// [`RunRequest`], and return its job id, or its result with `?wait=true`.
+async fn run_query(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RunParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    run(&state, &params, &headers, body, Dialect::Dsl).await
+}
+
+/// Handler for `/run-sql`: as `/run-query`, with the query in SQL.
+async fn run_sql(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RunParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    run(&state, &params, &headers, body, Dialect::Sql).await
+}
+
+/// Submit the query of `body`, in `dialect` unless a JSON body names
+/// another. SQL that cannot be translated is answered `400`.
+async fn run(
+    state: &AppState,
+    params: &RunParams,
+    headers: &HeaderMap,
+    body: String,
+    dialect: Dialect,
+) -> Response {
     info!(%body, "received query");
-    let (job_id, status, rx) = state.scheduler.enqueue(body).await;
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    let (query, options, dialect) = if json {
+        match serde_json::from_str::<RunRequest>(&body) {
+            Ok(request) => (
+                request.query,
+                request.options,
+                request.dialect.unwrap_or(dialect),
+            ),
+            Err(e) => {
+                return query_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request",
+                    &e.to_string(),
+                    None,
+                )
+            }
+        }
+    } else {
+        (body, RequestOptions::default(), dialect)
+    };
+    let query = match dialect {
+        Dialect::Dsl => query,
+        Dialect::Sql => match sql::to_query(&query) {
+            Ok(query) => query,
+            Err(e) => return query_error(StatusCode::BAD_REQUEST, "invalid_query", &e, None),
+        },
+    };
+    submit(state, headers, query, options, params).await
+}
+
+/// The result format named by the request's `Accept` header, if any.
+fn accepted_format(headers: &HeaderMap) -> Option<OutputFormat> {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(OutputFormat::from_accept)
+}
+
+/// Dataset name and TTL requested by [`REGISTER_HEADER`] and
+/// [`REGISTER_TTL_HEADER`].
+fn register_request(headers: &HeaderMap) -> Option<(String, Option<u64>)> {
+    let name = headers.get(REGISTER_HEADER)?.to_str().ok()?;
+    let ttl = headers
+        .get(REGISTER_TTL_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    Some((name.to_string(), ttl))
+}
+
+/// Register the result of a successful job as requested, recording the
+/// outcome with the job.
+async fn register_result(
+    scheduler: &Scheduler,
+    job_id: u64,
+    result: &JobResult,
+    request: Option<(String, Option<u64>)>,
+    user: &str,
+) -> Option<Value> {
+    let (name, ttl) = request?;
+    if result.output.is_err() {
+        return None;
+    }
+    let registered = match scheduler
+        .register_output(job_id, name, result, ttl, user)
+        .await
+    {
+        Ok(dataset) => json!({ "name": dataset.name, "expires_at": dataset.expires_at }),
+        Err(e) => json!({ "error": e }),
+    };
+    scheduler.jobs().set_registered(job_id, registered.clone());
+    Some(registered)
+}
+
+/// Submit `query` for the caller with the options of its `request`, its
+/// result serialized as the request or the `Accept` header asks, and limited
+/// to the run time the request or `params` set.
+/// Unless `params.wait` is set, answer `202` with the job id right away and
+/// leave the result to `GET /jobs/:id`; otherwise the result itself is the
+/// body when the `Accept` header named its format.
+/// Queries that do not parse are answered `400`, those reading paths outside
+/// the sandbox `403`, and jobs that fail with the status of their
+/// [`JobError`].
+async fn submit(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: String,
+    request: RequestOptions,
+    params: &RunParams,
+) -> Response {
+    let mut options = job_options(state, headers);
+    let accepted = accepted_format(headers);
+    options.format = request.format.or(accepted).unwrap_or_default();
+    options.timeout = request
+        .timeout_ms
+        .or(params.timeout_ms)
+        .map(Duration::from_millis);
+    options.inline_limit = request.inline_limit;
+    options.priority = request.priority.or(params.priority).unwrap_or_default();
+    options.streaming = request.streaming.or(params.streaming);
+    options.max_attempts = request
+        .max_attempts
+        .or(params.max_attempts)
+        .map(|n| n.max(1));
+    let warnings = match lint_query(state, query.clone()).await {
+        Ok(warnings) => warnings,
+        Err(e) => return query_error(error_status(&e), e.kind, &e.message, None),
+    };
+    if params.dry_run {
+        let scheduler = state.scheduler.clone();
+        let user = options.user.clone();
+        return match tokio::task::spawn_blocking(move || scheduler.explain(&query, &user))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+        {
+            Ok(plan) => Json(json!({
+                "dry_run": true,
+                "plan": plan,
+                "warnings": warnings,
+            }))
+            .into_response(),
+            Err(e) => catalog_error(e),
+        };
+    }
+    if let Err(e) = state.scheduler.admit(&options.user).await {
+        return throttled_error(e, state.scheduler.is_draining());
+    }
+    let (job_id, status, rx) = state.scheduler.enqueue_with(query, options.clone()).await;
+    let register = register_request(headers);
+    // Rejected jobs have already failed, so they are answered in full.
+    if !params.wait && status != "rejected" {
+        if register.is_some() {
+            let scheduler = state.scheduler.clone();
+            tokio::spawn(async move {
+                if let Ok(result) = rx.await {
+                    register_result(&scheduler, job_id, &result, register, &options.user).await;
+                }
+            });
+        }
+        let response = json!({
+            "job_id": job_id,
+            "status": status,
+            "cost": state.scheduler.jobs().get(job_id).map(|entry| entry.cost),
+            "warnings": warnings,
+            "limits": state.scheduler.limits(),
+        });
+        return (StatusCode::ACCEPTED, Json(response)).into_response();
+    }
     let result = rx.await.ok();
-    let output = result.as_ref().and_then(|r| {
-        if let Some(bytes) = &r.bytes {
-            Some(B64_ENGINE.encode(bytes))
-        } else {
-            r.path.clone()
+    let output = result.as_ref().and_then(|r| r.output_json(job_id));
+    let error = result.as_ref().and_then(|r| r.error());
+    let registered = match &result {
+        Some(r) => register_result(&state.scheduler, job_id, r, register, &options.user).await,
+        None => None,
+    };
+    if accepted == Some(options.format) {
+        if let Some(Ok(output)) = result.as_ref().map(|r| &r.output) {
+            return raw_output(job_id, output.clone()).await;
         }
-    });
-    Json(json!({
+    }
+    let status = state
+        .scheduler
+        .jobs()
+        .get(job_id)
+        .map_or(status, |entry| entry.status);
+    let mut response = json!({
         "job_id": job_id,
         "status": status,
         "duration_ms": result.as_ref().map(|r| r.duration.as_millis()),
         "cost": result.as_ref().map(|r| r.cost),
-        "output": output
-    }))
+        "cached": result.as_ref().map(|r| r.cached),
+        "output": output,
+        "format": result.as_ref().and_then(|r| r.format()),
+        "error": error.map(|e| job_error(e, job_id)),
+        "warnings": warnings,
+        "limits": state.scheduler.limits(),
+    });
+    if let Some(registered) = registered {
+        response["registered"] = registered;
+    }
+    let status = error.map_or(StatusCode::OK, error_status);
+    (status, Json(response)).into_response()
+}
+
+/// The serialized result of a finished job as the response body, in the
+/// media type of its format.
+async fn raw_output(job_id: u64, output: PreparedOutput) -> Response {
+    let format = output.format;
+    let bytes = tokio::task::spawn_blocking(move || utils::output_bytes(&output))
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+    match bytes {
+        Ok(Some(bytes)) => (
+            [
+                (header::CONTENT_TYPE, format.media_type().to_string()),
+                (
+                    header::HeaderName::from_static(JOB_ID_HEADER),
+                    job_id.to_string(),
+                ),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Ok(None) => query_error(
+            StatusCode::NOT_ACCEPTABLE,
+            "not_acceptable",
+            "the result was split into part files; read them from GET /jobs/:id",
+            Some(job_id),
+        ),
+        Err(e) => query_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            &e.to_string(),
+            Some(job_id),
+        ),
+    }
+}
+
+/// Most jobs `GET /jobs` returns in one page.
+pub const MAX_JOBS_PAGE: usize = 1000;
+
+/// Parameters of `GET /jobs`.
+#[derive(Debug, Deserialize)]
+struct JobListParams {
+    status: Option<String>,
+    user: Option<String>,
+    priority: Option<JobPriority>,
+    #[serde(default = "default_jobs_page")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+}
+
+fn default_jobs_page() -> usize {
+    50
+}
+
+/// Handler for `GET /jobs`, listing the jobs this server holds, most recent
+/// first.
+async fn list_jobs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    params: Result<Query<JobListParams>, QueryRejection>,
+) -> Response {
+    let params = match params {
+        Ok(Query(params)) => params,
+        Err(e) => return catalog_error(e.to_string()),
+    };
+    let user = job_options(&state, &headers).user;
+    let filter = JobFilter {
+        status: params.status,
+        user: params.user,
+        priority: params.priority,
+    };
+    let limit = params.limit.min(MAX_JOBS_PAGE);
+    match state
+        .scheduler
+        .list_jobs(&user, filter, params.offset, limit)
+    {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /jobs/:id`, reporting a job's status and, once it has
+/// finished, its result.
+async fn get_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.job_status(id, &user).await {
+        Ok(job) => Json(job).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /jobs/:id/events`, a server-sent event stream of the
+/// job as `GET /jobs/:id` returns it: first an event named after its current
+/// status, then one per change of status and a `progress` event each time a
+/// running job moves on to another step. The stream ends once the job has
+/// finished.
+async fn job_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    let (job, mut events) = match state.scheduler.watch_job(id, &user).await {
+        Ok(watch) => watch,
+        Err(e) => return catalog_error(e),
+    };
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let status = |job: &Value| job["status"].as_str().unwrap_or_default().to_string();
+        let mut next = (status(&job), job);
+        loop {
+            let (kind, job) = next;
+            let event = Event::default().event(&kind).json_data(&job);
+            if tx.send(event).await.is_err() || !jobs::is_pending(&status(&job)) {
+                return;
+            }
+            next = loop {
+                match events.recv().await {
+                    Ok(event) if event.job_id == id => break (event.kind.to_string(), event.job),
+                    Ok(_) => continue,
+                    // Events were missed: catch up from the job's current state.
+                    Err(RecvError::Lagged(_)) => {
+                        match state.scheduler.job_status(id, &user).await {
+                            Ok(job) => break (status(&job), job),
+                            Err(_) => return,
+                        }
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            };
+        }
+    });
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Handler for `DELETE /jobs/:id`, removing a queued job or signalling a
+/// running one to stop.
+async fn cancel_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.cancel_job(id, &user).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) if e.contains("is not queued or running") => {
+            (StatusCode::CONFLICT, Json(json!({ "error": e }))).into_response()
+        }
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Media type of Arrow IPC stream responses.
+pub const ARROW_STREAM: &str = "application/vnd.apache.arrow.stream";
+
+/// Header carrying the job id on Arrow stream responses.
+pub const JOB_ID_HEADER: &str = "x-rdata-job-id";
+
+/// Structured error returned for failed queries, so clients can raise a
+/// typed exception rather than parse a message.
+fn query_error(status: StatusCode, kind: &str, message: &str, job_id: Option<u64>) -> Response {
+    let body = json!({
+        "error": {
+            "kind": kind,
+            "message": message,
+            "job_id": job_id,
+            "status": status.as_u16(),
+        }
+    });
+    (status, Json(body)).into_response()
+}
+
+/// The answer to a submission refused by [`Scheduler::admit`]:
+/// `503 shutting_down` once the server is `draining`, otherwise
+/// `429 rate_limited`, with a `Retry-After` header.
+fn throttled_error(throttled: Throttled, draining: bool) -> Response {
+    let (status, kind) = if draining {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting_down")
+    } else {
+        (StatusCode::TOO_MANY_REQUESTS, "rate_limited")
+    };
+    let mut response = query_error(status, kind, &throttled.message, None);
+    let retry_after = HeaderValue::from(throttled.retry_after_secs());
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after);
+    response
+}
+
+/// Status answering a job that failed with `error`.
+fn error_status(error: &JobError) -> StatusCode {
+    match error.kind {
+        "invalid_query" => StatusCode::BAD_REQUEST,
+        "access_denied" => StatusCode::FORBIDDEN,
+        "cancelled" => StatusCode::CONFLICT,
+        "timeout" => StatusCode::GATEWAY_TIMEOUT,
+        "unknown_dataset" => StatusCode::NOT_FOUND,
+        "insufficient_storage" => StatusCode::INSUFFICIENT_STORAGE,
+        "transient_io" => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
+
+/// The `error` object of a job that failed with `error`, as [`query_error`]
+/// returns it plus the step that failed.
+fn job_error(error: &JobError, job_id: u64) -> Value {
+    json!({
+        "kind": error.kind,
+        "message": error.message,
+        "step": error.step,
+        "job_id": job_id,
+        "status": error_status(error).as_u16(),
+    })
+}
+
+/// Handler for `/run-query/arrow`: run a query and return its result as an
+/// Arrow IPC stream, readable directly by `pl.read_ipc_stream` or pyarrow.
+/// Failures are returned as JSON with a `kind` notebooks can branch on.
+async fn run_query_arrow(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    info!(%body, "received arrow query");
+    if let Err(e) = parser::parse_query(&body) {
+        return query_error(StatusCode::BAD_REQUEST, "invalid_query", &e, None);
+    }
+    let options = job_options(&state, &headers);
+    if let Err(e) = state.scheduler.admit(&options.user).await {
+        return throttled_error(e, state.scheduler.is_draining());
+    }
+    let (job_id, _, rx) = state.scheduler.enqueue_with(body, options).await;
+    let Ok(result) = rx.await else {
+        let message = "job was dropped before it finished";
+        return query_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            message,
+            Some(job_id),
+        );
+    };
+    let output = match result.output {
+        Ok(output) => output,
+        Err(e) => {
+            let body = json!({ "error": job_error(&e, job_id) });
+            return (error_status(&e), Json(body)).into_response();
+        }
+    };
+    let encoded = tokio::task::spawn_blocking(move || {
+        let df = utils::read_output(
+            output.bytes.as_deref(),
+            output.path.as_deref(),
+            output.parts.as_deref().unwrap_or_default(),
+        )?;
+        utils::encode_ipc_stream(&df).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    match encoded {
+        Ok(stream) => (
+            [
+                (header::CONTENT_TYPE, ARROW_STREAM.to_string()),
+                (
+                    header::HeaderName::from_static(JOB_ID_HEADER),
+                    job_id.to_string(),
+                ),
+            ],
+            stream,
+        )
+            .into_response(),
+        Err(e) => query_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            &e,
+            Some(job_id),
+        ),
+    }
+}
+
+async fn lint_query(state: &AppState, query: String) -> Result<Vec<LintWarning>, JobError> {
+    let scheduler = state.scheduler.clone();
+    tokio::task::spawn_blocking(move || scheduler.lint(&query))
+        .await
+        .unwrap_or_else(|e| Err(JobError::new("execution_failed", None, e.to_string())))
+}
+
+/// Estimate a query's rows, bytes scanned and cost without running it.
+async fn estimate_query(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    let scheduler = state.scheduler.clone();
+    match tokio::task::spawn_blocking(move || scheduler.estimate(&body, &user))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+    {
+        Ok(estimate) => Json(estimate).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Query parameters of `POST /explain`.
+#[derive(Debug, Default, Deserialize)]
+struct ExplainParams {
+    #[serde(default)]
+    format: ExplainFormat,
+}
+
+/// Describe a query's plan as Graphviz DOT or a node/edge graph with
+/// estimated rows, without running it.
+async fn explain_query(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    params: Result<Query<ExplainParams>, QueryRejection>,
+    body: String,
+) -> Response {
+    let format = match params {
+        Ok(Query(params)) => params.format,
+        Err(e) => return catalog_error(e.to_string()),
+    };
+    let user = job_options(&state, &headers).user;
+    let scheduler = state.scheduler.clone();
+    match tokio::task::spawn_blocking(move || scheduler.explain(&body, &user))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+    {
+        Ok(graph) if format == ExplainFormat::Dot => (
+            [(header::CONTENT_TYPE, "text/vnd.graphviz")],
+            graph.to_dot(),
+        )
+            .into_response(),
+        Ok(graph) => Json(graph).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Parse a query and lint it without running it.
+async fn validate_query(State(state): State<Arc<AppState>>, body: String) -> Json<Value> {
+    match lint_query(&state, body).await {
+        Ok(warnings) => Json(json!({ "valid": true, "warnings": warnings })),
+        Err(e) => Json(json!({ "valid": false, "error": e.message, "warnings": [] })),
+    }
+}
+
+/// Filters accepted by `GET /datasets`.
+#[derive(Debug, Default, Deserialize)]
+struct DatasetFilter {
+    owner: Option<String>,
+    tag: Option<String>,
+}
+
+/// JSON error response, `404` for unknown datasets, partitions, jobs, saved
+/// queries, sessions, frames, subscriptions or result files, `403` for denied
+/// access and
+/// `400` otherwise.
+fn catalog_error(e: String) -> Response {
+    let status = if e.starts_with("unknown dataset")
+        || e.starts_with("unknown partition")
+        || e.starts_with("unknown job")
+        || e.starts_with("unknown query")
+        || e.starts_with("unknown session")
+        || e.starts_with("unknown frame")
+        || e.starts_with("unknown subscription")
+        || e.starts_with("unknown file")
+    {
+        StatusCode::NOT_FOUND
+    } else if e.starts_with("access denied") {
+        StatusCode::FORBIDDEN
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    (status, Json(json!({ "error": e }))).into_response()
+}
+
+/// Check the caller holds `needed` on the dataset `name`.
+fn authorize(
+    state: &AppState,
+    headers: &HeaderMap,
+    name: &str,
+    needed: Permission,
+    action: &str,
+) -> Result<(), String> {
+    let user = job_options(state, headers).user;
+    state
+        .scheduler
+        .access()
+        .authorize(state.scheduler.catalog(), &user, name, needed, action)
+}
+
+/// Handler for `GET /datasets`, listing registered datasets.
+async fn list_datasets(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<DatasetFilter>,
+) -> impl IntoResponse {
+    let datasets: Vec<_> = state
+        .scheduler
+        .catalog()
+        .list()
+        .into_iter()
+        .filter(|d| filter.owner.as_ref().is_none_or(|o| &d.owner == o))
+        .filter(|d| filter.tag.as_ref().is_none_or(|t| d.tags.contains(t)))
+        .collect();
+    Json(json!({ "datasets": datasets }))
+}
+
+/// Handler for `GET /datasets/:name`.
+async fn get_dataset(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    match state.scheduler.catalog().get(&name) {
+        Some(dataset) => Json(dataset).into_response(),
+        None => catalog_error(format!("unknown dataset {}", name)),
+    }
+}
+
+/// Handler for `DELETE /datasets/:name`, unregistering a dataset. Its files
+/// are left in place.
+async fn delete_dataset(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Admin, "delete") {
+        return catalog_error(denied);
+    }
+    let catalog = state.scheduler.catalog().clone();
+    let result = tokio::task::spawn_blocking(move || catalog.remove(&name))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(dataset) => Json(dataset).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `POST /datasets`, registering or updating a dataset owned by
+/// the caller.
+async fn register_dataset(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(spec): Json<DatasetSpec>,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &spec.name, Permission::Write, "register") {
+        return catalog_error(denied);
+    }
+    let owner = job_options(&state, &headers).user;
+    let catalog = state.scheduler.catalog().clone();
+    let result = tokio::task::spawn_blocking(move || catalog.register(spec, &owner))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(dataset) => (StatusCode::CREATED, Json(dataset)).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /datasets/:name/checks`, returning the dataset's checks
+/// and their recent runs, newest first.
+async fn get_checks(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Read, "get_checks") {
+        return catalog_error(denied);
+    }
+    match state.scheduler.catalog().get(&name) {
+        Some(dataset) => {
+            let runs: Vec<_> = dataset.check_runs.iter().rev().collect();
+            Json(json!({ "checks": dataset.checks, "runs": runs })).into_response()
+        }
+        None => catalog_error(format!("unknown dataset {}", name)),
+    }
+}
+
+/// Handler for `PUT /datasets/:name/checks`, replacing the dataset's checks.
+async fn set_checks(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(checks): Json<Vec<CheckSpec>>,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "set_checks") {
+        return catalog_error(denied);
+    }
+    match state.scheduler.catalog().set_checks(&name, checks) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `POST /datasets/:name/checks/run`, running the checks now.
+async fn run_checks(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "run_checks") {
+        return catalog_error(denied);
+    }
+    let catalog = state.scheduler.catalog().clone();
+    let result = tokio::task::spawn_blocking(move || catalog.run_checks(&name))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(run) => Json(run).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `POST /datasets/:name/compact`, merging the dataset's small
+/// files into fewer larger ones.
+async fn compact_dataset(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "compact") {
+        return catalog_error(denied);
+    }
+    let catalog = state.scheduler.catalog().clone();
+    let target = state.scheduler.compaction().target_file_bytes;
+    let result = tokio::task::spawn_blocking(move || compaction::compact(&catalog, &name, target))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /datasets/:name/schema`, returning the columns, estimated
+/// rows and size of the current version from file metadata.
+async fn get_schema(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if state.scheduler.catalog().get(&name).is_none() {
+        return catalog_error(format!("unknown dataset {}", name));
+    }
+    let source = QueryPlan::ReadTable {
+        name,
+        version: None,
+        as_of: None,
+    };
+    describe_source(&state, &headers, source).await
+}
+
+/// Handler for `POST /schema`, describing a file, directory or glob given as
+/// `{"path": ..., "format": ...}` like `GET /datasets/:name/schema`.
+async fn post_schema(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<SchemaRequest>,
+) -> Response {
+    match request.source() {
+        Ok(source) => describe_source(&state, &headers, source).await,
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Describe `source` for the caller, off the async runtime.
+async fn describe_source(state: &AppState, headers: &HeaderMap, source: QueryPlan) -> Response {
+    let user = job_options(state, headers).user;
+    let scheduler = state.scheduler.clone();
+    match tokio::task::spawn_blocking(move || scheduler.source_schema(&source, &user))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+    {
+        Ok(schema) => Json(schema).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /datasets/:name/stats`, returning the most recently
+/// collected column statistics. Minimum and maximum values of columns masked
+/// for the caller are left out.
+async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Read, "get_stats") {
+        return catalog_error(denied);
+    }
+    let Some(dataset) = state.scheduler.catalog().get(&name) else {
+        return catalog_error(format!("unknown dataset {}", name));
+    };
+    let Some(mut stats) = dataset.stats.clone() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("no statistics collected for {}", name) })),
+        )
+            .into_response();
+    };
+    let user = job_options(&state, &headers).user;
+    let masked = state.scheduler.access().masked_columns(&dataset, &user);
+    for column in stats.columns.iter_mut() {
+        if masked.iter().any(|(c, _)| *c == column.name) {
+            column.min = None;
+            column.max = None;
+        }
+    }
+    Json(stats).into_response()
+}
+
+/// Handler for `POST /datasets/:name/stats`, collecting statistics of the
+/// current version now.
+async fn collect_stats(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "collect_stats") {
+        return catalog_error(denied);
+    }
+    let catalog = state.scheduler.catalog().clone();
+    let result = tokio::task::spawn_blocking(move || catalog.collect_stats(&name))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /datasets/:name/grants`.
+async fn get_grants(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Read, "get_grants") {
+        return catalog_error(denied);
+    }
+    match state.scheduler.catalog().get(&name) {
+        Some(dataset) => {
+            Json(json!({ "owner": dataset.owner, "grants": dataset.grants })).into_response()
+        }
+        None => catalog_error(format!("unknown dataset {}", name)),
+    }
+}
+
+/// Handler for `PUT /datasets/:name/grants`, replacing the dataset's grants.
+/// Requires admin permission.
+async fn set_grants(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(grants): Json<Vec<Grant>>,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Admin, "set_grants") {
+        return catalog_error(denied);
+    }
+    let detail = serde_json::to_string(&grants).unwrap_or_default();
+    match state.scheduler.catalog().set_grants(&name, grants) {
+        Ok(()) => {
+            let user = job_options(&state, &headers).user;
+            state
+                .scheduler
+                .access()
+                .audit()
+                .record(AuditEvent::new(&user, "set_grants", &name, true).with_detail(detail));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /datasets/:name/policies`.
+async fn get_policies(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Read, "get_policies") {
+        return catalog_error(denied);
+    }
+    match state.scheduler.catalog().get(&name) {
+        Some(dataset) => Json(json!({ "policies": dataset.column_policies })).into_response(),
+        None => catalog_error(format!("unknown dataset {}", name)),
+    }
+}
+
+/// Handler for `PUT /datasets/:name/policies`, replacing the dataset's column
+/// masking policies. Requires admin permission.
+async fn set_policies(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(policies): Json<Vec<ColumnPolicy>>,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Admin, "set_policies") {
+        return catalog_error(denied);
+    }
+    let detail = serde_json::to_string(&policies).unwrap_or_default();
+    match state
+        .scheduler
+        .catalog()
+        .set_column_policies(&name, policies)
+    {
+        Ok(()) => {
+            let user = job_options(&state, &headers).user;
+            state
+                .scheduler
+                .access()
+                .audit()
+                .record(AuditEvent::new(&user, "set_policies", &name, true).with_detail(detail));
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Current version's statistics for every partition of `name`.
+fn partition_stats(state: &AppState, name: &str) -> Result<Vec<PartitionStats>, String> {
+    let dataset = state
+        .scheduler
+        .catalog()
+        .get(name)
+        .ok_or_else(|| format!("unknown dataset {}", name))?;
+    if dataset.partition_by.is_none() {
+        return Err(format!("dataset {} is not partitioned", name));
+    }
+    Ok(dataset
+        .versions
+        .last()
+        .map(|v| v.partitions.clone())
+        .unwrap_or_default())
+}
+
+/// Handler for `GET /datasets/:name/partitions`.
+async fn list_partitions(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    match partition_stats(&state, &name) {
+        Ok(partitions) => Json(json!({ "partitions": partitions })).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /datasets/:name/partitions/:value`.
+async fn get_partition(
+    State(state): State<Arc<AppState>>,
+    Path((name, value)): Path<(String, String)>,
+) -> Response {
+    let result = partition_stats(&state, &name).and_then(|partitions| {
+        partitions
+            .into_iter()
+            .find(|p| p.value == value)
+            .ok_or_else(|| format!("unknown partition {}", value))
+    });
+    match result {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AddPartition {
+    /// Parquet file, or directory of files, copied into the partition.
+    /// Relative paths are resolved against the data directory, and the
+    /// path must lie within the sandbox.
+    source: String,
+}
+
+/// Handler for `POST /datasets/:name/partitions/:value`, adding a partition
+/// from files on the server.
+async fn add_partition(
+    State(state): State<Arc<AppState>>,
+    Path((name, value)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(body): Json<AddPartition>,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "add_partition") {
+        return catalog_error(denied);
+    }
+    let source = match state.scheduler.sandboxed_path(&body.source) {
+        Ok(source) => source,
+        Err(denied) => return catalog_error(denied),
+    };
+    let catalog = state.scheduler.catalog().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        partition::add_partition(&catalog, &name, &value, std::path::Path::new(&source))
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(dataset) => (StatusCode::CREATED, Json(dataset)).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `DELETE /datasets/:name/partitions/:value`.
+async fn drop_partition(
+    State(state): State<Arc<AppState>>,
+    Path((name, value)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "drop_partition") {
+        return catalog_error(denied);
+    }
+    let catalog = state.scheduler.catalog().clone();
+    let result =
+        tokio::task::spawn_blocking(move || partition::drop_partition(&catalog, &name, &value))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(dataset) => Json(dataset).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `POST /discovery/scan`, scanning the data roots now.
+async fn scan_roots(State(state): State<Arc<AppState>>) -> Response {
+    let catalog = state.scheduler.catalog().clone();
+    let config = state.scheduler.discovery().clone();
+    match tokio::task::spawn_blocking(move || discovery::scan(&catalog, &config)).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => catalog_error(e.to_string()),
+    }
+}
+
+/// Largest body accepted by `POST /ingest`.
+pub const MAX_INGEST_BYTES: usize = 512 * 1024 * 1024;
+
+/// Read the data an ingestion request points at. Local paths must lie
+/// within the sandbox and URLs must start with one of the configured
+/// `ingest_urls`.
+async fn fetch_source(state: &AppState, source: &str) -> Result<Vec<u8>, String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let allowed = &state.scheduler.config().data.ingest_urls;
+        if !allowed
+            .iter()
+            .any(|prefix| source.starts_with(prefix.as_str()))
+        {
+            return Err(format!(
+                "access denied: fetching {} is not allowed by data.ingest_urls",
+                source
+            ));
+        }
+        let response = reqwest::get(source)
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("failed to fetch {}: {}", source, e))?;
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        Ok(bytes.to_vec())
+    } else {
+        let path = state.scheduler.sandboxed_path(source)?;
+        tokio::fs::read(path)
+            .await
+            .map_err(|e| format!("failed to read {}: {}", source, e))
+    }
+}
+
+/// Handler for `POST /ingest`, converting CSV or NDJSON to a parquet dataset.
+///
+/// Data is either the request body, with options in the query string, or a
+/// `source` path or URL named in a JSON body.
+async fn ingest_data(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    query: Result<Query<IngestOptions>, QueryRejection>,
+    body: Bytes,
+) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let options = if content_type.starts_with("application/json") {
+        match serde_json::from_slice::<IngestOptions>(&body) {
+            Ok(options) if options.source.is_some() => options,
+            Ok(_) => return catalog_error("a JSON request must name a source".to_string()),
+            Err(e) => return catalog_error(e.to_string()),
+        }
+    } else {
+        match query {
+            Ok(Query(options)) => options,
+            Err(e) => return catalog_error(e.to_string()),
+        }
+    };
+    if let Err(denied) = authorize(
+        &state,
+        &headers,
+        &options.dataset,
+        Permission::Write,
+        "ingest",
+    ) {
+        return catalog_error(denied);
+    }
+    let bytes = match &options.source {
+        Some(source) => match fetch_source(&state, source).await {
+            Ok(bytes) => bytes,
+            Err(e) => return catalog_error(e),
+        },
+        None => body.to_vec(),
+    };
+    let format = options
+        .format
+        .or_else(|| IngestFormat::from_content_type(&content_type))
+        .or_else(|| options.source.as_deref().and_then(IngestFormat::from_path))
+        .unwrap_or_default();
+
+    let owner = job_options(&state, &headers).user;
+    let catalog = state.scheduler.catalog().clone();
+    let root = state.scheduler.ingest_dir().to_path_buf();
+    let result = tokio::task::spawn_blocking(move || {
+        let schema = ingest::parse_schema(options.schema.as_deref().unwrap_or_default())?;
+        let df = ingest::read(bytes, format, &schema)?;
+        ingest::ingest(&catalog, &root, df, options, &owner)
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(report) => (StatusCode::CREATED, Json(report)).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// NDJSON bytes parsed and buffered at a time while a stream is appended.
+const APPEND_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
+/// Parse `bytes` as `format` and buffer the rows for the dataset `name`.
+async fn append_rows(
+    state: &AppState,
+    name: &str,
+    bytes: Vec<u8>,
+    arrow: bool,
+    owner: &str,
+) -> Result<AppendReport, String> {
+    let appender = state.scheduler.appender().clone();
+    let name = name.to_string();
+    let owner = owner.to_string();
+    tokio::task::spawn_blocking(move || {
+        let df = if arrow {
+            streaming::read_arrow_stream(bytes)?
+        } else {
+            ingest::read(bytes, IngestFormat::Ndjson, &[])?
+        };
+        appender.append(&name, df, &owner)
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()))
+}
+
+/// Error response of an append, asking the caller to retry later when the
+/// buffers are full.
+fn append_error(state: &AppState, e: String) -> Response {
+    if !e.starts_with(BUFFER_FULL) {
+        return catalog_error(e);
+    }
+    let interval = state.scheduler.appender().config().flush_interval_ms;
+    let retry_after = interval.div_ceil(1000).max(1).to_string();
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after)],
+        Json(json!({ "error": e })),
+    )
+        .into_response()
+}
+
+/// Handler for `POST /datasets/:name/append`, buffering a stream of Arrow
+/// record batches or NDJSON lines to be flushed into the dataset as parquet
+/// files.
+///
+/// NDJSON is buffered as it arrives, so a long-running request becomes
+/// queryable while it is still streaming.
+async fn append_dataset(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    mut body: BodyStream,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "append") {
+        return catalog_error(denied);
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let arrow = content_type.starts_with(ARROW_STREAM);
+    if !arrow && IngestFormat::from_content_type(content_type) != Some(IngestFormat::Ndjson) {
+        return catalog_error(format!(
+            "unsupported content type {}: use {} or application/x-ndjson",
+            content_type, ARROW_STREAM
+        ));
+    }
+    let owner = job_options(&state, &headers).user;
+
+    let mut report = AppendReport {
+        dataset: name.clone(),
+        ..Default::default()
+    };
+    let mut pending = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return catalog_error(format!("failed to read request body: {}", e)),
+        };
+        pending.extend_from_slice(&chunk);
+        if arrow {
+            if pending.len() > MAX_INGEST_BYTES {
+                return catalog_error(format!(
+                    "Arrow streams are limited to {} bytes per request",
+                    MAX_INGEST_BYTES
+                ));
+            }
+            continue;
+        }
+        if pending.len() < APPEND_CHUNK_BYTES {
+            continue;
+        }
+        let Some(end) = pending.iter().rposition(|b| *b == b'\n') else {
+            continue;
+        };
+        let rest = pending.split_off(end + 1);
+        let lines = std::mem::replace(&mut pending, rest);
+        match append_rows(&state, &name, lines, false, &owner).await {
+            Ok(appended) => report.absorb(appended),
+            Err(e) => return append_error(&state, e),
+        }
+    }
+    if !pending.iter().all(u8::is_ascii_whitespace) {
+        match append_rows(&state, &name, pending, arrow, &owner).await {
+            Ok(appended) => report.absorb(appended),
+            Err(e) => return append_error(&state, e),
+        }
+    }
+    (StatusCode::ACCEPTED, Json(report)).into_response()
+}
+
+/// Response for a view operation: the view's status or a JSON error.
+fn view_response(result: Result<ViewStatus, String>, ok: StatusCode) -> Response {
+    match result {
+        Ok(status) => (ok, Json(status)).into_response(),
+        Err(e) if e.starts_with("unknown view") => {
+            (StatusCode::NOT_FOUND, Json(json!({ "error": e }))).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+/// Filters accepted by `GET /views`.
+#[derive(Debug, Default, Deserialize)]
+struct ViewFilter {
+    /// Only views whose last refresh failed.
+    #[serde(default)]
+    failing: bool,
+}
+
+/// Handler for `GET /views`, listing views with their staleness and refresh
+/// status. `?failing=true` lists only views whose last refresh failed.
+async fn list_views(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<ViewFilter>,
+) -> impl IntoResponse {
+    let views: Vec<_> = state
+        .scheduler
+        .views()
+        .list()
+        .into_iter()
+        .filter(|v| !filter.failing || v.failing())
+        .collect();
+    Json(json!({ "views": views }))
+}
+
+/// Handler for `GET /views/:name`.
+async fn get_view(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    view_response(state.scheduler.views().status(&name), StatusCode::OK)
+}
+
+/// Handler for `POST /views`, defining a view and materializing it.
+async fn define_view(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(spec): Json<ViewSpec>,
+) -> Response {
+    if let Err(denied) = authorize(
+        &state,
+        &headers,
+        &spec.name,
+        Permission::Write,
+        "define_view",
+    ) {
+        return catalog_error(denied);
+    }
+    let owner = job_options(&state, &headers).user;
+    let access = state.scheduler.access();
+    if let Err(e) = access.authorize_query(state.scheduler.catalog(), &owner, &spec.query) {
+        return catalog_error(e);
+    }
+    let views = state.scheduler.views().clone();
+    let result = tokio::task::spawn_blocking(move || views.define(spec, &owner))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    view_response(result, StatusCode::CREATED)
+}
+
+/// Handler for `POST /views/:name/refresh`, re-materializing a view.
+async fn refresh_view(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Write, "refresh_view") {
+        return catalog_error(denied);
+    }
+    let views = state.scheduler.views().clone();
+    let result = tokio::task::spawn_blocking(move || views.refresh(&name))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    view_response(result, StatusCode::OK)
+}
+
+/// Filters accepted by `GET /lineage`.
+#[derive(Debug, Default, Deserialize)]
+struct LineageFilter {
+    /// Output file to trace.
+    path: String,
+}
+
+/// Handler for `GET /lineage?path=...`, tracing a result or view file back to
+/// the queries and inputs it was derived from. Only jobs and views the
+/// caller may read are included.
+async fn file_lineage(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<LineageFilter>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let user = job_options(&state, &headers).user;
+    let lineage = state.scheduler.file_lineage(&filter.path, &user);
+    Json(json!({ "lineage": lineage }))
+}
+
+/// Handler for `GET /lineage/jobs/:id`, for the job's owner and admins.
+async fn job_lineage(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.job_lineage(id, &user) {
+        Ok(Some(lineage)) => Json(lineage).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("no lineage recorded for job {}", id) })),
+        )
+            .into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /lineage/datasets/:name`, for views.
+async fn dataset_lineage(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize(&state, &headers, &name, Permission::Read, "lineage") {
+        return catalog_error(denied);
+    }
+    match Lineage::of_view(state.scheduler.catalog(), &name) {
+        Ok(lineage) => Json(lineage).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /queries`.
+async fn list_queries(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!({ "queries": state.scheduler.queries().list() }))
+}
+
+/// Handler for `GET /queries/:name`, including the variables it takes.
+async fn get_saved_query(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    let queries = state.scheduler.queries();
+    let Some(query) = queries.get(&name) else {
+        return catalog_error(format!("unknown query {}", name));
+    };
+    match queries.variables(&name) {
+        Ok(variables) => Json(json!({ "query": query, "variables": variables })).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// With access control enabled, only the owner of an existing saved query and
+/// configured admins may change it.
+fn authorize_saved_query(state: &AppState, headers: &HeaderMap, name: &str) -> Result<(), String> {
+    let access = state.scheduler.access();
+    let user = job_options(state, headers).user;
+    match state.scheduler.queries().get(name) {
+        Some(query) if access.enabled() && query.owner != user && !access.is_admin(&user) => Err(
+            format!("access denied: {} does not own saved query {}", user, name),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Handler for `PUT /queries/:name`, saving a query template or snippet.
+async fn save_query(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    Json(spec): Json<SavedQuerySpec>,
+) -> Response {
+    if let Err(denied) = authorize_saved_query(&state, &headers, &name) {
+        return catalog_error(denied);
+    }
+    let owner = job_options(&state, &headers).user;
+    match state.scheduler.queries().save(&name, spec, &owner) {
+        Ok(query) => Json(query).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `DELETE /queries/:name`.
+async fn delete_query(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(denied) = authorize_saved_query(&state, &headers, &name) {
+        return catalog_error(denied);
+    }
+    match state.scheduler.queries().delete(&name) {
+        Ok(query) => Json(query).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Expand the saved query `name` with the variables in `body`, a JSON object
+/// that may be omitted when there are none.
+fn render_saved_query(state: &AppState, name: &str, body: &[u8]) -> Result<String, String> {
+    let vars: BTreeMap<String, Value> = if body.is_empty() {
+        BTreeMap::new()
+    } else {
+        serde_json::from_slice(body).map_err(|e| format!("invalid variables: {}", e))?
+    };
+    state.scheduler.queries().render(name, &vars)
+}
+
+/// Handler for `POST /queries/:name/render`, returning the expanded query.
+async fn render_query(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    body: Bytes,
+) -> Response {
+    match render_saved_query(&state, &name, &body) {
+        Ok(query) => Json(json!({ "query": query })).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `POST /queries/:name/run`, expanding and running a saved query.
+async fn run_saved_query(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(params): Query<RunParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    match render_saved_query(&state, &name, &body) {
+        Ok(query) => {
+            info!(saved = %name, %query, "running saved query");
+            submit(&state, &headers, query, RequestOptions::default(), &params).await
+        }
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `POST /sessions`, opening a session for the caller.
+async fn create_session(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.sessions().create(&user) {
+        Ok(session) => (StatusCode::CREATED, Json(session)).into_response(),
+        Err(e) => (StatusCode::TOO_MANY_REQUESTS, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+/// Handler for `GET /sessions/:id`.
+async fn get_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.sessions().get(&id, &user) {
+        Ok(session) => Json(session).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `DELETE /sessions/:id`.
+async fn close_session(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.sessions().close(&id, &user) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Parameters of `POST /sessions/:id/steps`.
+#[derive(Debug, Deserialize)]
+struct StepParams {
+    /// Frame the step starts from.
+    from: Option<String>,
+    /// Frame the result is kept as.
+    #[serde(default = "default_frame")]
+    into: String,
+}
+
+fn default_frame() -> String {
+    "df".to_string()
+}
+
+/// Handler for `POST /sessions/:id/steps`, running a query in a session and
+/// keeping its result in memory.
+async fn run_step(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(params): Query<StepParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    let sessions = state.scheduler.sessions().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        sessions.run(&id, &user, &body, params.from.as_deref(), &params.into)
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    match result {
+        Ok(step) => Json(step).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `DELETE /sessions/:id/frames/:name`.
+async fn drop_frame(
+    State(state): State<Arc<AppState>>,
+    Path((id, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.sessions().drop_frame(&id, &user, &name) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Parameters of `GET /diff`.
+#[derive(Debug, Deserialize)]
+struct DiffParams {
+    left: u64,
+    right: u64,
+    key: Option<String>,
+}
+
+/// Handler for `GET /diff`, comparing the stored results of two jobs.
+async fn diff_results(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<DiffParams>,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state
+        .scheduler
+        .diff_jobs(params.left, params.right, params.key, &user)
+        .await
+    {
+        Ok(diff) => Json(diff).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+async fn list_subscriptions(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!(state.scheduler.subscriptions().list()))
+}
+
+/// Handler for `POST /subscriptions`, re-running a saved query whenever a
+/// dataset the caller may read changes.
+async fn create_subscription(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(spec): Json<SubscriptionSpec>,
+) -> Response {
+    if let Err(denied) = authorize(
+        &state,
+        &headers,
+        &spec.dataset,
+        Permission::Read,
+        "subscribe",
+    ) {
+        return catalog_error(denied);
+    }
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.subscribe(spec, &user) {
+        Ok(subscription) => (StatusCode::CREATED, Json(subscription)).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Look up subscription `id`, which only its owner and configured admins
+/// may see when access control is enabled.
+fn owned_subscription(
+    state: &AppState,
+    headers: &HeaderMap,
+    id: u64,
+) -> Result<Subscription, String> {
+    let subscription = state
+        .scheduler
+        .subscriptions()
+        .get(id)
+        .ok_or_else(|| format!("unknown subscription {}", id))?;
+    let access = state.scheduler.access();
+    let user = job_options(state, headers).user;
+    if access.enabled() && subscription.owner != user && !access.is_admin(&user) {
+        return Err(format!(
+            "access denied: {} does not own subscription {}",
+            user, id
+        ));
+    }
+    Ok(subscription)
+}
+
+async fn get_subscription(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    match owned_subscription(&state, &headers, id) {
+        Ok(subscription) => Json(subscription).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+async fn delete_subscription(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = owned_subscription(&state, &headers, id) {
+        return catalog_error(e);
+    }
+    match state.scheduler.subscriptions().remove(id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /subscriptions/:id/events`, a server-sent event stream
+/// with a `result` event each time the subscribed query is re-run.
+async fn subscription_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(e) = owned_subscription(&state, &headers, id) {
+        return catalog_error(e);
+    }
+    let events = BroadcastStream::new(state.scheduler.subscriptions().listen()).filter_map(
+        move |notification| match notification {
+            Ok(n) if n.subscription == id => Some(Event::default().event("result").json_data(n)),
+            // Other subscriptions, or notifications missed by a slow listener.
+            _ => None,
+        },
+    );
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Body of `POST /results/:id/convert`.
+#[derive(Debug, Deserialize)]
+struct ConvertResult {
+    format: ConvertFormat,
+}
+
+/// Handler for `POST /results/:id/convert`, converting a job's stored result
+/// to CSV, parquet or JSON.
+async fn convert_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Json(body): Json<ConvertResult>,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state.scheduler.convert_job(id, body.format, &user).await {
+        Ok(converted) => Json(converted).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Query of `GET /jobs/:id/download`.
+#[derive(Debug, Deserialize)]
+struct DownloadParams {
+    /// Index of the part to download when the result was split into parts.
+    part: Option<usize>,
+}
+
+/// Handler for `GET /jobs/:id/download`, streaming a job's stored result
+/// file, or one of its parts, in the media type of its format. A `Range`
+/// header asks for a single byte range of the file.
+async fn download_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    Query(params): Query<DownloadParams>,
+    headers: HeaderMap,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    let path = match state.scheduler.job_file(id, params.part, &user).await {
+        Ok(path) => path,
+        Err(e) => return catalog_error(e),
+    };
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return catalog_error(format!(
+                "unknown file: the result of job {} has been removed",
+                id
+            ))
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| download::parse_range(v, len))
+        .transpose();
+    // A header that is not a single byte range is ignored: the whole file.
+    let range = match range {
+        Ok(range) => range.flatten(),
+        Err(e) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", len))],
+                Json(json!({ "error": e })),
+            )
+                .into_response()
+        }
+    };
+    let media_type = path
+        .extension()
+        .and_then(|e| OutputFormat::from_extension(&e.to_string_lossy()))
+        .map_or("application/octet-stream", OutputFormat::media_type);
+    let (status, bytes) = match &range {
+        Some(range) => (StatusCode::PARTIAL_CONTENT, range.clone()),
+        None => (StatusCode::OK, 0..len),
+    };
+    if let Err(e) = file.seek(SeekFrom::Start(bytes.start)).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    let body = StreamBody::new(ReaderStream::new(file.take(bytes.end - bytes.start)));
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, media_type.to_string()),
+            (
+                header::CONTENT_LENGTH,
+                (bytes.end - bytes.start).to_string(),
+            ),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+        ],
+        body,
+    )
+        .into_response();
+    if range.is_some() {
+        let content_range = format!("bytes {}-{}/{}", bytes.start, bytes.end - 1, len);
+        if let Ok(value) = header::HeaderValue::from_str(&content_range) {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
+
+/// Body of `POST /jobs/:id/register`.
+#[derive(Debug, Deserialize)]
+struct RegisterResult {
+    name: String,
+    ttl_secs: Option<u64>,
+}
+
+/// Handler for `POST /jobs/:id/register`, exposing a job's stored result as
+/// a temporary dataset.
+async fn register_job_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Json(body): Json<RegisterResult>,
+) -> Response {
+    let user = job_options(&state, &headers).user;
+    match state
+        .scheduler
+        .register_job_result(id, body.name, body.ttl_secs, &user)
+        .await
+    {
+        Ok(dataset) => (StatusCode::CREATED, Json(dataset)).into_response(),
+        Err(e) => catalog_error(e),
+    }
+}
+
+/// Handler for `GET /metrics`, serving the scheduler's metrics for
+/// Prometheus to scrape.
+async fn prometheus_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.scheduler.render_metrics(),
+    )
+}
+
+/// Handler for `GET /healthz`: the process is alive and its scheduler loop
+/// is running.
+async fn healthz(State(state): State<Arc<AppState>>) -> Response {
+    if state.scheduler.is_running() {
+        Json(json!({"status": "ok"})).into_response()
+    } else {
+        let body = Json(json!({"status": "scheduler stopped"}));
+        (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+    }
+}
+
+/// Handler for `GET /readyz`, answering `503` while the server should not
+/// be sent new jobs.
+async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    let scheduler = state.scheduler.clone();
+    let readiness = match tokio::task::spawn_blocking(move || scheduler.readiness()).await {
+        Ok(r) => r,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
+
+/// Handler for `POST /admin/abort-all`, cancelling every queued job and
+/// signalling every running one to stop.
+async fn abort_all(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let token = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !state.scheduler.authorize_admin(token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(state.scheduler.abort_all().await).into_response()
+}
+
+/// Handler for `GET /config`, the effective configuration with secrets
+/// redacted. Requires the admin token when one is configured.
+async fn get_config(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let token = headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let config = state.scheduler.config();
+    if config.scheduler.admin_token.is_some() && !state.scheduler.authorize_admin(token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    Json(config.redacted()).into_response()
+}
+
+/// Check the cluster token on an internal request, returning the dispatcher.
+fn internal_dispatcher(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<Arc<cluster::Dispatcher>, StatusCode> {
+    let dispatcher = state.scheduler.dispatcher().ok_or(StatusCode::NOT_FOUND)?;
+    let token = headers
+        .get(cluster::TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !dispatcher.authorize(token) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(dispatcher.clone())
+}
+
+/// Handler for `/internal/jobs/claim`, long-polled by workers for their next job.
+async fn claim_job(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let dispatcher = match internal_dispatcher(&state, &headers) {
+        Ok(d) => d,
+        Err(status) => return status.into_response(),
+    };
+    match dispatcher.claim().await {
+        Some(item) => Json(item).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Handler for `/internal/jobs/:id/complete`, called by workers with a result.
+async fn complete_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+    Json(result): Json<WorkResult>,
+) -> StatusCode {
+    let dispatcher = match internal_dispatcher(&state, &headers) {
+        Ok(d) => d,
+        Err(status) => return status,
+    };
+    if result.id != id {
+        return StatusCode::BAD_REQUEST;
+    }
+    if dispatcher.complete(result) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Handler for `/internal/jobs/:id/heartbeat`, called by workers to renew
+/// the lease on a job they are running.
+async fn renew_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> StatusCode {
+    let dispatcher = match internal_dispatcher(&state, &headers) {
+        Ok(d) => d,
+        Err(status) => return status,
+    };
+    if dispatcher.renew(id) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
 }
 
 /// Build the application router with CORS support.
 pub fn app(state: AppState) -> Router {
     Router::new()
         .route("/run-query", post(run_query))
+        .route("/run-query/arrow", post(run_query_arrow))
+        .route("/run-sql", post(run_sql))
+        .route("/validate", post(validate_query))
+        .route("/estimate", post(estimate_query))
+        .route("/explain", post(explain_query))
+        .route("/schema", post(post_schema))
+        .route("/queries", get(list_queries))
+        .route(
+            "/queries/:name",
+            get(get_saved_query).put(save_query).delete(delete_query),
+        )
+        .route("/queries/:name/render", post(render_query))
+        .route("/queries/:name/run", post(run_saved_query))
+        .route("/sessions", post(create_session))
+        .route("/sessions/:id", get(get_session).delete(close_session))
+        .route("/sessions/:id/steps", post(run_step))
+        .route("/sessions/:id/frames/:name", delete(drop_frame))
+        .route("/diff", get(diff_results))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/events", get(job_events))
+        .route("/jobs/:id/download", get(download_result))
+        .route("/jobs/:id/register", post(register_job_result))
+        .route("/results/:id/convert", post(convert_result))
+        .route(
+            "/subscriptions",
+            get(list_subscriptions).post(create_subscription),
+        )
+        .route(
+            "/subscriptions/:id",
+            get(get_subscription).delete(delete_subscription),
+        )
+        .route("/subscriptions/:id/events", get(subscription_events))
+        .route("/admin/abort-all", post(abort_all))
+        .route("/config", get(get_config))
+        .route("/metrics", get(prometheus_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/datasets", get(list_datasets).post(register_dataset))
+        .route("/datasets/:name", get(get_dataset).delete(delete_dataset))
+        .route("/datasets/:name/checks", get(get_checks).put(set_checks))
+        .route("/datasets/:name/checks/run", post(run_checks))
+        .route("/datasets/:name/append", post(append_dataset))
+        .route("/datasets/:name/compact", post(compact_dataset))
+        .route("/datasets/:name/grants", get(get_grants).put(set_grants))
+        .route(
+            "/datasets/:name/policies",
+            get(get_policies).put(set_policies),
+        )
+        .route("/datasets/:name/schema", get(get_schema))
+        .route("/datasets/:name/stats", get(get_stats).post(collect_stats))
+        .route("/datasets/:name/partitions", get(list_partitions))
+        .route(
+            "/datasets/:name/partitions/:value",
+            get(get_partition)
+                .post(add_partition)
+                .delete(drop_partition),
+        )
+        .route("/discovery/scan", post(scan_roots))
+        .route(
+            "/ingest",
+            post(ingest_data).layer(DefaultBodyLimit::max(MAX_INGEST_BYTES)),
+        )
+        .route("/views", get(list_views).post(define_view))
+        .route("/views/:name", get(get_view))
+        .route("/views/:name/refresh", post(refresh_view))
+        .route("/lineage", get(file_lineage))
+        .route("/lineage/jobs/:id", get(job_lineage))
+        .route("/lineage/datasets/:name", get(dataset_lineage))
+        .route("/internal/jobs/claim", post(claim_job))
+        .route("/internal/jobs/:id/complete", post(complete_job))
+        .route("/internal/jobs/:id/heartbeat", post(renew_job))
+        .layer(middleware::from_fn(trace_request))
         .layer(CorsLayer::permissive())
         .with_state(Arc::new(state))
 }
 
-/// Start the HTTP server on `127.0.0.1:3000`.
-pub async fn start_server() {
-    let scheduler = Scheduler::new();
-    let app = app(AppState { scheduler });
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::info!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+/// Handle each request in a span continuing the trace of its `traceparent`
+/// header, so jobs it submits are traced as its children.
+async fn trace_request<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let span = tracing::info_span!(
+        "http_request",
+        otel.kind = "server",
+        otel.name = %format!("{} {}", request.method(), route),
+        http.method = %request.method(),
+        http.route = %route,
+        http.status_code = tracing::field::Empty,
+    );
+    telemetry::set_parent(&span, request.headers());
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+    response
+}
+
+/// Start the HTTP server described by `config`, or the worker loop when
+/// configured with the worker role. Fails with the configuration's errors
+/// when it is invalid, or with why the server or worker stopped.
+pub async fn serve(config: Config) -> Result<(), Vec<String>> {
+    let invalid = |errors: Vec<String>| {
+        errors
+            .into_iter()
+            .map(|e| format!("invalid configuration: {}", e))
+            .collect::<Vec<_>>()
+    };
+    if config.cluster.role == Role::Worker {
+        config.validate().map_err(invalid)?;
+        systemd::notify_ready();
+        systemd::spawn_watchdog();
+        return cluster::run_worker(config)
+            .await
+            .map_err(|e| vec![format!("worker stopped: {}", e)]);
+    }
+    let server = QueryServer::builder()
+        .with_config(config)
+        .build()
+        .map_err(invalid)?;
+    match server.serve().await {
+        Ok(report) if report.drained => tracing::info!("all jobs finished, exiting"),
+        Ok(report) => tracing::warn!(
+            queued = ?report.cancelled.queued,
+            running = ?report.cancelled.running,
+            "cancelled jobs still pending at shutdown"
+        ),
+        Err(e) => return Err(vec![e]),
+    }
+    Ok(())
+}
+
+/// Resolve on Ctrl-C or, on Unix, SIGTERM.
+pub(crate) async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
 }
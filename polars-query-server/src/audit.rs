@@ -0,0 +1,104 @@
+//! Append-only audit log of access decisions and permission changes.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::catalog;
+
+/// A recorded decision or change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Unix timestamp in seconds.
+    pub at: u64,
+    pub user: String,
+    /// What was attempted, e.g. `query` or `set_grants`.
+    pub action: String,
+    pub dataset: String,
+    pub allowed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(user: &str, action: &str, dataset: &str, allowed: bool) -> Self {
+        AuditEvent {
+            at: catalog::now_secs(),
+            user: user.to_string(),
+            action: action.to_string(),
+            dataset: dataset.to_string(),
+            allowed,
+            detail: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// Audit events appended to a file as JSON lines; only logged when no file
+/// is configured.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        AuditLog {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Record `event`, logging rather than failing if it cannot be written.
+    pub fn record(&self, event: AuditEvent) {
+        tracing::info!(
+            user = %event.user,
+            action = %event.action,
+            dataset = %event.dataset,
+            allowed = event.allowed,
+            "audit"
+        );
+        if let Err(e) = self.append(&event) {
+            tracing::error!("failed to write audit log: {}", e);
+        }
+    }
+
+    fn append(&self, event: &AuditEvent) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)
+    }
+
+    /// Events recorded so far, oldest first.
+    pub fn events(&self) -> io::Result<Vec<AuditEvent>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let _guard = self.lock.lock().unwrap();
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Args;
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::scheduler::Scheduler;
+
+/// Arguments of the `bench` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct BenchArgs {
+    /// Parquet file substituted for `{dataset}` in the queries.
+    #[arg(long)]
+    pub dataset: Option<PathBuf>,
+    /// File of queries separated by lines containing only `---`. Defaults to
+    /// a full scan of `--dataset`.
+    #[arg(long)]
+    pub queries: Option<PathBuf>,
+    /// Number of jobs executing at once.
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+    /// Number of times each query is submitted.
+    #[arg(long, default_value_t = 10)]
+    pub iterations: usize,
+}
+
+/// Latency and throughput of a benchmark run.
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub jobs: usize,
+    pub failures: usize,
+    pub elapsed: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl BenchReport {
+    /// Report of `latencies`, in any order, measured over `elapsed`.
+    pub(crate) fn from_latencies(
+        mut latencies: Vec<Duration>,
+        failures: usize,
+        elapsed: Duration,
+    ) -> Self {
+        latencies.sort();
+        BenchReport {
+            jobs: latencies.len(),
+            failures,
+            elapsed,
+            p50: percentile(&latencies, 50.0),
+            p90: percentile(&latencies, 90.0),
+            p99: percentile(&latencies, 99.0),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn throughput(&self) -> f64 {
+        self.jobs as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "jobs:       {} ({} failed)", self.jobs, self.failures)?;
+        writeln!(f, "elapsed:    {:?}", self.elapsed)?;
+        writeln!(f, "throughput: {:.2} jobs/s", self.throughput())?;
+        writeln!(f, "latency p50: {:?}", self.p50)?;
+        writeln!(f, "latency p90: {:?}", self.p90)?;
+        writeln!(f, "latency p99: {:?}", self.p99)?;
+        write!(f, "latency max: {:?}", self.max)
+    }
+}
+
+/// Value at percentile `p` (0-100) of sorted `samples`.
+fn percentile(samples: &[Duration], p: f64) -> Duration {
+    if samples.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * (samples.len() - 1) as f64).round() as usize;
+    samples[rank.min(samples.len() - 1)]
+}
+
+/// Load the benchmark queries, substituting `{dataset}`.
+fn load_queries(args: &BenchArgs) -> Result<Vec<String>, String> {
+    let dataset = args
+        .dataset
+        .as_deref()
+        .map(Path::to_string_lossy)
+        .unwrap_or_default();
+    let text = match &args.queries {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?,
+        None if args.dataset.is_some() => "df = pl.read_parquet(\"{dataset}\")".to_string(),
+        None => return Err("either --dataset or --queries is required".to_string()),
+    };
+    let queries: Vec<String> = text
+        .split("\n---")
+        .map(|q| q.trim().replace("{dataset}", &dataset))
+        .filter(|q| !q.is_empty())
+        .collect();
+    if queries.is_empty() {
+        return Err("no queries to run".to_string());
+    }
+    Ok(queries)
+}
+
+/// Run the benchmark directly against a scheduler built from `config`.
+pub async fn run(args: &BenchArgs, config: &Config) -> Result<BenchReport, String> {
+    let queries = load_queries(args)?;
+    let mut config = config.clone();
+    config.scheduler.max_concurrency = args.concurrency.max(1);
+    let scheduler = Scheduler::from_config(&config);
+
+    let start = Instant::now();
+    let mut handles = Vec::new();
+    for _ in 0..args.iterations {
+        for query in &queries {
+            let scheduler = scheduler.clone();
+            let query = query.clone();
+            handles.push(tokio::spawn(async move {
+                let submitted = Instant::now();
+                let (_, _, rx) = scheduler.enqueue(query).await;
+                let ok = matches!(rx.await, Ok(ref r) if r.output.is_ok());
+                (submitted.elapsed(), ok)
+            }));
+        }
+    }
+
+    let mut latencies = Vec::with_capacity(handles.len());
+    let mut failures = 0;
+    for handle in handles {
+        let (latency, ok) = handle.await.map_err(|e| e.to_string())?;
+        latencies.push(latency);
+        if !ok {
+            failures += 1;
+        }
+    }
+    Ok(BenchReport::from_latencies(
+        latencies,
+        failures,
+        start.elapsed(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+    use std::fs::File;
+    use std::time::Duration;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn percentile_of_sorted_samples() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_millis(51));
+        assert_eq!(percentile(&samples, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn bench_runs_dataset_scan() {
+        let mut df = df!["a" => [1, 2, 3]].unwrap();
+        let file = NamedTempFile::new().unwrap();
+        ParquetWriter::new(File::create(file.path()).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let args = BenchArgs {
+            dataset: Some(file.path().to_path_buf()),
+            queries: None,
+            concurrency: 2,
+            iterations: 3,
+        };
+        let report = run(&args, &Config::default()).await.unwrap();
+        assert_eq!(report.jobs, 3);
+        assert_eq!(report.failures, 0);
+    }
+}
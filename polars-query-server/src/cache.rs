@@ -0,0 +1,239 @@
+//! Cache of job results keyed by the normalized query and the state of its
+//! sources, so identical queries over unchanged data are answered without
+//! running again.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::catalog::{self, DatasetFormat};
+use crate::executor::{self, ExecContext};
+use crate::parser::{self, QueryPlan};
+use crate::utils::{OutputFormat, PreparedOutput};
+
+/// Result cache settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Serve repeated queries from the cache. Off by default.
+    pub enabled: bool,
+    /// Most results kept; the least recently used is dropped beyond it.
+    pub max_entries: usize,
+    /// Most bytes of inline results held in memory. Results written to
+    /// files are only referenced.
+    pub max_bytes: u64,
+    /// Seconds a result is served after it was computed.
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            max_entries: 256,
+            max_bytes: 256 * 1024 * 1024,
+            ttl_secs: 300,
+        }
+    }
+}
+
+struct Entry {
+    output: PreparedOutput,
+    bytes: u64,
+    created: Instant,
+    /// Value of the use counter when the entry was last read or written.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    bytes: u64,
+    uses: u64,
+}
+
+/// Least recently used results, bounded in count, inline bytes and age.
+pub struct ResultCache {
+    config: CacheConfig,
+    entries: Mutex<Entries>,
+}
+
+impl ResultCache {
+    pub fn new(config: CacheConfig) -> Self {
+        ResultCache {
+            config,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// The result stored under `key`, unless it has expired or its files
+    /// have since been removed.
+    pub fn get(&self, key: &str) -> Option<PreparedOutput> {
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = Duration::from_secs(self.config.ttl_secs);
+        let entry = entries.map.get(key)?;
+        let files_exist = entry
+            .output
+            .path
+            .iter()
+            .chain(entry.output.parts.iter().flatten().map(|p| &p.path))
+            .all(|path| Path::new(path).exists());
+        if entry.created.elapsed() > ttl || !files_exist {
+            let bytes = entry.bytes;
+            entries.map.remove(key);
+            entries.bytes -= bytes;
+            return None;
+        }
+        entries.uses += 1;
+        let uses = entries.uses;
+        let entry = entries.map.get_mut(key)?;
+        entry.used = uses;
+        Some(entry.output.clone())
+    }
+
+    /// Store `output` under `key`, dropping the least recently used results
+    /// to stay within the limits. Outputs larger than the byte limit are not
+    /// kept.
+    pub fn insert(&self, key: String, output: &PreparedOutput) {
+        let bytes = output.bytes.as_ref().map_or(0, |b| b.len() as u64);
+        if !self.config.enabled || self.config.max_entries == 0 || bytes > self.config.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.uses += 1;
+        let entry = Entry {
+            output: output.clone(),
+            bytes,
+            created: Instant::now(),
+            used: entries.uses,
+        };
+        if let Some(old) = entries.map.insert(key, entry) {
+            entries.bytes -= old.bytes;
+        }
+        entries.bytes += bytes;
+        while entries.map.len() > self.config.max_entries || entries.bytes > self.config.max_bytes {
+            let Some(oldest) = entries
+                .map
+                .iter()
+                .min_by_key(|(_, e)| e.used)
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            if let Some(old) = entries.map.remove(&oldest) {
+                entries.bytes -= old.bytes;
+            }
+        }
+    }
+}
+
+/// Key of the result of `query` run in `exec` as `format`: a hash of the
+/// parsed plan, so layout and comments do not matter, and of the state of
+/// every source it reads — the size and modification time of files, the
+/// version of datasets and the columns masked for the caller.
+pub fn key(query: &str, exec: &ExecContext, format: OutputFormat) -> Result<String, String> {
+    let steps = parser::parse_query(query)?;
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{:?}\n{:?}\n", steps, format));
+    for source in steps.iter().filter_map(QueryPlan::source) {
+        let files = match source {
+            QueryPlan::ReadParquet(paths) => exec.parquet_files(paths)?,
+            QueryPlan::ReadCsv { path, .. } => {
+                let path = exec.resolve_path(path);
+                catalog::snapshot_files(&path, DatasetFormat::Parquet, None)?
+            }
+            QueryPlan::ReadTable {
+                name,
+                version,
+                as_of,
+            } => {
+                let catalog = exec
+                    .catalog
+                    .as_ref()
+                    .ok_or("no dataset catalog configured")?;
+                let selector = executor::version_selector(*version, as_of.as_deref())?;
+                let (_, version) = catalog.resolve(name, selector)?;
+                let masked = match (&exec.user, &exec.access, catalog.get(name)) {
+                    (Some(user), Some(access), Some(dataset)) => {
+                        access.masked_columns(&dataset, user)
+                    }
+                    _ => Vec::new(),
+                };
+                hasher.update(format!("{} {} {:?}\n", name, version.version, masked));
+                continue;
+            }
+            _ => continue,
+        };
+        for file in files {
+            hasher.update(format!("{} {} {}\n", file.path, file.size, file.modified));
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline(bytes: usize) -> PreparedOutput {
+        PreparedOutput {
+            bytes: Some(vec![0; bytes]),
+            path: None,
+            parts: None,
+            reused: false,
+            format: OutputFormat::default(),
+            rows: None,
+        }
+    }
+
+    #[test]
+    fn least_recently_used_results_are_dropped() {
+        let cache = ResultCache::new(CacheConfig {
+            enabled: true,
+            max_entries: 2,
+            max_bytes: 10,
+            ttl_secs: 60,
+        });
+        cache.insert("a".into(), &inline(4));
+        cache.insert("b".into(), &inline(4));
+        assert!(cache.get("a").is_some());
+        cache.insert("c".into(), &inline(4));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        // Over the byte limit the oldest entries go too.
+        cache.insert("d".into(), &inline(8));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("c").is_none());
+        assert!(cache.get("d").is_some());
+        cache.insert("e".into(), &inline(11));
+        assert!(cache.get("e").is_none());
+    }
+
+    #[test]
+    fn keys_follow_the_plan_and_its_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n").unwrap();
+        let exec = ExecContext {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let ipc = OutputFormat::default();
+        let a = key("df = pl.read_csv(\"a.csv\")", &exec, ipc).unwrap();
+        let spaced = key("# note\ndf = pl.read_csv( 'a.csv' )\n", &exec, ipc).unwrap();
+        assert_eq!(a, spaced);
+        assert_ne!(
+            a,
+            key("df = pl.read_csv(\"a.csv\")", &exec, OutputFormat::Csv).unwrap()
+        );
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n").unwrap();
+        assert_ne!(a, key("df = pl.read_csv(\"a.csv\")", &exec, ipc).unwrap());
+        assert!(key("df = pl.read_csv(\"missing.csv\")", &exec, ipc).is_err());
+    }
+}
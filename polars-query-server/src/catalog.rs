@@ -0,0 +1,915 @@
+//! Catalog of named datasets with ownership and descriptive metadata.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::access::Grant;
+use crate::compaction::CompactionConfig;
+use crate::discovery::DiscoveryConfig;
+use crate::masking::ColumnPolicy;
+use crate::partition::{self, PartitionStats};
+use crate::quality::{self, CheckRun, CheckSpec, MAX_CHECK_RUNS};
+use crate::sandbox::Sandbox;
+use crate::schema::{self, FileCoercion, SchemaMode};
+use crate::stats::{self, DatasetStats, StatsConfig};
+use crate::temporary::TemporaryConfig;
+use crate::views::ViewDefinition;
+
+/// File format of a dataset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatasetFormat {
+    #[default]
+    Parquet,
+    Ipc,
+}
+
+/// Name and data type of a column at registration time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub dtype: String,
+}
+
+/// A file belonging to a dataset version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionFile {
+    pub path: String,
+    pub size: u64,
+    /// Modification time in Unix seconds.
+    pub modified: u64,
+}
+
+/// Snapshot manifest of the files making up a dataset at one point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasetVersion {
+    pub version: u64,
+    pub files: Vec<VersionFile>,
+    pub schema: Vec<ColumnInfo>,
+    pub created_at: u64,
+    /// How the files' schemas are combined when the version is read.
+    #[serde(default)]
+    pub schema_mode: SchemaMode,
+    /// Files that needed nulls or casts to fit the schema.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coercions: Vec<FileCoercion>,
+    /// Statistics per partition, for partitioned datasets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<PartitionStats>,
+}
+
+/// A registered dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+    pub name: String,
+    /// File, directory or glob the dataset is read from.
+    pub location: String,
+    pub format: DatasetFormat,
+    /// Column the dataset's directory is partitioned by (`<key>=<value>/`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_by: Option<String>,
+    /// Schema of the current version.
+    pub schema: Vec<ColumnInfo>,
+    pub owner: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// Current version number.
+    #[serde(default)]
+    pub version: u64,
+    /// Every version, oldest first.
+    #[serde(default)]
+    pub versions: Vec<DatasetVersion>,
+    /// Set when the dataset is a materialized view.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<ViewDefinition>,
+    /// Data quality checks run on demand and whenever a version is added.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckSpec>,
+    /// Most recent check runs, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub check_runs: Vec<CheckRun>,
+    /// Permissions granted to users other than the owner.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub grants: Vec<Grant>,
+    /// Columns masked for callers without the privilege to see them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub column_policies: Vec<ColumnPolicy>,
+    /// Column statistics, possibly of an earlier version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<DatasetStats>,
+    /// Unix seconds at which the dataset's location was found to have
+    /// disappeared. Cleared when it is registered again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missing_since: Option<u64>,
+    /// Unix seconds after which a temporary dataset is dropped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Dataset {
+    /// A spec re-registering the dataset with its current settings.
+    pub fn spec(&self) -> DatasetSpec {
+        DatasetSpec {
+            name: self.name.clone(),
+            location: self.location.clone(),
+            format: self.format,
+            description: self.description.clone(),
+            tags: self.tags.clone(),
+            schema_mode: self.versions.last().map(|v| v.schema_mode),
+            partition_by: self.partition_by.clone(),
+        }
+    }
+}
+
+/// Which version of a dataset to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionSelector {
+    Latest,
+    Version(u64),
+    /// The newest version created at or before this Unix timestamp.
+    AsOf(u64),
+}
+
+/// Request to register or update a dataset.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DatasetSpec {
+    pub name: String,
+    pub location: String,
+    #[serde(default)]
+    pub format: DatasetFormat,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Overrides the server's `data.schema_mode` for this dataset.
+    #[serde(default)]
+    pub schema_mode: Option<SchemaMode>,
+    /// Partition column; the location must then be a directory holding
+    /// `<key>=<value>` subdirectories.
+    #[serde(default)]
+    pub partition_by: Option<String>,
+}
+
+/// Catalog configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CatalogConfig {
+    /// JSON file the catalog is persisted to; kept in memory only when unset.
+    pub path: Option<PathBuf>,
+    /// Directory materialized views are written to.
+    pub views_dir: PathBuf,
+    /// JSON file saved queries and snippets are persisted to; kept in memory
+    /// only when unset.
+    pub queries_path: Option<PathBuf>,
+    /// JSON file subscriptions are persisted to; kept in memory only when
+    /// unset.
+    pub subscriptions_path: Option<PathBuf>,
+    /// Seconds between checks for views due a scheduled or on-change refresh,
+    /// and for datasets with subscriptions changing.
+    pub refresh_interval_secs: u64,
+    pub compaction: CompactionConfig,
+    pub stats: StatsConfig,
+    pub discovery: DiscoveryConfig,
+    pub temporary: TemporaryConfig,
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        CatalogConfig {
+            path: Some(PathBuf::from("catalog.json")),
+            views_dir: PathBuf::from("views"),
+            queries_path: Some(PathBuf::from("queries.json")),
+            subscriptions_path: Some(PathBuf::from("subscriptions.json")),
+            refresh_interval_secs: 30,
+            compaction: CompactionConfig::default(),
+            stats: StatsConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            temporary: TemporaryConfig::default(),
+        }
+    }
+}
+
+/// Whether `name` is usable as a dataset name that also appears in paths:
+/// letters, digits, `_` and `-` only.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parse an `as_of` timestamp: RFC 3339, `YYYY-MM-DDTHH:MM:SS` (UTC) or a
+/// bare date, meaning midnight UTC at the start of that day.
+pub fn parse_timestamp(value: &str) -> Result<u64, String> {
+    let secs = if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        dt.timestamp()
+    } else if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+        dt.and_utc().timestamp()
+    } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp()
+    } else {
+        return Err(format!("invalid timestamp {}", value));
+    };
+    Ok(secs.max(0) as u64)
+}
+
+fn modified_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn extension(format: DatasetFormat) -> &'static str {
+    match format {
+        DatasetFormat::Parquet => "parquet",
+        DatasetFormat::Ipc => "ipc",
+    }
+}
+
+/// List the files currently at `location`, which may be a single file, a
+/// directory (its files with the format's extension, or those of its
+/// partition directories when `partition_by` is set) or a glob pattern.
+pub fn snapshot_files(
+    location: &str,
+    format: DatasetFormat,
+    partition_by: Option<&str>,
+) -> Result<Vec<VersionFile>, String> {
+    let path = Path::new(location);
+    let mut paths: Vec<PathBuf> = if let Some(key) = partition_by {
+        if !path.is_dir() {
+            return Err(format!(
+                "partitioned dataset location {} is not a directory",
+                location
+            ));
+        }
+        partition::list_files(path, key, extension(format))?
+    } else if path.is_dir() {
+        fs::read_dir(path)
+            .map_err(|e| format!("failed to list {}: {}", location, e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(extension(format)))
+            .collect()
+    } else if location.contains(['*', '?', '[']) {
+        glob::glob(location)
+            .map_err(|e| format!("invalid pattern {}: {}", location, e))?
+            .filter_map(Result::ok)
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    paths.sort();
+
+    let files = paths
+        .into_iter()
+        .map(|p| {
+            let meta =
+                fs::metadata(&p).map_err(|e| format!("failed to read {}: {}", p.display(), e))?;
+            Ok(VersionFile {
+                path: p.to_string_lossy().to_string(),
+                size: meta.len(),
+                modified: modified_secs(&meta),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if files.is_empty() {
+        return Err(format!(
+            "no {} files found at {}",
+            extension(format),
+            location
+        ));
+    }
+    Ok(files)
+}
+
+/// Lazily scan `files` as a single frame, combining their schemas according
+/// to `mode`. Also returns the files that needed coercion.
+///
+/// Files in `key=value` directories get a `key` column holding the value.
+pub fn scan_files(
+    files: &[VersionFile],
+    format: DatasetFormat,
+    mode: SchemaMode,
+) -> PolarsResult<(LazyFrame, Vec<FileCoercion>)> {
+    let frames = files
+        .iter()
+        .map(|f| {
+            let lf = match format {
+                DatasetFormat::Parquet => LazyFrame::scan_parquet(&f.path, Default::default()),
+                DatasetFormat::Ipc => LazyFrame::scan_ipc(&f.path, Default::default()),
+            }?;
+            let partitions: Vec<Expr> = partition::partition_values(&f.path)
+                .iter()
+                .map(|(k, v)| partition::value_lit(v).alias(k))
+                .collect();
+            let lf = if partitions.is_empty() {
+                lf
+            } else {
+                lf.with_columns(partitions)
+            };
+            Ok((f.path.clone(), lf))
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    schema::combine(frames, mode)
+}
+
+/// Read the schema of `files`, along with any coercions needed to read them
+/// together.
+pub fn snapshot_schema(
+    files: &[VersionFile],
+    format: DatasetFormat,
+    mode: SchemaMode,
+) -> PolarsResult<(Vec<ColumnInfo>, Vec<FileCoercion>)> {
+    let (lf, coercions) = scan_files(files, format, mode)?;
+    let schema = lf.schema()?;
+    let columns = schema
+        .iter()
+        .map(|(name, dtype)| ColumnInfo {
+            name: name.to_string(),
+            dtype: dtype.to_string(),
+        })
+        .collect();
+    Ok((columns, coercions))
+}
+
+/// Datasets by name, optionally persisted to a JSON file.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    path: Option<PathBuf>,
+    schema_mode: SchemaMode,
+    stats_on_register: bool,
+    /// Directories dataset locations must lie within.
+    sandbox: Sandbox,
+    datasets: RwLock<BTreeMap<String, Dataset>>,
+}
+
+impl Catalog {
+    /// A catalog that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Open the catalog persisted at `path`, starting empty if it does not exist.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let datasets = if path.exists() {
+            let list: Vec<Dataset> = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            list.into_iter().map(|d| (d.name.clone(), d)).collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Catalog {
+            path: Some(path),
+            schema_mode: SchemaMode::default(),
+            stats_on_register: false,
+            sandbox: Sandbox::default(),
+            datasets: RwLock::new(datasets),
+        })
+    }
+
+    /// Combine files of datasets registered without an explicit schema mode
+    /// according to `mode`.
+    pub fn with_schema_mode(mut self, mode: SchemaMode) -> Self {
+        self.schema_mode = mode;
+        self
+    }
+
+    /// Collect column statistics whenever registration adds a version.
+    pub fn with_stats_on_register(mut self, enabled: bool) -> Self {
+        self.stats_on_register = enabled;
+        self
+    }
+
+    /// Refuse datasets located outside `sandbox`.
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Fail with an "access denied" error if `location` lies outside the
+    /// directories datasets may be read from.
+    pub fn check_location(&self, location: &str) -> Result<(), String> {
+        if self.sandbox.allows(location) {
+            Ok(())
+        } else {
+            Err(format!(
+                "access denied: {} is outside the allowed data directories",
+                location
+            ))
+        }
+    }
+
+    /// Open the catalog described by `config`, falling back to an in-memory
+    /// catalog (with an error logged) if the file cannot be read.
+    pub fn from_config(config: &CatalogConfig) -> Self {
+        match &config.path {
+            Some(path) => Catalog::open(path).unwrap_or_else(|e| {
+                tracing::error!("failed to open catalog {}: {}", path.display(), e);
+                Catalog::in_memory()
+            }),
+            None => Catalog::in_memory(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn persist(&self, datasets: &BTreeMap<String, Dataset>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let list: Vec<&Dataset> = datasets.values().collect();
+        let json = serde_json::to_vec_pretty(&list).map_err(|e| io::Error::other(e.to_string()))?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Register a dataset, or update it if `spec.name` already exists.
+    ///
+    /// The files at the location are snapshotted; a new version is recorded
+    /// whenever the file manifest differs from the current version, and the
+    /// dataset's checks are run against it. Locations outside the sandbox
+    /// are refused.
+    pub fn register(&self, spec: DatasetSpec, owner: &str) -> Result<Dataset, String> {
+        if spec.name.is_empty() {
+            return Err("dataset name must not be empty".to_string());
+        }
+        if let Some(key) = &spec.partition_by {
+            if !valid_name(key) {
+                return Err(format!("invalid partition column {}", key));
+            }
+        }
+        self.check_location(&spec.location)?;
+        let files = snapshot_files(&spec.location, spec.format, spec.partition_by.as_deref())?;
+        let now = now_secs();
+        let mut datasets = self.datasets.write().unwrap();
+        let previous = datasets.get(&spec.name);
+        let created_at = previous.map(|d| d.created_at).unwrap_or(now);
+        let mut versions = previous.map(|d| d.versions.clone()).unwrap_or_default();
+        let mode = spec.schema_mode.unwrap_or(self.schema_mode);
+        let unchanged = versions.last().is_some_and(|v| {
+            v.files == files
+                && v.schema_mode == mode
+                && previous
+                    .is_some_and(|d| d.format == spec.format && d.partition_by == spec.partition_by)
+        });
+        if !unchanged {
+            let (schema, coercions) = snapshot_schema(&files, spec.format, mode)
+                .map_err(|e| format!("failed to read {}: {}", spec.location, e))?;
+            let partitions = match &spec.partition_by {
+                Some(key) => partition::collect_stats(&files, spec.format, key)
+                    .map_err(|e| format!("failed to read {}: {}", spec.location, e))?,
+                None => Vec::new(),
+            };
+            for c in &coercions {
+                tracing::info!(
+                    dataset = %spec.name,
+                    file = %c.path,
+                    added = ?c.added,
+                    cast = ?c.cast,
+                    "coerced file to dataset schema"
+                );
+            }
+            versions.push(DatasetVersion {
+                version: versions.last().map_or(1, |v| v.version + 1),
+                files,
+                schema,
+                created_at: now,
+                schema_mode: mode,
+                coercions,
+                partitions,
+            });
+        }
+        let current = versions.last().unwrap();
+        let dataset = Dataset {
+            name: spec.name.clone(),
+            location: spec.location,
+            format: spec.format,
+            partition_by: spec.partition_by,
+            schema: current.schema.clone(),
+            owner: owner.to_string(),
+            description: spec.description,
+            tags: spec.tags,
+            created_at,
+            updated_at: now,
+            version: current.version,
+            versions,
+            view: previous.and_then(|d| d.view.clone()),
+            checks: previous.map(|d| d.checks.clone()).unwrap_or_default(),
+            check_runs: previous.map(|d| d.check_runs.clone()).unwrap_or_default(),
+            grants: previous.map(|d| d.grants.clone()).unwrap_or_default(),
+            column_policies: previous
+                .map(|d| d.column_policies.clone())
+                .unwrap_or_default(),
+            stats: previous.and_then(|d| d.stats.clone()),
+            missing_since: None,
+            expires_at: previous.and_then(|d| d.expires_at),
+        };
+        datasets.insert(spec.name.clone(), dataset.clone());
+        self.persist(&datasets).map_err(|e| e.to_string())?;
+        drop(datasets);
+
+        if unchanged || (dataset.checks.is_empty() && !self.stats_on_register) {
+            return Ok(dataset);
+        }
+        if !dataset.checks.is_empty() {
+            match self.run_checks(&spec.name) {
+                Ok(run) if !run.passed => tracing::warn!(
+                    dataset = %spec.name,
+                    version = run.version,
+                    "data quality checks failed"
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!(dataset = %spec.name, "failed to run checks: {}", e),
+            }
+        }
+        if self.stats_on_register {
+            if let Err(e) = self.collect_stats(&spec.name) {
+                tracing::warn!(dataset = %spec.name, "failed to collect statistics: {}", e);
+            }
+        }
+        self.get(&spec.name)
+            .ok_or_else(|| format!("unknown dataset {}", spec.name))
+    }
+
+    /// Rewrite file paths in every version of `name` after files were moved,
+    /// so earlier versions stay readable.
+    pub fn relocate_files(
+        &self,
+        name: &str,
+        moves: &HashMap<String, String>,
+    ) -> Result<(), String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        for file in dataset.versions.iter_mut().flat_map(|v| v.files.iter_mut()) {
+            if let Some(to) = moves.get(&file.path) {
+                file.path = to.clone();
+            }
+        }
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Replace the checks of the dataset `name`.
+    pub fn set_checks(&self, name: &str, checks: Vec<CheckSpec>) -> Result<(), String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        dataset.checks = checks;
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Record that the location of `name` disappeared. Its versions stay
+    /// listed so readers of archived files keep working.
+    pub fn mark_missing(&self, name: &str) -> Result<(), String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        dataset.missing_since = Some(now_secs());
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Replace the grants of the dataset `name`.
+    pub fn set_grants(&self, name: &str, grants: Vec<Grant>) -> Result<(), String> {
+        if let Some(g) = grants.iter().find(|g| g.user.is_some() == g.role.is_some()) {
+            return Err(format!(
+                "grant of {} must name exactly one of user and role",
+                g.permission.as_str()
+            ));
+        }
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        dataset.grants = grants;
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Replace the column masking policies of the dataset `name`.
+    pub fn set_column_policies(
+        &self,
+        name: &str,
+        policies: Vec<ColumnPolicy>,
+    ) -> Result<(), String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        if let Some(p) = policies
+            .iter()
+            .find(|p| !dataset.schema.iter().any(|c| c.name == p.column))
+        {
+            return Err(format!("dataset {} has no column {}", name, p.column));
+        }
+        dataset.column_policies = policies;
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Run the checks of `name` against its current version and store the
+    /// result.
+    pub fn run_checks(&self, name: &str) -> Result<CheckRun, String> {
+        let dataset = self
+            .get(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        let (format, version) = self.resolve(name, VersionSelector::Latest)?;
+        let (lf, _) =
+            scan_files(&version.files, format, version.schema_mode).map_err(|e| e.to_string())?;
+        let previous_rows = dataset.check_runs.last().map(|r| r.rows);
+        let run = quality::run_checks(
+            &dataset.checks,
+            lf,
+            version.version,
+            previous_rows,
+            now_secs(),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut datasets = self.datasets.write().unwrap();
+        if let Some(dataset) = datasets.get_mut(name) {
+            dataset.check_runs.push(run.clone());
+            let excess = dataset.check_runs.len().saturating_sub(MAX_CHECK_RUNS);
+            dataset.check_runs.drain(..excess);
+        }
+        self.persist(&datasets).map_err(|e| e.to_string())?;
+        Ok(run)
+    }
+
+    /// Compute column statistics of the current version of `name` and store
+    /// them.
+    pub fn collect_stats(&self, name: &str) -> Result<DatasetStats, String> {
+        let (format, version) = self.resolve(name, VersionSelector::Latest)?;
+        let (lf, _) =
+            scan_files(&version.files, format, version.schema_mode).map_err(|e| e.to_string())?;
+        let bytes = version.files.iter().map(|f| f.size).sum();
+        let stats = stats::compute(lf, bytes, version.version, now_secs())
+            .map_err(|e| format!("failed to compute statistics of {}: {}", name, e))?;
+
+        let mut datasets = self.datasets.write().unwrap();
+        if let Some(dataset) = datasets.get_mut(name) {
+            dataset.stats = Some(stats.clone());
+        }
+        self.persist(&datasets).map_err(|e| e.to_string())?;
+        Ok(stats)
+    }
+
+    /// Attach a view definition to the dataset `name`.
+    pub fn set_view(&self, name: &str, view: ViewDefinition) -> Result<(), String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        dataset.view = Some(view);
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Set or clear the time after which the dataset `name` is dropped.
+    pub fn set_expiry(&self, name: &str, expires_at: Option<u64>) -> Result<(), String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let dataset = datasets
+            .get_mut(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        dataset.expires_at = expires_at;
+        self.persist(&datasets).map_err(|e| e.to_string())
+    }
+
+    /// Remove the dataset `name` from the catalog. Its files are left alone.
+    pub fn remove(&self, name: &str) -> Result<Dataset, String> {
+        let mut datasets = self.datasets.write().unwrap();
+        let removed = datasets
+            .remove(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        self.persist(&datasets).map_err(|e| e.to_string())?;
+        Ok(removed)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Dataset> {
+        self.datasets.read().unwrap().get(name).cloned()
+    }
+
+    /// Look up the version of `name` picked by `selector`.
+    pub fn resolve(
+        &self,
+        name: &str,
+        selector: VersionSelector,
+    ) -> Result<(DatasetFormat, DatasetVersion), String> {
+        let datasets = self.datasets.read().unwrap();
+        let dataset = datasets
+            .get(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        let version = match selector {
+            VersionSelector::Latest => dataset.versions.last(),
+            VersionSelector::Version(n) => dataset.versions.iter().find(|v| v.version == n),
+            VersionSelector::AsOf(ts) => dataset.versions.iter().rev().find(|v| v.created_at <= ts),
+        };
+        let version = version.ok_or_else(|| match selector {
+            VersionSelector::Version(n) => format!("dataset {} has no version {}", name, n),
+            VersionSelector::AsOf(ts) => format!("dataset {} has no version as of {}", name, ts),
+            VersionSelector::Latest => format!("dataset {} has no versions", name),
+        })?;
+        Ok((dataset.format, version.clone()))
+    }
+
+    /// All datasets ordered by name.
+    pub fn list(&self) -> Vec<Dataset> {
+        self.datasets.read().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn register_persists_and_reloads() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("sales.parquet");
+        let mut df = df!["city" => ["NY"], "amount" => [1.5]].unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+
+        let path = dir.path().join("catalog.json");
+        let catalog = Catalog::open(&path).unwrap();
+        let ds = catalog
+            .register(
+                DatasetSpec {
+                    name: "sales".into(),
+                    location: data.to_string_lossy().to_string(),
+                    format: DatasetFormat::Parquet,
+                    description: Some("daily sales".into()),
+                    tags: vec!["finance".into()],
+                    schema_mode: None,
+                    partition_by: None,
+                },
+                "alice",
+            )
+            .unwrap();
+        assert_eq!(ds.schema.len(), 2);
+        assert_eq!(ds.schema[0].name, "city");
+        assert_eq!(ds.version, 1);
+
+        let reopened = Catalog::open(&path).unwrap();
+        let ds = reopened.get("sales").unwrap();
+        assert_eq!(ds.owner, "alice");
+        assert_eq!(ds.tags, vec!["finance".to_string()]);
+    }
+
+    #[test]
+    fn register_missing_file_fails() {
+        let catalog = Catalog::in_memory();
+        let spec = DatasetSpec {
+            name: "missing".into(),
+            location: "/nonexistent/file.parquet".into(),
+            format: DatasetFormat::Parquet,
+            description: None,
+            tags: vec![],
+            schema_mode: None,
+            partition_by: None,
+        };
+        assert!(catalog.register(spec, "bob").is_err());
+        assert!(catalog.list().is_empty());
+    }
+
+    #[test]
+    fn locations_outside_the_sandbox_are_refused() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        let mut df = df!["x" => [1]].unwrap();
+        for path in [data.join("a.parquet"), dir.path().join("secret.parquet")] {
+            ParquetWriter::new(File::create(path).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        }
+        let catalog = Catalog::in_memory().with_sandbox(Sandbox::new(std::slice::from_ref(&data)));
+        let spec = |name: &str, location: PathBuf| DatasetSpec {
+            name: name.into(),
+            location: location.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        assert!(catalog
+            .register(spec("a", data.join("a.parquet")), "alice")
+            .is_ok());
+        let err = catalog
+            .register(spec("secret", data.join("../secret.parquet")), "alice")
+            .unwrap_err();
+        assert!(err.starts_with("access denied"), "{}", err);
+        assert!(catalog.get("secret").is_none());
+    }
+
+    #[test]
+    fn changed_files_create_new_version() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str, rows: i32| {
+            let values: Vec<i32> = (0..rows).collect();
+            let mut df = df!["x" => values].unwrap();
+            ParquetWriter::new(File::create(dir.path().join(name)).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        };
+        write("a.parquet", 1);
+        let catalog = Catalog::in_memory();
+        let spec = DatasetSpec {
+            name: "events".into(),
+            location: dir.path().to_string_lossy().to_string(),
+            format: DatasetFormat::Parquet,
+            description: None,
+            tags: vec![],
+            schema_mode: None,
+            partition_by: None,
+        };
+        assert_eq!(catalog.register(spec.clone(), "alice").unwrap().version, 1);
+        assert_eq!(catalog.register(spec.clone(), "alice").unwrap().version, 1);
+        write("b.parquet", 2);
+        let ds = catalog.register(spec, "alice").unwrap();
+        assert_eq!(ds.version, 2);
+        assert_eq!(ds.versions[1].files.len(), 2);
+
+        let (_, v1) = catalog
+            .resolve("events", VersionSelector::Version(1))
+            .unwrap();
+        assert_eq!(v1.files.len(), 1);
+        assert!(catalog.resolve("events", VersionSelector::AsOf(0)).is_err());
+        let (_, latest) = catalog
+            .resolve("events", VersionSelector::AsOf(u64::MAX))
+            .unwrap();
+        assert_eq!(latest.version, 2);
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-02").unwrap(), 86_400);
+        assert_eq!(parse_timestamp("1970-01-01T00:01:00").unwrap(), 60);
+        assert_eq!(parse_timestamp("1970-01-01T01:00:00+01:00").unwrap(), 0);
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn checks_run_when_a_version_is_added() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str, ids: &[i32]| {
+            let mut df = df!["id" => ids].unwrap();
+            ParquetWriter::new(File::create(dir.path().join(name)).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        };
+        write("a.parquet", &[1, 2]);
+        let catalog = Catalog::in_memory();
+        let spec = DatasetSpec {
+            name: "ids".into(),
+            location: dir.path().to_string_lossy().to_string(),
+            format: DatasetFormat::Parquet,
+            description: None,
+            tags: vec![],
+            schema_mode: None,
+            partition_by: None,
+        };
+        catalog.register(spec.clone(), "alice").unwrap();
+        catalog
+            .set_checks(
+                "ids",
+                vec![CheckSpec::Unique {
+                    columns: vec!["id".into()],
+                }],
+            )
+            .unwrap();
+        assert!(catalog.run_checks("ids").unwrap().passed);
+
+        write("b.parquet", &[2]);
+        let ds = catalog.register(spec, "alice").unwrap();
+        assert_eq!(ds.check_runs.len(), 2);
+        let last = ds.check_runs.last().unwrap();
+        assert_eq!(last.version, 2);
+        assert!(!last.passed);
+    }
+}
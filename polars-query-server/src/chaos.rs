@@ -0,0 +1,132 @@
+//! Fault injection for testing how the scheduler copes with failing, slow
+//! and crashing queries. Nothing is injected unless enabled in the
+//! configuration.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::utils::{splitmix64, unit_f64, SPLITMIX_GAMMA};
+
+/// Prefix of the errors and panic messages of injected faults.
+pub const INJECTED: &str = "injected fault";
+
+/// Fault injection settings. Rates are probabilities between 0 and 1.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    /// Rate at which reading a source fails.
+    pub scan_failure_rate: f64,
+    /// Rate at which reading a source is delayed by `slow_io_ms`.
+    pub slow_io_rate: f64,
+    pub slow_io_ms: u64,
+    /// Rate at which executing a query panics.
+    pub panic_rate: f64,
+    /// Seed of the random draws, for reproducible runs. Seeded from the
+    /// clock when unset.
+    pub seed: Option<u64>,
+}
+
+impl ChaosConfig {
+    /// Problems with the configured rates.
+    pub fn validate(&self) -> Vec<String> {
+        [
+            ("scan_failure_rate", self.scan_failure_rate),
+            ("slow_io_rate", self.slow_io_rate),
+            ("panic_rate", self.panic_rate),
+        ]
+        .into_iter()
+        .filter(|(_, rate)| !(0.0..=1.0).contains(rate))
+        .map(|(name, _)| format!("chaos.{} must be between 0 and 1", name))
+        .collect()
+    }
+}
+
+/// Injects the faults of a [`ChaosConfig`].
+#[derive(Debug)]
+pub struct Chaos {
+    config: ChaosConfig,
+    state: AtomicU64,
+}
+
+impl Chaos {
+    /// Fault injector for `config`, or `None` when it is disabled.
+    pub fn from_config(config: &ChaosConfig) -> Option<Arc<Chaos>> {
+        if !config.enabled {
+            return None;
+        }
+        let seed = config.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        tracing::warn!(?config, "fault injection enabled");
+        Some(Arc::new(Chaos {
+            config: config.clone(),
+            state: AtomicU64::new(seed),
+        }))
+    }
+
+    /// Uniform draw in `[0, 1)`.
+    fn draw(&self) -> f64 {
+        let state = self
+            .state
+            .fetch_add(SPLITMIX_GAMMA, Ordering::Relaxed)
+            .wrapping_add(SPLITMIX_GAMMA);
+        unit_f64(splitmix64(state))
+    }
+
+    fn hit(&self, rate: f64) -> bool {
+        rate > 0.0 && self.draw() < rate
+    }
+
+    /// Called before `source` is read: may block for the slow I/O delay and
+    /// may fail the read.
+    pub fn before_scan(&self, source: &str) -> Result<(), String> {
+        if self.hit(self.config.slow_io_rate) {
+            std::thread::sleep(Duration::from_millis(self.config.slow_io_ms));
+        }
+        if self.hit(self.config.scan_failure_rate) {
+            return Err(format!("{}: failed to scan {}", INJECTED, source));
+        }
+        Ok(())
+    }
+
+    /// Called before a query is executed: may panic.
+    pub fn before_execute(&self) {
+        if self.hit(self.config.panic_rate) {
+            panic!("{}: executor panic", INJECTED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_honoured() {
+        let config = ChaosConfig {
+            enabled: true,
+            scan_failure_rate: 0.25,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let chaos = Chaos::from_config(&config).unwrap();
+        let failures = (0..10_000)
+            .filter(|_| chaos.before_scan("a.parquet").is_err())
+            .count();
+        assert!((2_000..3_000).contains(&failures), "{}", failures);
+        chaos.before_execute();
+
+        assert!(Chaos::from_config(&ChaosConfig::default()).is_none());
+        let bad = ChaosConfig {
+            panic_rate: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(bad.validate().len(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+use clap::{Parser, Subcommand};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::bench::{self, BenchArgs};
+use crate::config::{self, Config};
+use crate::replay::{self, ReplayArgs};
+use crate::synthetic::{self, GenerateArgs, LoadArgs};
+use crate::{api, doctor, telemetry};
+
+/// Command line interface of the `rdata-server` binary.
+#[derive(Debug, Parser)]
+#[command(
+    name = "rdata-server",
+    version,
+    about = "HTTP server executing Polars query plans"
+)]
+pub struct Cli {
+    /// TOML configuration file, overridden by environment variables and
+    /// flags. Defaults to `RDATA_CONFIG`, or `config.toml` if it exists.
+    #[arg(long, short)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on.
+    #[arg(long)]
+    pub bind: Option<IpAddr>,
+    /// Port to listen on.
+    #[arg(long, short)]
+    pub port: Option<u16>,
+    /// Directory relative source paths in queries are resolved against.
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    /// Directory result files are written to.
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+    /// Directory for temporary and spill files.
+    #[arg(long)]
+    pub scratch_dir: Option<PathBuf>,
+    /// Maximum number of concurrently executing jobs.
+    #[arg(long)]
+    pub max_concurrency: Option<usize>,
+    /// Most jobs waiting to start before submissions wait.
+    #[arg(long)]
+    pub queue_capacity: Option<usize>,
+    /// Validate the configuration, print the result and exit.
+    #[arg(long)]
+    pub validate_config: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands run instead of the server.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Drive the scheduler and executor directly and report throughput and
+    /// latency percentiles.
+    Bench(BenchArgs),
+    /// Check configuration, directories, datasets, credentials and the listen
+    /// address, printing a diagnostic report.
+    Doctor,
+    /// Write synthetic parquet datasets with the given row counts,
+    /// cardinality and skew.
+    Generate(GenerateArgs),
+    /// Submit a mixed query workload to a server for a fixed duration and
+    /// report latency percentiles.
+    Load(LoadArgs),
+    /// Re-submit the queries recorded in the metrics store to a server in
+    /// their original arrival pattern and report latency percentiles.
+    Replay(ReplayArgs),
+}
+
+impl Cli {
+    /// Override `config` with any flags given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(bind) = self.bind {
+            config.server.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(dir) = &self.data_dir {
+            config.data.data_dir = Some(dir.clone());
+        }
+        if let Some(dir) = &self.output_dir {
+            config.storage.output_dir = dir.clone();
+        }
+        if let Some(dir) = &self.scratch_dir {
+            config.storage.scratch_dir = Some(dir.clone());
+        }
+        if let Some(n) = self.max_concurrency {
+            config.scheduler.max_concurrency = n;
+        }
+        if let Some(n) = self.queue_capacity {
+            config.scheduler.queue_capacity = n;
+        }
+    }
+
+    /// Run the command given on the command line, or the server when there
+    /// is none, exiting non-zero on failure.
+    pub async fn run(self) {
+        let mut config = match Config::try_load(self.config.as_deref()) {
+            Ok(config) => config,
+            Err(errors) => {
+                for e in errors {
+                    eprintln!("error: {}", e);
+                }
+                std::process::exit(1);
+            }
+        };
+        self.apply(&mut config);
+        telemetry::init(&config.telemetry);
+        if let Some(path) = config::config_file(self.config.as_deref()) {
+            tracing::info!("read configuration from {}", path.display());
+        }
+
+        if self.validate_config {
+            match config.validate() {
+                Ok(()) => println!("configuration is valid"),
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("error: {}", e);
+                    }
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        // The Polars thread pool reads this when first used, so it must be set
+        // before any query runs.
+        if let Some(threads) = config.resources.polars_threads {
+            if std::env::var("POLARS_MAX_THREADS").is_err() {
+                std::env::set_var("POLARS_MAX_THREADS", threads.to_string());
+            }
+        }
+        tracing::info!(
+            max_concurrency = config.scheduler.max_concurrency,
+            polars_threads = ?config.resources.polars_threads,
+            memory_budget_bytes = ?config.resources.memory_budget_bytes,
+            "resource configuration"
+        );
+
+        // Polars spills streaming state to `POLARS_TEMP_DIR`; keep it alongside
+        // our own temporary files.
+        if let Some(dir) = &config.storage.scratch_dir {
+            std::env::set_var("POLARS_TEMP_DIR", dir);
+        }
+
+        if let Some(Command::Doctor) = &self.command {
+            let checks = doctor::run(&config);
+            for check in &checks {
+                println!("{}", check);
+            }
+            if doctor::has_failures(&checks) {
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        if let Some(Command::Bench(args)) = &self.command {
+            match bench::run(args, &config).await {
+                Ok(report) => println!("{}", report),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        if let Some(Command::Replay(args)) = &self.command {
+            match replay::run(args).await {
+                Ok(report) => println!("{}", report),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        if let Some(Command::Generate(args)) = &self.command {
+            match synthetic::generate(args) {
+                Ok(paths) => {
+                    for path in paths {
+                        println!("{}", path.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        if let Some(Command::Load(args)) = &self.command {
+            match synthetic::run(args).await {
+                Ok(report) => println!("{}", report),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        if std::env::var("SKIP_SERVER").is_ok() {
+            // Used in tests to avoid starting the server
+            return;
+        }
+
+        let served = api::serve(config).await;
+        telemetry::shutdown();
+        if let Err(errors) = served {
+            for e in errors {
+                eprintln!("error: {}", e);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_config() {
+        let cli = Cli::parse_from(["rdata-server", "--port", "8080", "--max-concurrency", "8"]);
+        let mut config = Config::default();
+        cli.apply(&mut config);
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.scheduler.max_concurrency, 8);
+        assert!(!cli.validate_config);
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn bench_subcommand() {
+        let cli = Cli::parse_from(["rdata-server", "bench", "--dataset", "f.parquet"]);
+        match cli.command {
+            Some(Command::Bench(args)) => {
+                assert_eq!(args.dataset, Some(PathBuf::from("f.parquet")));
+                assert_eq!(args.concurrency, 4);
+            }
+            _ => panic!("expected bench subcommand"),
+        }
+    }
+
+    #[test]
+    fn replay_subcommand() {
+        let cli = Cli::parse_from([
+            "rdata-server",
+            "replay",
+            "--from",
+            "metrics/",
+            "--speed",
+            "2x",
+        ]);
+        match cli.command {
+            Some(Command::Replay(args)) => {
+                assert_eq!(args.from, PathBuf::from("metrics/"));
+                assert_eq!(args.speed, 2.0);
+            }
+            _ => panic!("expected replay subcommand"),
+        }
+    }
+
+    #[test]
+    fn generate_and_load_subcommands() {
+        let cli = Cli::parse_from(["rdata-server", "generate", "--rows", "500", "--skew", "1.2"]);
+        match cli.command {
+            Some(Command::Generate(args)) => {
+                assert_eq!(args.rows, 500);
+                assert_eq!(args.skew, 1.2);
+                assert_eq!(args.files, 1);
+            }
+            _ => panic!("expected generate subcommand"),
+        }
+
+        let cli = Cli::parse_from(["rdata-server", "load", "--mix", "scan=1,sort=2"]);
+        match cli.command {
+            Some(Command::Load(args)) => {
+                assert_eq!(args.mix.0.len(), 2);
+                assert_eq!(args.concurrency, 8);
+            }
+            _ => panic!("expected load subcommand"),
+        }
+        assert!(Cli::try_parse_from(["rdata-server", "load", "--mix", "join=1"]).is_err());
+    }
+}
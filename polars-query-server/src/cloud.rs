@@ -0,0 +1,103 @@
+//! Object store locations (`s3://`, `gs://`, `az://`) in query paths.
+//!
+//! Polars reads them when the server is built with the `cloud` feature.
+//! Credentials not set in [`CloudConfig`] are taken from each provider's
+//! usual environment variables, such as `AWS_ACCESS_KEY_ID`,
+//! `GOOGLE_APPLICATION_CREDENTIALS` or `AZURE_STORAGE_ACCOUNT_NAME`.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// URL schemes of the supported object stores.
+const SCHEMES: [&str; 7] = ["s3", "s3a", "gs", "gcs", "az", "azure", "abfss"];
+
+/// Object store settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloudConfig {
+    /// Region of S3 buckets.
+    pub aws_region: Option<String>,
+    /// Endpoint of an S3 compatible store, such as MinIO.
+    pub aws_endpoint: Option<String>,
+    /// Further object store options by key, for example
+    /// `aws_allow_http` or `google_service_account`.
+    pub options: BTreeMap<String, String>,
+}
+
+impl CloudConfig {
+    /// The configured options as object store keys.
+    #[cfg_attr(not(feature = "cloud"), allow(dead_code))]
+    fn settings(&self) -> Vec<(String, String)> {
+        let mut settings: Vec<(String, String)> = self
+            .options
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if let Some(region) = &self.aws_region {
+            settings.push(("aws_region".into(), region.clone()));
+        }
+        if let Some(endpoint) = &self.aws_endpoint {
+            settings.push(("aws_endpoint".into(), endpoint.clone()));
+        }
+        settings
+    }
+}
+
+/// Whether `path` is an object store URL rather than a local path.
+pub fn is_url(path: &str) -> bool {
+    path.split_once("://")
+        .is_some_and(|(scheme, _)| SCHEMES.contains(&scheme))
+}
+
+/// Lazily scan the parquet object or glob at `url`.
+#[cfg(feature = "cloud")]
+pub fn scan_parquet(url: &str, config: &CloudConfig) -> PolarsResult<LazyFrame> {
+    use polars::io::cloud::CloudOptions;
+
+    let args = ScanArgsParquet {
+        cloud_options: Some(CloudOptions::from_untyped_config(url, config.settings())?),
+        ..Default::default()
+    };
+    LazyFrame::scan_parquet(url, args)
+}
+
+/// Lazily scan the parquet object or glob at `url`.
+#[cfg(not(feature = "cloud"))]
+pub fn scan_parquet(url: &str, _config: &CloudConfig) -> PolarsResult<LazyFrame> {
+    Err(PolarsError::ComputeError(
+        format!("reading {} requires the `cloud` feature", url).into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_told_from_local_paths() {
+        assert!(is_url("s3://bucket/sales.parquet"));
+        assert!(is_url("gs://bucket/sales/*.parquet"));
+        assert!(is_url("az://container/sales.parquet"));
+        assert!(!is_url("sales.parquet"));
+        assert!(!is_url("/data/s3://odd.parquet"));
+        assert!(!is_url("file://data/sales.parquet"));
+    }
+
+    #[test]
+    fn settings_include_region_and_endpoint() {
+        let config = CloudConfig {
+            aws_region: Some("eu-west-2".into()),
+            aws_endpoint: Some("http://minio:9000".into()),
+            options: BTreeMap::from([("aws_allow_http".into(), "true".into())]),
+        };
+        assert_eq!(
+            config.settings(),
+            vec![
+                ("aws_allow_http".to_string(), "true".to_string()),
+                ("aws_region".to_string(), "eu-west-2".to_string()),
+                ("aws_endpoint".to_string(), "http://minio:9000".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,526 @@
+//! Split coordinator/worker deployment.
+//!
+//! A coordinator accepts HTTP submissions and schedules them as usual, but
+//! instead of executing jobs itself it hands them to workers. Workers run no
+//! public API; they pull jobs from the coordinator's internal endpoints,
+//! execute them with their own local scheduler and report the result back.
+//! Result files are expected on storage shared by coordinator and workers.
+
+use base64::engine::general_purpose::STANDARD as B64_ENGINE;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{oneshot, Notify};
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::metrics;
+use crate::scheduler::{JobError, JobOptions, JobResult, Scheduler};
+use crate::utils::{OutputFormat, OutputPart, PreparedOutput};
+
+/// Header carrying the shared secret on internal endpoints.
+pub const TOKEN_HEADER: &str = "x-cluster-token";
+
+/// Role this process plays in a deployment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Accept HTTP submissions and execute them locally.
+    #[default]
+    Standalone,
+    /// Accept HTTP submissions and dispatch them to workers.
+    Coordinator,
+    /// Execute jobs pulled from a coordinator.
+    Worker,
+}
+
+/// Cluster settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterConfig {
+    pub role: Role,
+    /// Base URL of the coordinator, required for workers.
+    pub coordinator_url: Option<String>,
+    /// Shared secret workers present to the coordinator, required for
+    /// coordinators.
+    pub token: Option<String>,
+    /// How long a worker's claim request waits for a job before retrying.
+    pub claim_timeout_ms: u64,
+    /// How long a claimed job may go without a heartbeat from its worker
+    /// before the coordinator hands it to another worker.
+    pub lease_ms: u64,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            role: Role::Standalone,
+            coordinator_url: None,
+            token: None,
+            claim_timeout_ms: 30_000,
+            lease_ms: 60_000,
+        }
+    }
+}
+
+/// A job handed to a worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkItem {
+    pub id: u64,
+    pub query: String,
+    pub user: String,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub inline_limit: Option<usize>,
+    #[serde(default)]
+    pub streaming: Option<bool>,
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// Lease the worker must renew with heartbeats, set by the coordinator
+    /// when the job is claimed.
+    #[serde(default)]
+    pub lease_ms: Option<u64>,
+}
+
+/// Outcome of a job reported by a worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkResult {
+    pub id: u64,
+    /// Base64 encoded compressed output when returned inline.
+    pub output: Option<String>,
+    pub path: Option<String>,
+    pub parts: Option<Vec<OutputPart>>,
+    pub error: Option<String>,
+    /// Kind of the [`JobError`] the job failed with on the worker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Rows of the result, when the worker reports them.
+    #[serde(default)]
+    pub rows: Option<u64>,
+}
+
+impl WorkResult {
+    fn from_job_result(id: u64, result: &JobResult) -> Self {
+        let output = result.output.as_ref().ok();
+        WorkResult {
+            id,
+            output: output
+                .and_then(|o| o.bytes.as_ref())
+                .map(|b| B64_ENGINE.encode(b)),
+            path: output.and_then(|o| o.path.clone()),
+            parts: output.and_then(|o| o.parts.clone()),
+            error: result.error().map(|e| e.message.clone()),
+            error_kind: result.error().map(|e| e.kind.to_string()),
+            format: output.map(|o| o.format).unwrap_or_default(),
+            rows: output.and_then(|o| o.rows),
+        }
+    }
+
+    /// Convert back into the output a local execution would have produced.
+    pub fn into_output(self) -> Result<PreparedOutput, JobError> {
+        if let Some(e) = self.error {
+            let kind = self.error_kind.as_deref().unwrap_or("execution_failed");
+            return Err(JobError::named(kind, None, e));
+        }
+        let bytes = self
+            .output
+            .map(|o| B64_ENGINE.decode(o))
+            .transpose()
+            .map_err(|e| {
+                let message = format!("invalid output from worker: {}", e);
+                JobError::new("execution_failed", None, message)
+            })?;
+        Ok(PreparedOutput {
+            bytes,
+            path: self.path,
+            parts: self.parts,
+            reused: false,
+            format: self.format,
+            rows: self.rows,
+        })
+    }
+}
+
+/// Coordinator side queue of jobs waiting for a worker.
+pub struct Dispatcher {
+    token: Option<String>,
+    claim_timeout: Duration,
+    lease: Duration,
+    pending: Mutex<VecDeque<WorkItem>>,
+    /// Claimed jobs and when their worker's lease runs out.
+    claimed: Mutex<HashMap<u64, (WorkItem, Instant)>>,
+    waiting: Mutex<HashMap<u64, oneshot::Sender<WorkResult>>>,
+    notify: Notify,
+}
+
+impl Dispatcher {
+    pub fn new(config: &ClusterConfig) -> Self {
+        Dispatcher {
+            token: config.token.clone(),
+            claim_timeout: Duration::from_millis(config.claim_timeout_ms),
+            lease: Duration::from_millis(config.lease_ms),
+            pending: Mutex::new(VecDeque::new()),
+            claimed: Mutex::new(HashMap::new()),
+            waiting: Mutex::new(HashMap::new()),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Whether `token` grants access to the internal endpoints. Without a
+    /// configured token every request is refused.
+    pub fn authorize(&self, token: Option<&str>) -> bool {
+        match &self.token {
+            Some(expected) => token == Some(expected.as_str()),
+            None => false,
+        }
+    }
+
+    /// Queue `item` for a worker and wait for its result.
+    pub async fn dispatch(&self, item: WorkItem) -> Result<WorkResult, String> {
+        let (tx, rx) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(item.id, tx);
+        self.pending.lock().unwrap().push_back(item);
+        self.notify.notify_one();
+        rx.await
+            .map_err(|_| "worker result channel closed".to_string())
+    }
+
+    /// Drop job `id` if no worker has claimed it yet and stop waiting for its
+    /// result otherwise.
+    pub fn cancel(&self, id: u64) {
+        self.pending.lock().unwrap().retain(|item| item.id != id);
+        self.claimed.lock().unwrap().remove(&id);
+        self.waiting.lock().unwrap().remove(&id);
+    }
+
+    /// Drop every job not yet claimed by a worker and stop waiting for the
+    /// results of claimed ones.
+    pub fn cancel_all(&self) {
+        self.pending.lock().unwrap().clear();
+        self.claimed.lock().unwrap().clear();
+        self.waiting.lock().unwrap().clear();
+    }
+
+    /// Take the next pending job, waiting up to the configured claim timeout
+    /// for one to arrive. The job is leased to the caller, which must renew
+    /// the lease with [`Dispatcher::renew`] until it reports the result.
+    pub async fn claim(&self) -> Option<WorkItem> {
+        let deadline = Instant::now() + self.claim_timeout;
+        loop {
+            let notified = self.notify.notified();
+            self.requeue_expired();
+            let next = self.pending.lock().unwrap().pop_front();
+            if let Some(mut item) = next {
+                item.lease_ms = Some(self.lease.as_millis() as u64);
+                self.claimed
+                    .lock()
+                    .unwrap()
+                    .insert(item.id, (item.clone(), Instant::now() + self.lease));
+                return Some(item);
+            }
+            // Wake up when a lease runs out, as its job can be claimed again.
+            let expiry = self.claimed.lock().unwrap().values().map(|c| c.1).min();
+            let wake = expiry.map_or(deadline, |t| t.min(deadline));
+            if tokio::time::timeout_at(wake, notified).await.is_err() && wake == deadline {
+                return None;
+            }
+        }
+    }
+
+    /// Extend the lease on claimed job `id`. Returns `false` if the job is
+    /// not claimed, e.g. because its lease ran out and it was re-queued.
+    pub fn renew(&self, id: u64) -> bool {
+        match self.claimed.lock().unwrap().get_mut(&id) {
+            Some((_, until)) => {
+                *until = Instant::now() + self.lease;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Put claimed jobs whose lease ran out, their worker presumably gone,
+    /// back at the front of the queue.
+    fn requeue_expired(&self) {
+        let now = Instant::now();
+        let mut claimed = self.claimed.lock().unwrap();
+        let expired: Vec<u64> = claimed
+            .iter()
+            .filter(|(_, (_, until))| *until <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some((item, _)) = claimed.remove(&id) {
+                tracing::warn!(job_id = id, "worker lease expired, re-queueing job");
+                self.pending.lock().unwrap().push_front(item);
+            }
+        }
+    }
+
+    /// Deliver a worker's result. Returns `false` for unknown job ids.
+    pub fn complete(&self, result: WorkResult) -> bool {
+        self.claimed.lock().unwrap().remove(&result.id);
+        match self.waiting.lock().unwrap().remove(&result.id) {
+            Some(tx) => tx.send(result).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Run this process as a worker pulling jobs from the configured coordinator.
+pub async fn run_worker(config: Config) -> Result<(), String> {
+    let base_url = config
+        .cluster
+        .coordinator_url
+        .clone()
+        .ok_or("cluster.coordinator_url is required for workers")?;
+    let base_url = base_url.trim_end_matches('/').to_string();
+
+    let mut local = config.clone();
+    local.cluster.role = Role::Standalone;
+    let scheduler = Scheduler::from_config(&local);
+    metrics::spawn_flusher(scheduler.metrics_log().clone());
+    let http = reqwest::Client::new();
+
+    tracing::info!(coordinator = %base_url, "worker started");
+    let mut loops = Vec::new();
+    for _ in 0..config.scheduler.max_concurrency.max(1) {
+        let worker = Worker {
+            base_url: base_url.clone(),
+            token: config.cluster.token.clone(),
+            http: http.clone(),
+            scheduler: scheduler.clone(),
+        };
+        loops.push(tokio::spawn(async move { worker.run().await }));
+    }
+    for handle in loops {
+        handle.await.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+struct Worker {
+    base_url: String,
+    token: Option<String>,
+    http: reqwest::Client,
+    scheduler: Scheduler,
+}
+
+impl Worker {
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let req = self.http.post(format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => req.header(TOKEN_HEADER, token),
+            None => req,
+        }
+    }
+
+    async fn claim(&self) -> Result<Option<WorkItem>, String> {
+        let resp = self
+            .post("/internal/jobs/claim")
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .error_for_status()
+            .map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        resp.json().await.map(Some).map_err(|e| e.to_string())
+    }
+
+    async fn heartbeat(&self, id: u64) -> Result<(), String> {
+        self.post(&format!("/internal/jobs/{}/heartbeat", id))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn report(&self, result: &WorkResult) -> Result<(), String> {
+        self.post(&format!("/internal/jobs/{}/complete", result.id))
+            .json(result)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn run(self) {
+        loop {
+            let item = match self.claim().await {
+                Ok(Some(item)) => item,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::warn!("failed to claim job: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            tracing::info!(job_id = item.id, "claimed job");
+            let options = JobOptions {
+                user: item.user.clone(),
+                format: item.format,
+                inline_limit: item.inline_limit,
+                streaming: item.streaming,
+                max_attempts: item.max_attempts,
+                ..Default::default()
+            };
+            let (_, _, mut rx) = self.scheduler.enqueue_with(item.query, options).await;
+            // Renew the lease while the job runs so the coordinator does not
+            // hand it to another worker.
+            let lease = item
+                .lease_ms
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis);
+            let outcome = match lease {
+                Some(lease) => {
+                    let mut beat = tokio::time::interval(lease / 3);
+                    beat.tick().await;
+                    loop {
+                        tokio::select! {
+                            outcome = &mut rx => break outcome,
+                            _ = beat.tick() => {
+                                if let Err(e) = self.heartbeat(item.id).await {
+                                    tracing::warn!(job_id = item.id, "failed to renew lease: {}", e);
+                                }
+                            }
+                        }
+                    }
+                }
+                None => rx.await,
+            };
+            let result = match outcome {
+                Ok(r) => WorkResult::from_job_result(item.id, &r),
+                Err(_) => WorkResult {
+                    id: item.id,
+                    output: None,
+                    path: None,
+                    parts: None,
+                    error: Some("worker scheduler stopped".to_string()),
+                    error_kind: Some("execution_failed".to_string()),
+                    format: item.format,
+                    rows: None,
+                },
+            };
+            for attempt in 1..=3 {
+                match self.report(&result).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        tracing::warn!(job_id = item.id, attempt, "failed to report result: {}", e);
+                        tokio::time::sleep(Duration::from_secs(attempt)).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn dispatch_claim_complete() {
+        let dispatcher = Arc::new(Dispatcher::new(&ClusterConfig {
+            token: Some("secret".into()),
+            claim_timeout_ms: 10,
+            ..Default::default()
+        }));
+        assert!(!dispatcher.authorize(None));
+        assert!(dispatcher.authorize(Some("secret")));
+
+        let d = dispatcher.clone();
+        let pending = tokio::spawn(async move {
+            d.dispatch(WorkItem {
+                id: 7,
+                query: "q".into(),
+                user: "u".into(),
+                format: OutputFormat::Csv,
+                inline_limit: None,
+                streaming: None,
+                max_attempts: None,
+                lease_ms: None,
+            })
+            .await
+        });
+
+        let item = dispatcher.claim().await.unwrap();
+        assert_eq!(item.id, 7);
+        assert!(dispatcher.renew(7));
+        assert!(dispatcher.complete(WorkResult {
+            id: 7,
+            output: None,
+            path: Some("out.feather".into()),
+            parts: None,
+            error: None,
+            error_kind: None,
+            format: OutputFormat::Csv,
+            rows: Some(2),
+        }));
+        let result = pending.await.unwrap().unwrap();
+        assert_eq!(result.path.as_deref(), Some("out.feather"));
+        assert_eq!(result.format, OutputFormat::Csv);
+        assert!(dispatcher.claim().await.is_none());
+        assert!(!dispatcher.renew(7));
+    }
+
+    #[tokio::test]
+    async fn expired_leases_are_claimed_again() {
+        let dispatcher = Arc::new(Dispatcher::new(&ClusterConfig {
+            token: Some("secret".into()),
+            claim_timeout_ms: 100,
+            lease_ms: 20,
+            ..Default::default()
+        }));
+        let d = dispatcher.clone();
+        let pending = tokio::spawn(async move {
+            d.dispatch(WorkItem {
+                id: 3,
+                query: "q".into(),
+                user: "u".into(),
+                format: OutputFormat::default(),
+                inline_limit: None,
+                streaming: None,
+                max_attempts: None,
+                lease_ms: None,
+            })
+            .await
+        });
+
+        let item = dispatcher.claim().await.unwrap();
+        assert_eq!(item.lease_ms, Some(20));
+        // The first worker never renews its lease, so a claim waiting for
+        // work gets the job once the lease runs out.
+        let item = dispatcher.claim().await.unwrap();
+        assert_eq!(item.id, 3);
+        assert!(dispatcher.complete(WorkResult {
+            id: 3,
+            output: None,
+            path: None,
+            parts: None,
+            error: Some("boom".into()),
+            error_kind: Some("unknown_dataset".into()),
+            format: OutputFormat::default(),
+            rows: None,
+        }));
+        let error = pending.await.unwrap().unwrap().into_output().err().unwrap();
+        assert_eq!(error.kind, "unknown_dataset");
+        assert_eq!(error.message, "boom");
+    }
+
+    #[test]
+    fn no_token_refuses_internal_requests() {
+        let dispatcher = Dispatcher::new(&ClusterConfig::default());
+        assert!(!dispatcher.authorize(None));
+        assert!(!dispatcher.authorize(Some("")));
+    }
+}
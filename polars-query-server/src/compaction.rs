@@ -0,0 +1,271 @@
+//! Rewriting a dataset's many small parquet files into fewer larger ones.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::catalog::{self, Catalog, DatasetFormat, VersionFile, VersionSelector};
+use crate::cron::Schedule;
+use crate::schema::SchemaMode;
+
+/// Directory, next to the compacted files, that originals are moved into.
+pub const COMPACTED_DIR: &str = "_compacted";
+
+/// Compaction settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompactionConfig {
+    /// Size compacted files are built up to, in bytes. Files smaller than
+    /// half of this are considered small.
+    pub target_file_bytes: u64,
+    /// Cron expression on which every dataset is compacted.
+    pub schedule: Option<String>,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        CompactionConfig {
+            target_file_bytes: 128 * 1024 * 1024,
+            schedule: None,
+        }
+    }
+}
+
+/// Outcome of compacting a dataset.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionReport {
+    pub dataset: String,
+    pub files_before: usize,
+    pub files_after: usize,
+    /// Small files merged into new files.
+    pub files_compacted: usize,
+    /// Version registered after compaction; unchanged when nothing was done.
+    pub version: u64,
+}
+
+/// Group small files into batches of roughly `target` bytes, never mixing
+/// files from different directories so partitioning is preserved.
+fn plan(files: &[VersionFile], target: u64) -> Vec<Vec<VersionFile>> {
+    let mut by_dir: BTreeMap<PathBuf, Vec<&VersionFile>> = BTreeMap::new();
+    for file in files.iter().filter(|f| f.size < target / 2) {
+        let dir = Path::new(&file.path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        by_dir.entry(dir).or_default().push(file);
+    }
+
+    let mut batches = Vec::new();
+    for small in by_dir.into_values() {
+        let mut batch: Vec<VersionFile> = Vec::new();
+        let mut size = 0;
+        for file in small {
+            batch.push(file.clone());
+            size += file.size;
+            if size >= target {
+                batches.push(std::mem::take(&mut batch));
+                size = 0;
+            }
+        }
+        batches.push(batch);
+    }
+    // Rewriting a single file gains nothing.
+    batches.retain(|b| b.len() > 1);
+    batches
+}
+
+/// Write `batch` as one parquet file in its directory, returning its path.
+fn write_batch(
+    batch: &[VersionFile],
+    mode: SchemaMode,
+    stamp: u64,
+    index: usize,
+) -> Result<PathBuf, String> {
+    let (lf, _) =
+        catalog::scan_files(batch, DatasetFormat::Parquet, mode).map_err(|e| e.to_string())?;
+    let mut df = lf.collect().map_err(|e| e.to_string())?;
+    let dir = Path::new(&batch[0].path)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let path = dir.join(format!("compacted-{}-{}.parquet", stamp, index));
+    let tmp = dir.join(format!(".compacted-{}-{}.parquet.tmp", stamp, index));
+    let written = File::create(&tmp)
+        .map_err(|e| e.to_string())
+        .and_then(|f| {
+            ParquetWriter::new(f)
+                .finish(&mut df)
+                .map_err(|e| e.to_string())
+        })
+        .and_then(|_| fs::rename(&tmp, &path).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// Compact the small files of the parquet dataset `name`.
+///
+/// Merged originals are moved into a `_compacted` directory beside them and
+/// earlier versions are updated to point there, so time travel keeps
+/// working. The compacted layout is registered as a new version.
+pub fn compact(catalog: &Catalog, name: &str, target: u64) -> Result<CompactionReport, String> {
+    let dataset = catalog
+        .get(name)
+        .ok_or_else(|| format!("unknown dataset {}", name))?;
+    if dataset.format != DatasetFormat::Parquet {
+        return Err(format!("dataset {} is not stored as parquet", name));
+    }
+    let (_, current) = catalog.resolve(name, VersionSelector::Latest)?;
+    let batches = plan(&current.files, target);
+    let mut report = CompactionReport {
+        dataset: name.to_string(),
+        files_before: current.files.len(),
+        files_after: current.files.len(),
+        files_compacted: 0,
+        version: current.version,
+    };
+    if batches.is_empty() {
+        return Ok(report);
+    }
+
+    let stamp = catalog::now_secs();
+    let mut moves = HashMap::new();
+    for (i, batch) in batches.iter().enumerate() {
+        write_batch(batch, current.schema_mode, stamp, i)?;
+        for file in batch {
+            let from = Path::new(&file.path);
+            let archive = from
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(COMPACTED_DIR)
+                .join(stamp.to_string());
+            fs::create_dir_all(&archive).map_err(|e| e.to_string())?;
+            let to = archive.join(from.file_name().unwrap_or_default());
+            fs::rename(from, &to).map_err(|e| e.to_string())?;
+            moves.insert(file.path.clone(), to.to_string_lossy().to_string());
+        }
+        report.files_compacted += batch.len();
+    }
+    catalog.relocate_files(name, &moves)?;
+
+    let updated = catalog.register(dataset.spec(), &dataset.owner)?;
+    report.files_after = updated.versions.last().map_or(0, |v| v.files.len());
+    report.version = updated.version;
+    tracing::info!(
+        dataset = name,
+        before = report.files_before,
+        after = report.files_after,
+        "compacted dataset"
+    );
+    Ok(report)
+}
+
+/// Compact every parquet dataset that is not a materialized view.
+pub fn compact_all(catalog: &Catalog, target: u64) -> Vec<Result<CompactionReport, String>> {
+    catalog
+        .list()
+        .into_iter()
+        .filter(|d| d.view.is_none() && d.format == DatasetFormat::Parquet)
+        .map(|d| compact(catalog, &d.name, target))
+        .collect()
+}
+
+/// Spawn a task compacting every dataset on `config.schedule`, if set.
+pub fn spawn_schedule(catalog: Arc<Catalog>, config: CompactionConfig) {
+    let Some(expr) = config.schedule.clone() else {
+        return;
+    };
+    let schedule = match Schedule::parse(&expr) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("invalid compaction schedule {}: {}", expr, e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        loop {
+            let now = catalog::now_secs();
+            let Some(next) = schedule.next_after_secs(now) else {
+                return;
+            };
+            tokio::time::sleep(Duration::from_secs(next - now)).await;
+            let catalog = catalog.clone();
+            let target = config.target_file_bytes;
+            let results = tokio::task::spawn_blocking(move || compact_all(&catalog, target))
+                .await
+                .unwrap_or_default();
+            for e in results.into_iter().filter_map(Result::err) {
+                tracing::error!("scheduled compaction failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::DatasetSpec;
+    use tempfile::tempdir;
+
+    #[test]
+    fn small_files_are_merged() {
+        let dir = tempdir().unwrap();
+        for i in 0..4 {
+            let mut df = df!["x" => [i, i + 10]].unwrap();
+            ParquetWriter::new(File::create(dir.path().join(format!("{}.parquet", i))).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        }
+        let catalog = Catalog::in_memory();
+        catalog
+            .register(
+                DatasetSpec {
+                    name: "xs".into(),
+                    location: dir.path().to_string_lossy().to_string(),
+                    format: DatasetFormat::Parquet,
+                    description: None,
+                    tags: vec![],
+                    schema_mode: None,
+                    partition_by: None,
+                },
+                "alice",
+            )
+            .unwrap();
+
+        let report = compact(&catalog, "xs", 1024 * 1024).unwrap();
+        assert_eq!(report.files_before, 4);
+        assert_eq!(report.files_after, 1);
+        assert_eq!(report.version, 2);
+
+        for version in [1, 2] {
+            let (format, v) = catalog
+                .resolve("xs", VersionSelector::Version(version))
+                .unwrap();
+            let (lf, _) = catalog::scan_files(&v.files, format, Default::default()).unwrap();
+            assert_eq!(lf.collect().unwrap().height(), 8);
+        }
+    }
+
+    #[test]
+    fn plan_keeps_directories_apart() {
+        let file = |path: &str| VersionFile {
+            path: path.into(),
+            size: 10,
+            modified: 0,
+        };
+        let files = vec![
+            file("d/p=1/a.parquet"),
+            file("d/p=1/b.parquet"),
+            file("d/p=2/c.parquet"),
+        ];
+        let batches = plan(&files, 100);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 2);
+    }
+}
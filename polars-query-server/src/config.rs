@@ -0,0 +1,739 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::access::AccessConfig;
+use crate::cache::CacheConfig;
+use crate::catalog::CatalogConfig;
+use crate::chaos::ChaosConfig;
+use crate::cloud::CloudConfig;
+use crate::cluster::{ClusterConfig, Role};
+use crate::metrics::MetricsConfig;
+use crate::quota::{QuotaConfig, QuotaPolicy};
+use crate::ratelimit::RateLimitConfig;
+use crate::resources::{self, Resources};
+use crate::retention::RetentionConfig;
+use crate::schema::SchemaMode;
+use crate::sessions::SessionConfig;
+use crate::state::StateConfig;
+use crate::streaming::StreamingConfig;
+use crate::telemetry::TelemetryConfig;
+use crate::utils::OutputConfig;
+
+/// Effective server configuration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub scheduler: SchedulerConfig,
+    pub storage: StorageConfig,
+    pub data: DataConfig,
+    pub resources: ResourcesConfig,
+    pub state: StateConfig,
+    pub cluster: ClusterConfig,
+    pub catalog: CatalogConfig,
+    pub access: AccessConfig,
+    pub sessions: SessionConfig,
+    pub chaos: ChaosConfig,
+    pub cache: CacheConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+/// HTTP listener settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Seconds to wait on SIGTERM or Ctrl-C for queued and running jobs to
+    /// finish before cancelling them.
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3000,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+}
+
+/// Job scheduling limits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Maximum number of jobs executing at once.
+    pub max_concurrency: usize,
+    /// Total estimated cost of the jobs executing at once, so that many
+    /// cheap jobs can run side by side but few expensive ones. A job costing
+    /// more runs alone. Only `max_concurrency` limits jobs when unset.
+    pub cost_budget: Option<usize>,
+    /// Most jobs waiting to start; further submissions wait for room.
+    pub queue_capacity: usize,
+    /// Token required by the `/admin` endpoints, which are refused when unset.
+    pub admin_token: Option<String>,
+    /// Finished jobs whose status and result `GET /jobs/:id` keeps in memory.
+    pub retained_jobs: usize,
+    /// Longest a job may run, in milliseconds, unless its submission sets its
+    /// own limit. Unlimited when unset.
+    pub job_timeout_ms: Option<u64>,
+    /// Queued jobs beyond which `GET /readyz` reports the server not ready.
+    /// Defaults to the queue capacity.
+    pub ready_queue_limit: Option<usize>,
+    /// Queued jobs beyond which submissions are refused with 429. Unlimited
+    /// when unset.
+    pub max_queued: Option<usize>,
+    /// How fast jobs may be submitted.
+    pub rate_limit: RateLimitConfig,
+    /// How jobs failing with transient I/O errors are retried.
+    pub retry: RetryConfig,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            max_concurrency: 4,
+            cost_budget: None,
+            queue_capacity: 100,
+            admin_token: None,
+            retained_jobs: 1000,
+            job_timeout_ms: None,
+            ready_queue_limit: None,
+            max_queued: None,
+            rate_limit: RateLimitConfig::default(),
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+/// Retries of jobs failing with transient I/O errors, such as object store
+/// outages. Jobs failing otherwise are not retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Attempts at a job, the first included, unless its submission sets
+    /// its own; 1 disables retries.
+    pub max_attempts: u32,
+    /// Milliseconds before the first retry, doubled for each later one.
+    pub backoff_ms: u64,
+    /// Longest wait between attempts, in milliseconds.
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            backoff_ms: 200,
+            max_backoff_ms: 10_000,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Wait before the attempt following attempt `attempt`, counted from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(32);
+        Duration::from_millis(
+            self.backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+/// Where and how results are stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Directory results are written to, created on startup.
+    pub output_dir: PathBuf,
+    pub scratch_dir: Option<PathBuf>,
+    pub min_free_bytes: u64,
+    #[serde(flatten)]
+    pub output: OutputConfig,
+    pub quota: QuotaConfig,
+    /// How long result files are kept.
+    pub retention: RetentionConfig,
+    /// Where and how often rows of finished jobs are written.
+    pub metrics: MetricsConfig,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            output_dir: PathBuf::from("output"),
+            scratch_dir: None,
+            min_free_bytes: 0,
+            output: OutputConfig::default(),
+            quota: QuotaConfig::default(),
+            retention: RetentionConfig::default(),
+            metrics: MetricsConfig::default(),
+        }
+    }
+}
+
+/// Location of source data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataConfig {
+    /// Directory relative paths in queries are resolved against.
+    pub data_dir: Option<PathBuf>,
+    /// Directories local source paths must lie within. Any readable file may
+    /// be queried when empty.
+    pub allowed_roots: Vec<PathBuf>,
+    /// How multi-file scans combine files whose schemas differ.
+    pub schema_mode: SchemaMode,
+    /// Directory ingested datasets are written to, one subdirectory each.
+    pub ingest_dir: PathBuf,
+    /// URL prefixes, such as `https://example.com/exports/`, that
+    /// `POST /ingest` may fetch sources from. URL sources are refused when
+    /// empty.
+    pub ingest_urls: Vec<String>,
+    /// Buffering of rows streamed to `POST /datasets/:name/append`.
+    pub streaming: StreamingConfig,
+    /// Options for reading `s3://`, `gs://` and `az://` paths.
+    pub cloud: CloudConfig,
+}
+
+impl Default for DataConfig {
+    fn default() -> Self {
+        DataConfig {
+            data_dir: None,
+            allowed_roots: Vec::new(),
+            schema_mode: SchemaMode::default(),
+            ingest_dir: PathBuf::from("data"),
+            ingest_urls: Vec::new(),
+            streaming: StreamingConfig::default(),
+            cloud: CloudConfig::default(),
+        }
+    }
+}
+
+/// Compute resources available to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourcesConfig {
+    /// Derive defaults from cgroup CPU and memory limits at startup.
+    pub auto_detect: bool,
+    /// Threads in the Polars pool (`POLARS_MAX_THREADS`); Polars picks when unset.
+    pub polars_threads: Option<usize>,
+    /// Memory the server aims to stay within, in bytes. Cached results may
+    /// use at most a quarter of it and the frames of all sessions together
+    /// at most half.
+    pub memory_budget_bytes: Option<u64>,
+    /// Run queries on Polars' streaming engine, which processes scans in
+    /// batches so filters and group-bys over data larger than memory
+    /// complete. Submissions can override it with `streaming`.
+    pub streaming_engine: bool,
+}
+
+impl Default for ResourcesConfig {
+    fn default() -> Self {
+        ResourcesConfig {
+            auto_detect: true,
+            polars_threads: None,
+            memory_budget_bytes: None,
+            streaming_engine: false,
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.parse().ok())
+}
+
+/// Prefix of environment variables overriding configuration keys.
+///
+/// Nested keys are separated by a double underscore, so
+/// `RDATA__SCHEDULER__MAX_CONCURRENCY=8` sets `scheduler.max_concurrency`.
+pub const ENV_PREFIX: &str = "RDATA__";
+
+/// Environment variable naming the configuration file.
+pub const CONFIG_FILE_VAR: &str = "RDATA_CONFIG";
+
+/// Configuration file read from the working directory, if it exists, when
+/// neither `--config` nor [`CONFIG_FILE_VAR`] names one.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Placeholder of secrets in [`Config::redacted`].
+pub const REDACTED: &str = "<redacted>";
+
+/// The configuration file to read: `path` when given, otherwise the one
+/// named by [`CONFIG_FILE_VAR`], otherwise [`DEFAULT_CONFIG_FILE`] if it
+/// exists.
+pub fn config_file(path: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = path {
+        return Some(path.to_path_buf());
+    }
+    if let Some(path) = std::env::var_os(CONFIG_FILE_VAR) {
+        return Some(path.into());
+    }
+    let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+    default.is_file().then_some(default)
+}
+
+/// Keys and values of the TOML file at `path`.
+fn read_file(path: &Path) -> Result<Value, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Set the keys of `base` present in `file`, recursing into tables. Keys
+/// `base` does not have are reported in `errors`, except within maps empty
+/// by default, such as `access.roles`, which are taken as given.
+fn merge_value(base: &mut Value, file: Value, path: &str, errors: &mut Vec<String>) {
+    match (base, file) {
+        (Value::Object(base), Value::Object(file)) if !base.is_empty() => {
+            for (key, value) in file {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match base.get_mut(&key) {
+                    Some(slot) => merge_value(slot, value, &key_path, errors),
+                    None => errors.push(format!("unknown configuration key {}", key_path)),
+                }
+            }
+        }
+        (base, file) => *base = file,
+    }
+}
+
+impl Config {
+    /// Configuration from the configuration file and the environment,
+    /// ignoring (and logging) settings that cannot be applied. See
+    /// [`Config::try_load`].
+    pub fn from_env() -> Self {
+        let (config, errors) = Config::load(None);
+        for e in errors {
+            tracing::warn!("ignoring configuration setting: {}", e);
+        }
+        config
+    }
+
+    /// Configuration as [`Config::try_load`] reads it without `--config`.
+    pub fn try_from_env() -> Result<Self, Vec<String>> {
+        Config::try_load(None)
+    }
+
+    /// Defaults overridden, in order, by the settings of the configuration
+    /// file (see [`config_file`]), the legacy environment variables and
+    /// `RDATA__*` variables, failing if the file cannot be read or any
+    /// setting is invalid. Command line flags are applied on top by
+    /// [`crate::cli::Cli::apply`].
+    pub fn try_load(file: Option<&Path>) -> Result<Self, Vec<String>> {
+        let (config, errors) = Config::load(file);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The layered configuration, applying every valid setting, and the
+    /// problems with the others.
+    fn load(file: Option<&Path>) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let file =
+            config_file(file).and_then(|path| read_file(&path).map_err(|e| errors.push(e)).ok());
+        let mut config = Config::default();
+        // Resource detection runs first, so that any explicit setting wins
+        // over the derived defaults.
+        let detect = std::env::var(format!("{}RESOURCES__AUTO_DETECT", ENV_PREFIX)).as_deref()
+            != Ok("false")
+            && file
+                .as_ref()
+                .and_then(|f| f.pointer("/resources/auto_detect"))
+                != Some(&Value::Bool(false));
+        if detect {
+            config.apply_resources(&resources::detect());
+        }
+        if let Some(file) = file {
+            if let Err(e) = config.merge_file(file) {
+                errors.extend(e);
+            }
+        }
+        config.apply_legacy_env();
+        if let Err(e) = config.apply_overrides(std::env::vars()) {
+            errors.extend(e);
+        }
+        (config, errors)
+    }
+
+    /// Apply the settings of a parsed configuration file, whose tables and
+    /// keys mirror the configuration's sections and fields. Known keys are
+    /// applied even when others are unknown, unless a value has the wrong
+    /// type.
+    pub fn merge_file(&mut self, file: Value) -> Result<(), Vec<String>> {
+        let mut value = serde_json::to_value(&*self).map_err(|e| vec![e.to_string()])?;
+        let mut errors = Vec::new();
+        merge_value(&mut value, file, "", &mut errors);
+        match serde_json::from_value(value) {
+            Ok(config) => *self = config,
+            Err(e) => errors.push(format!("invalid configuration file: {}", e)),
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// This configuration with tokens, the masking salt, the Redis URL and
+    /// object store options replaced by [`REDACTED`], for display.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        let hide = |secret: &mut String| {
+            if !secret.is_empty() {
+                *secret = REDACTED.to_string();
+            }
+        };
+        [
+            &mut config.scheduler.admin_token,
+            &mut config.cluster.token,
+            &mut config.state.redis_url,
+            &mut config.access.trusted_proxy_token,
+        ]
+        .into_iter()
+        .flatten()
+        .for_each(hide);
+        hide(&mut config.access.mask_salt);
+        config.data.cloud.options.values_mut().for_each(hide);
+        config
+    }
+
+    /// Apply `RDATA__SECTION__KEY=value` pairs from `vars`, ignoring variables
+    /// without the prefix. Values are parsed as JSON where the key is not a
+    /// string, so numbers, booleans and `null` work as expected.
+    ///
+    /// Valid overrides are applied even when others fail.
+    pub fn apply_overrides<I>(&mut self, vars: I) -> Result<(), Vec<String>>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut errors = Vec::new();
+        for (name, raw) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let path: Vec<String> = key.split("__").map(|s| s.to_lowercase()).collect();
+            if let Err(e) = self.set_path(&path, &raw) {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Set the key at `path` from its raw string representation. Values
+    /// that parse as JSON but not as the key's type, such as a numeric token
+    /// for an unset `Option<String>`, are taken as strings.
+    fn set_path(&mut self, path: &[String], raw: &str) -> Result<(), String> {
+        let mut value = serde_json::to_value(&*self).map_err(|e| e.to_string())?;
+        let pointer = format!("/{}", path.join("/"));
+        let slot = value
+            .pointer_mut(&pointer)
+            .ok_or_else(|| format!("unknown configuration key {}", path.join(".")))?;
+        let parsed = match slot {
+            serde_json::Value::String(_) => None,
+            _ => serde_json::from_str(raw).ok(),
+        };
+        let mut typed_error = None;
+        if let Some(parsed) = parsed {
+            *slot = parsed;
+            match serde_json::from_value(value.clone()) {
+                Ok(config) => {
+                    *self = config;
+                    return Ok(());
+                }
+                Err(e) => typed_error = Some(e.to_string()),
+            }
+        }
+        if let Some(slot) = value.pointer_mut(&pointer) {
+            *slot = serde_json::Value::String(raw.to_string());
+        }
+        *self = serde_json::from_value(value)
+            .map_err(|e| typed_error.unwrap_or_else(|| e.to_string()))?;
+        Ok(())
+    }
+
+    /// Derive defaults from detected container limits: one running job and
+    /// one Polars thread per CPU, and a memory budget of 80% of the limit.
+    pub fn apply_resources(&mut self, resources: &Resources) {
+        if let Some(cpus) = resources.cpus {
+            let cpus = (cpus.ceil() as usize).max(1);
+            self.scheduler.max_concurrency = cpus;
+            self.resources.polars_threads = Some(cpus);
+        }
+        if let Some(bytes) = resources.memory_bytes {
+            self.resources.memory_budget_bytes = Some(bytes / 10 * 8);
+        }
+    }
+
+    /// Apply the environment variables the server has historically
+    /// understood (`OUTPUT_DIR`, `OUTPUT_QUOTA_BYTES`, ...) and the
+    /// conventional `BIND_ADDR` and `PORT` of container platforms.
+    fn apply_legacy_env(&mut self) {
+        if let Some(bind) = env_parse("BIND_ADDR") {
+            self.server.bind = bind;
+        }
+        if let Some(port) = env_parse("PORT") {
+            self.server.port = port;
+        }
+        if let Ok(dir) = std::env::var("OUTPUT_DIR") {
+            self.storage.output_dir = dir.into();
+        }
+        if let Ok(dir) = std::env::var("SCRATCH_DIR") {
+            self.storage.scratch_dir = Some(dir.into());
+        }
+        if let Some(v) = env_parse("MIN_FREE_BYTES") {
+            self.storage.min_free_bytes = v;
+        }
+        if let Some(v) = env_parse("OUTPUT_INLINE_LIMIT") {
+            self.storage.output.inline_limit = v;
+        }
+        if let Some(v) = env_parse("OUTPUT_PART_SIZE") {
+            self.storage.output.part_size = v;
+        }
+        if let Some(v) = env_parse("OUTPUT_QUOTA_BYTES") {
+            self.storage.quota.limit_bytes = Some(v);
+        }
+        if let Ok(policy) = std::env::var("OUTPUT_QUOTA_POLICY") {
+            self.storage.quota.policy = match policy.as_str() {
+                "evict" | "evict_oldest" => QuotaPolicy::EvictOldest,
+                _ => QuotaPolicy::Reject,
+            };
+        }
+    }
+
+    /// Check the configuration for values the server cannot start with.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.scheduler.max_concurrency == 0 {
+            errors.push("scheduler.max_concurrency must be at least 1".to_string());
+        }
+        if self.scheduler.retry.max_attempts == 0 {
+            errors.push("scheduler.retry.max_attempts must be at least 1".to_string());
+        }
+        if self.scheduler.cost_budget == Some(0) {
+            errors.push("scheduler.cost_budget must be at least 1".to_string());
+        }
+        if self.scheduler.queue_capacity == 0 {
+            errors.push("scheduler.queue_capacity must be at least 1".to_string());
+        }
+        if self.storage.output.part_size == 0 {
+            errors.push("storage.part_size must be at least 1".to_string());
+        }
+        if self.state.redis_url.is_some() && !cfg!(feature = "redis") {
+            errors.push("state.redis_url requires building with the `redis` feature".to_string());
+        }
+        if self.cluster.role == Role::Worker && self.cluster.coordinator_url.is_none() {
+            errors.push("cluster.coordinator_url is required for workers".to_string());
+        }
+        if self.cluster.role == Role::Coordinator
+            && self.cluster.token.as_deref().is_none_or(str::is_empty)
+        {
+            errors.push("cluster.token is required for coordinators".to_string());
+        }
+        if self.access.enabled
+            && self
+                .access
+                .trusted_proxy_token
+                .as_deref()
+                .is_none_or(str::is_empty)
+        {
+            errors.push(
+                "access.trusted_proxy_token is required when access control is enabled".to_string(),
+            );
+        }
+        if self.cluster.lease_ms == 0 {
+            errors.push("cluster.lease_ms must be at least 1".to_string());
+        }
+        errors.extend(self.chaos.validate());
+        errors.extend(self.data.streaming.validate());
+        errors.extend(self.storage.metrics.validate());
+        errors.extend(self.telemetry.validate());
+        if let Some(dir) = &self.data.data_dir {
+            if !dir.is_dir() {
+                errors.push(format!(
+                    "data.data_dir {} is not a directory",
+                    dir.display()
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = Config::default();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.server.addr().to_string(), "127.0.0.1:3000");
+    }
+
+    #[test]
+    fn coordinators_require_a_token() {
+        let mut config = Config::default();
+        config.cluster.role = Role::Coordinator;
+        assert_eq!(
+            config.validate().unwrap_err(),
+            ["cluster.token is required for coordinators"]
+        );
+        config.cluster.token = Some("secret".into());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn env_overrides_nested_keys() {
+        let mut config = Config::default();
+        config
+            .apply_overrides([
+                (
+                    "RDATA__SCHEDULER__MAX_CONCURRENCY".to_string(),
+                    "8".to_string(),
+                ),
+                ("RDATA__SERVER__BIND".to_string(), "0.0.0.0".to_string()),
+                ("RDATA__STORAGE__OUTPUT_DIR".to_string(), "2024".to_string()),
+                ("UNRELATED".to_string(), "x".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(config.scheduler.max_concurrency, 8);
+        assert_eq!(config.server.bind.to_string(), "0.0.0.0");
+        assert_eq!(config.storage.output_dir, PathBuf::from("2024"));
+    }
+
+    #[test]
+    fn env_values_fall_back_to_strings() {
+        let mut config = Config::default();
+        config
+            .apply_overrides([
+                (
+                    "RDATA__STORAGE__SCRATCH_DIR".to_string(),
+                    "123456".to_string(),
+                ),
+                ("RDATA__DATA__DATA_DIR".to_string(), "true".to_string()),
+                (
+                    "RDATA__SCHEDULER__QUEUE_CAPACITY".to_string(),
+                    "7".to_string(),
+                ),
+            ])
+            .unwrap();
+        assert_eq!(config.storage.scratch_dir, Some(PathBuf::from("123456")));
+        assert_eq!(config.data.data_dir, Some(PathBuf::from("true")));
+        assert_eq!(config.scheduler.queue_capacity, 7);
+        let errors = config
+            .apply_overrides([(
+                "RDATA__SCHEDULER__MAX_CONCURRENCY".to_string(),
+                "many".to_string(),
+            )])
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn unknown_env_key_is_an_error() {
+        let mut config = Config::default();
+        let errors = config
+            .apply_overrides([("RDATA__SCHEDULER__NOPE".to_string(), "1".to_string())])
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn file_settings_are_merged_and_overridden() {
+        let file: Value = toml::from_str(
+            r#"
+            [server]
+            port = 8080
+
+            [scheduler]
+            max_concurrency = 2
+            admin_token = "s3cret"
+
+            [storage]
+            output_dir = "/var/lib/rdata"
+            inline_limit = 1024
+
+            [access.roles]
+            analysts = ["ann"]
+
+            [cache]
+            enabled = true
+            nope = 1
+            "#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        let errors = config.merge_file(file).unwrap_err();
+        assert_eq!(errors, ["unknown configuration key cache.nope"]);
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.bind, ServerConfig::default().bind);
+        assert_eq!(config.storage.output.inline_limit, 1024);
+        assert_eq!(config.access.roles["analysts"], ["ann"]);
+        assert!(config.cache.enabled);
+
+        config
+            .apply_overrides([(
+                "RDATA__SCHEDULER__MAX_CONCURRENCY".to_string(),
+                "4".to_string(),
+            )])
+            .unwrap();
+        assert_eq!(config.scheduler.max_concurrency, 4);
+        assert_eq!(config.storage.output_dir, PathBuf::from("/var/lib/rdata"));
+
+        let redacted = config.redacted();
+        assert_eq!(redacted.scheduler.admin_token.as_deref(), Some(REDACTED));
+        assert_eq!(redacted.access.mask_salt, "");
+
+        let wrong: Value = toml::from_str("[server]\nport = \"http\"").unwrap();
+        assert!(config.merge_file(wrong).is_err());
+        assert_eq!(config.server.port, 8080);
+    }
+
+    #[test]
+    fn detected_resources_set_defaults() {
+        let mut config = Config::default();
+        config.apply_resources(&Resources {
+            cpus: Some(2.5),
+            memory_bytes: Some(1000),
+        });
+        assert_eq!(config.scheduler.max_concurrency, 3);
+        assert_eq!(config.resources.polars_threads, Some(3));
+        assert_eq!(config.resources.memory_budget_bytes, Some(800));
+    }
+
+    #[test]
+    fn zero_concurrency_is_rejected() {
+        let mut config = Config::default();
+        config.scheduler.max_concurrency = 0;
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+    }
+}
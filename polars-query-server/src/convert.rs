@@ -0,0 +1,144 @@
+//! Conversion of stored job results to other file formats, cached so a result
+//! is only converted once per format.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::diff;
+use crate::state::JobRecord;
+use crate::storage::content_hash;
+
+/// Format a stored result can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConvertFormat {
+    Csv,
+    Parquet,
+    /// A JSON array with one object per row.
+    Json,
+}
+
+impl ConvertFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Csv => "csv",
+            ConvertFormat::Parquet => "parquet",
+            ConvertFormat::Json => "json",
+        }
+    }
+}
+
+/// A converted result file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Converted {
+    pub job_id: u64,
+    pub format: ConvertFormat,
+    pub path: String,
+    pub size: u64,
+    /// `true` when an earlier conversion of the same result was reused.
+    pub cached: bool,
+}
+
+fn write(df: &mut DataFrame, path: &Path, format: ConvertFormat) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    let written = match format {
+        ConvertFormat::Csv => CsvWriter::new(&mut file).finish(df),
+        ConvertFormat::Parquet => ParquetWriter::new(&mut file).finish(df).map(|_| ()),
+        ConvertFormat::Json => JsonWriter::new(&mut file)
+            .with_json_format(JsonFormat::Json)
+            .finish(df),
+    };
+    written.map_err(|e| e.to_string())
+}
+
+/// Convert the stored result of the job `record` describes to `format`
+/// under `dir`, reusing an earlier conversion of the same result.
+pub fn convert(dir: &Path, record: &JobRecord, format: ConvertFormat) -> Result<Converted, String> {
+    let files = match &record.output_location {
+        Some(path) => vec![path.clone()],
+        None => record.output_parts.clone(),
+    };
+    if files.is_empty() {
+        return Err(format!(
+            "job {} has no stored result (status {}); only results written to files can be converted",
+            record.id, record.status
+        ));
+    }
+    // Result files are named after their content, so their names identify
+    // the result.
+    let key = content_hash(files.join("\n").as_bytes());
+    let path = dir.join(format!("{}.{}", key, format.extension()));
+    let mut cached = true;
+    if !path.exists() {
+        cached = false;
+        let mut df = diff::load(record)?;
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        // Write to a temporary name so concurrent requests never read a
+        // partial conversion.
+        let tmp = path.with_extension(format!("{}.tmp.{}", format.extension(), record.id));
+        if let Err(e) = write(&mut df, &tmp, format) {
+            let _ = fs::remove_file(&tmp);
+            return Err(format!(
+                "failed to convert result of job {}: {}",
+                record.id, e
+            ));
+        }
+        fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+    }
+    let size = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+    Ok(Converted {
+        job_id: record.id,
+        format,
+        path: path.to_string_lossy().to_string(),
+        size,
+        cached,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn conversions_are_cached() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("output_abc.ipc");
+        let mut df = df!["id" => [1, 2], "name" => ["a", "b"]].unwrap();
+        IpcWriter::new(File::create(&source).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let mut record = JobRecord {
+            id: 1,
+            user: "alice".into(),
+            query: String::new(),
+            status: "completed".into(),
+            submitted_at_ms: None,
+            started_at_ms: None,
+            duration_ms: None,
+            cost: 0,
+            output_location: Some(source.to_string_lossy().to_string()),
+            output_parts: Vec::new(),
+            error: None,
+        };
+        let out = dir.path().join("converted");
+
+        let csv = convert(&out, &record, ConvertFormat::Csv).unwrap();
+        assert!(!csv.cached);
+        assert_eq!(
+            fs::read_to_string(&csv.path).unwrap(),
+            "id,name\n1,a\n2,b\n"
+        );
+        fs::remove_file(&source).unwrap();
+        let again = convert(&out, &record, ConvertFormat::Csv).unwrap();
+        assert!(again.cached);
+        assert_eq!(again.path, csv.path);
+        assert!(convert(&out, &record, ConvertFormat::Json).is_err());
+
+        record.output_location = None;
+        assert!(convert(&out, &record, ConvertFormat::Parquet).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Five-field cron expressions (`minute hour day-of-month month day-of-week`)
+//! evaluated in UTC.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+
+/// A parsed cron schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day-of-month / day-of-week were left as `*`.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parse one field into a bitmask of the values it allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => (
+                r,
+                s.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("invalid step in {}", item))?,
+            ),
+            None => (item, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let a = a
+                .parse()
+                .map_err(|_| format!("invalid value in {}", item))?;
+            let b = b
+                .parse()
+                .map_err(|_| format!("invalid value in {}", item))?;
+            (a, b)
+        } else {
+            let v = range
+                .parse()
+                .map_err(|_| format!("invalid value in {}", item))?;
+            // `5/15` means every 15 starting at 5.
+            (v, if item.contains('/') { max } else { v })
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format!("{} is out of range {}-{}", item, min, max));
+        }
+        for v in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+impl Schedule {
+    /// Parse a cron expression. The `@hourly`, `@daily`, `@weekly` and
+    /// `@monthly` shorthands are also accepted.
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields in cron expression {}", expr));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // Both 0 and 7 mean Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Schedule {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        // As in cron, when both fields are restricted either may match.
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// The first time strictly after `after` matching the schedule, or `None`
+    /// if there is none within the next five years.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = after + Duration::days(5 * 366);
+        while t <= limit {
+            if self.months & (1 << t.month()) == 0 {
+                let (y, m) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(y, m, 1)?
+                    .and_hms_opt(0, 0, 0)?
+                    .and_utc();
+                continue;
+            }
+            if !self.matches_day(t.date_naive()) {
+                t = (t.date_naive() + Duration::days(1))
+                    .and_hms_opt(0, 0, 0)?
+                    .and_utc();
+                continue;
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+                continue;
+            }
+            return Some(t);
+        }
+        None
+    }
+
+    /// [`Schedule::next_after`] in Unix seconds.
+    pub fn next_after_secs(&self, after: u64) -> Option<u64> {
+        let after = DateTime::<Utc>::from_timestamp(after as i64, 0)?;
+        self.next_after(after).map(|t| t.timestamp() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn next_occurrences() {
+        let every_15 = Schedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every_15.next_after(at("2024-06-01T10:07:30Z")),
+            Some(at("2024-06-01T10:15:00Z"))
+        );
+
+        let nightly = Schedule::parse("30 2 * * *").unwrap();
+        assert_eq!(
+            nightly.next_after(at("2024-06-01T02:30:00Z")),
+            Some(at("2024-06-02T02:30:00Z"))
+        );
+
+        let monday = Schedule::parse("0 9 * * 1").unwrap();
+        assert_eq!(
+            monday.next_after(at("2024-06-01T00:00:00Z")),
+            Some(at("2024-06-03T09:00:00Z"))
+        );
+
+        let new_year = Schedule::parse("@monthly").unwrap();
+        assert_eq!(
+            new_year.next_after(at("2024-12-15T00:00:00Z")),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(Schedule::parse("* * * *").is_err());
+        assert!(Schedule::parse("60 * * * *").is_err());
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+        assert!(Schedule::parse("5-1 * * * *").is_err());
+    }
+}
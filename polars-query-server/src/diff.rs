@@ -0,0 +1,235 @@
+//! Comparison of the stored results of two jobs.
+
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::state::JobRecord;
+use crate::utils::read_feather;
+
+/// Most keys listed per kind of row difference.
+pub const SAMPLE_KEYS: usize = 100;
+
+/// Column marking joined rows, unlikely to clash with result columns.
+const PRESENT: &str = "__rdata_present";
+
+/// A column whose type differs between the results.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnChange {
+    pub column: String,
+    pub left: String,
+    pub right: String,
+}
+
+/// Differences between the schemas of two results.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SchemaDiff {
+    pub only_left: Vec<String>,
+    pub only_right: Vec<String>,
+    pub changed: Vec<ColumnChange>,
+}
+
+/// Row-level differences of two results matched on a key column. Columns in
+/// both results are compared by their string form.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowDiff {
+    pub key: String,
+    /// Keys only in the right result.
+    pub added: usize,
+    /// Keys only in the left result.
+    pub removed: usize,
+    /// Keys in both whose values differ.
+    pub changed: usize,
+    pub unchanged: usize,
+    /// Up to [`SAMPLE_KEYS`] keys of each kind.
+    pub added_keys: Vec<String>,
+    pub removed_keys: Vec<String>,
+    pub changed_keys: Vec<String>,
+}
+
+/// Differences between two results.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResultDiff {
+    pub left_rows: usize,
+    pub right_rows: usize,
+    pub schema: SchemaDiff,
+    /// Set when a key was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<RowDiff>,
+}
+
+/// Load the result of the job `record` describes from the result store.
+/// Results returned inline are not stored and cannot be loaded.
+pub fn load(record: &JobRecord) -> Result<DataFrame, String> {
+    if let Some(path) = &record.output_location {
+        return read_feather(path);
+    }
+    let mut parts = record.output_parts.iter();
+    let Some(first) = parts.next() else {
+        return Err(format!(
+            "job {} has no stored result (status {}); only results written to files can be compared",
+            record.id, record.status
+        ));
+    };
+    let mut df = read_feather(first)?;
+    for part in parts {
+        df.vstack_mut(&read_feather(part)?)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(df)
+}
+
+fn schema_diff(left: &DataFrame, right: &DataFrame) -> SchemaDiff {
+    let (ls, rs) = (left.schema(), right.schema());
+    let mut diff = SchemaDiff::default();
+    for (name, dtype) in ls.iter() {
+        match rs.get(name.as_str()) {
+            None => diff.only_left.push(name.to_string()),
+            Some(other) if other != dtype => diff.changed.push(ColumnChange {
+                column: name.to_string(),
+                left: dtype.to_string(),
+                right: other.to_string(),
+            }),
+            Some(_) => {}
+        }
+    }
+    diff.only_right = rs
+        .iter_names()
+        .filter(|name| !ls.contains(name.as_str()))
+        .map(|name| name.to_string())
+        .collect();
+    diff
+}
+
+fn check_key(df: &DataFrame, key: &str, side: &str) -> Result<(), String> {
+    let column = df
+        .column(key)
+        .map_err(|_| format!("key column {} is missing from the {} result", key, side))?;
+    if column.n_unique().map_err(|e| e.to_string())? != df.height() {
+        return Err(format!(
+            "key column {} is not unique in the {} result",
+            key, side
+        ));
+    }
+    Ok(())
+}
+
+/// Rows of `df` left-joined with the keys of `other`, and whether each found
+/// a match.
+fn match_keys(df: &DataFrame, other: &DataFrame, key: &str) -> PolarsResult<LazyFrame> {
+    let keys = other
+        .select([key])?
+        .lazy()
+        .with_column(lit(true).alias(PRESENT));
+    Ok(df.clone().lazy().left_join(keys, col(key), col(key)))
+}
+
+/// Up to [`SAMPLE_KEYS`] values of `key` in `lf`, and how many rows it has.
+fn keys(lf: LazyFrame, key: &str) -> PolarsResult<(usize, Vec<String>)> {
+    let df = lf.select([col(key).cast(DataType::Utf8)]).collect()?;
+    let sample = df
+        .column(key)?
+        .utf8()?
+        .into_iter()
+        .take(SAMPLE_KEYS)
+        .map(|v| v.unwrap_or("null").to_string())
+        .collect();
+    Ok((df.height(), sample))
+}
+
+fn row_diff(left: &DataFrame, right: &DataFrame, key: &str) -> Result<RowDiff, String> {
+    check_key(left, key, "left")?;
+    check_key(right, key, "right")?;
+    let (lt, rt) = (
+        left.column(key).unwrap().dtype(),
+        right.column(key).unwrap().dtype(),
+    );
+    if lt != rt {
+        return Err(format!(
+            "key column {} is {} in the left result but {} in the right",
+            key, lt, rt
+        ));
+    }
+    let common: Vec<String> = left
+        .get_column_names()
+        .into_iter()
+        .filter(|c| *c != key && right.column(c).is_ok())
+        .map(str::to_string)
+        .collect();
+
+    let run = || -> PolarsResult<RowDiff> {
+        let absent = col(PRESENT).is_null();
+        let (removed, removed_keys) =
+            keys(match_keys(left, right, key)?.filter(absent.clone()), key)?;
+        let (added, added_keys) = keys(match_keys(right, left, key)?.filter(absent), key)?;
+
+        let mut right_cols = vec![col(key)];
+        right_cols.extend(common.iter().map(|c| col(c).alias(&format!("{}_right", c))));
+        let matched = left.clone().lazy().inner_join(
+            right.clone().lazy().select(right_cols),
+            col(key),
+            col(key),
+        );
+        let differs = common.iter().fold(lit(false), |acc, c| {
+            let (l, r) = (
+                col(c).cast(DataType::Utf8),
+                col(&format!("{}_right", c)).cast(DataType::Utf8),
+            );
+            acc.or(l.clone().neq(r.clone()).or(l.is_null().neq(r.is_null())))
+        });
+        let (changed, changed_keys) = keys(matched.filter(differs), key)?;
+        Ok(RowDiff {
+            key: key.to_string(),
+            added,
+            removed,
+            changed,
+            unchanged: left.height() - removed - changed,
+            added_keys,
+            removed_keys,
+            changed_keys,
+        })
+    };
+    run().map_err(|e| e.to_string())
+}
+
+/// Compare `left` with `right`, matching rows on `key` when given.
+pub fn diff(left: &DataFrame, right: &DataFrame, key: Option<&str>) -> Result<ResultDiff, String> {
+    Ok(ResultDiff {
+        left_rows: left.height(),
+        right_rows: right.height(),
+        schema: schema_diff(left, right),
+        rows: key.map(|k| row_diff(left, right, k)).transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_and_rows_are_compared() {
+        let left = df![
+            "id" => [1, 2, 3],
+            "city" => ["NY", "LA", "SF"],
+            "old" => [true, false, true],
+        ]
+        .unwrap();
+        let right = df![
+            "id" => [2, 3, 4],
+            "city" => ["LA", "Oakland", "SF"],
+            "score" => [1.0, 2.0, 3.0],
+        ]
+        .unwrap();
+        let result = diff(&left, &right, Some("id")).unwrap();
+        assert_eq!((result.left_rows, result.right_rows), (3, 3));
+        assert_eq!(result.schema.only_left, vec!["old"]);
+        assert_eq!(result.schema.only_right, vec!["score"]);
+        let rows = result.rows.unwrap();
+        assert_eq!(rows.removed_keys, vec!["1"]);
+        assert_eq!(rows.added_keys, vec!["4"]);
+        assert_eq!(rows.changed_keys, vec!["3"]);
+        assert_eq!(rows.unchanged, 1);
+
+        let dup = df!["id" => [1, 1]].unwrap();
+        assert!(diff(&dup, &right, Some("id")).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Automatic registration of parquet files and directories found under
+//! configured data roots.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::catalog::{self, Catalog, DatasetFormat, DatasetSpec};
+
+/// Tag added to datasets registered by discovery.
+pub const DISCOVERED_TAG: &str = "discovered";
+
+/// Directory discovery settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Directories scanned for datasets; discovery is off when empty.
+    pub roots: Vec<PathBuf>,
+    /// Seconds between scans.
+    pub interval_secs: u64,
+    /// Prefix of discovered dataset names.
+    pub prefix: String,
+    /// Owner of discovered datasets.
+    pub owner: String,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            roots: Vec::new(),
+            interval_secs: 60,
+            prefix: String::new(),
+            owner: "discovery".to_string(),
+        }
+    }
+}
+
+/// Outcome of a scan.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiscoveryReport {
+    /// Datasets registered for the first time.
+    pub registered: Vec<String>,
+    /// Datasets whose files changed, given a new version.
+    pub updated: Vec<String>,
+    /// Datasets whose location disappeared.
+    pub missing: Vec<String>,
+    /// Candidates that could not be registered, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Dataset name for `entry` of a root: the file stem or directory name with
+/// characters other than letters, digits, `_` and `-` replaced by `_`.
+pub fn dataset_name(prefix: &str, entry: &Path) -> Option<String> {
+    let stem = if entry.is_dir() {
+        entry.file_name()?
+    } else {
+        entry.file_stem()?
+    };
+    let stem: String = stem
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Some(format!("{}{}", prefix, stem))
+}
+
+fn is_parquet(path: &Path) -> bool {
+    path.is_file() && path.extension().and_then(|e| e.to_str()) == Some("parquet")
+}
+
+/// Partition key of a directory laid out as `<key>=<value>/` subdirectories.
+fn partition_key(dir: &Path) -> Option<String> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir())
+        .filter_map(|p| {
+            let name = p.file_name()?.to_str()?.to_string();
+            let (key, _) = name.split_once('=')?;
+            catalog::valid_name(key).then(|| key.to_string())
+        })
+        .next()
+}
+
+/// Dataset specs for the parquet files and directories directly in `root`.
+/// Entries starting with `.` or `_` are skipped.
+pub fn candidates(root: &Path, prefix: &str) -> Vec<DatasetSpec> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut specs: Vec<DatasetSpec> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| !n.starts_with('.') && !n.starts_with('_'))
+        })
+        .filter_map(|path| {
+            let partition_by = if path.is_dir() {
+                let key = partition_key(&path);
+                let has_files = fs::read_dir(&path)
+                    .map(|entries| {
+                        entries
+                            .filter_map(Result::ok)
+                            .any(|e| is_parquet(&e.path()))
+                    })
+                    .unwrap_or(false);
+                if key.is_none() && !has_files {
+                    return None;
+                }
+                key
+            } else if is_parquet(&path) {
+                None
+            } else {
+                return None;
+            };
+            Some(DatasetSpec {
+                name: dataset_name(prefix, &path)?,
+                location: path.to_string_lossy().to_string(),
+                format: DatasetFormat::Parquet,
+                tags: vec![DISCOVERED_TAG.to_string()],
+                partition_by,
+                ..Default::default()
+            })
+        })
+        .collect();
+    specs.sort_by(|a, b| a.name.cmp(&b.name));
+    specs
+}
+
+/// Register new datasets found under the configured roots, add versions to
+/// those whose files changed, and mark datasets whose location under a root
+/// disappeared as missing.
+pub fn scan(catalog: &Catalog, config: &DiscoveryConfig) -> DiscoveryReport {
+    let mut report = DiscoveryReport::default();
+    for root in &config.roots {
+        for spec in candidates(root, &config.prefix) {
+            let name = spec.name.clone();
+            let previous = catalog.get(&name);
+            if previous
+                .as_ref()
+                .is_some_and(|d| d.location != spec.location)
+            {
+                report.failed.push((
+                    name.clone(),
+                    format!("a dataset named {} already exists elsewhere", name),
+                ));
+                continue;
+            }
+            if let Some(dataset) = previous.as_ref().filter(|d| d.missing_since.is_none()) {
+                let files = catalog::snapshot_files(
+                    &dataset.location,
+                    dataset.format,
+                    dataset.partition_by.as_deref(),
+                );
+                if files.ok().as_ref() == dataset.versions.last().map(|v| &v.files) {
+                    continue;
+                }
+            }
+            let spec = match &previous {
+                // Keep the description, tags and settings users gave it.
+                Some(dataset) => dataset.spec(),
+                None => spec,
+            };
+            let owner = previous
+                .as_ref()
+                .map_or(config.owner.clone(), |d| d.owner.clone());
+            match catalog.register(spec, &owner) {
+                Ok(dataset) => match previous {
+                    None => report.registered.push(name),
+                    Some(p) if p.version != dataset.version || p.missing_since.is_some() => {
+                        report.updated.push(name)
+                    }
+                    Some(_) => {}
+                },
+                Err(e) => report.failed.push((name, e)),
+            }
+        }
+    }
+    for dataset in catalog.list() {
+        let under_root = config
+            .roots
+            .iter()
+            .any(|root| Path::new(&dataset.location).starts_with(root));
+        if under_root
+            && dataset.missing_since.is_none()
+            && !Path::new(&dataset.location).exists()
+            && catalog.mark_missing(&dataset.name).is_ok()
+        {
+            report.missing.push(dataset.name);
+        }
+    }
+    report
+}
+
+/// Scan every `config.interval_secs`, if any roots are configured.
+pub fn spawn(catalog: Arc<Catalog>, config: DiscoveryConfig) {
+    if config.roots.is_empty() {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let catalog = catalog.clone();
+            let config = config.clone();
+            let Ok(report) = tokio::task::spawn_blocking(move || scan(&catalog, &config)).await
+            else {
+                continue;
+            };
+            for name in &report.registered {
+                tracing::info!(dataset = %name, "registered discovered dataset");
+            }
+            for name in &report.missing {
+                tracing::warn!(dataset = %name, "dataset location disappeared");
+            }
+            for (name, e) in &report.failed {
+                tracing::warn!(dataset = %name, "failed to register discovered dataset: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    fn write(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut df = df!["x" => [1, 2]].unwrap();
+        ParquetWriter::new(File::create(path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+    }
+
+    #[test]
+    fn files_and_directories_are_discovered_and_tracked() {
+        let root = tempdir().unwrap();
+        write(&root.path().join("daily sales.parquet"));
+        write(&root.path().join("events").join("a.parquet"));
+        write(&root.path().join("logs").join("day=1").join("a.parquet"));
+        write(&root.path().join("_staging").join("a.parquet"));
+        fs::write(root.path().join("notes.txt"), "ignored").unwrap();
+
+        let catalog = Catalog::in_memory();
+        let config = DiscoveryConfig {
+            roots: vec![root.path().to_path_buf()],
+            prefix: "raw_".into(),
+            ..Default::default()
+        };
+        let report = scan(&catalog, &config);
+        assert_eq!(
+            report.registered,
+            vec!["raw_daily_sales", "raw_events", "raw_logs"]
+        );
+        assert_eq!(
+            catalog.get("raw_logs").unwrap().partition_by.as_deref(),
+            Some("day")
+        );
+        assert_eq!(catalog.get("raw_events").unwrap().owner, "discovery");
+
+        write(&root.path().join("events").join("b.parquet"));
+        fs::remove_file(root.path().join("daily sales.parquet")).unwrap();
+        let report = scan(&catalog, &config);
+        assert!(report.registered.is_empty());
+        assert_eq!(report.updated, vec!["raw_events"]);
+        assert_eq!(report.missing, vec!["raw_daily_sales"]);
+        assert!(catalog
+            .get("raw_daily_sales")
+            .unwrap()
+            .missing_since
+            .is_some());
+        assert_eq!(catalog.get("raw_events").unwrap().version, 2);
+    }
+}
@@ -0,0 +1,206 @@
+//! `rdata-server doctor`: diagnose common deployment problems.
+
+use polars::prelude::*;
+use std::fmt;
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+
+use crate::cluster::Role;
+use crate::config::Config;
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// A diagnostic check and its result.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{:>4}] {}: {}", tag, self.name, self.detail)
+    }
+}
+
+/// Run every check against `config`.
+pub fn run(config: &Config) -> Vec<Check> {
+    let mut checks = vec![check_config(config)];
+    checks.push(check_writable(
+        "output directory",
+        &config.storage.output_dir,
+    ));
+    if let Some(dir) = &config.storage.scratch_dir {
+        checks.push(check_writable("scratch directory", dir));
+    }
+    if let Some(dir) = &config.data.data_dir {
+        checks.extend(check_datasets(dir));
+    }
+    checks.push(check_cloud_credentials());
+    if config.cluster.role != Role::Worker {
+        checks.push(check_port(config));
+    }
+    checks
+}
+
+/// Whether any check failed.
+pub fn has_failures(checks: &[Check]) -> bool {
+    checks.iter().any(|c| c.status == Status::Fail)
+}
+
+fn check_config(config: &Config) -> Check {
+    match config.validate() {
+        Ok(()) => Check::new("configuration", Status::Ok, "valid"),
+        Err(errors) => Check::new("configuration", Status::Fail, errors.join("; ")),
+    }
+}
+
+fn check_writable(name: &str, dir: &Path) -> Check {
+    if let Err(e) = fs::create_dir_all(dir) {
+        return Check::new(name, Status::Fail, format!("{}: {}", dir.display(), e));
+    }
+    let probe = dir.join(".rdata-doctor");
+    match fs::write(&probe, b"ok") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            let free = fs2::available_space(dir)
+                .map(|b| format!(", {} MiB free", b / (1024 * 1024)))
+                .unwrap_or_default();
+            Check::new(
+                name,
+                Status::Ok,
+                format!("{} writable{}", dir.display(), free),
+            )
+        }
+        Err(e) => Check::new(
+            name,
+            Status::Fail,
+            format!("{} not writable: {}", dir.display(), e),
+        ),
+    }
+}
+
+/// Check that every parquet file directly under `dir` can be opened.
+fn check_datasets(dir: &Path) -> Vec<Check> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return vec![Check::new(
+                "data directory",
+                Status::Fail,
+                format!("{}: {}", dir.display(), e),
+            )]
+        }
+    };
+    let mut checks = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("parquet") {
+            continue;
+        }
+        let name = format!("dataset {}", path.display());
+        let readable = fs::File::open(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|f| ParquetReader::new(f).num_rows().map_err(|e| e.to_string()));
+        checks.push(match readable {
+            Ok(rows) => Check::new(name, Status::Ok, format!("{} rows", rows)),
+            Err(e) => Check::new(name, Status::Fail, e),
+        });
+    }
+    if checks.is_empty() {
+        checks.push(Check::new(
+            "data directory",
+            Status::Warn,
+            format!("no parquet files in {}", dir.display()),
+        ));
+    }
+    checks
+}
+
+fn check_cloud_credentials() -> Check {
+    let providers: Vec<&str> = [
+        ("aws", "AWS_ACCESS_KEY_ID"),
+        ("gcp", "GOOGLE_APPLICATION_CREDENTIALS"),
+        ("azure", "AZURE_STORAGE_ACCOUNT_NAME"),
+    ]
+    .iter()
+    .filter(|(_, var)| std::env::var(var).is_ok())
+    .map(|(name, _)| *name)
+    .collect();
+    let status = if !providers.is_empty() && !cfg!(feature = "cloud") {
+        Status::Warn
+    } else {
+        Status::Ok
+    };
+    let detail = match (providers.is_empty(), cfg!(feature = "cloud")) {
+        (true, _) => "no cloud credentials in environment".to_string(),
+        (false, true) => format!("found credentials for {}", providers.join(", ")),
+        (false, false) => format!(
+            "found credentials for {} but built without the `cloud` feature",
+            providers.join(", ")
+        ),
+    };
+    Check::new("cloud credentials", status, detail)
+}
+
+fn check_port(config: &Config) -> Check {
+    let addr = config.server.addr();
+    match TcpListener::bind(addr) {
+        Ok(_) => Check::new("listen address", Status::Ok, format!("{} available", addr)),
+        Err(e) => Check::new("listen address", Status::Fail, format!("{}: {}", addr, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn reports_readable_and_broken_datasets() {
+        let dir = tempdir().unwrap();
+        let mut df = df!["a" => [1, 2]].unwrap();
+        ParquetWriter::new(File::create(dir.path().join("good.parquet")).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        fs::write(dir.path().join("bad.parquet"), b"not parquet").unwrap();
+
+        let checks = check_datasets(dir.path());
+        assert_eq!(checks.len(), 2);
+        assert!(has_failures(&checks));
+        assert!(checks.iter().any(|c| c.status == Status::Ok));
+    }
+
+    #[test]
+    fn unwritable_output_dir_fails() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        assert_eq!(check_writable("output", &file).status, Status::Fail);
+        assert_eq!(check_writable("output", dir.path()).status, Status::Ok);
+    }
+}
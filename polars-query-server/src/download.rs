@@ -0,0 +1,70 @@
+//! Byte ranges of result downloads, as asked for by an HTTP `Range` header.
+
+use std::ops::Range;
+
+/// The bytes of a `len` byte file named by the `Range` header `value`.
+///
+/// Only single byte ranges are served. Malformed headers and requests for
+/// several ranges give `Ok(None)`, the whole file, as HTTP allows a server to
+/// ignore them. Ranges starting past the end of the file are an error, to be
+/// answered with `416 Range Not Satisfiable`.
+pub fn parse_range(value: &str, len: u64) -> Result<Option<Range<u64>>, String> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return Ok(None);
+    };
+    let range = match (start.trim(), end.trim()) {
+        // A suffix range: the last `end` bytes.
+        ("", end) => match end.parse::<u64>() {
+            Ok(0) => 0..0,
+            Ok(suffix) => len.saturating_sub(suffix)..len,
+            Err(_) => return Ok(None),
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => start..len,
+            Err(_) => return Ok(None),
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..(end + 1).min(len),
+            _ => return Ok(None),
+        },
+    };
+    if range.start >= range.end {
+        return Err(format!(
+            "range {} is outside the {} byte result",
+            value, len
+        ));
+    }
+    Ok(Some(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(0..100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Ok(Some(990..1000)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some(0..1000)));
+    }
+
+    #[test]
+    fn unsupported_ranges_serve_the_whole_file() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=9-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=a-", 1000), Ok(None));
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert!(parse_range("bytes=1000-", 1000).is_err());
+        assert!(parse_range("bytes=-0", 1000).is_err());
+        assert!(parse_range("bytes=0-", 0).is_err());
+    }
+}
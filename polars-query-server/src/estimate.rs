@@ -0,0 +1,583 @@
+//! Pre-run estimates of rows, bytes scanned and cost of a query plan.
+
+use polars::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+
+use crate::catalog::{self, DatasetFormat, VersionFile};
+use crate::cloud;
+use crate::executor::{self, ExecContext};
+use crate::parser::{JoinKind, QueryPlan};
+use crate::partition;
+use crate::stats::ColumnStats;
+
+/// Fraction of rows kept by a filter nothing is known about.
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+/// Fraction of rows kept by an equality filter on a column without statistics.
+const EQ_SELECTIVITY: f64 = 0.1;
+/// Fraction of rows kept by a range filter on a column without min/max.
+const RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// Estimated size of a query's work. Row counts are unknown for sources that
+/// are neither parquet nor have statistics.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Estimate {
+    /// Rows read by every source of the plan.
+    pub input_rows: Option<u64>,
+    /// Rows of the result.
+    pub output_rows: Option<u64>,
+    /// Bytes of the files read, after partition pruning.
+    pub bytes_scanned: u64,
+    /// Cost jobs are scheduled with: a unit per step plus one per MiB of
+    /// the columns read, counted again for each group-by, pivot, unique and
+    /// sort and twice again for each join.
+    pub cost: usize,
+    /// Files read, after partition pruning, and object store URLs read.
+    pub files: Vec<String>,
+}
+
+/// Cost score of `steps` reading `bytes` bytes: a unit per step plus one
+/// per MiB read, weighted by [`shape_weight`].
+pub fn cost_score(steps: &[QueryPlan], bytes: u64) -> usize {
+    let mib = bytes as f64 / (1024.0 * 1024.0);
+    steps.len() + (mib * shape_weight(steps)).ceil() as usize
+}
+
+/// How many times over a plan is charged for the bytes it reads: once, plus
+/// once more per group-by, pivot, unique and sort, which hold the whole
+/// frame, and twice more per join.
+fn shape_weight(steps: &[QueryPlan]) -> f64 {
+    let extra: f64 = steps
+        .iter()
+        .map(|step| match step {
+            QueryPlan::GroupBy(_)
+            | QueryPlan::Pivot { .. }
+            | QueryPlan::Unique { .. }
+            | QueryPlan::Sort(_) => 1.0,
+            QueryPlan::Join { .. } => 2.0,
+            _ => 0.0,
+        })
+        .sum();
+    1.0 + extra
+}
+
+/// Columns kept by the first `select` after the read at `i`, before the
+/// next read, when it only keeps columns.
+fn projected_columns(steps: &[QueryPlan], i: usize) -> Option<usize> {
+    steps[i + 1..]
+        .iter()
+        .take_while(|step| {
+            !matches!(
+                step,
+                QueryPlan::ReadParquet(_) | QueryPlan::ReadCsv { .. } | QueryPlan::ReadTable { .. }
+            )
+        })
+        .find_map(|step| match step {
+            QueryPlan::Select(exprs) if !step.computes_columns() => Some(Some(exprs.len())),
+            QueryPlan::Select(_) => Some(None),
+            _ => None,
+        })
+        .flatten()
+}
+
+/// Bytes of `read` a plan reading `projected` of its columns reads: parquet
+/// is read by column, so a share of its bytes, and other formats in full.
+fn read_bytes(read: &Read, projected: Option<usize>) -> u64 {
+    match (read.width, projected) {
+        (Some(width), Some(n)) if width > 0 && n < width => {
+            (read.bytes as f64 * n as f64 / width as f64).ceil() as u64
+        }
+        _ => read.bytes,
+    }
+}
+
+/// What a read contributes to the estimate.
+struct Read {
+    rows: Option<u64>,
+    bytes: u64,
+    files: Vec<String>,
+    /// Statistics of columns visible to the caller.
+    columns: Vec<ColumnStats>,
+    /// Partition key whose filters were applied by pruning.
+    pruned_key: Option<String>,
+    /// Columns of the files, known for parquet, which is read by column.
+    width: Option<usize>,
+}
+
+fn parquet_rows(files: &[VersionFile]) -> Option<u64> {
+    files
+        .iter()
+        .map(|f| {
+            let file = File::open(&f.path).ok()?;
+            ParquetReader::new(file).num_rows().ok().map(|n| n as u64)
+        })
+        .sum()
+}
+
+/// Columns of the parquet `files`, from the footer of the first.
+fn parquet_width(files: &[VersionFile]) -> Option<usize> {
+    let file = File::open(&files.first()?.path).ok()?;
+    ParquetReader::new(file).schema().ok().map(|s| s.len())
+}
+
+/// A read of unknown size from the object store `urls`, which are not listed
+/// before the query runs.
+fn unknown_read(urls: &[String]) -> Read {
+    Read {
+        rows: None,
+        bytes: 0,
+        files: urls.to_vec(),
+        columns: Vec::new(),
+        pruned_key: None,
+        width: None,
+    }
+}
+
+fn read_parquet(paths: &[String], ctx: &ExecContext) -> Result<Read, String> {
+    if paths.iter().any(|p| cloud::is_url(p)) {
+        return Ok(unknown_read(paths));
+    }
+    let files = ctx.parquet_files(paths)?;
+    Ok(Read {
+        rows: parquet_rows(&files),
+        bytes: files.iter().map(|f| f.size).sum(),
+        width: parquet_width(&files),
+        files: files.into_iter().map(|f| f.path).collect(),
+        columns: Vec::new(),
+        pruned_key: None,
+    })
+}
+
+fn read_csv(path: &str, ctx: &ExecContext) -> Result<Read, String> {
+    if cloud::is_url(path) {
+        return Ok(unknown_read(&[path.to_string()]));
+    }
+    let path = ctx.resolve_path(path);
+    // The format only selects the files of directories, which read_csv does
+    // not read.
+    let files = catalog::snapshot_files(&path, DatasetFormat::Parquet, None)?;
+    Ok(Read {
+        rows: None,
+        bytes: files.iter().map(|f| f.size).sum(),
+        files: files.into_iter().map(|f| f.path).collect(),
+        columns: Vec::new(),
+        pruned_key: None,
+        width: None,
+    })
+}
+
+fn read_table(
+    name: &str,
+    version: Option<u64>,
+    as_of: Option<&str>,
+    filters: &[(String, String, String)],
+    ctx: &ExecContext,
+) -> Result<Read, String> {
+    let catalog = ctx
+        .catalog
+        .as_ref()
+        .ok_or("no dataset catalog configured")?;
+    let selector = executor::version_selector(version, as_of)?;
+    let (format, version) = catalog.resolve(name, selector)?;
+    let dataset = catalog
+        .get(name)
+        .ok_or_else(|| format!("unknown dataset {}", name))?;
+    let masked = match (&ctx.user, &ctx.access) {
+        (Some(user), Some(access)) => access.masked_columns(&dataset, user),
+        _ => Vec::new(),
+    };
+    let mut files = version.files.clone();
+    // As when reading, masked partition columns are not pruned on.
+    let pruned_key = dataset
+        .partition_by
+        .clone()
+        .filter(|key| !masked.iter().any(|(c, _)| c == key));
+    if let Some(key) = &pruned_key {
+        let on_key: Vec<(String, String)> = filters
+            .iter()
+            .filter(|(c, _, _)| c == key)
+            .map(|(_, op, val)| (op.clone(), val.clone()))
+            .collect();
+        files = partition::prune(files, key, &on_key);
+    }
+    let bytes: u64 = files.iter().map(|f| f.size).sum();
+    let stats = dataset
+        .stats
+        .filter(|s| s.version == version.version && s.bytes > 0);
+    let width = match format {
+        DatasetFormat::Parquet => parquet_width(&files),
+        _ => None,
+    };
+    let rows = match (&stats, format) {
+        (_, DatasetFormat::Parquet) => parquet_rows(&files),
+        (Some(s), _) => Some((s.rows as f64 * bytes as f64 / s.bytes as f64).round() as u64),
+        (None, _) => None,
+    };
+    let columns = stats
+        .map(|s| s.columns)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|c| !masked.iter().any(|(m, _)| *m == c.name))
+        .collect();
+    Ok(Read {
+        rows,
+        bytes,
+        files: files.into_iter().map(|f| f.path).collect(),
+        columns,
+        pruned_key,
+        width,
+    })
+}
+
+/// Fraction of rows a `column op value` filter keeps, from the column's
+/// statistics where known.
+fn selectivity(op: &str, value: &str, stats: Option<&ColumnStats>) -> f64 {
+    let distinct = stats.and_then(|s| s.distinct).filter(|d| *d > 0);
+    match op {
+        "==" => distinct.map_or(EQ_SELECTIVITY, |d| 1.0 / d as f64),
+        "!=" => distinct.map_or(1.0 - EQ_SELECTIVITY, |d| 1.0 - 1.0 / d as f64),
+        _ => {
+            let bounds = stats.and_then(|s| {
+                let min = s.min.as_ref()?.parse::<f64>().ok()?;
+                let max = s.max.as_ref()?.parse::<f64>().ok()?;
+                Some((min, max))
+            });
+            let (Some((min, max)), Ok(value)) = (bounds, value.parse::<f64>()) else {
+                return RANGE_SELECTIVITY;
+            };
+            if max <= min {
+                return RANGE_SELECTIVITY;
+            }
+            let below = ((value - min) / (max - min)).clamp(0.0, 1.0);
+            if op.starts_with('<') {
+                below
+            } else {
+                1.0 - below
+            }
+        }
+    }
+}
+
+/// Estimate `steps` without running them, from parquet metadata and the
+/// statistics of catalog datasets.
+pub fn estimate(steps: &[QueryPlan], ctx: &ExecContext) -> Result<Estimate, String> {
+    estimate_steps(steps, ctx).map(|(estimate, _)| estimate)
+}
+
+/// Like [`estimate`], also returning the rows of the frame after each step,
+/// before any grouping (which is applied last).
+pub fn estimate_steps(
+    steps: &[QueryPlan],
+    ctx: &ExecContext,
+) -> Result<(Estimate, Vec<Option<u64>>), String> {
+    let mut input_rows = Some(0u64);
+    let mut bytes_scanned = 0;
+    // Bytes of the columns read, which the cost is charged for.
+    let mut bytes_read = 0;
+    let mut files = Vec::new();
+    let mut rows: Option<f64> = None;
+    let mut columns: Vec<ColumnStats> = Vec::new();
+    let mut pruned_key: Option<String> = None;
+    let mut group_by: Option<&[String]> = None;
+    let mut step_rows = Vec::with_capacity(steps.len());
+
+    for (i, step) in steps.iter().enumerate() {
+        let read = match step {
+            QueryPlan::ReadParquet(paths) => Some(read_parquet(paths, ctx)?),
+            QueryPlan::ReadCsv { path, .. } => Some(read_csv(path, ctx)?),
+            QueryPlan::ReadTable {
+                name,
+                version,
+                as_of,
+            } => Some(read_table(
+                name,
+                *version,
+                as_of.as_deref(),
+                &executor::following_filters(steps, i),
+                ctx,
+            )?),
+            QueryPlan::Filter(expr) => {
+                // The predicates of an `&` chain are taken as independent.
+                for conjunct in expr.conjuncts() {
+                    match conjunct.comparison() {
+                        None => rows = rows.map(|r| r * DEFAULT_SELECTIVITY),
+                        Some((column, op, value)) => {
+                            if pruned_key.as_deref() != Some(column.as_str()) {
+                                let stats = columns.iter().find(|c| c.name == column);
+                                let keep = selectivity(&op, value.trim_matches('"'), stats);
+                                rows = rows.map(|r| r * keep);
+                            }
+                        }
+                    }
+                }
+                None
+            }
+            QueryPlan::GroupBy(keys) => {
+                group_by = Some(keys.as_slice());
+                None
+            }
+            QueryPlan::Join { source, how, .. } => {
+                let joined = match &**source {
+                    QueryPlan::ReadParquet(paths) => read_parquet(paths, ctx)?,
+                    QueryPlan::ReadCsv { path, .. } => read_csv(path, ctx)?,
+                    QueryPlan::ReadTable {
+                        name,
+                        version,
+                        as_of,
+                    } => read_table(name, *version, as_of.as_deref(), &[], ctx)?,
+                    _ => {
+                        return Err(
+                            "join source must be read_parquet, read_csv or read_table".to_string()
+                        )
+                    }
+                };
+                input_rows = input_rows.zip(joined.rows).map(|(a, b)| a + b);
+                bytes_scanned += joined.bytes;
+                bytes_read += joined.bytes;
+                files.extend(joined.files);
+                // Joins are assumed to match each row at most once.
+                let right = joined.rows.map(|r| r as f64);
+                rows = match how {
+                    JoinKind::Inner => rows.zip(right).map(|(l, r)| l.min(r)),
+                    JoinKind::Left => rows,
+                    JoinKind::Outer => rows.zip(right).map(|(l, r)| l.max(r)),
+                };
+                // Later filters are no longer applied by pruning.
+                pruned_key = None;
+                None
+            }
+            QueryPlan::Head(_) | QueryPlan::Tail(_) | QueryPlan::Slice { .. } => {
+                // As in execution, slices after a groupby keep groups.
+                if let Some(keys) = group_by.take() {
+                    rows = grouped_rows(rows, keys, &columns);
+                }
+                if let Some((offset, length)) = step.slice_bounds() {
+                    rows = rows.map(|r| sliced_rows(r, offset, length));
+                }
+                // Later filters are no longer applied by pruning.
+                pruned_key = None;
+                None
+            }
+            QueryPlan::Unique { subset, .. } => {
+                if let Some(keys) = group_by.take() {
+                    rows = grouped_rows(rows, keys, &columns);
+                }
+                // Rows are assumed distinct unless a subset is compared.
+                if let Some(subset) = subset {
+                    rows = grouped_rows(rows, subset, &columns);
+                }
+                pruned_key = None;
+                None
+            }
+            QueryPlan::DropNulls(subset) => {
+                let kept: f64 = columns
+                    .iter()
+                    .filter(|c| subset.as_ref().is_none_or(|s| s.contains(&c.name)))
+                    .map(|c| 1.0 - c.null_pct / 100.0)
+                    .product();
+                rows = rows.map(|r| r * kept);
+                None
+            }
+            QueryPlan::FillNull(_) | QueryPlan::WithColumns(_) => {
+                // Later filters are no longer applied by pruning.
+                pruned_key = None;
+                None
+            }
+            QueryPlan::Pivot { index, .. } => {
+                if let Some(keys) = group_by.take() {
+                    rows = grouped_rows(rows, keys, &columns);
+                }
+                // A row per combination of index values, with columns only
+                // known once the data is read.
+                rows = grouped_rows(rows, index, &columns);
+                columns = Vec::new();
+                pruned_key = None;
+                None
+            }
+            QueryPlan::Melt {
+                id_vars,
+                value_vars,
+                ..
+            } => {
+                if let Some(keys) = group_by.take() {
+                    rows = grouped_rows(rows, keys, &columns);
+                }
+                // A row for each row and melted column.
+                let melted = if !value_vars.is_empty() {
+                    Some(value_vars.len())
+                } else if columns.is_empty() {
+                    None
+                } else {
+                    Some(
+                        columns
+                            .iter()
+                            .filter(|c| !id_vars.contains(&c.name))
+                            .count(),
+                    )
+                };
+                rows = rows.zip(melted).map(|(r, n)| r * n as f64);
+                columns = Vec::new();
+                pruned_key = None;
+                None
+            }
+            QueryPlan::Select(_) if step.computes_columns() => {
+                pruned_key = None;
+                None
+            }
+            QueryPlan::Select(_) | QueryPlan::Agg(_) | QueryPlan::Sort(_) => None,
+        };
+        if let Some(read) = read {
+            input_rows = input_rows.zip(read.rows).map(|(a, b)| a + b);
+            bytes_scanned += read.bytes;
+            bytes_read += read_bytes(&read, projected_columns(steps, i));
+            files.extend(read.files);
+            // Like execution, each read replaces the frame.
+            rows = read.rows.map(|r| r as f64);
+            columns = read.columns;
+            pruned_key = read.pruned_key;
+        }
+        step_rows.push(rows.map(|r| r.round() as u64));
+    }
+    if let Some(keys) = group_by {
+        rows = grouped_rows(rows, keys, &columns);
+    }
+    let estimate = Estimate {
+        input_rows,
+        output_rows: rows.map(|r| r.round() as u64),
+        bytes_scanned,
+        cost: cost_score(steps, bytes_read),
+        files,
+    };
+    Ok((estimate, step_rows))
+}
+
+/// Groups of `rows` rows grouped by `keys`: at most as many as combinations
+/// of key values, or their square root when distinct counts are unknown.
+fn grouped_rows(rows: Option<f64>, keys: &[String], columns: &[ColumnStats]) -> Option<f64> {
+    let distinct: Option<f64> = keys
+        .iter()
+        .map(|key| {
+            columns
+                .iter()
+                .find(|c| &c.name == key)
+                .and_then(|c| c.distinct)
+                .map(|d| d as f64)
+        })
+        .product();
+    rows.map(|r| match distinct {
+        Some(d) => r.min(d),
+        None => r.sqrt().ceil(),
+    })
+}
+
+/// Rows of `rows` kept by a slice of `length` rows from `offset`.
+fn sliced_rows(rows: f64, offset: i64, length: Option<u64>) -> f64 {
+    let available = if offset < 0 {
+        rows.min(offset.unsigned_abs() as f64)
+    } else {
+        (rows - offset as f64).max(0.0)
+    };
+    length.map_or(available, |n| available.min(n as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{Catalog, DatasetSpec};
+    use crate::parser::parse_query;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn rows_and_bytes_are_estimated() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("people.parquet");
+        let mut df = df![
+            "city" => ["NY", "LA", "SF", "NY"],
+            "age" => [10, 20, 30, 40],
+        ]
+        .unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let catalog = Catalog::in_memory().with_stats_on_register(true);
+        catalog
+            .register(
+                DatasetSpec {
+                    name: "people".into(),
+                    location: data.to_string_lossy().to_string(),
+                    format: DatasetFormat::Parquet,
+                    ..Default::default()
+                },
+                "alice",
+            )
+            .unwrap();
+        let ctx = ExecContext {
+            catalog: Some(Arc::new(catalog)),
+            ..Default::default()
+        };
+        let size = std::fs::metadata(&data).unwrap().len();
+
+        let query = "df = pl.read_table(\"people\")\ndf = df.filter(pl.col(\"age\") > 25)";
+        let est = estimate(&parse_query(query).unwrap(), &ctx).unwrap();
+        assert_eq!(est.input_rows, Some(4));
+        assert_eq!(est.output_rows, Some(2));
+        assert_eq!(est.bytes_scanned, size);
+        assert_eq!(est.cost, 3);
+
+        let query = "df = pl.read_table(\"people\")\ndf = df.filter((pl.col(\"age\") > 25) & (pl.col(\"city\") == \"NY\"))";
+        let est = estimate(&parse_query(query).unwrap(), &ctx).unwrap();
+        assert_eq!(est.output_rows, Some(1));
+
+        let query = "df = pl.read_table(\"people\")\ndf = df.groupby(\"city\")\ndf = df.agg(pl.col(\"age\").sum())";
+        let est = estimate(&parse_query(query).unwrap(), &ctx).unwrap();
+        assert_eq!(est.output_rows, Some(3));
+
+        let query = "df = pl.read_table(\"people\")\ndf = df.groupby(\"city\")\ndf = df.head(2)";
+        let est = estimate(&parse_query(query).unwrap(), &ctx).unwrap();
+        assert_eq!(est.output_rows, Some(2));
+        let query = "df = pl.read_table(\"people\")\ndf = df.slice(-3, 2)";
+        let est = estimate(&parse_query(query).unwrap(), &ctx).unwrap();
+        assert_eq!(est.output_rows, Some(2));
+    }
+
+    #[test]
+    fn cost_follows_bytes_read_and_plan_shape() {
+        let mib = 1024 * 1024;
+        let plan = |query: &str| parse_query(query).unwrap();
+        let scan = plan("df = pl.read_parquet(\"a.parquet\")\ndf = df.filter(pl.col(\"x\") > 1)");
+        assert_eq!(cost_score(&scan, 10 * mib), 12);
+        let grouped = plan("df = pl.read_parquet(\"a.parquet\")\ndf = df.groupby(\"k\")\ndf = df.agg(pl.col(\"x\").sum())");
+        assert_eq!(cost_score(&grouped, 10 * mib), 23);
+        let joined = plan("df = pl.read_parquet(\"a.parquet\")\ndf = df.join(pl.read_parquet(\"b.parquet\"), on=\"k\")\ndf = df.sort(\"x\")");
+        assert_eq!(cost_score(&joined, 10 * mib), 43);
+
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("wide.parquet");
+        let mut df = df![
+            "a" => (0..10_000i64).collect::<Vec<_>>(),
+            "b" => (0..10_000i64).collect::<Vec<_>>(),
+            "c" => (0..10_000i64).collect::<Vec<_>>(),
+            "d" => (0..10_000i64).collect::<Vec<_>>(),
+        ]
+        .unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let size = std::fs::metadata(&data).unwrap().len();
+        let read = format!("df = pl.read_parquet(\"{}\")", data.display());
+        let all = plan(&format!(
+            "{}\ndf = df.select([\"a\", \"b\", \"c\", \"d\"])",
+            read
+        ));
+        let one = plan(&format!("{}\ndf = df.select([\"a\"])", read));
+        let ctx = ExecContext::default();
+        assert_eq!(projected_columns(&one, 0), Some(1));
+        let full = estimate(&all, &ctx).unwrap();
+        let narrow = estimate(&one, &ctx).unwrap();
+        assert_eq!(full.bytes_scanned, size);
+        assert_eq!(narrow.bytes_scanned, size);
+        assert_eq!(full.cost, cost_score(&all, size));
+        assert_eq!(narrow.cost, cost_score(&one, size.div_ceil(4)));
+    }
+}
@@ -1,110 +1,1010 @@
-use once_cell::sync::Lazy;
+use chrono::NaiveDate;
 use polars::prelude::*;
-use regex::Regex;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
 
-use crate::parser::{parse_query, QueryPlan};
+use crate::access::AccessControl;
+use crate::catalog::{self, Catalog, DatasetFormat, VersionSelector};
+use crate::chaos::Chaos;
+use crate::cloud::{self, CloudConfig};
+use crate::expr::{self as ast, AggFunc, BinaryOp, DType, Literal, StrOp};
+use crate::masking;
+use crate::partition;
+use crate::sandbox::Sandbox;
+use crate::schema::{self, SchemaMode};
+
+use crate::parser::{
+    parse_query, FillNull, FillStrategy, JoinKind, PivotAggregate, QueryPlan, UniqueKeep,
+};
+
+/// Error of a query stopped through its [`CancelToken`].
+pub const CANCELLED: &str = "job cancelled";
+
+/// Lowercase phrases of errors from storage that may succeed when retried:
+/// object store throttling and outages, dropped connections and stale NFS
+/// handles.
+const TRANSIENT_MESSAGES: [&str; 11] = [
+    "service unavailable",
+    "too many requests",
+    "slow down",
+    "slowdown",
+    "connection reset",
+    "connection refused",
+    "connection aborted",
+    "broken pipe",
+    "timed out",
+    "temporarily unavailable",
+    "stale file handle",
+];
+
+/// A failure whose [`JobError`](crate::scheduler::JobError) kind is known
+/// where it is raised, carried through `PolarsError` to [`error_kind`].
+#[derive(Debug)]
+struct KindedError {
+    kind: &'static str,
+    message: String,
+}
+
+impl fmt::Display for KindedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for KindedError {}
+
+fn kinded_error(kind: &'static str, msg: impl Into<String>) -> PolarsError {
+    PolarsError::Io(io::Error::other(KindedError {
+        kind,
+        message: msg.into(),
+    }))
+}
+
+/// The job error kind `error` was raised with: `access_denied`,
+/// `unknown_dataset` or `cancelled`, or none for other failures.
+pub fn error_kind(error: &PolarsError) -> Option<&'static str> {
+    match error {
+        PolarsError::Io(e) => e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<KindedError>())
+            .map(|e| e.kind),
+        _ => None,
+    }
+}
+
+/// Whether `error` is a transient I/O failure worth retrying, rather than a
+/// failure the same query would hit again.
+pub fn is_transient(error: &PolarsError) -> bool {
+    if let PolarsError::Io(e) = error {
+        use std::io::ErrorKind;
+        if matches!(
+            e.kind(),
+            ErrorKind::TimedOut
+                | ErrorKind::Interrupted
+                | ErrorKind::WouldBlock
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::NotConnected
+                | ErrorKind::BrokenPipe
+        ) {
+            return true;
+        }
+    }
+    is_transient_message(&error.to_string())
+}
+
+/// Whether the error `message` reads as a transient I/O failure. Object
+/// store errors reach the executor as text, so they are told by it; injected
+/// scan failures stand in for them.
+pub fn is_transient_message(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    message.contains(&format!("{}: failed to scan", crate::chaos::INJECTED))
+        || TRANSIENT_MESSAGES.iter().any(|m| message.contains(m))
+}
+
+/// Asks a running query to stop. Execution checks the token between steps
+/// and before collecting the result; Polars cannot be interrupted while it
+/// collects.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled. Meant for a single waiter.
+    pub async fn cancelled(&self) {
+        if !self.is_cancelled() {
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Environment a plan is executed in.
+#[derive(Debug, Clone, Default)]
+pub struct ExecContext {
+    /// Directory relative source paths are resolved against.
+    pub data_dir: Option<PathBuf>,
+    /// Catalog `read_table` names are looked up in.
+    pub catalog: Option<Arc<Catalog>>,
+    /// How `read_parquet` globs matching files with differing schemas are read.
+    pub schema_mode: SchemaMode,
+    /// Caller the plan runs for; `read_table` masks columns their policies
+    /// hide from this user. Nothing is masked when unset.
+    pub user: Option<String>,
+    /// Decides which columns are masked for `user`.
+    pub access: Option<Arc<AccessControl>>,
+    /// Injects faults into execution when configured.
+    pub chaos: Option<Arc<Chaos>>,
+    /// Stops execution early once cancelled.
+    pub cancel: Option<CancelToken>,
+    /// How object store paths are read.
+    pub cloud: Arc<CloudConfig>,
+    /// Directories local source paths must lie within.
+    pub sandbox: Arc<Sandbox>,
+    /// Collect results with Polars' streaming engine, in batches. Steps it
+    /// doesn't support run in memory as usual.
+    pub streaming: bool,
+}
+
+impl ExecContext {
+    /// Resolve a source path from a query against `data_dir`. Object store
+    /// URLs are left as they are.
+    pub fn resolve_path(&self, path: &str) -> String {
+        match &self.data_dir {
+            Some(dir) if Path::new(path).is_relative() && !cloud::is_url(path) => {
+                dir.join(path).to_string_lossy().to_string()
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    /// `path` resolved against `data_dir`, or an "access denied" error if it
+    /// lies outside the sandbox.
+    pub fn sandboxed_path(&self, path: &str) -> Result<String, String> {
+        let resolved = self.resolve_path(path);
+        if self.sandbox.allows(&resolved) {
+            Ok(resolved)
+        } else {
+            Err(format!(
+                "access denied: {} is outside the allowed data directories",
+                path
+            ))
+        }
+    }
+
+    /// Fail with an "access denied" error if any local path `steps` read,
+    /// including the locations of the datasets they read, lies outside the
+    /// sandbox.
+    pub fn check_sources(&self, steps: &[QueryPlan]) -> Result<(), String> {
+        for step in steps {
+            let paths = match step.source() {
+                Some(QueryPlan::ReadParquet(paths)) => paths.as_slice(),
+                Some(QueryPlan::ReadCsv { path, .. }) => std::slice::from_ref(path),
+                Some(QueryPlan::ReadTable { name, .. }) => {
+                    if let Some(catalog) = &self.catalog {
+                        if let Some(dataset) = catalog.get(name) {
+                            catalog.check_location(&dataset.location)?;
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            for path in paths {
+                self.sandboxed_path(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The parquet files at `paths`, each a file, directory or glob resolved
+    /// against `data_dir`. A path matching no files is an error, as is an
+    /// object store URL, whose objects are not listed.
+    pub fn parquet_files(&self, paths: &[String]) -> Result<Vec<catalog::VersionFile>, String> {
+        let mut files = Vec::new();
+        for path in paths {
+            if cloud::is_url(path) {
+                return Err(format!("cannot list the objects at {}", path));
+            }
+            let path = self.resolve_path(path);
+            let found = catalog::snapshot_files(&path, DatasetFormat::Parquet, None)?;
+            files.extend(found);
+        }
+        Ok(files)
+    }
+
+    /// Fail with [`CANCELLED`] if the query has been cancelled.
+    fn check_cancelled(&self) -> PolarsResult<()> {
+        match &self.cancel {
+            Some(token) if token.is_cancelled() => Err(kinded_error("cancelled", CANCELLED)),
+            _ => Ok(()),
+        }
+    }
+}
 
 /// Execute a textual query plan and return the resulting DataFrame.
+#[cfg(test)]
 pub fn execute_plan(plan: &str) -> PolarsResult<DataFrame> {
+    execute_plan_with(plan, &ExecContext::default())
+}
+
+/// Execute a textual query plan within `ctx`.
+pub fn execute_plan_with(plan: &str, ctx: &ExecContext) -> PolarsResult<DataFrame> {
+    let steps = parse_query(plan).map_err(|e| PolarsError::ComputeError(e.into()))?;
+    execute_steps(steps, ctx, None)
+}
+
+/// Execute a textual query plan on `df` within `ctx`. A read in the plan
+/// replaces `df`, as it would any earlier frame.
+pub fn execute_plan_on(df: DataFrame, plan: &str, ctx: &ExecContext) -> PolarsResult<DataFrame> {
     let steps = parse_query(plan).map_err(|e| PolarsError::ComputeError(e.into()))?;
-    execute_steps(steps)
+    execute_steps(steps, ctx, Some(df.lazy()))
+}
+
+/// Execute parsed or built steps, such as a [`crate::plan::Plan`]'s, within
+/// `ctx`.
+pub fn execute_steps_with(steps: Vec<QueryPlan>, ctx: &ExecContext) -> PolarsResult<DataFrame> {
+    execute_steps(steps, ctx, None)
+}
+
+/// Polars' optimized plan for `steps` within `ctx`, without running them.
+pub fn describe_plan(steps: &[QueryPlan], ctx: &ExecContext) -> PolarsResult<String> {
+    build_steps(steps.to_vec(), ctx, None)?.describe_optimized_plan()
 }
 
-fn execute_steps(steps: Vec<QueryPlan>) -> PolarsResult<DataFrame> {
-    let mut lf: Option<LazyFrame> = None;
-    let mut group_by: Option<String> = None;
+/// The schema of the frame `steps` produce within `ctx`, resolved from file
+/// metadata without running them.
+pub fn plan_schema(steps: &[QueryPlan], ctx: &ExecContext) -> PolarsResult<SchemaRef> {
+    build_steps(steps.to_vec(), ctx, None)?.schema()
+}
+
+fn execute_steps(
+    steps: Vec<QueryPlan>,
+    ctx: &ExecContext,
+    start: Option<LazyFrame>,
+) -> PolarsResult<DataFrame> {
+    if let Some(chaos) = &ctx.chaos {
+        chaos.before_execute();
+    }
+    let lf = build_steps(steps, ctx, start)?.with_streaming(ctx.streaming);
+    ctx.check_cancelled()?;
+    tracing::info_span!("collect").in_scope(|| lf.collect())
+}
+
+/// The frame `steps` produce from `start`, as a lazy query.
+fn build_steps(
+    steps: Vec<QueryPlan>,
+    ctx: &ExecContext,
+    start: Option<LazyFrame>,
+) -> PolarsResult<LazyFrame> {
+    let mut lf = start;
+    let mut group_by: Option<Vec<String>> = None;
     let mut aggs: Vec<Expr> = Vec::new();
+    let filters: Vec<_> = (0..steps.len())
+        .map(|i| following_filters(&steps, i))
+        .collect();
 
-    for step in steps {
+    for (i, step) in steps.into_iter().enumerate() {
+        ctx.check_cancelled()?;
+        // Steps only build the lazy query, apart from reads resolving their
+        // sources and pivots collecting their input; the rest of the work
+        // runs in `collect`.
+        let _span = tracing::info_span!("plan_step", index = i, op = step.op()).entered();
         match step {
-            QueryPlan::ReadParquet(path) => {
-                lf = Some(LazyFrame::scan_parquet(&path, Default::default())?);
+            QueryPlan::ReadParquet(_) | QueryPlan::ReadCsv { .. } | QueryPlan::ReadTable { .. } => {
+                lf = Some(read_source(&step, &filters[i], ctx)?);
+            }
+            QueryPlan::Join { source, on, how } => {
+                // Filters after the join may be on joined columns, so the
+                // joined source is not pruned.
+                let right = read_source(&source, &[], ctx)?;
+                if let Some(lf_val) = lf.take() {
+                    let how = match how {
+                        JoinKind::Inner => JoinType::Inner,
+                        JoinKind::Left => JoinType::Left,
+                        JoinKind::Outer => JoinType::Outer,
+                    };
+                    lf = Some(lf_val.join(right, [col(&on)], [col(&on)], JoinArgs::new(how)));
+                }
             }
             QueryPlan::Filter(expr) => {
                 if let Some(lf_val) = lf.take() {
-                    lf = Some(lf_val.filter(parse_filter(&expr)?));
+                    let expr = match lf_val.schema() {
+                        Ok(schema) => typed_literals(&expr, &schema),
+                        Err(_) => expr,
+                    };
+                    lf = Some(lf_val.filter(lower(&expr)));
                 }
             }
-            QueryPlan::Select(cols) => {
+            QueryPlan::DropNulls(subset) => {
                 if let Some(lf_val) = lf.take() {
-                    let exprs: Vec<Expr> = cols.iter().map(|c| col(c)).collect();
+                    let subset = subset.map(|cols| cols.iter().map(|c| col(c)).collect());
+                    lf = Some(lf_val.drop_nulls(subset));
+                }
+            }
+            QueryPlan::FillNull(fill) => {
+                if let Some(lf_val) = lf.take() {
+                    lf = Some(lf_val.with_columns([fill_nulls(&fill)]));
+                }
+            }
+            QueryPlan::WithColumns(exprs) => {
+                if let Some(lf_val) = lf.take() {
+                    let exprs: Vec<Expr> = exprs.iter().map(lower).collect();
+                    lf = Some(lf_val.with_columns(exprs));
+                }
+            }
+            QueryPlan::Select(exprs) => {
+                if let Some(lf_val) = lf.take() {
+                    let exprs: Vec<Expr> = exprs.iter().map(lower).collect();
                     lf = Some(lf_val.select(exprs));
                 }
             }
-            QueryPlan::GroupBy(colname) => {
-                group_by = Some(colname);
+            QueryPlan::GroupBy(keys) => {
+                group_by = Some(keys);
             }
-            QueryPlan::Agg(expr) => {
-                aggs.push(parse_agg(&expr)?);
+            QueryPlan::Agg(exprs) => {
+                aggs.extend(exprs.iter().map(lower));
             }
+            // Sorts and slices after a groupby apply to the aggregated frame.
             QueryPlan::Sort(colname) => {
-                if let Some(lf_val) = lf.take() {
-                    lf = Some(lf_val.sort(&colname, Default::default()));
+                lf = aggregate(lf, group_by.take(), &mut aggs)
+                    .map(|lf| lf.sort(&colname, Default::default()));
+            }
+            QueryPlan::Head(n) => {
+                lf = aggregate(lf, group_by.take(), &mut aggs).map(|lf| lf.limit(idx(n)));
+            }
+            QueryPlan::Tail(n) => {
+                lf = aggregate(lf, group_by.take(), &mut aggs).map(|lf| lf.tail(idx(n)));
+            }
+            QueryPlan::Slice { offset, length } => {
+                let length = length.map_or(IdxSize::MAX, idx);
+                lf = aggregate(lf, group_by.take(), &mut aggs).map(|lf| lf.slice(offset, length));
+            }
+            // As does a unique, which dedups aggregated rows after a groupby.
+            QueryPlan::Unique { subset, keep } => {
+                let keep = match keep {
+                    UniqueKeep::Any => UniqueKeepStrategy::Any,
+                    UniqueKeep::First => UniqueKeepStrategy::First,
+                    UniqueKeep::Last => UniqueKeepStrategy::Last,
+                    UniqueKeep::None => UniqueKeepStrategy::None,
+                };
+                lf = aggregate(lf, group_by.take(), &mut aggs)
+                    .map(|lf| lf.unique_stable(subset, keep));
+            }
+            // Pivots and melts reshape aggregated rows after a groupby too.
+            QueryPlan::Pivot {
+                values,
+                index,
+                columns,
+                aggregate: agg,
+                sort_columns,
+            } => {
+                if let Some(lf_val) = aggregate(lf.take(), group_by.take(), &mut aggs) {
+                    ctx.check_cancelled()?;
+                    let df = lf_val.with_streaming(ctx.streaming).collect()?;
+                    let values = if values.is_empty() {
+                        df.get_column_names()
+                            .into_iter()
+                            .filter(|c| !index.iter().chain(&columns).any(|k| k == c))
+                            .map(str::to_string)
+                            .collect()
+                    } else {
+                        values
+                    };
+                    let pivoted = polars::lazy::frame::pivot::pivot(
+                        &df,
+                        values,
+                        index,
+                        columns,
+                        sort_columns,
+                        Some(pivot_aggregate(agg)),
+                        None,
+                    )?;
+                    lf = Some(pivoted.lazy());
                 }
             }
+            QueryPlan::Melt {
+                id_vars,
+                value_vars,
+                variable_name,
+                value_name,
+            } => {
+                let args = MeltArgs {
+                    id_vars: id_vars.iter().map(|c| c.as_str().into()).collect(),
+                    value_vars: value_vars.iter().map(|c| c.as_str().into()).collect(),
+                    variable_name: variable_name.map(|n| n.as_str().into()),
+                    value_name: value_name.map(|n| n.as_str().into()),
+                    ..Default::default()
+                };
+                lf = aggregate(lf, group_by.take(), &mut aggs).map(|lf| lf.melt(args));
+            }
         }
     }
 
-    if let Some(gb) = group_by {
-        if let Some(lf_val) = lf.take() {
-            lf = Some(lf_val.group_by([col(&gb)]).agg(aggs));
+    aggregate(lf, group_by, &mut aggs).ok_or_else(|| compute_error("query does not read any data"))
+}
+
+/// Group `lf` by `keys`, when there are any, computing the pending `aggs`.
+fn aggregate(
+    lf: Option<LazyFrame>,
+    keys: Option<Vec<String>>,
+    aggs: &mut Vec<Expr>,
+) -> Option<LazyFrame> {
+    match (lf, keys) {
+        (Some(lf), Some(keys)) => {
+            let keys: Vec<Expr> = keys.iter().map(|k| col(k)).collect();
+            Some(lf.group_by(keys).agg(std::mem::take(aggs)))
         }
+        (lf, _) => lf,
+    }
+}
+
+/// The expression `pivot` aggregates the values of each cell with, applied
+/// to them as the unnamed column Polars gives them.
+fn pivot_aggregate(agg: PivotAggregate) -> Expr {
+    let values = col("");
+    match agg {
+        PivotAggregate::First => values.first(),
+        PivotAggregate::Last => values.last(),
+        PivotAggregate::Sum => values.sum(),
+        PivotAggregate::Min => values.min(),
+        PivotAggregate::Max => values.max(),
+        PivotAggregate::Mean => values.mean(),
+        PivotAggregate::Median => values.median(),
+        PivotAggregate::Count => values.count(),
     }
+}
 
-    lf.expect("no dataframe built").collect()
+/// The columns `fill` applies to with their nulls replaced. As in Polars, a
+/// value only fills columns of its type.
+fn fill_nulls(fill: &FillNull) -> Expr {
+    let numeric = || {
+        dtype_cols([
+            DataType::Int32,
+            DataType::Int64,
+            DataType::UInt32,
+            DataType::UInt64,
+            DataType::Float32,
+            DataType::Float64,
+        ])
+    };
+    match fill {
+        FillNull::Value(value) => {
+            let columns = match value {
+                Literal::Int(_) => numeric(),
+                Literal::Float(_) => dtype_cols([DataType::Float32, DataType::Float64]),
+                Literal::Str(_) => dtype_col(&DataType::Utf8),
+                Literal::Bool(_) => dtype_col(&DataType::Boolean),
+                Literal::Date(_) => dtype_col(&DataType::Date),
+                Literal::Datetime(_) => dtype_col(&datetime()),
+                Literal::Null => all(),
+            };
+            columns.fill_null(lower(&ast::Expr::Literal(value.clone())))
+        }
+        FillNull::Strategy(strategy) => match strategy {
+            FillStrategy::Forward => all().forward_fill(None),
+            FillStrategy::Backward => all().backward_fill(None),
+            FillStrategy::Min => all().fill_null(all().min()),
+            FillStrategy::Max => all().fill_null(all().max()),
+            FillStrategy::Mean => all().fill_null(all().mean()),
+            FillStrategy::Zero => numeric().fill_null(lit(0)),
+            FillStrategy::One => numeric().fill_null(lit(1)),
+        },
+    }
 }
 
-static FILTER_RE: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r#"pl\.col\("(?P<col>[^"]+)"\)\s*(?P<op>>=|<=|==|!=|>|<)\s*(?P<val>.+)"#).unwrap()
-});
+/// A row count as Polars' index type, saturating.
+fn idx(n: u64) -> IdxSize {
+    IdxSize::try_from(n).unwrap_or(IdxSize::MAX)
+}
 
-fn parse_filter(expr: &str) -> PolarsResult<Expr> {
-    if let Some(c) = FILTER_RE.captures(expr) {
-        let col_name = c.name("col").unwrap().as_str();
-        let op = c.name("op").unwrap().as_str();
-        let val_str = c.name("val").unwrap().as_str().trim().trim_matches('"');
-        let val_expr = if let Ok(v) = val_str.parse::<i64>() {
-            lit(v)
-        } else if let Ok(v) = val_str.parse::<f64>() {
-            lit(v)
+/// Lazily read the source of a `ReadParquet`, `ReadCsv` or `ReadTable`
+/// step, pruning partitions on `filters`, the comparisons applied to it.
+fn read_source(
+    step: &QueryPlan,
+    filters: &[(String, String, String)],
+    ctx: &ExecContext,
+) -> PolarsResult<LazyFrame> {
+    ctx.check_sources(std::slice::from_ref(step))
+        .map_err(|e| kinded_error("access_denied", e))?;
+    match step {
+        QueryPlan::ReadParquet(paths) => {
+            if let Some(chaos) = &ctx.chaos {
+                for path in paths {
+                    chaos
+                        .before_scan(&ctx.resolve_path(path))
+                        .map_err(compute_error)?;
+                }
+            }
+            scan_parquet(paths, ctx)
+        }
+        QueryPlan::ReadCsv { path, options } => {
+            if cloud::is_url(path) {
+                return Err(compute_error(format!(
+                    "reading CSV from object stores is not supported: {}",
+                    path
+                )));
+            }
+            let path = ctx.resolve_path(path);
+            if let Some(chaos) = &ctx.chaos {
+                chaos.before_scan(&path).map_err(compute_error)?;
+            }
+            LazyCsvReader::new(&path)
+                .with_separator(options.delimiter)
+                .has_header(options.has_header)
+                .with_infer_schema_length(options.infer_schema_length)
+                .finish()
+        }
+        QueryPlan::ReadTable {
+            name,
+            version,
+            as_of,
+        } => {
+            let catalog = ctx
+                .catalog
+                .as_ref()
+                .ok_or_else(|| compute_error("no dataset catalog configured"))?;
+            let dataset = catalog.get(name).ok_or_else(|| {
+                kinded_error("unknown_dataset", format!("unknown dataset {}", name))
+            })?;
+            let selector = version_selector(*version, as_of.as_deref()).map_err(compute_error)?;
+            let (format, version) = catalog.resolve(name, selector).map_err(compute_error)?;
+            let masked = match (&ctx.user, &ctx.access) {
+                (Some(user), Some(access)) => access.masked_columns(&dataset, user),
+                _ => Vec::new(),
+            };
+            let mut files = version.files.clone();
+            // Pruning on a masked partition column would reveal its values.
+            let key = dataset
+                .partition_by
+                .filter(|key| !masked.iter().any(|(c, _)| c == key));
+            if let Some(key) = key {
+                let on_key: Vec<(String, String)> = filters
+                    .iter()
+                    .filter(|(c, _, _)| *c == key)
+                    .map(|(_, op, val)| (op.clone(), val.clone()))
+                    .collect();
+                files = partition::prune(files, &key, &on_key);
+                // Keep one file so an empty result still has the schema;
+                // the filter itself removes its rows.
+                if files.is_empty() {
+                    files = version.files.iter().take(1).cloned().collect();
+                }
+            }
+            if let Some(chaos) = &ctx.chaos {
+                chaos.before_scan(name).map_err(compute_error)?;
+            }
+            let scanned = catalog::scan_files(&files, format, version.schema_mode)?.0;
+            let salt = ctx.access.as_ref().map_or("", |a| a.mask_salt());
+            masking::apply(scanned, &masked, salt)
+        }
+        _ => Err(compute_error("not a read step")),
+    }
+}
+
+/// Scan parquet files, directories or globs. A single file or glob is left to
+/// Polars unless the glob is read in relaxed mode; otherwise the paths are
+/// expanded and their files scanned together, in relaxed mode aligned to a
+/// common schema and logging any coercions.
+///
+/// Object store URLs, which cannot be expanded, are scanned one by one by
+/// Polars and combined with the other paths.
+fn scan_parquet(paths: &[String], ctx: &ExecContext) -> PolarsResult<LazyFrame> {
+    if paths.iter().any(|p| cloud::is_url(p)) {
+        let frames = paths
+            .iter()
+            .map(|path| {
+                let lf = if cloud::is_url(path) {
+                    cloud::scan_parquet(path, &ctx.cloud)?
+                } else {
+                    scan_parquet(std::slice::from_ref(path), ctx)?
+                };
+                Ok((path.clone(), lf))
+            })
+            .collect::<PolarsResult<Vec<_>>>()?;
+        return Ok(schema::combine(frames, ctx.schema_mode)?.0);
+    }
+    if let [path] = paths {
+        let path = ctx.resolve_path(path);
+        let glob = path.contains(['*', '?', '[']);
+        if !Path::new(&path).is_dir() && (ctx.schema_mode == SchemaMode::Strict || !glob) {
+            return LazyFrame::scan_parquet(path, Default::default());
+        }
+    }
+    let files = ctx.parquet_files(paths).map_err(compute_error)?;
+    let (lf, coercions) = catalog::scan_files(&files, DatasetFormat::Parquet, ctx.schema_mode)?;
+    for c in coercions {
+        tracing::info!(
+            file = %c.path,
+            added = ?c.added,
+            cast = ?c.cast,
+            "coerced file to common schema"
+        );
+    }
+    Ok(lf)
+}
+
+/// Version a `read_table` step pinned to `version` or `as_of` reads.
+pub(crate) fn version_selector(
+    version: Option<u64>,
+    as_of: Option<&str>,
+) -> Result<VersionSelector, String> {
+    Ok(match (version, as_of) {
+        (Some(v), _) => VersionSelector::Version(v),
+        (None, Some(ts)) => VersionSelector::AsOf(catalog::parse_timestamp(ts)?),
+        (None, None) => VersionSelector::Latest,
+    })
+}
+
+/// `(column, op, value)` of the filters applied to the frame read at
+/// `steps[index]`, i.e. those before the next read, join, slice, unique,
+/// fill_null or with_columns. Filters after a slice or unique only see the
+/// rows it kept, and after a fill_null or with_columns see values the read
+/// does not have, so they cannot prune the read.
+pub(crate) fn following_filters(
+    steps: &[QueryPlan],
+    index: usize,
+) -> Vec<(String, String, String)> {
+    steps[index + 1..]
+        .iter()
+        .take_while(|s| {
+            s.source().is_none()
+                && s.slice_bounds().is_none()
+                && !matches!(
+                    s,
+                    QueryPlan::Unique { .. }
+                        | QueryPlan::FillNull(_)
+                        | QueryPlan::WithColumns(_)
+                        | QueryPlan::Pivot { .. }
+                        | QueryPlan::Melt { .. }
+                )
+                && !s.computes_columns()
+        })
+        .filter_map(|s| match s {
+            QueryPlan::Filter(expr) => Some(expr.conjuncts()),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|expr| expr.comparison())
+        .map(|(col, op, val)| (col, op, val.trim_matches('"').to_string()))
+        .collect()
+}
+
+fn compute_error(msg: impl Into<String>) -> PolarsError {
+    PolarsError::ComputeError(msg.into().into())
+}
+
+/// The Polars expression for a parsed query expression.
+fn lower(expr: &ast::Expr) -> Expr {
+    match expr {
+        ast::Expr::Column(name) => col(name),
+        ast::Expr::Literal(value) => match value {
+            Literal::Int(v) => lit(*v),
+            Literal::Float(v) => lit(*v),
+            Literal::Str(s) => lit(s.as_str()),
+            Literal::Bool(b) => lit(*b),
+            Literal::Date(date) => lit(epoch_days(date)).cast(DataType::Date),
+            Literal::Datetime(dt) => lit(dt.and_utc().timestamp_micros()).cast(datetime()),
+            Literal::Null => Expr::Literal(LiteralValue::Null),
+        },
+        ast::Expr::Binary { left, op, right } => {
+            let (left, right) = (lower(left), lower(right));
+            match op {
+                BinaryOp::Eq => left.eq(right),
+                BinaryOp::NotEq => left.neq(right),
+                BinaryOp::Lt => left.lt(right),
+                BinaryOp::LtEq => left.lt_eq(right),
+                BinaryOp::Gt => left.gt(right),
+                BinaryOp::GtEq => left.gt_eq(right),
+                BinaryOp::And => left.and(right),
+                BinaryOp::Or => left.or(right),
+                BinaryOp::Add => left + right,
+                BinaryOp::Sub => left - right,
+                BinaryOp::Mul => left * right,
+                BinaryOp::Div => left / right,
+            }
+        }
+        ast::Expr::Not(expr) => lower(expr).not(),
+        ast::Expr::Agg { func, expr } => {
+            let expr = lower(expr);
+            match func {
+                AggFunc::Sum => expr.sum(),
+                AggFunc::Mean => expr.mean(),
+                AggFunc::Median => expr.median(),
+                AggFunc::Min => expr.min(),
+                AggFunc::Max => expr.max(),
+                AggFunc::Count => expr.count(),
+                AggFunc::NUnique => expr.n_unique(),
+                AggFunc::First => expr.first(),
+                AggFunc::Last => expr.last(),
+                AggFunc::Std(ddof) => expr.std(*ddof),
+                AggFunc::Var(ddof) => expr.var(*ddof),
+                AggFunc::Quantile(q) => expr.quantile(lit(*q), QuantileInterpolOptions::Nearest),
+            }
+        }
+        ast::Expr::Alias { expr, name } => lower(expr).alias(name),
+        ast::Expr::IsIn { expr, values } => lower(expr).is_in(lit(literal_series(values))),
+        ast::Expr::IsNull {
+            expr,
+            negated: false,
+        } => lower(expr).is_null(),
+        ast::Expr::IsNull {
+            expr,
+            negated: true,
+        } => lower(expr).is_not_null(),
+        ast::Expr::Str { expr, op, pattern } => {
+            let strs = lower(expr).str();
+            match op {
+                StrOp::Contains => strs.contains(lit(pattern.as_str()), true),
+                StrOp::ContainsLiteral => strs.contains_literal(lit(pattern.as_str())),
+                StrOp::StartsWith => strs.starts_with(lit(pattern.as_str())),
+                StrOp::EndsWith => strs.ends_with(lit(pattern.as_str())),
+            }
+        }
+        ast::Expr::Between { expr, low, high } => {
+            let expr = lower(expr);
+            expr.clone().gt_eq(lower(low)).and(expr.lt_eq(lower(high)))
+        }
+        ast::Expr::Cast {
+            expr,
+            dtype,
+            strict,
+        } => cast(lower(expr), *dtype, *strict),
+        ast::Expr::CumSum(expr) => lower(expr).cumsum(false),
+        ast::Expr::Rank {
+            expr,
+            method,
+            descending,
+        } => {
+            let method = match method {
+                ast::RankMethod::Average => RankMethod::Average,
+                ast::RankMethod::Min => RankMethod::Min,
+                ast::RankMethod::Max => RankMethod::Max,
+                ast::RankMethod::Dense => RankMethod::Dense,
+                ast::RankMethod::Ordinal => RankMethod::Ordinal,
+            };
+            let options = RankOptions {
+                method,
+                descending: *descending,
+            };
+            lower(expr).rank(options, None)
+        }
+        ast::Expr::Over { expr, partition_by } => {
+            let partition_by: Vec<Expr> = partition_by.iter().map(|c| col(c)).collect();
+            lower(expr).over(partition_by)
+        }
+    }
+}
+
+/// The Polars type named by `dtype`.
+fn data_type(dtype: DType) -> DataType {
+    match dtype {
+        DType::Int32 => DataType::Int32,
+        DType::Int64 => DataType::Int64,
+        DType::UInt32 => DataType::UInt32,
+        DType::UInt64 => DataType::UInt64,
+        DType::Float32 => DataType::Float32,
+        DType::Float64 => DataType::Float64,
+        DType::Utf8 => DataType::Utf8,
+        DType::Boolean => DataType::Boolean,
+        DType::Date => DataType::Date,
+        DType::Datetime => datetime(),
+    }
+}
+
+/// `expr` cast to `dtype`. Polars casts text to dates and datetimes through
+/// their integer representation, so text is parsed as ISO 8601 instead.
+fn cast(expr: Expr, dtype: DType, strict: bool) -> Expr {
+    let target = data_type(dtype);
+    if !matches!(dtype, DType::Date | DType::Datetime) {
+        return if strict {
+            expr.strict_cast(target)
         } else {
-            lit(val_str)
+            expr.cast(target)
         };
-        let column = col(col_name);
-        let out = match op {
-            ">" => column.gt(val_expr),
-            "<" => column.lt(val_expr),
-            ">=" => column.gt_eq(val_expr),
-            "<=" => column.lt_eq(val_expr),
-            "==" => column.eq(val_expr),
-            "!=" => column.neq(val_expr),
-            _ => unreachable!(),
-        };
-        Ok(out)
-    } else {
-        Err(PolarsError::ComputeError("unsupported filter".into()))
     }
+    let output = GetOutput::from_type(target.clone());
+    expr.map(
+        move |s| {
+            let out = if s.dtype() == &DataType::Utf8 {
+                parse_temporal(&s, &target, strict)
+            } else if strict {
+                s.strict_cast(&target)
+            } else {
+                s.cast(&target)
+            };
+            out.map(Some)
+        },
+        output,
+    )
+}
+
+/// The text column `s` parsed as `dtype`; with `strict`, a value that is not
+/// an ISO 8601 date or datetime fails the query rather than becoming null.
+fn parse_temporal(s: &Series, dtype: &DataType, strict: bool) -> PolarsResult<Series> {
+    let micros: Int64Chunked = s
+        .utf8()?
+        .into_iter()
+        .map(|value| {
+            let Some(value) = value else {
+                return Ok(None);
+            };
+            match Literal::parse_temporal(value) {
+                Some(Literal::Date(date)) => Ok(date.and_hms_opt(0, 0, 0)),
+                Some(Literal::Datetime(dt)) => Ok(Some(dt)),
+                _ if strict => Err(compute_error(format!(
+                    "cannot cast {:?} in column {} to {}",
+                    value,
+                    s.name(),
+                    dtype
+                ))),
+                _ => Ok(None),
+            }
+            .map(|dt| dt.map(|dt| dt.and_utc().timestamp_micros()))
+        })
+        .collect::<PolarsResult<_>>()?;
+    let mut out = micros.into_series().cast(&datetime())?.cast(dtype)?;
+    out.rename(s.name());
+    Ok(out)
 }
 
-static AGG_RE: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r#"pl\.col\("(?P<col>[^"]+)"\)\.(?P<func>\w+)\(\)"#).unwrap());
+/// The type of `pl.datetime` literals and casts.
+fn datetime() -> DataType {
+    DataType::Datetime(TimeUnit::Microseconds, None)
+}
 
-fn parse_agg(expr: &str) -> PolarsResult<Expr> {
-    if let Some(c) = AGG_RE.captures(expr) {
-        let col_name = c.name("col").unwrap().as_str();
-        let func = c.name("func").unwrap().as_str();
-        let column = col(col_name);
-        let out = match func {
-            "sum" => column.sum(),
-            "mean" => column.mean(),
-            "min" => column.min(),
-            "max" => column.max(),
-            "count" => column.count(),
-            _ => return Err(PolarsError::ComputeError("unsupported agg".into())),
-        };
-        Ok(out)
-    } else {
-        Err(PolarsError::ComputeError("unsupported agg".into()))
+fn epoch_days(date: &NaiveDate) -> i32 {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    (*date - epoch).num_days() as i32
+}
+
+/// `expr` with the ISO 8601 strings it compares with date or datetime
+/// values, such as `pl.col("ts") >= "2024-01-01"` on a datetime column of
+/// `schema`, replaced by date or datetime literals, so they are compared as
+/// points in time rather than as text.
+fn typed_literals(expr: &ast::Expr, schema: &Schema) -> ast::Expr {
+    let temporal = |expr: &ast::Expr| match expr {
+        ast::Expr::Column(name) => {
+            matches!(
+                schema.get(name),
+                Some(DataType::Date | DataType::Datetime(..))
+            )
+        }
+        ast::Expr::Cast { dtype, .. } => matches!(dtype, DType::Date | DType::Datetime),
+        _ => false,
+    };
+    let typed = |expr: &ast::Expr| match expr {
+        ast::Expr::Literal(Literal::Str(s)) => Literal::parse_temporal(s)
+            .map(ast::Expr::Literal)
+            .unwrap_or_else(|| expr.clone()),
+        other => typed_literals(other, schema),
+    };
+    let boxed = |expr: &ast::Expr| Box::new(typed(expr));
+    match expr {
+        ast::Expr::Binary { left, op, right } if op.precedence() == 1 => {
+            let (left, right) = if temporal(left) {
+                (left.clone(), boxed(right))
+            } else if temporal(right) {
+                (boxed(left), right.clone())
+            } else {
+                (
+                    Box::new(typed_literals(left, schema)),
+                    Box::new(typed_literals(right, schema)),
+                )
+            };
+            ast::Expr::Binary {
+                left,
+                op: *op,
+                right,
+            }
+        }
+        ast::Expr::Binary { left, op, right } => ast::Expr::Binary {
+            left: Box::new(typed_literals(left, schema)),
+            op: *op,
+            right: Box::new(typed_literals(right, schema)),
+        },
+        ast::Expr::Not(inner) => ast::Expr::Not(Box::new(typed_literals(inner, schema))),
+        ast::Expr::Between { expr, low, high } if temporal(expr) => ast::Expr::Between {
+            expr: expr.clone(),
+            low: boxed(low),
+            high: boxed(high),
+        },
+        ast::Expr::IsIn { expr, values } if temporal(expr) => {
+            let parsed: Option<Vec<Literal>> = values
+                .iter()
+                .map(|v| match v {
+                    Literal::Str(s) => Literal::parse_temporal(s),
+                    other => Some(other.clone()),
+                })
+                .collect();
+            ast::Expr::IsIn {
+                expr: expr.clone(),
+                values: parsed.unwrap_or_else(|| values.clone()),
+            }
+        }
+        other => other.clone(),
+    }
+}
+
+/// `values`, all of one type as the parser checks, as a series.
+fn literal_series(values: &[Literal]) -> Series {
+    match values.first() {
+        Some(Literal::Str(_)) => {
+            let strs: Vec<&str> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Literal::Str(s) => Some(s.as_str()),
+                    _ => None,
+                })
+                .collect();
+            Series::new("", strs)
+        }
+        Some(Literal::Date(_)) => {
+            let days: Vec<i32> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Literal::Date(date) => Some(epoch_days(date)),
+                    _ => None,
+                })
+                .collect();
+            Series::new("", days).cast(&DataType::Date).unwrap()
+        }
+        Some(Literal::Datetime(_)) => {
+            let micros: Vec<i64> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Literal::Datetime(dt) => Some(dt.and_utc().timestamp_micros()),
+                    _ => None,
+                })
+                .collect();
+            Series::new("", micros).cast(&datetime()).unwrap()
+        }
+        Some(Literal::Bool(_)) => {
+            let bools: Vec<bool> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Literal::Bool(b) => Some(*b),
+                    _ => None,
+                })
+                .collect();
+            Series::new("", bools)
+        }
+        _ if values.iter().all(|v| matches!(v, Literal::Int(_))) => {
+            let ints: Vec<i64> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Literal::Int(i) => Some(*i),
+                    _ => None,
+                })
+                .collect();
+            Series::new("", ints)
+        }
+        _ => {
+            let floats: Vec<f64> = values
+                .iter()
+                .filter_map(|v| match v {
+                    Literal::Int(i) => Some(*i as f64),
+                    Literal::Float(f) => Some(*f),
+                    _ => None,
+                })
+                .collect();
+            Series::new("", floats)
+        }
     }
 }
 
@@ -115,6 +1015,20 @@ mod tests {
     use std::fs::File;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn transient_io_errors_are_told_from_query_errors() {
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(is_transient(&PolarsError::Io(reset)));
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_transient(&PolarsError::Io(missing)));
+        assert!(is_transient(&compute_error(
+            "Generic S3 error: response error \"503 Service Unavailable\""
+        )));
+        assert!(is_transient_message("Stale file handle (os error 116)"));
+        assert!(!is_transient(&compute_error("column age not found")));
+        assert!(!is_transient(&PolarsError::ColumnNotFound("age".into())));
+    }
+
     #[test]
     fn execute_basic_plan() {
         let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
@@ -129,4 +1043,554 @@ mod tests {
         let out = execute_plan(&q).unwrap();
         assert_eq!(out.height(), 1);
     }
+
+    #[test]
+    fn sources_outside_the_sandbox_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        std::fs::write(data.join("a.csv"), "x\n1\n").unwrap();
+        std::fs::write(dir.path().join("secret.csv"), "x\n2\n").unwrap();
+        let ctx = ExecContext {
+            data_dir: Some(data.clone()),
+            sandbox: Arc::new(Sandbox::new(&[data])),
+            ..Default::default()
+        };
+        let out = execute_plan_with("df = pl.read_csv(\"a.csv\")", &ctx).unwrap();
+        assert_eq!(out.height(), 1);
+
+        let q = "df = pl.read_csv(\"../secret.csv\")";
+        let err = execute_plan_with(q, &ctx).unwrap_err().to_string();
+        assert!(err.contains("access denied: ../secret.csv"), "{}", err);
+        let plan = parse_query(q).unwrap();
+        assert!(ctx
+            .check_sources(&plan)
+            .unwrap_err()
+            .starts_with("access denied"));
+
+        // A dataset registered before the sandbox was configured.
+        let mut df = df!["x" => [2]].unwrap();
+        let secret = dir.path().join("secret.parquet");
+        ParquetWriter::new(std::fs::File::create(&secret).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let path = dir.path().join("catalog.json");
+        let spec = catalog::DatasetSpec {
+            name: "secret".into(),
+            location: secret.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        Catalog::open(&path)
+            .unwrap()
+            .register(spec, "alice")
+            .unwrap();
+        let catalog = Catalog::open(&path)
+            .unwrap()
+            .with_sandbox((*ctx.sandbox).clone());
+        let ctx = ExecContext {
+            catalog: Some(Arc::new(catalog)),
+            ..ctx
+        };
+        let err = execute_plan_with("df = pl.read_table(\"secret\")", &ctx)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("access denied"), "{}", err);
+    }
+
+    #[test]
+    fn streaming_matches_in_memory_execution() {
+        let mut df = df![
+            "city" => ["NY", "LA", "NY", "SF", "LA"],
+            "amount" => [1i64, 2, 3, 4, 5],
+        ]
+        .unwrap();
+        let file = NamedTempFile::new().unwrap();
+        ParquetWriter::new(File::create(file.path()).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let q = format!(
+            "df = pl.read_parquet(\"{}\")\ndf = df.filter(pl.col(\"amount\") > 1).groupby(\"city\").agg(pl.col(\"amount\").sum()).sort(\"city\")",
+            file.path().to_str().unwrap()
+        );
+        let in_memory = execute_plan_with(&q, &ExecContext::default()).unwrap();
+        let ctx = ExecContext {
+            streaming: true,
+            ..Default::default()
+        };
+        let streamed = execute_plan_with(&q, &ctx).unwrap();
+        assert!(streamed.frame_equal(&in_memory));
+        assert_eq!(streamed.height(), 3);
+    }
+
+    #[test]
+    fn execute_compound_expressions() {
+        let df = df![
+            "name" => ["a, b", "c", "d"],
+            "age" => [20, 40, 60],
+            "city" => ["NY", "NY", "LA"]
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let q = "df = df.filter((pl.col(\"name\") == \"a, b\") | (pl.col(\"age\") * 2 > 100))";
+        assert_eq!(execute_plan_on(df.clone(), q, &ctx).unwrap().height(), 2);
+
+        let q = "df = df.filter(~(pl.col(\"city\") == \"LA\")).groupby(\"city\").agg(pl.col(\"age\").sum().alias(\"total\"))";
+        let out = execute_plan_on(df, q, &ctx).unwrap();
+        assert_eq!(out.height(), 1);
+        let total = out.column("total").unwrap().cast(&DataType::Int64).unwrap();
+        assert_eq!(total.i64().unwrap().get(0), Some(60));
+    }
+
+    #[test]
+    fn execute_filter_predicates() {
+        let df = df![
+            "city" => [Some("NY"), Some("LA"), None, Some("SF")],
+            "age" => [Some(20i64), None, Some(40), Some(60)],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let height = |q: &str| execute_plan_on(df.clone(), q, &ctx).unwrap().height();
+        assert_eq!(
+            height("df = df.filter(pl.col(\"city\").is_in([\"NY\", \"LA\"]))"),
+            2
+        );
+        assert_eq!(height("df = df.filter(pl.col(\"age\").is_in([20, 60]))"), 2);
+        assert_eq!(height("df = df.filter(pl.col(\"city\").is_null())"), 1);
+        assert_eq!(height("df = df.filter(pl.col(\"age\").is_not_null())"), 3);
+        assert_eq!(
+            height("df = df.filter(pl.col(\"age\").is_between(20, 40))"),
+            2
+        );
+        assert_eq!(
+            height("df = df.filter(~pl.col(\"city\").is_in([\"SF\"]) & pl.col(\"age\").is_between(10, 30))"),
+            1
+        );
+    }
+
+    #[test]
+    fn execute_casts() {
+        let df = df![
+            "x" => ["1.5", "2.5", "oops"],
+            "n" => [1i64, 2, 3],
+            "day" => ["2024-01-01", "2024-02-01", "2024-03-01"],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let q = "df = df.with_columns(pl.col(\"n\").cast(pl.Utf8), pl.col(\"day\").cast(pl.Date))";
+        let out = execute_plan_on(df.clone(), q, &ctx).unwrap();
+        assert_eq!(out.column("n").unwrap().dtype(), &DataType::Utf8);
+        assert_eq!(out.column("day").unwrap().dtype(), &DataType::Date);
+
+        let q = "df = df.filter(pl.col(\"x\").cast(pl.Float64, strict=False) > 2)";
+        assert_eq!(execute_plan_on(df.clone(), q, &ctx).unwrap().height(), 1);
+        // "oops" is not a number, so a strict cast fails the query.
+        let q = "df = df.filter(pl.col(\"x\").cast(pl.Float64) > 2)";
+        assert!(execute_plan_on(df, q, &ctx).is_err());
+    }
+
+    #[test]
+    fn execute_pivot_and_melt() {
+        let df = df![
+            "id" => [1i64, 1, 2, 2, 2],
+            "k" => ["a", "b", "a", "b", "b"],
+            "v" => [10i64, 20, 30, 40, 50],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let q = "df = df.pivot(values=\"v\", index=\"id\", columns=\"k\", aggregate_function=\"sum\")\ndf = df.filter(pl.col(\"b\") > 30)";
+        let out = execute_plan_on(df.clone(), q, &ctx).unwrap();
+        assert_eq!(out.get_column_names(), ["id", "a", "b"]);
+        assert_eq!(out.height(), 1);
+        assert_eq!(out.column("b").unwrap().i64().unwrap().get(0), Some(90));
+
+        // Melting the pivot gives back a row per id and key.
+        let q = "df = df.pivot(values=\"v\", index=\"id\", columns=\"k\")\ndf = df.melt(id_vars=\"id\", variable_name=\"k\", value_name=\"v\")\ndf = df.sort(\"v\")";
+        let out = execute_plan_on(df.clone(), q, &ctx).unwrap();
+        assert_eq!(out.get_column_names(), ["id", "k", "v"]);
+        let values: Vec<_> = out
+            .column("v")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(values, [Some(10), Some(20), Some(30), Some(40)]);
+
+        // After a groupby the aggregated rows are pivoted.
+        let q = "df = df.groupby(\"id\", \"k\").agg(pl.col(\"v\").count().alias(\"n\"))\ndf = df.pivot(values=\"n\", index=\"id\", columns=\"k\", sort_columns=True)\ndf = df.sort(\"id\")";
+        let out = execute_plan_on(df, q, &ctx).unwrap();
+        assert_eq!(out.get_column_names(), ["id", "a", "b"]);
+        let counts = out.column("b").unwrap().cast(&DataType::Int64).unwrap();
+        assert_eq!(counts.i64().unwrap().get(1), Some(2));
+    }
+
+    #[test]
+    fn execute_window_expressions() {
+        let df = df![
+            "city" => ["NY", "LA", "NY", "NY", "LA"],
+            "sales" => [10i64, 5, 20, 10, 7],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let q = "df = df.with_columns(pl.col(\"sales\").sum().over(\"city\").alias(\"city_total\"), pl.col(\"sales\").cum_sum().over(\"city\").alias(\"running\"), pl.col(\"sales\").rank(\"dense\", descending=True).over(\"city\").alias(\"rank\"))";
+        let out = execute_plan_on(df.clone(), q, &ctx).unwrap();
+        let ints = |name: &str| -> Vec<Option<i64>> {
+            let column = out.column(name).unwrap().cast(&DataType::Int64).unwrap();
+            column.i64().unwrap().into_iter().collect()
+        };
+        assert_eq!(
+            ints("city_total"),
+            [Some(40), Some(12), Some(40), Some(40), Some(12)]
+        );
+        assert_eq!(
+            ints("running"),
+            [Some(10), Some(5), Some(30), Some(40), Some(12)]
+        );
+        assert_eq!(ints("rank"), [Some(2), Some(2), Some(1), Some(2), Some(1)]);
+
+        // A select keeps only what it names, computed columns included.
+        let q = "df = df.select(\"city\", (pl.col(\"sales\") * 2).alias(\"double\"))\ndf = df.filter(pl.col(\"double\") > 15)";
+        let out = execute_plan_on(df, q, &ctx).unwrap();
+        assert_eq!(out.get_column_names(), ["city", "double"]);
+        assert_eq!(out.height(), 3);
+    }
+
+    #[test]
+    fn execute_temporal_filters() {
+        let day = |d: u32| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let days = Series::new("day", [day(1), day(15), day(31)]);
+        let ts = Series::new("ts", [day(1), day(15), day(31)])
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, None))
+            .unwrap();
+        let text = Series::new("text", ["2024-01-01", "2024-01-15", "2024-01-31"]);
+        let df = DataFrame::new(vec![days, ts, text]).unwrap();
+        let ctx = ExecContext::default();
+        let height = |q: &str| execute_plan_on(df.clone(), q, &ctx).unwrap().height();
+        assert_eq!(
+            height("df = df.filter(pl.col(\"ts\") >= \"2024-01-15T00:00:00\")"),
+            2
+        );
+        assert_eq!(height("df = df.filter(pl.col(\"ts\") > \"2024-01-15\")"), 1);
+        assert_eq!(
+            height("df = df.filter(pl.col(\"day\") < pl.date(2024, 1, 15))"),
+            1
+        );
+        assert_eq!(
+            height("df = df.filter(pl.col(\"ts\").is_between(pl.datetime(2024, 1, 2), \"2024-01-31\"))"),
+            2
+        );
+        assert_eq!(
+            height("df = df.filter(pl.col(\"day\").is_in([\"2024-01-01\", \"2024-01-31\"]))"),
+            2
+        );
+        assert_eq!(
+            height("df = df.filter(pl.col(\"text\").cast(pl.Date) == \"2024-01-31\")"),
+            1
+        );
+        // Text columns keep string comparisons.
+        assert_eq!(
+            height("df = df.filter(pl.col(\"text\") >= \"2024-01-15\")"),
+            2
+        );
+    }
+
+    #[test]
+    fn execute_string_predicates() {
+        let df = df![
+            "path" => ["/api/users", "/api/v1.2/items", "/health", "/static/app.js"],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let height = |q: &str| execute_plan_on(df.clone(), q, &ctx).unwrap().height();
+        assert_eq!(
+            height("df = df.filter(pl.col(\"path\").str.starts_with(\"/api\"))"),
+            2
+        );
+        assert_eq!(
+            height("df = df.filter(pl.col(\"path\").str.ends_with(\".js\"))"),
+            1
+        );
+        // A pattern is a regular expression unless literal=True.
+        assert_eq!(
+            height("df = df.filter(pl.col(\"path\").str.contains(r\"/v\\d\"))"),
+            1
+        );
+        assert_eq!(
+            height("df = df.filter(pl.col(\"path\").str.contains(\".\"))"),
+            4
+        );
+        assert_eq!(
+            height("df = df.filter(pl.col(\"path\").str.contains(\".\", literal=True))"),
+            2
+        );
+        assert!(execute_plan_on(
+            df.clone(),
+            "df = df.filter(pl.col(\"path\").str.contains(\"(\"))",
+            &ctx
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn execute_multiple_aggregations() {
+        let df = df![
+            "city" => ["NY", "NY", "LA"],
+            "a" => [1, 2, 3],
+            "b" => [1.0, 3.0, 5.0]
+        ]
+        .unwrap();
+        let q = "df = df.filter(pl.col(\"city\") == \"NY\")\ndf = df.groupby(\"city\").agg([pl.col(\"a\").count(), pl.col(\"b\").mean()])";
+        let out = execute_plan_on(df, q, &ExecContext::default()).unwrap();
+        assert_eq!(out.get_column_names(), vec!["city", "a", "b"]);
+        assert_eq!(out.column("b").unwrap().f64().unwrap().get(0), Some(2.0));
+    }
+
+    #[test]
+    fn execute_statistical_aggregations() {
+        let df = df!["g" => ["x"; 5], "b" => [4.0, 1.0, 3.0, 3.0, 10.0]].unwrap();
+        let q = "df = df.groupby(\"g\").agg([pl.col(\"b\").median().alias(\"median\"), \
+                 pl.col(\"b\").quantile(0.95).alias(\"p95\"), \
+                 pl.col(\"b\").var(ddof=0).alias(\"var\"), \
+                 pl.col(\"b\").first().alias(\"first\"), \
+                 pl.col(\"b\").last().alias(\"last\"), \
+                 pl.col(\"b\").n_unique().alias(\"distinct\")])";
+        let out = execute_plan_on(df, q, &ExecContext::default()).unwrap();
+        let value = |c: &str| {
+            let column = out.column(c).unwrap().cast(&DataType::Float64).unwrap();
+            column.f64().unwrap().get(0).unwrap()
+        };
+        assert_eq!(value("median"), 3.0);
+        assert_eq!(value("p95"), 10.0);
+        assert!((value("var") - 9.36).abs() < 1e-9);
+        assert_eq!(value("first"), 4.0);
+        assert_eq!(value("last"), 10.0);
+        assert_eq!(value("distinct"), 4.0);
+    }
+
+    #[test]
+    fn execute_multiple_group_keys() {
+        let df = df![
+            "city" => ["NY", "NY", "NY", "LA"],
+            "year" => [2023, 2024, 2024, 2024],
+            "a" => [1, 2, 3, 4]
+        ]
+        .unwrap();
+        let q =
+            "df = df.groupby([\"city\", \"year\"]).agg(pl.col(\"a\").sum())\ndf = df.sort(\"a\")";
+        let out = execute_plan_on(df, q, &ExecContext::default()).unwrap();
+        assert_eq!(out.get_column_names(), vec!["city", "year", "a"]);
+        let sums: Vec<_> = out
+            .column("a")
+            .unwrap()
+            .i32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(sums, vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn execute_slices() {
+        let df = df![
+            "city" => ["NY", "NY", "LA", "SF"],
+            "n" => [1, 2, 3, 4],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let n = |q: &str| -> Vec<i32> {
+            let out = execute_plan_on(df.clone(), q, &ctx).unwrap();
+            out.column("n")
+                .unwrap()
+                .i32()
+                .unwrap()
+                .into_no_null_iter()
+                .collect()
+        };
+        assert_eq!(n("df = df.head(2)"), vec![1, 2]);
+        assert_eq!(n("df = df.tail(1)"), vec![4]);
+        assert_eq!(n("df = df.slice(-3, 2)"), vec![2, 3]);
+        assert_eq!(n("df = df.slice(1)"), vec![2, 3, 4]);
+        // A slice after a groupby keeps aggregated rows.
+        let q = "df = df.groupby(\"city\").agg(pl.col(\"n\").sum())\ndf = df.head(2)";
+        assert_eq!(execute_plan_on(df.clone(), q, &ctx).unwrap().height(), 2);
+    }
+
+    #[test]
+    fn execute_unique() {
+        let df = df![
+            "id" => [1, 1, 2, 3, 3],
+            "n" => [1, 2, 3, 4, 5],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let n = |q: &str| -> Vec<i32> {
+            let out = execute_plan_on(df.clone(), q, &ctx).unwrap();
+            out.column("n")
+                .unwrap()
+                .i32()
+                .unwrap()
+                .into_no_null_iter()
+                .collect()
+        };
+        assert_eq!(n("df = df.unique()"), vec![1, 2, 3, 4, 5]);
+        assert_eq!(
+            n("df = df.unique(subset=[\"id\"], keep=\"first\")"),
+            vec![1, 3, 4]
+        );
+        assert_eq!(n("df = df.unique(\"id\", keep=\"last\")"), vec![2, 3, 5]);
+        assert_eq!(n("df = df.distinct(\"id\", keep=\"none\")"), vec![3]);
+        let q = "df = df.select(\"id\")\ndf = df.unique()";
+        assert_eq!(execute_plan_on(df.clone(), q, &ctx).unwrap().height(), 3);
+    }
+
+    #[test]
+    fn execute_null_handling() {
+        let df = df![
+            "id" => [Some(1i64), None, Some(3), None],
+            "name" => [Some("a"), Some("b"), None, None],
+        ]
+        .unwrap();
+        let ctx = ExecContext::default();
+        let run = |q: &str| execute_plan_on(df.clone(), q, &ctx).unwrap();
+        assert_eq!(run("df = df.drop_nulls()").height(), 1);
+        assert_eq!(run("df = df.drop_nulls(\"id\")").height(), 2);
+        assert_eq!(run("df = df.drop_nulls(subset=[\"name\"])").height(), 2);
+
+        let ids = |out: DataFrame| -> Vec<Option<i64>> {
+            out.column("id")
+                .unwrap()
+                .i64()
+                .unwrap()
+                .into_iter()
+                .collect()
+        };
+        // A number fills numeric columns only.
+        let out = run("df = df.fill_null(0)");
+        assert_eq!(out.column("name").unwrap().null_count(), 2);
+        assert_eq!(ids(out), vec![Some(1), Some(0), Some(3), Some(0)]);
+        let out = run("df = df.fill_null(\"?\")");
+        assert_eq!(out.column("name").unwrap().null_count(), 0);
+        assert_eq!(out.column("id").unwrap().null_count(), 2);
+        assert_eq!(
+            ids(run("df = df.fill_null(strategy=\"forward\")")),
+            vec![Some(1), Some(1), Some(3), Some(3)]
+        );
+        assert_eq!(
+            ids(run("df = df.fill_null(strategy=\"backward\")")),
+            vec![Some(1), Some(3), Some(3), None]
+        );
+        assert_eq!(
+            ids(run("df = df.fill_null(strategy=\"max\")")),
+            vec![Some(1), Some(3), Some(3), Some(3)]
+        );
+    }
+
+    #[test]
+    fn execute_read_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("people.csv"), "a;20\nb;40\n").unwrap();
+        let ctx = ExecContext {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let q = "df = pl.read_csv(\"people.csv\", delimiter=\";\", has_header=False)\ndf = df.filter(pl.col(\"column_2\") > 30)";
+        let out = execute_plan_with(q, &ctx).unwrap();
+        assert_eq!(out.height(), 1);
+        assert_eq!(out.get_column_names(), vec!["column_1", "column_2"]);
+    }
+
+    #[test]
+    fn execute_parquet_globs_and_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        for (year, ages) in [("2023", [20, 40]), ("2024", [50, 60])] {
+            std::fs::create_dir(dir.path().join(year)).unwrap();
+            let mut df = df!["age" => ages].unwrap();
+            let path = dir.path().join(year).join("part-0.parquet");
+            ParquetWriter::new(File::create(path).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        }
+        let ctx = ExecContext {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let q = "df = pl.read_parquet(\"*/*.parquet\")\ndf = df.filter(pl.col(\"age\") > 30)";
+        assert_eq!(execute_plan_with(q, &ctx).unwrap().height(), 3);
+        let q = "df = pl.read_parquet(\"2024\")";
+        assert_eq!(execute_plan_with(q, &ctx).unwrap().height(), 2);
+        let q = "df = pl.read_parquet([\"2023/*.parquet\", \"2024\"])";
+        assert_eq!(execute_plan_with(q, &ctx).unwrap().height(), 4);
+        let q = "df = pl.read_parquet([\"2023\", \"2025/*.parquet\"])";
+        let err = execute_plan_with(q, &ctx).unwrap_err();
+        assert!(err.to_string().contains("no parquet files found"));
+    }
+
+    #[test]
+    fn object_store_urls_are_not_resolved_against_the_data_dir() {
+        let ctx = ExecContext {
+            data_dir: Some(PathBuf::from("/data")),
+            ..Default::default()
+        };
+        assert_eq!(ctx.resolve_path("a.parquet"), "/data/a.parquet");
+        assert_eq!(
+            ctx.resolve_path("s3://bucket/a.parquet"),
+            "s3://bucket/a.parquet"
+        );
+        let q = "df = pl.read_csv(\"gs://bucket/a.csv\")";
+        let err = execute_plan_with(q, &ctx).unwrap_err();
+        assert!(err.to_string().contains("not supported"));
+        #[cfg(not(feature = "cloud"))]
+        {
+            let q = "df = pl.read_parquet(\"s3://bucket/a.parquet\")";
+            let err = execute_plan_with(q, &ctx).unwrap_err();
+            assert!(err.to_string().contains("requires the `cloud` feature"));
+        }
+    }
+
+    #[test]
+    fn cancelled_plan_stops() {
+        let token = CancelToken::default();
+        let ctx = ExecContext {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+        let df = df!["age" => [20, 40]].unwrap();
+        let q = "df = df.filter(pl.col(\"age\") > 30)";
+        assert_eq!(execute_plan_on(df.clone(), q, &ctx).unwrap().height(), 1);
+        token.cancel();
+        let err = execute_plan_on(df, q, &ctx).unwrap_err();
+        assert!(err.to_string().contains(CANCELLED));
+    }
+
+    #[test]
+    fn execute_join() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, mut df: DataFrame| {
+            let path = dir.path().join(name);
+            ParquetWriter::new(File::create(&path).unwrap())
+                .finish(&mut df)
+                .unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let people = write(
+            "people.parquet",
+            df!["id" => [1, 2, 3], "name" => ["a", "b", "c"]].unwrap(),
+        );
+        let cities = write(
+            "cities.parquet",
+            df!["id" => [1, 3, 4], "city" => ["NY", "LA", "SF"]].unwrap(),
+        );
+        let join = |how: &str| {
+            let q = format!(
+                "df = pl.read_parquet(\"{}\")\ndf = df.join(pl.read_parquet(\"{}\"), on=\"id\", how=\"{}\")",
+                people, cities, how
+            );
+            execute_plan(&q).unwrap()
+        };
+        assert_eq!(join("inner").height(), 2);
+        let left = join("left");
+        assert_eq!(left.height(), 3);
+        assert_eq!(left.column("city").unwrap().null_count(), 1);
+        assert_eq!(join("outer").height(), 4);
+    }
 }
@@ -0,0 +1,352 @@
+//! Query plans as graphs of operators, with the estimated rows each one
+//! produces, for rendering as Graphviz DOT or by a UI.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::estimate::{self, Estimate};
+use crate::executor::{self, ExecContext};
+use crate::expr::Expr;
+use crate::parser::{FillNull, QueryPlan};
+
+/// Output format of `POST /explain`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExplainFormat {
+    Dot,
+    #[default]
+    JsonGraph,
+}
+
+/// An operator of the plan.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanNode {
+    pub id: usize,
+    /// `scan`, `filter`, `select`, `with_columns`, `sort`, `slice`,
+    /// `unique`, `drop_nulls`, `fill_null`, `pivot`, `melt`, `join` or
+    /// `aggregate`.
+    pub op: &'static str,
+    pub label: String,
+    pub estimated_rows: Option<u64>,
+}
+
+/// Rows flowing from the operator `from` into `to`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanEdge {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Operators in the order they are executed. Grouping is applied after every
+/// other step, so a `groupby` and its `agg`s become one final `aggregate`
+/// node. Each read starts a new frame: operators before a later read do not
+/// feed the result.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanGraph {
+    pub nodes: Vec<PlanNode>,
+    pub edges: Vec<PlanEdge>,
+    pub estimate: Estimate,
+    /// The plan Polars runs after optimization, as `describe_optimized_plan`
+    /// prints it.
+    pub optimized_plan: String,
+}
+
+fn scan_label(step: &QueryPlan) -> String {
+    match step {
+        QueryPlan::ReadParquet(paths) => format!("read_parquet {}", paths.join(", ")),
+        QueryPlan::ReadCsv { path, .. } => format!("read_csv {}", path),
+        QueryPlan::ReadTable {
+            name,
+            version: Some(v),
+            ..
+        } => format!("read_table {} version {}", name, v),
+        QueryPlan::ReadTable {
+            name,
+            as_of: Some(ts),
+            ..
+        } => format!("read_table {} as of {}", name, ts),
+        QueryPlan::ReadTable { name, .. } => format!("read_table {}", name),
+        _ => String::new(),
+    }
+}
+
+/// Build the graph of `steps`, estimating rows as [`estimate::estimate`]
+/// does.
+pub fn explain(steps: &[QueryPlan], ctx: &ExecContext) -> Result<PlanGraph, String> {
+    let (estimate, step_rows) = estimate::estimate_steps(steps, ctx)?;
+    let mut nodes: Vec<PlanNode> = Vec::new();
+    let mut edges = Vec::new();
+    let mut group_by = None;
+    let mut aggs = Vec::new();
+    let mut last: Option<usize> = None;
+
+    for (i, (step, rows)) in steps.iter().zip(step_rows).enumerate() {
+        let (op, label) = match step {
+            QueryPlan::ReadParquet(_) | QueryPlan::ReadCsv { .. } | QueryPlan::ReadTable { .. } => {
+                last = None;
+                ("scan", scan_label(step))
+            }
+            QueryPlan::Filter(expr) => ("filter", expr.to_string()),
+            QueryPlan::Select(exprs) => (
+                "select",
+                exprs
+                    .iter()
+                    .map(|e| match e {
+                        Expr::Column(name) => name.clone(),
+                        e => e.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            QueryPlan::WithColumns(exprs) => (
+                "with_columns",
+                exprs
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            QueryPlan::Sort(column) => ("sort", format!("by {}", column)),
+            QueryPlan::Join { source, on, how } => {
+                let scanned = estimate::estimate(std::slice::from_ref(&**source), ctx)?;
+                let id = nodes.len();
+                nodes.push(PlanNode {
+                    id,
+                    op: "scan",
+                    label: scan_label(source),
+                    estimated_rows: scanned.output_rows,
+                });
+                edges.push(PlanEdge {
+                    from: id,
+                    to: id + 1,
+                });
+                ("join", format!("{} on {}", how.name(), on))
+            }
+            QueryPlan::GroupBy(keys) => {
+                group_by = Some(keys.join(", "));
+                continue;
+            }
+            QueryPlan::Agg(exprs) => {
+                aggs.extend(exprs.iter().map(ToString::to_string));
+                continue;
+            }
+            QueryPlan::Head(n) => ("slice", format!("head {}", n)),
+            QueryPlan::Tail(n) => ("slice", format!("tail {}", n)),
+            QueryPlan::Slice { offset, length } => {
+                let length = length.map_or("all".to_string(), |n| n.to_string());
+                ("slice", format!("{} rows from {}", length, offset))
+            }
+            QueryPlan::DropNulls(subset) => (
+                "drop_nulls",
+                subset
+                    .as_ref()
+                    .map_or("any column".to_string(), |s| s.join(", ")),
+            ),
+            QueryPlan::FillNull(FillNull::Value(value)) => ("fill_null", format!("with {}", value)),
+            QueryPlan::FillNull(FillNull::Strategy(strategy)) => {
+                ("fill_null", strategy.name().to_string())
+            }
+            QueryPlan::Pivot {
+                values,
+                index,
+                columns,
+                aggregate,
+                ..
+            } => {
+                let values = if values.is_empty() {
+                    "other columns".to_string()
+                } else {
+                    values.join(", ")
+                };
+                let label = format!(
+                    "{} of {} by {} across {}",
+                    aggregate.name(),
+                    values,
+                    index.join(", "),
+                    columns.join(", ")
+                );
+                ("pivot", label)
+            }
+            QueryPlan::Melt {
+                id_vars,
+                value_vars,
+                ..
+            } => {
+                let melted = if value_vars.is_empty() {
+                    "other columns".to_string()
+                } else {
+                    value_vars.join(", ")
+                };
+                if id_vars.is_empty() {
+                    ("melt", melted)
+                } else {
+                    ("melt", format!("{} keeping {}", melted, id_vars.join(", ")))
+                }
+            }
+            QueryPlan::Unique { subset, keep } => {
+                let columns = subset
+                    .as_ref()
+                    .map_or("all columns".to_string(), |s| s.join(", "));
+                ("unique", format!("{} keep {}", columns, keep.name()))
+            }
+        };
+        // As in execution, slices, uniques, pivots and melts after a groupby
+        // keep groups.
+        let regroups = step.slice_bounds().is_some()
+            || matches!(
+                step,
+                QueryPlan::Unique { .. } | QueryPlan::Pivot { .. } | QueryPlan::Melt { .. }
+            );
+        if let Some(keys) = group_by.as_ref().filter(|_| regroups) {
+            if last.is_some() {
+                let grouped = estimate::estimate(&steps[..i], ctx)?.output_rows;
+                let label = aggregate_label(keys, &aggs);
+                push_node(
+                    &mut nodes,
+                    &mut edges,
+                    &mut last,
+                    "aggregate",
+                    label,
+                    grouped,
+                );
+            }
+            group_by = None;
+            aggs.clear();
+        }
+        push_node(&mut nodes, &mut edges, &mut last, op, label, rows);
+    }
+    if let (Some(keys), Some(_)) = (group_by, last) {
+        let label = aggregate_label(&keys, &aggs);
+        push_node(
+            &mut nodes,
+            &mut edges,
+            &mut last,
+            "aggregate",
+            label,
+            estimate.output_rows,
+        );
+    }
+    let optimized_plan = executor::describe_plan(steps, ctx).map_err(|e| e.to_string())?;
+    Ok(PlanGraph {
+        nodes,
+        edges,
+        estimate,
+        optimized_plan,
+    })
+}
+
+/// Append a node, linked from the `last` one when there is one.
+fn push_node(
+    nodes: &mut Vec<PlanNode>,
+    edges: &mut Vec<PlanEdge>,
+    last: &mut Option<usize>,
+    op: &'static str,
+    label: String,
+    estimated_rows: Option<u64>,
+) {
+    let id = nodes.len();
+    if let Some(from) = *last {
+        edges.push(PlanEdge { from, to: id });
+    }
+    nodes.push(PlanNode {
+        id,
+        op,
+        label,
+        estimated_rows,
+    });
+    *last = Some(id);
+}
+
+fn aggregate_label(keys: &str, aggs: &[String]) -> String {
+    if aggs.is_empty() {
+        format!("by {}", keys)
+    } else {
+        format!("by {}: {}", keys, aggs.join(", "))
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl PlanGraph {
+    /// The graph in Graphviz DOT, data flowing upwards from the scans.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph plan {\n  rankdir=BT;\n  node [shape=box];\n");
+        for node in &self.nodes {
+            let rows = match node.estimated_rows {
+                Some(rows) => format!("~{} rows", rows),
+                None => "rows unknown".to_string(),
+            };
+            let _ = writeln!(
+                dot,
+                "  n{} [label=\"{}\\n{}\\n{}\"];",
+                node.id,
+                node.op,
+                escape(&node.label),
+                rows
+            );
+        }
+        for edge in &self.edges {
+            let _ = writeln!(dot, "  n{} -> n{};", edge.from, edge.to);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+    use polars::prelude::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn plan_is_a_chain_ending_in_the_aggregate() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("people.parquet");
+        let mut df = df![
+            "city" => ["NY", "LA"],
+            "name" => ["a", "b"],
+            "age" => [10, 40],
+        ]
+        .unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let query = format!(
+            "df = pl.read_parquet(\"{}\")\ndf = df.groupby(\"city\")\ndf = df.agg(pl.col(\"age\").sum())\ndf = df.filter(pl.col(\"name\") == \"a\\\"b\")",
+            data.display()
+        );
+        let graph = explain(&parse_query(&query).unwrap(), &ExecContext::default()).unwrap();
+        let ops: Vec<_> = graph.nodes.iter().map(|n| n.op).collect();
+        assert_eq!(ops, vec!["scan", "filter", "aggregate"]);
+        assert_eq!(graph.nodes[0].estimated_rows, Some(2));
+        assert_eq!(graph.nodes[2].label, "by city: pl.col(\"age\").sum()");
+        assert_eq!(
+            graph.estimate.files,
+            vec![data.to_string_lossy().to_string()]
+        );
+        assert!(graph.optimized_plan.contains("AGGREGATE"));
+        assert_eq!(
+            graph.edges,
+            vec![PlanEdge { from: 0, to: 1 }, PlanEdge { from: 1, to: 2 }]
+        );
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(dot.contains(r#"pl.col(\"name\") == \"a\\\"b\""#));
+
+        let query = format!(
+            "df = pl.read_parquet(\"{}\")\ndf = df.groupby(\"city\")\ndf = df.head(1)",
+            data.display()
+        );
+        let graph = explain(&parse_query(&query).unwrap(), &ExecContext::default()).unwrap();
+        let ops: Vec<_> = graph.nodes.iter().map(|n| n.op).collect();
+        assert_eq!(ops, vec!["scan", "aggregate", "slice"]);
+        assert_eq!(graph.nodes[2].label, "head 1");
+        assert_eq!(graph.nodes[2].estimated_rows, Some(1));
+    }
+}
@@ -0,0 +1,717 @@
+//! Typed expressions of the query language, as parsed from `filter` and `agg`
+//! arguments and lowered to Polars expressions by the executor.
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Timelike};
+use std::fmt;
+
+/// An expression such as `pl.col("age") > 30` or `pl.col("balance").mean()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// `pl.col("name")`
+    Column(String),
+    /// A literal, written bare or as `pl.lit(value)`.
+    Literal(Literal),
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    /// `~expr`
+    Not(Box<Expr>),
+    /// `expr.sum()` and the other aggregations.
+    Agg { func: AggFunc, expr: Box<Expr> },
+    /// `expr.alias("name")`
+    Alias { expr: Box<Expr>, name: String },
+    /// `expr.is_in([values])`
+    IsIn {
+        expr: Box<Expr>,
+        values: Vec<Literal>,
+    },
+    /// `expr.is_null()`, or `expr.is_not_null()` when negated.
+    IsNull { expr: Box<Expr>, negated: bool },
+    /// `expr.str.contains(pattern)` and the other string predicates.
+    Str {
+        expr: Box<Expr>,
+        op: StrOp,
+        pattern: String,
+    },
+    /// `expr.is_between(low, high)`, including both bounds.
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+    },
+    /// `expr.cast(pl.Int64)`, or with `strict=False` to turn values that
+    /// don't convert into nulls instead of failing.
+    Cast {
+        expr: Box<Expr>,
+        dtype: DType,
+        strict: bool,
+    },
+    /// `expr.cum_sum()`, the running total.
+    CumSum(Box<Expr>),
+    /// `expr.rank(method="dense", descending=True)`
+    Rank {
+        expr: Box<Expr>,
+        method: RankMethod,
+        descending: bool,
+    },
+    /// `expr.over("group")`, evaluating `expr` within each group of the
+    /// `partition_by` columns and giving every row its group's result.
+    Over {
+        expr: Box<Expr>,
+        partition_by: Vec<String>,
+    },
+}
+
+/// How `rank` numbers ties.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RankMethod {
+    /// The mean of the ranks the ties span.
+    #[default]
+    Average,
+    Min,
+    Max,
+    /// Ties share the lowest rank and the next value takes the next rank.
+    Dense,
+    /// Ties are ranked in order of appearance.
+    Ordinal,
+}
+
+impl RankMethod {
+    pub fn from_name(name: &str) -> Option<RankMethod> {
+        Some(match name {
+            "average" => RankMethod::Average,
+            "min" => RankMethod::Min,
+            "max" => RankMethod::Max,
+            "dense" => RankMethod::Dense,
+            "ordinal" => RankMethod::Ordinal,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RankMethod::Average => "average",
+            RankMethod::Min => "min",
+            RankMethod::Max => "max",
+            RankMethod::Dense => "dense",
+            RankMethod::Ordinal => "ordinal",
+        }
+    }
+}
+
+/// A data type named as in Polars, such as `pl.Float64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    Int32,
+    Int64,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    /// Also spelled `pl.String`.
+    Utf8,
+    Boolean,
+    Date,
+    /// Microsecond precision, without a time zone.
+    Datetime,
+}
+
+impl DType {
+    pub fn from_name(name: &str) -> Option<DType> {
+        Some(match name {
+            "Int32" => DType::Int32,
+            "Int64" => DType::Int64,
+            "UInt32" => DType::UInt32,
+            "UInt64" => DType::UInt64,
+            "Float32" => DType::Float32,
+            "Float64" => DType::Float64,
+            "Utf8" | "String" => DType::Utf8,
+            "Boolean" => DType::Boolean,
+            "Date" => DType::Date,
+            "Datetime" => DType::Datetime,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DType::Int32 => "Int32",
+            DType::Int64 => "Int64",
+            DType::UInt32 => "UInt32",
+            DType::UInt64 => "UInt64",
+            DType::Float32 => "Float32",
+            DType::Float64 => "Float64",
+            DType::Utf8 => "Utf8",
+            DType::Boolean => "Boolean",
+            DType::Date => "Date",
+            DType::Datetime => "Datetime",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    /// `pl.date(2024, 1, 31)`
+    Date(NaiveDate),
+    /// `pl.datetime(2024, 1, 31, 12, 30)`, in UTC.
+    Datetime(NaiveDateTime),
+    Null,
+}
+
+impl Literal {
+    /// The date or datetime an ISO 8601 string such as `"2024-01-31"` or
+    /// `"2024-01-31T12:30:00"` names. Datetimes with an offset are converted
+    /// to UTC.
+    pub fn parse_temporal(value: &str) -> Option<Literal> {
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            return Some(Literal::Date(date));
+        }
+        if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+            return Some(Literal::Datetime(dt.naive_utc()));
+        }
+        [
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%Y-%m-%dT%H:%M",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(Literal::Datetime)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    /// The operator's symbol in queries.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Eq => "==",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+        }
+    }
+
+    /// Binding strength, following Python: comparisons bind loosest, then
+    /// `|`, `&`, `+ -` and `* /`.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq => 1,
+            BinaryOp::Or => 2,
+            BinaryOp::And => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div => 5,
+        }
+    }
+}
+
+/// A string predicate, testing values against a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrOp {
+    /// `str.contains(pattern)`, a regular expression as in Polars.
+    Contains,
+    /// `str.contains(pattern, literal=True)`
+    ContainsLiteral,
+    StartsWith,
+    EndsWith,
+}
+
+impl StrOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            StrOp::Contains | StrOp::ContainsLiteral => "contains",
+            StrOp::StartsWith => "starts_with",
+            StrOp::EndsWith => "ends_with",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggFunc {
+    Sum,
+    Mean,
+    Median,
+    Min,
+    Max,
+    Count,
+    /// Number of distinct values.
+    NUnique,
+    First,
+    Last,
+    /// Standard deviation with the given delta degrees of freedom.
+    Std(u8),
+    /// Variance with the given delta degrees of freedom.
+    Var(u8),
+    /// Value at a quantile between 0 and 1, the nearest one in the data.
+    Quantile(f64),
+}
+
+impl AggFunc {
+    /// The aggregation called `name`, with default arguments: `ddof` 1 for
+    /// `std` and `var`, and the median for `quantile`.
+    pub fn from_name(name: &str) -> Option<AggFunc> {
+        Some(match name {
+            "sum" => AggFunc::Sum,
+            "mean" => AggFunc::Mean,
+            "median" => AggFunc::Median,
+            "min" => AggFunc::Min,
+            "max" => AggFunc::Max,
+            "count" => AggFunc::Count,
+            "n_unique" => AggFunc::NUnique,
+            "first" => AggFunc::First,
+            "last" => AggFunc::Last,
+            "std" => AggFunc::Std(1),
+            "var" => AggFunc::Var(1),
+            "quantile" => AggFunc::Quantile(0.5),
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AggFunc::Sum => "sum",
+            AggFunc::Mean => "mean",
+            AggFunc::Median => "median",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+            AggFunc::Count => "count",
+            AggFunc::NUnique => "n_unique",
+            AggFunc::First => "first",
+            AggFunc::Last => "last",
+            AggFunc::Std(_) => "std",
+            AggFunc::Var(_) => "var",
+            AggFunc::Quantile(_) => "quantile",
+        }
+    }
+}
+
+/// Renders the call as written in a query, such as `quantile(0.95)`, leaving
+/// out default arguments.
+impl fmt::Display for AggFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggFunc::Std(ddof) | AggFunc::Var(ddof) if *ddof != 1 => {
+                write!(f, "{}(ddof={})", self.name(), ddof)
+            }
+            AggFunc::Quantile(q) => write!(f, "quantile({:?})", q),
+            _ => write!(f, "{}()", self.name()),
+        }
+    }
+}
+
+impl Expr {
+    /// `(column, op, value)` of a `pl.col("x") <op> literal` comparison, with
+    /// the value as written and strings quoted.
+    pub fn comparison(&self) -> Option<(String, String, String)> {
+        match self {
+            Expr::Binary { left, op, right } if op.precedence() == 1 => match (&**left, &**right) {
+                (Expr::Column(column), Expr::Literal(value)) => {
+                    // Dates are compared with partition values as ISO strings.
+                    let value = match value {
+                        Literal::Str(s) => format!("\"{}\"", s),
+                        Literal::Date(date) => format!("\"{}\"", date),
+                        Literal::Datetime(dt) => {
+                            format!("\"{}\"", dt.format("%Y-%m-%dT%H:%M:%S%.f"))
+                        }
+                        other => other.to_string(),
+                    };
+                    Some((column.clone(), op.symbol().to_string(), value))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The operands of a chain of `&`, or `self` alone: the predicates a row
+    /// must all satisfy to pass a filter on `self`.
+    pub fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                left,
+                op: BinaryOp::And,
+                right,
+            } => {
+                let mut conjuncts = left.conjuncts();
+                conjuncts.extend(right.conjuncts());
+                conjuncts
+            }
+            other => vec![other],
+        }
+    }
+
+    /// Whether `self` needs parentheses as an operand of an operator binding
+    /// with `precedence`.
+    fn needs_parens(&self, precedence: u8) -> bool {
+        matches!(self, Expr::Binary { op, .. } if op.precedence() <= precedence)
+    }
+}
+
+/// Write `expr` as the receiver of a method call, in parentheses when it is
+/// an operation.
+fn write_receiver(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    if matches!(expr, Expr::Binary { .. } | Expr::Not(_)) {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+/// Write `s` as a double-quoted string literal.
+pub(crate) fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Int(v) => write!(f, "{}", v),
+            Literal::Float(v) => write!(f, "{:?}", v),
+            Literal::Str(s) => write_str(f, s),
+            Literal::Bool(true) => f.write_str("True"),
+            Literal::Bool(false) => f.write_str("False"),
+            Literal::Date(date) => write!(f, "pl.date({})", date.format("%Y, %-m, %-d")),
+            Literal::Datetime(dt) => {
+                write!(
+                    f,
+                    "pl.datetime({}",
+                    dt.format("%Y, %-m, %-d, %-H, %-M, %-S")
+                )?;
+                if dt.nanosecond() > 0 {
+                    write!(f, ", {}", dt.nanosecond() / 1000)?;
+                }
+                f.write_str(")")
+            }
+            Literal::Null => f.write_str("None"),
+        }
+    }
+}
+
+/// Renders the expression as it would be written in a query.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(name) => {
+                f.write_str("pl.col(")?;
+                write_str(f, name)?;
+                f.write_str(")")
+            }
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Binary { left, op, right } => {
+                let p = op.precedence();
+                // Operators are left-associative, so only a right operand of
+                // equal precedence needs parentheses; comparisons do not chain.
+                if left.needs_parens(if p == 1 { p } else { p - 1 }) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                if right.needs_parens(p) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+            Expr::Not(expr) => {
+                if expr.needs_parens(u8::MAX) {
+                    write!(f, "~({})", expr)
+                } else {
+                    write!(f, "~{}", expr)
+                }
+            }
+            Expr::Agg { func, expr } => {
+                write_receiver(f, expr)?;
+                write!(f, ".{}", func)
+            }
+            Expr::Alias { expr, name } => {
+                write_receiver(f, expr)?;
+                f.write_str(".alias(")?;
+                write_str(f, name)?;
+                f.write_str(")")
+            }
+            Expr::IsIn { expr, values } => {
+                write_receiver(f, expr)?;
+                f.write_str(".is_in([")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("])")
+            }
+            Expr::IsNull { expr, negated } => {
+                write_receiver(f, expr)?;
+                f.write_str(if *negated {
+                    ".is_not_null()"
+                } else {
+                    ".is_null()"
+                })
+            }
+            Expr::Str { expr, op, pattern } => {
+                write_receiver(f, expr)?;
+                write!(f, ".str.{}(", op.name())?;
+                write_str(f, pattern)?;
+                if *op == StrOp::ContainsLiteral {
+                    f.write_str(", literal=True")?;
+                }
+                f.write_str(")")
+            }
+            Expr::Between { expr, low, high } => {
+                write_receiver(f, expr)?;
+                write!(f, ".is_between({}, {})", low, high)
+            }
+            Expr::Cast {
+                expr,
+                dtype,
+                strict,
+            } => {
+                write_receiver(f, expr)?;
+                write!(f, ".cast(pl.{}", dtype.name())?;
+                if !strict {
+                    f.write_str(", strict=False")?;
+                }
+                f.write_str(")")
+            }
+            Expr::CumSum(expr) => {
+                write_receiver(f, expr)?;
+                f.write_str(".cum_sum()")
+            }
+            Expr::Rank {
+                expr,
+                method,
+                descending,
+            } => {
+                write_receiver(f, expr)?;
+                f.write_str(".rank(")?;
+                if *method != RankMethod::Average {
+                    write!(f, "\"{}\"", method.name())?;
+                }
+                if *descending {
+                    if *method != RankMethod::Average {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str("descending=True")?;
+                }
+                f.write_str(")")
+            }
+            Expr::Over { expr, partition_by } => {
+                write_receiver(f, expr)?;
+                f.write_str(".over(")?;
+                if partition_by.len() > 1 {
+                    f.write_str("[")?;
+                }
+                for (i, column) in partition_by.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write_str(f, column)?;
+                }
+                if partition_by.len() > 1 {
+                    f.write_str("]")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+        Expr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    #[test]
+    fn display_parenthesizes_by_precedence() {
+        let age = || Expr::Column("age".into());
+        let over = binary(age(), BinaryOp::Gt, Expr::Literal(Literal::Int(30)));
+        let named = binary(
+            Expr::Column("name".into()),
+            BinaryOp::Eq,
+            Expr::Literal(Literal::Str("a\"b".into())),
+        );
+        assert_eq!(
+            binary(over.clone(), BinaryOp::And, Expr::Not(Box::new(named))).to_string(),
+            r#"(pl.col("age") > 30) & ~(pl.col("name") == "a\"b")"#
+        );
+        let sum = Expr::Agg {
+            func: AggFunc::Sum,
+            expr: Box::new(binary(
+                age(),
+                BinaryOp::Sub,
+                binary(age(), BinaryOp::Sub, age()),
+            )),
+        };
+        assert_eq!(
+            sum.to_string(),
+            r#"(pl.col("age") - (pl.col("age") - pl.col("age"))).sum()"#
+        );
+
+        assert_eq!(
+            over.comparison(),
+            Some(("age".into(), ">".into(), "30".into()))
+        );
+        assert!(sum.comparison().is_none());
+
+        let agg = |func| Expr::Agg {
+            func,
+            expr: Box::new(age()),
+        };
+        assert_eq!(
+            agg(AggFunc::Quantile(0.95)).to_string(),
+            r#"pl.col("age").quantile(0.95)"#
+        );
+        assert_eq!(agg(AggFunc::Std(1)).to_string(), r#"pl.col("age").std()"#);
+        assert_eq!(
+            agg(AggFunc::Var(0)).to_string(),
+            r#"pl.col("age").var(ddof=0)"#
+        );
+
+        let cast = |strict| Expr::Cast {
+            expr: Box::new(binary(age(), BinaryOp::Add, age())),
+            dtype: DType::Float64,
+            strict,
+        };
+        assert_eq!(
+            cast(true).to_string(),
+            r#"(pl.col("age") + pl.col("age")).cast(pl.Float64)"#
+        );
+        assert_eq!(
+            cast(false).to_string(),
+            r#"(pl.col("age") + pl.col("age")).cast(pl.Float64, strict=False)"#
+        );
+        assert_eq!(DType::from_name("String"), Some(DType::Utf8));
+
+        let running = Expr::Over {
+            expr: Box::new(Expr::CumSum(Box::new(age()))),
+            partition_by: vec!["city".into()],
+        };
+        assert_eq!(
+            running.to_string(),
+            r#"pl.col("age").cum_sum().over("city")"#
+        );
+        let rank = Expr::Over {
+            expr: Box::new(Expr::Rank {
+                expr: Box::new(age()),
+                method: RankMethod::Dense,
+                descending: true,
+            }),
+            partition_by: vec!["city".into(), "year".into()],
+        };
+        assert_eq!(
+            rank.to_string(),
+            r#"pl.col("age").rank("dense", descending=True).over(["city", "year"])"#
+        );
+    }
+
+    #[test]
+    fn temporal_literals() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        let noon = date.and_hms_opt(12, 30, 0).unwrap();
+        assert_eq!(
+            Literal::parse_temporal("2024-01-31"),
+            Some(Literal::Date(date))
+        );
+        for value in [
+            "2024-01-31T12:30:00",
+            "2024-01-31 12:30:00",
+            "2024-01-31T12:30",
+            "2024-01-31T13:30:00+01:00",
+        ] {
+            assert_eq!(
+                Literal::parse_temporal(value),
+                Some(Literal::Datetime(noon)),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(Literal::parse_temporal("2024-02-30"), None);
+        assert_eq!(Literal::parse_temporal("NY"), None);
+
+        assert_eq!(Literal::Date(date).to_string(), "pl.date(2024, 1, 31)");
+        assert_eq!(
+            Literal::Datetime(noon).to_string(),
+            "pl.datetime(2024, 1, 31, 12, 30, 0)"
+        );
+        let after = Expr::Binary {
+            left: Box::new(Expr::Column("day".into())),
+            op: BinaryOp::GtEq,
+            right: Box::new(Expr::Literal(Literal::Date(date))),
+        };
+        assert_eq!(
+            after.comparison(),
+            Some(("day".into(), ">=".into(), "\"2024-01-31\"".into()))
+        );
+    }
+
+    #[test]
+    fn conjuncts_split_and_chains_only() {
+        let cmp = |c: &str| {
+            binary(
+                Expr::Column(c.into()),
+                BinaryOp::Eq,
+                Expr::Literal(Literal::Int(1)),
+            )
+        };
+        let either = binary(cmp("b"), BinaryOp::Or, cmp("c"));
+        let all = binary(
+            binary(cmp("a"), BinaryOp::And, either.clone()),
+            BinaryOp::And,
+            cmp("d"),
+        );
+        assert_eq!(all.conjuncts(), vec![&cmp("a"), &either, &cmp("d")]);
+        assert_eq!(either.conjuncts(), vec![&either]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Liveness and readiness reports behind `GET /healthz` and `GET /readyz`.
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// One condition the server must meet to take traffic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReadinessCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Whether the server can take new jobs, and why not.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+impl Readiness {
+    pub fn new(checks: Vec<ReadinessCheck>) -> Self {
+        Readiness {
+            ready: checks.iter().all(|c| c.ok),
+            checks,
+        }
+    }
+}
+
+/// Whether the scheduler loop is still taking jobs: running, and not
+/// draining them to shut down.
+pub fn check_scheduler(running: bool, draining: bool) -> ReadinessCheck {
+    let detail = match (running, draining) {
+        (false, _) => "stopped",
+        (true, true) => "draining",
+        (true, false) => "running",
+    };
+    ReadinessCheck {
+        name: "scheduler",
+        ok: running && !draining,
+        detail: detail.to_string(),
+    }
+}
+
+/// Whether `queued` jobs are within `limit`.
+pub fn check_queue(queued: u64, limit: usize) -> ReadinessCheck {
+    ReadinessCheck {
+        name: "queue",
+        ok: queued <= limit as u64,
+        detail: format!("{} of {} jobs queued", queued, limit),
+    }
+}
+
+/// Whether results can be written to `dir`, by writing and removing a probe
+/// file.
+pub fn check_output_dir(dir: &Path) -> ReadinessCheck {
+    let probe = dir.join(".rdata-ready");
+    let written = fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b"ok"));
+    let _ = fs::remove_file(&probe);
+    ReadinessCheck {
+        name: "output_dir",
+        ok: written.is_ok(),
+        detail: match written {
+            Ok(()) => format!("{} writable", dir.display()),
+            Err(e) => format!("{} not writable: {}", dir.display(), e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness_fails_with_any_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready = Readiness::new(vec![
+            check_scheduler(true, false),
+            check_queue(3, 3),
+            check_output_dir(dir.path()),
+        ]);
+        assert!(ready.ready);
+        assert!(!dir.path().join(".rdata-ready").exists());
+
+        let file = dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        let not_ready = Readiness::new(vec![
+            check_queue(4, 3),
+            check_output_dir(&file),
+            check_scheduler(true, true),
+        ]);
+        assert!(!not_ready.ready);
+        assert_eq!(not_ready.checks[0].detail, "4 of 3 jobs queued");
+        assert!(!not_ready.checks[1].ok);
+        assert_eq!(not_ready.checks[2].detail, "draining");
+    }
+}
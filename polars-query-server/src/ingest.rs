@@ -0,0 +1,321 @@
+//! Converting uploaded CSV and NDJSON data to parquet datasets.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::catalog::{self, Catalog, DatasetFormat, DatasetSpec};
+
+/// Format of data being ingested.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+/// Whether ingested data is added to or replaces the dataset's data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestMode {
+    #[default]
+    Append,
+    Overwrite,
+}
+
+/// Options of an ingestion request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IngestOptions {
+    /// Dataset to create or append to.
+    pub dataset: String,
+    /// Taken from the content type or source extension when unset.
+    pub format: Option<IngestFormat>,
+    #[serde(default)]
+    pub mode: IngestMode,
+    /// Column types overriding inference, as `column:type,column:type`.
+    pub schema: Option<String>,
+    /// Path, resolved against the data directory, or `http(s)://` URL to
+    /// read instead of the request body.
+    pub source: Option<String>,
+    pub description: Option<String>,
+    /// A list, or a comma separated string in query parameters.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect(),
+        OneOrMany::Many(v) => v,
+    })
+}
+
+impl IngestFormat {
+    /// Format implied by a content type such as `application/x-ndjson`.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next()?.trim();
+        match essence {
+            "text/csv" | "application/csv" => Some(IngestFormat::Csv),
+            "application/x-ndjson" | "application/jsonl" | "application/jsonlines" => {
+                Some(IngestFormat::Ndjson)
+            }
+            _ => None,
+        }
+    }
+
+    /// Format implied by a file extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Path::new(path).extension()?.to_str()? {
+            "csv" => Some(IngestFormat::Csv),
+            "ndjson" | "jsonl" => Some(IngestFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of an ingestion.
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestReport {
+    pub dataset: String,
+    pub rows: usize,
+    pub file: String,
+    pub version: u64,
+}
+
+/// Directory, inside a dataset's directory, replaced files are moved to.
+pub const REPLACED_DIR: &str = "_replaced";
+
+/// Parse a type name as accepted in [`IngestOptions::schema`].
+pub fn parse_dtype(name: &str) -> Result<DataType, String> {
+    Ok(match name.trim().to_lowercase().as_str() {
+        "bool" | "boolean" => DataType::Boolean,
+        "i32" | "int32" => DataType::Int32,
+        "i64" | "int" | "int64" | "integer" => DataType::Int64,
+        "u32" | "uint32" => DataType::UInt32,
+        "u64" | "uint64" => DataType::UInt64,
+        "f32" | "float32" => DataType::Float32,
+        "f64" | "float" | "float64" | "double" => DataType::Float64,
+        "str" | "string" | "utf8" => DataType::Utf8,
+        "date" => DataType::Date,
+        "datetime" => DataType::Datetime(TimeUnit::Microseconds, None),
+        other => return Err(format!("unsupported type {}", other)),
+    })
+}
+
+/// Parse `column:type,column:type`.
+pub fn parse_schema(spec: &str) -> Result<Vec<(String, DataType)>, String> {
+    spec.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|item| {
+            let (name, dtype) = item
+                .split_once(':')
+                .ok_or_else(|| format!("expected column:type, got {}", item))?;
+            Ok((name.trim().to_string(), parse_dtype(dtype)?))
+        })
+        .collect()
+}
+
+/// Read `bytes` as `format`, casting the columns listed in `schema`.
+pub fn read(
+    bytes: Vec<u8>,
+    format: IngestFormat,
+    schema: &[(String, DataType)],
+) -> Result<DataFrame, String> {
+    let cursor = Cursor::new(bytes);
+    let mut df = match format {
+        IngestFormat::Csv => CsvReader::new(cursor).has_header(true).finish(),
+        IngestFormat::Ndjson => JsonLineReader::new(cursor).finish(),
+    }
+    .map_err(|e| format!("failed to parse input: {}", e))?;
+    for (name, dtype) in schema {
+        let column = df
+            .column(name)
+            .map_err(|_| format!("schema column {} is not in the input", name))?
+            .strict_cast(dtype)
+            .map_err(|e| format!("cannot convert column {} to {}: {}", name, dtype, e))?;
+        df.with_column(column).map_err(|e| e.to_string())?;
+    }
+    Ok(df)
+}
+
+/// Move the parquet files directly in `dir` aside, returning old -> new paths.
+fn archive_existing(dir: &Path, stamp: u64) -> Result<HashMap<String, String>, String> {
+    let mut moves = HashMap::new();
+    let archive = dir.join(REPLACED_DIR).join(stamp.to_string());
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("parquet") {
+            continue;
+        }
+        fs::create_dir_all(&archive).map_err(|e| e.to_string())?;
+        let to = archive.join(path.file_name().unwrap_or_default());
+        fs::rename(&path, &to).map_err(|e| e.to_string())?;
+        moves.insert(
+            path.to_string_lossy().to_string(),
+            to.to_string_lossy().to_string(),
+        );
+    }
+    Ok(moves)
+}
+
+/// Write `df` as a new part of the dataset in `root/<dataset>/` and register
+/// the directory in `catalog`, adding a version.
+pub fn ingest(
+    catalog: &Catalog,
+    root: &Path,
+    mut df: DataFrame,
+    options: IngestOptions,
+    owner: &str,
+) -> Result<IngestReport, String> {
+    if !catalog::valid_name(&options.dataset) {
+        return Err(format!(
+            "invalid dataset name {}: use letters, digits, '_' and '-'",
+            options.dataset
+        ));
+    }
+    let dir: PathBuf = root.join(&options.dataset);
+    let location = dir.to_string_lossy().to_string();
+    let existing = catalog.get(&options.dataset);
+    if let Some(existing) = &existing {
+        if existing.location != location {
+            return Err(format!(
+                "dataset {} is registered at {} and not managed by ingestion",
+                options.dataset, existing.location
+            ));
+        }
+    }
+
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let stamp = catalog::now_secs();
+    if options.mode == IngestMode::Overwrite && existing.is_some() {
+        let moves = archive_existing(&dir, stamp)?;
+        catalog.relocate_files(&options.dataset, &moves)?;
+    }
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let name = format!("part-{}.parquet", nanos);
+    let path = dir.join(&name);
+    let tmp = dir.join(format!(".{}.tmp", name));
+    let written = File::create(&tmp)
+        .map_err(|e| e.to_string())
+        .and_then(|f| {
+            ParquetWriter::new(f)
+                .finish(&mut df)
+                .map_err(|e| e.to_string())
+        })
+        .and_then(|_| fs::rename(&tmp, &path).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    let owner = existing.as_ref().map_or(owner, |d| d.owner.as_str());
+    let dataset = catalog.register(
+        DatasetSpec {
+            name: options.dataset.clone(),
+            location,
+            format: DatasetFormat::Parquet,
+            description: options
+                .description
+                .or_else(|| existing.as_ref().and_then(|d| d.description.clone())),
+            tags: if options.tags.is_empty() {
+                existing
+                    .as_ref()
+                    .map(|d| d.tags.clone())
+                    .unwrap_or_default()
+            } else {
+                options.tags
+            },
+            schema_mode: None,
+            partition_by: None,
+        },
+        owner,
+    )?;
+    Ok(IngestReport {
+        dataset: dataset.name,
+        rows: df.height(),
+        file: path.to_string_lossy().to_string(),
+        version: dataset.version,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn csv_is_converted_and_appended() {
+        let dir = tempdir().unwrap();
+        let catalog = Catalog::in_memory();
+        let schema = parse_schema("age:f64").unwrap();
+        let options = IngestOptions {
+            dataset: "people".into(),
+            ..Default::default()
+        };
+
+        let df = read(
+            b"name,age\na,20\nb,30\n".to_vec(),
+            IngestFormat::Csv,
+            &schema,
+        )
+        .unwrap();
+        assert_eq!(df.column("age").unwrap().dtype(), &DataType::Float64);
+        let report = ingest(&catalog, dir.path(), df, options.clone(), "alice").unwrap();
+        assert_eq!((report.rows, report.version), (2, 1));
+
+        let df = read(
+            b"{\"name\": \"c\", \"age\": 40}\n".to_vec(),
+            IngestFormat::Ndjson,
+            &schema,
+        )
+        .unwrap();
+        let report = ingest(&catalog, dir.path(), df, options.clone(), "bob").unwrap();
+        assert_eq!(report.version, 2);
+        let ds = catalog.get("people").unwrap();
+        assert_eq!(ds.owner, "alice");
+        assert_eq!(ds.versions[1].files.len(), 2);
+
+        let df = read(b"name,age\nd,50\n".to_vec(), IngestFormat::Csv, &schema).unwrap();
+        let overwrite = IngestOptions {
+            mode: IngestMode::Overwrite,
+            ..options
+        };
+        ingest(&catalog, dir.path(), df, overwrite, "alice").unwrap();
+        let ds = catalog.get("people").unwrap();
+        assert_eq!(ds.versions[2].files.len(), 1);
+        // The replaced files are still readable through version 2.
+        assert!(ds.versions[1]
+            .files
+            .iter()
+            .all(|f| Path::new(&f.path).exists()));
+    }
+
+    #[test]
+    fn schema_parsing() {
+        let schema = parse_schema("id:int, name:str").unwrap();
+        assert_eq!(schema[0], ("id".to_string(), DataType::Int64));
+        assert!(parse_schema("id").is_err());
+        assert!(parse_schema("id:blob").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Column names and types of a source, read from file metadata without
+//! running a query, for UIs offering column pickers.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::catalog::ColumnInfo;
+use crate::cloud;
+use crate::estimate;
+use crate::executor::{self, ExecContext};
+use crate::parser::{CsvOptions, QueryPlan};
+
+/// Bytes of a CSV file sampled to estimate its rows.
+const CSV_SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// Body of `POST /schema`: a file, directory or glob to describe.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchemaRequest {
+    pub path: String,
+    /// `parquet` or `csv`; told by the extension when unset, with files not
+    /// ending in `.csv` or `.tsv` read as parquet.
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+impl SchemaRequest {
+    /// The read step describing the requested path.
+    pub fn source(&self) -> Result<QueryPlan, String> {
+        let lower = self.path.to_ascii_lowercase();
+        let format = match &self.format {
+            Some(format) => format.as_str(),
+            None if lower.ends_with(".csv") || lower.ends_with(".tsv") => "csv",
+            None => "parquet",
+        };
+        match format {
+            "parquet" => Ok(QueryPlan::ReadParquet(vec![self.path.clone()])),
+            "csv" => Ok(QueryPlan::ReadCsv {
+                path: self.path.clone(),
+                options: CsvOptions {
+                    delimiter: if lower.ends_with(".tsv") { b'\t' } else { b',' },
+                    ..CsvOptions::default()
+                },
+            }),
+            other => Err(format!(
+                "unknown format {:?}, expected parquet or csv",
+                other
+            )),
+        }
+    }
+}
+
+/// Schema and size of a source.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceSchema {
+    pub columns: Vec<ColumnInfo>,
+    /// Rows of the source: exact for parquet and datasets with statistics,
+    /// extrapolated from the start of CSV files, unknown for object stores.
+    pub estimated_rows: Option<u64>,
+    /// Total size of the files read, after partition pruning.
+    pub bytes: u64,
+    pub files: Vec<String>,
+}
+
+/// Describe the read step `source` within `ctx`, reading only metadata, or
+/// the start of CSV files to infer their types.
+pub fn describe(source: &QueryPlan, ctx: &ExecContext) -> Result<SourceSchema, String> {
+    let steps = std::slice::from_ref(source);
+    let schema = executor::plan_schema(steps, ctx).map_err(|e| e.to_string())?;
+    let estimate = estimate::estimate(steps, ctx)?;
+    let estimated_rows = match source {
+        QueryPlan::ReadCsv { path, options } if estimate.input_rows.is_none() => {
+            if cloud::is_url(path) {
+                None
+            } else {
+                csv_rows(Path::new(&ctx.resolve_path(path)), options.has_header).ok()
+            }
+        }
+        _ => estimate.input_rows,
+    };
+    Ok(SourceSchema {
+        columns: schema
+            .iter()
+            .map(|(name, dtype)| ColumnInfo {
+                name: name.to_string(),
+                dtype: dtype.to_string(),
+            })
+            .collect(),
+        estimated_rows,
+        bytes: estimate.bytes_scanned,
+        files: estimate.files,
+    })
+}
+
+/// Rows of the CSV file at `path`: counted when it fits in the sample,
+/// otherwise extrapolated from the lines of the sample.
+fn csv_rows(path: &Path, has_header: bool) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut sample = Vec::new();
+    (&mut file)
+        .take(CSV_SAMPLE_BYTES)
+        .read_to_end(&mut sample)?;
+    let mut lines = sample.iter().filter(|b| **b == b'\n').count() as u64;
+    if size <= CSV_SAMPLE_BYTES {
+        if sample.last().is_some_and(|b| *b != b'\n') {
+            lines += 1;
+        }
+    } else if lines > 0 {
+        lines = (lines as f64 * size as f64 / sample.len() as f64).round() as u64;
+    }
+    Ok(lines.saturating_sub(has_header as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn csv_rows_are_counted_or_extrapolated() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("small.csv");
+        fs::write(&small, "a,b\n1,2\n3,4").unwrap();
+        assert_eq!(csv_rows(&small, true).unwrap(), 2);
+        assert_eq!(csv_rows(&small, false).unwrap(), 3);
+
+        let large = dir.path().join("large.csv");
+        let mut text = String::from("id\n");
+        for i in 0..100_000 {
+            text.push_str(&format!("{:07}\n", i));
+        }
+        fs::write(&large, text).unwrap();
+        let rows = csv_rows(&large, true).unwrap();
+        assert!((99_000..=101_000).contains(&rows), "{}", rows);
+    }
+
+    #[test]
+    fn sources_are_described_without_running() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("people.tsv"), "name\tage\na\t20\nb\t40\n").unwrap();
+        let ctx = ExecContext {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let request = SchemaRequest {
+            path: "people.tsv".into(),
+            format: None,
+        };
+        let schema = describe(&request.source().unwrap(), &ctx).unwrap();
+        let columns: Vec<_> = schema.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(columns, ["name", "age"]);
+        assert_eq!(schema.columns[1].dtype, "i64");
+        assert_eq!(schema.estimated_rows, Some(2));
+        assert_eq!(schema.bytes, 19);
+
+        let request = SchemaRequest {
+            path: "people.tsv".into(),
+            format: Some("json".into()),
+        };
+        assert!(request.source().is_err());
+    }
+}
@@ -0,0 +1,458 @@
+//! Registry of submitted jobs, so clients can submit a query and poll for its
+//! status and result, or listen for its events, instead of holding a request
+//! open until it finishes.
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+use crate::scheduler::{self, JobPriority, JobResult};
+
+/// Statuses a job can be in.
+pub const STATUSES: [&str; 8] = [
+    "queued",
+    "running",
+    "completed",
+    "failed",
+    "cancelled",
+    "timeout",
+    "rejected",
+    "expired",
+];
+
+/// Events kept for listeners that fall behind.
+const EVENT_BUFFER: usize = 256;
+
+/// Whether a job in `status` may still change, other than expiring.
+pub fn is_pending(status: &str) -> bool {
+    status == "queued" || status == "running"
+}
+
+/// A change of a job, as `GET /jobs/:id/events` streams it.
+#[derive(Debug, Clone)]
+pub struct JobEvent {
+    pub job_id: u64,
+    /// The job's new status, or `progress` when a running job moves on to
+    /// another step.
+    pub kind: &'static str,
+    /// The job as `GET /jobs/:id` returns it after the change.
+    pub job: Value,
+}
+
+/// A submitted job as last seen by the scheduler.
+#[derive(Clone)]
+pub struct JobEntry {
+    pub id: u64,
+    pub user: String,
+    /// One of [`STATUSES`].
+    pub status: &'static str,
+    pub cost: usize,
+    pub priority: JobPriority,
+    /// Unix milliseconds at which the job was submitted.
+    pub submitted_at_ms: u64,
+    /// Unix milliseconds at which the job started running.
+    pub started_at_ms: Option<u64>,
+    /// What a running job is doing: `parse`, `execute` or `store`, when the
+    /// job runs on this server.
+    pub step: Option<&'static str>,
+    /// Set once the job has finished.
+    pub result: Option<JobResult>,
+    /// Outcome of registering the result as a temporary dataset.
+    pub registered: Option<Value>,
+}
+
+impl JobEntry {
+    /// The job as `GET /jobs/:id` returns it, with the result in the same
+    /// form `/run-query` returns it.
+    pub fn to_json(&self) -> Value {
+        let result = self.result.as_ref();
+        let error = result.and_then(|r| r.error());
+        let mut value = json!({
+            "job_id": self.id,
+            "status": self.status,
+            "priority": self.priority,
+            "step": self.step,
+            "duration_ms": result.map(|r| r.duration.as_millis()),
+            "cost": self.cost,
+            "cached": result.map(|r| r.cached),
+            "attempts": result.map(|r| r.attempts),
+            "output": result.and_then(|r| r.output_json(self.id)),
+            "format": result.and_then(|r| r.format()),
+            "error": error.map(|e| e.message.clone()),
+            "error_kind": error.map(|e| e.kind),
+            "failed_step": error.and_then(|e| e.step),
+        });
+        if let Some(registered) = &self.registered {
+            value["registered"] = registered.clone();
+        }
+        value
+    }
+
+    /// The job as `GET /jobs` lists it: its timings and where its stored
+    /// result can be downloaded, without inline results.
+    pub fn summary_json(&self) -> Value {
+        let result = self.result.as_ref();
+        let output = result.and_then(|r| r.output.as_ref().ok());
+        let location = output.and_then(|o| match (&o.path, &o.parts) {
+            (Some(_), _) => Some(json!(scheduler::download_url(self.id, None))),
+            (None, Some(parts)) => Some(json!((0..parts.len())
+                .map(|i| scheduler::download_url(self.id, Some(i)))
+                .collect::<Vec<_>>())),
+            (None, None) => None,
+        });
+        json!({
+            "job_id": self.id,
+            "user": self.user,
+            "status": self.status,
+            "priority": self.priority,
+            "submitted_at": self.submitted_at_ms,
+            "started_at": self.started_at_ms,
+            "duration_ms": result.map(|r| r.duration.as_millis()),
+            "cost": self.cost,
+            "output_location": location,
+        })
+    }
+}
+
+/// Which jobs `GET /jobs` lists.
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    /// Only jobs in this status.
+    pub status: Option<String>,
+    /// Only jobs of this user.
+    pub user: Option<String>,
+    /// Only jobs of this priority.
+    pub priority: Option<JobPriority>,
+}
+
+impl JobFilter {
+    fn matches(&self, entry: &JobEntry) -> bool {
+        self.status.as_deref().is_none_or(|s| s == entry.status)
+            && self.user.as_deref().is_none_or(|u| u == entry.user)
+            && self.priority.is_none_or(|p| p == entry.priority)
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    jobs: HashMap<u64, JobEntry>,
+    /// Ids of finished jobs, oldest first.
+    finished: VecDeque<u64>,
+}
+
+/// Jobs of this scheduler, keeping the `retain` most recently finished ones
+/// with their results.
+pub struct JobRegistry {
+    retain: usize,
+    entries: Mutex<Entries>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl JobRegistry {
+    pub fn new(retain: usize) -> Self {
+        JobRegistry {
+            retain,
+            entries: Mutex::new(Entries::default()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    /// Listen for the events of every job.
+    pub fn listen(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, kind: &'static str, entry: &JobEntry) {
+        // Sending only fails when nobody is listening.
+        let _ = self.events.send(JobEvent {
+            job_id: entry.id,
+            kind,
+            job: entry.to_json(),
+        });
+    }
+
+    /// Record a job accepted with `status`.
+    pub fn submitted(
+        &self,
+        id: u64,
+        user: &str,
+        priority: JobPriority,
+        status: &'static str,
+        cost: usize,
+    ) {
+        let entry = JobEntry {
+            id,
+            user: user.to_string(),
+            status,
+            cost,
+            priority,
+            submitted_at_ms: scheduler::now_ms(),
+            started_at_ms: None,
+            step: None,
+            result: None,
+            registered: None,
+        };
+        self.notify(status, &entry);
+        self.entries.lock().unwrap().jobs.insert(id, entry);
+    }
+
+    /// Mark a queued job as running.
+    pub fn started(&self, id: u64) {
+        if let Some(entry) = self.entries.lock().unwrap().jobs.get_mut(&id) {
+            if entry.result.is_none() {
+                entry.status = "running";
+                entry.started_at_ms = Some(scheduler::now_ms());
+                self.notify("running", entry);
+            }
+        }
+    }
+
+    /// Record that a running job moved on to `step`.
+    pub fn progress(&self, id: u64, step: &'static str) {
+        if let Some(entry) = self.entries.lock().unwrap().jobs.get_mut(&id) {
+            if entry.result.is_none() && entry.step != Some(step) {
+                entry.step = Some(step);
+                self.notify("progress", entry);
+            }
+        }
+    }
+
+    /// Record the result of a job, forgetting the oldest finished jobs beyond
+    /// the retention limit.
+    pub fn finished(&self, id: u64, status: &'static str, result: JobResult) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.jobs.get_mut(&id) else {
+            return;
+        };
+        if entry.result.is_some() {
+            return;
+        }
+        entry.status = status;
+        entry.step = None;
+        entry.result = Some(result);
+        self.notify(status, entry);
+        entries.finished.push_back(id);
+        while entries.finished.len() > self.retain {
+            if let Some(old) = entries.finished.pop_front() {
+                entries.jobs.remove(&old);
+            }
+        }
+    }
+
+    /// Mark the jobs whose results were stored in any of the removed files
+    /// `paths` as `expired`, dropping their output. Returns their ids.
+    pub fn expire(&self, paths: &HashSet<String>) -> Vec<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut expired = Vec::new();
+        for entry in entries.jobs.values_mut() {
+            let Some(Ok(output)) = entry.result.as_mut().map(|r| &mut r.output) else {
+                continue;
+            };
+            let mut stored = output
+                .path
+                .iter()
+                .chain(output.parts.iter().flatten().map(|p| &p.path));
+            if stored.any(|path| paths.contains(path)) {
+                output.path = None;
+                output.parts = None;
+                entry.status = "expired";
+                expired.push(entry.id);
+            }
+        }
+        expired.sort_unstable();
+        expired
+    }
+
+    /// Attach the outcome of registering a job's result as a dataset.
+    pub fn set_registered(&self, id: u64, registered: Value) {
+        if let Some(entry) = self.entries.lock().unwrap().jobs.get_mut(&id) {
+            entry.registered = Some(registered);
+        }
+    }
+
+    /// Number of jobs still queued or running.
+    pub fn pending(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .jobs
+            .values()
+            .filter(|e| is_pending(e.status))
+            .count()
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobEntry> {
+        self.entries.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    /// Jobs matching `filter`, most recently submitted first, skipping
+    /// `offset` and returning at most `limit`, with the number that matched.
+    pub fn list(&self, filter: &JobFilter, offset: usize, limit: usize) -> (usize, Vec<JobEntry>) {
+        let entries = self.entries.lock().unwrap();
+        let mut matched: Vec<&JobEntry> = entries
+            .jobs
+            .values()
+            .filter(|e| filter.matches(e))
+            .collect();
+        matched.sort_by_key(|e| std::cmp::Reverse((e.submitted_at_ms, e.id)));
+        let total = matched.len();
+        let page = matched
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        (total, page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::JobError;
+    use crate::utils::PreparedOutput;
+    use std::time::Duration;
+
+    fn result(error: Option<&str>) -> JobResult {
+        let output = PreparedOutput {
+            bytes: None,
+            path: Some("out.ipc".into()),
+            parts: None,
+            reused: false,
+            format: Default::default(),
+            rows: Some(2),
+        };
+        JobResult {
+            output: match error {
+                Some(e) => Err(JobError::new("execution_failed", Some("execute"), e)),
+                None => Ok(output),
+            },
+            duration: Duration::from_millis(5),
+            cost: 1,
+            cached: false,
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn finished_jobs_are_retained_up_to_the_limit() {
+        let registry = JobRegistry::new(1);
+        registry.submitted(1, "alice", JobPriority::Normal, "queued", 1);
+        registry.submitted(2, "alice", JobPriority::Normal, "queued", 1);
+        registry.started(1);
+        assert_eq!(registry.get(1).unwrap().status, "running");
+        assert!(registry.get(1).unwrap().to_json()["output"].is_null());
+
+        registry.finished(1, "completed", result(None));
+        let json = registry.get(1).unwrap().to_json();
+        assert_eq!(json["status"], "completed");
+        assert_eq!(json["output"], "/jobs/1/download");
+        assert_eq!(json["format"], "ipc");
+
+        // A late start does not revive a finished job.
+        registry.started(1);
+        assert_eq!(registry.get(1).unwrap().status, "completed");
+
+        registry.finished(2, "failed", result(Some("boom")));
+        assert!(registry.get(1).is_none());
+        let json = registry.get(2).unwrap().to_json();
+        assert_eq!(json["error"], "boom");
+        assert_eq!(json["error_kind"], "execution_failed");
+        assert_eq!(json["failed_step"], "execute");
+    }
+
+    #[test]
+    fn state_changes_are_broadcast() {
+        let registry = JobRegistry::new(10);
+        let mut events = registry.listen();
+        registry.submitted(1, "alice", JobPriority::Normal, "queued", 1);
+        registry.started(1);
+        registry.progress(1, "execute");
+        registry.progress(1, "execute");
+        assert_eq!(registry.get(1).unwrap().to_json()["step"], "execute");
+        registry.finished(1, "completed", result(None));
+        registry.progress(1, "store");
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.job_id, 1);
+            seen.push((event.kind, event.job["step"].clone()));
+        }
+        assert_eq!(
+            seen,
+            [
+                ("queued", Value::Null),
+                ("running", Value::Null),
+                ("progress", json!("execute")),
+                ("completed", Value::Null),
+            ]
+        );
+        assert!(is_pending("running"));
+        assert!(!is_pending("completed"));
+    }
+
+    #[test]
+    fn jobs_are_listed_newest_first_by_filter() {
+        let registry = JobRegistry::new(10);
+        for id in 1..=4 {
+            let user = if id % 2 == 0 { "bob" } else { "alice" };
+            let priority = if id == 4 {
+                JobPriority::High
+            } else {
+                JobPriority::Normal
+            };
+            registry.submitted(id, user, priority, "queued", 1);
+        }
+        registry.started(3);
+        registry.finished(1, "completed", result(None));
+
+        let (total, page) = registry.list(&JobFilter::default(), 1, 2);
+        assert_eq!(total, 4);
+        assert_eq!(page.iter().map(|e| e.id).collect::<Vec<_>>(), [3, 2]);
+        assert!(page[0].started_at_ms.is_some());
+
+        let alice = JobFilter {
+            user: Some("alice".into()),
+            ..Default::default()
+        };
+        let (total, page) = registry.list(&alice, 0, 10);
+        assert_eq!(total, 2);
+        assert_eq!(
+            page[1].summary_json()["output_location"],
+            "/jobs/1/download"
+        );
+
+        let running = JobFilter {
+            status: Some("running".into()),
+            ..Default::default()
+        };
+        assert_eq!(registry.list(&running, 0, 10).1[0].id, 3);
+
+        let high = JobFilter {
+            priority: Some(JobPriority::High),
+            ..Default::default()
+        };
+        let (total, page) = registry.list(&high, 0, 10);
+        assert_eq!(total, 1);
+        assert_eq!(page[0].summary_json()["priority"], "high");
+    }
+
+    #[test]
+    fn jobs_with_removed_files_expire() {
+        let registry = JobRegistry::new(10);
+        for id in 1..=2 {
+            registry.submitted(id, "alice", JobPriority::Normal, "queued", 1);
+        }
+        registry.finished(1, "completed", result(None));
+        registry.finished(2, "failed", result(Some("boom")));
+
+        let removed = HashSet::from(["out.ipc".to_string()]);
+        assert_eq!(registry.expire(&removed), [1]);
+        let json = registry.get(1).unwrap().to_json();
+        assert_eq!(json["status"], "expired");
+        assert!(json["output"].is_null());
+        assert_eq!(json["duration_ms"], 5);
+        assert_eq!(registry.get(2).unwrap().status, "failed");
+        assert!(registry.expire(&removed).is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+//! Tokenizer for the query language.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    /// A string literal with its escapes resolved, or as written in a raw
+    /// string such as `r"\d+"`.
+    Str(String),
+    /// An operator or delimiter such as `(`, `==` or `.`.
+    Punct(&'static str),
+    /// End of a statement. Line breaks inside brackets do not end one.
+    Newline,
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Int(v) => write!(f, "`{}`", v),
+            Token::Float(v) => write!(f, "`{}`", v),
+            Token::Str(s) => write!(f, "string {:?}", s),
+            Token::Punct(p) => write!(f, "`{}`", p),
+            Token::Newline => f.write_str("end of line"),
+            Token::Eof => f.write_str("end of query"),
+        }
+    }
+}
+
+/// A token and the byte offset it starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub offset: usize,
+}
+
+const PUNCTS: [&str; 20] = [
+    "==", "!=", "<=", ">=", "(", ")", "[", "]", ",", ".", "=", "<", ">", "&", "|", "~", "+", "-",
+    "*", "/",
+];
+
+/// `message` prefixed with the 1-based line and column of `offset` in `src`.
+pub fn error_at(src: &str, offset: usize, message: impl fmt::Display) -> String {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    format!("line {}, column {}: {}", line, column, message)
+}
+
+/// Split `src` into tokens, ending with [`Token::Eof`]. `#` starts a comment
+/// running to the end of the line.
+pub fn tokenize(src: &str) -> Result<Vec<Spanned>, String> {
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        let raw = matches!(c, 'r' | 'R') && src[offset + 1..].starts_with(['"', '\'']);
+        let token = match c {
+            '\n' => {
+                chars.next();
+                if depth > 0
+                    || matches!(
+                        tokens.last(),
+                        None | Some(Spanned {
+                            token: Token::Newline,
+                            ..
+                        })
+                    )
+                {
+                    continue;
+                }
+                Token::Newline
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '#' => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+                continue;
+            }
+            c if raw || c == '"' || c == '\'' => {
+                if raw {
+                    chars.next();
+                }
+                let quote = chars.next().map_or(c, |(_, q)| q);
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, q)) if q == quote => break,
+                        // As in Python, a backslash in a raw string is kept
+                        // but still stops the next quote ending it.
+                        Some((_, '\\')) if raw => match chars.next() {
+                            Some((_, e)) if e != '\n' => {
+                                value.push('\\');
+                                value.push(e);
+                            }
+                            _ => return Err(error_at(src, offset, "unterminated string")),
+                        },
+                        // As in Python, unknown escapes are kept as written,
+                        // so Windows paths read as expected.
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, 'n')) => value.push('\n'),
+                            Some((_, 't')) => value.push('\t'),
+                            Some((_, e @ ('\\' | '"' | '\''))) => value.push(e),
+                            Some((_, e)) => {
+                                value.push('\\');
+                                value.push(e);
+                            }
+                            None => return Err(error_at(src, offset, "unterminated string")),
+                        },
+                        Some((_, '\n')) | None => {
+                            return Err(error_at(src, offset, "unterminated string"))
+                        }
+                        Some((_, ch)) => value.push(ch),
+                    }
+                }
+                Token::Str(value)
+            }
+            c if c.is_ascii_digit() => {
+                let (end, float) = scan_number(src.as_bytes(), offset);
+                while chars.next_if(|&(at, _)| at < end).is_some() {}
+                let text = src[offset..end].replace('_', "");
+                let token = if float {
+                    text.parse().map(Token::Float).ok()
+                } else {
+                    text.parse().map(Token::Int).ok()
+                };
+                token.ok_or_else(|| {
+                    error_at(
+                        src,
+                        offset,
+                        format!("invalid number `{}`", &src[offset..end]),
+                    )
+                })?
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = offset;
+                while let Some((at, ch)) =
+                    chars.next_if(|&(_, ch)| ch.is_alphanumeric() || ch == '_')
+                {
+                    end = at + ch.len_utf8();
+                }
+                Token::Ident(src[offset..end].to_string())
+            }
+            _ => {
+                let Some(punct) = PUNCTS.iter().find(|p| src[offset..].starts_with(**p)) else {
+                    return Err(error_at(
+                        src,
+                        offset,
+                        format!("unexpected character `{}`", c),
+                    ));
+                };
+                for _ in 0..punct.len() {
+                    chars.next();
+                }
+                match *punct {
+                    "(" | "[" => depth += 1,
+                    ")" | "]" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                Token::Punct(punct)
+            }
+        };
+        tokens.push(Spanned { token, offset });
+    }
+    tokens.push(Spanned {
+        token: Token::Eof,
+        offset: src.len(),
+    });
+    Ok(tokens)
+}
+
+/// End of the number starting at `bytes[start]`, and whether it has a
+/// fraction or exponent. A dot only continues a number when a digit follows,
+/// so `1.sum` is not read as a float.
+fn scan_number(bytes: &[u8], start: usize) -> (usize, bool) {
+    let digits = |mut i: usize| {
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'_') {
+            i += 1;
+        }
+        i
+    };
+    let is_digit = |i: usize| bytes.get(i).is_some_and(u8::is_ascii_digit);
+    let mut end = digits(start);
+    let mut float = false;
+    if bytes.get(end) == Some(&b'.') && is_digit(end + 1) {
+        end = digits(end + 1);
+        float = true;
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        if is_digit(end + 1 + sign) {
+            end = digits(end + 1 + sign);
+            float = true;
+        }
+    }
+    (end, float)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {
+        tokenize(src)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_statements() {
+        assert_eq!(
+            tokens("df = df.filter(\n  pl.col('a') >= -1.5e3 # note\n)\n\ndf.x"),
+            vec![
+                Token::Ident("df".into()),
+                Token::Punct("="),
+                Token::Ident("df".into()),
+                Token::Punct("."),
+                Token::Ident("filter".into()),
+                Token::Punct("("),
+                Token::Ident("pl".into()),
+                Token::Punct("."),
+                Token::Ident("col".into()),
+                Token::Punct("("),
+                Token::Str("a".into()),
+                Token::Punct(")"),
+                Token::Punct(">="),
+                Token::Punct("-"),
+                Token::Float(1500.0),
+                Token::Punct(")"),
+                Token::Newline,
+                Token::Ident("df".into()),
+                Token::Punct("."),
+                Token::Ident("x".into()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokens(r#""a\"b, (c)\t" 'C:\data' 1_000"#),
+            vec![
+                Token::Str("a\"b, (c)\t".into()),
+                Token::Str("C:\\data".into()),
+                Token::Int(1000),
+                Token::Eof
+            ]
+        );
+        assert_eq!(
+            tokens(r#"r"^a\d+\"" R'\n' rx"#),
+            vec![
+                Token::Str(r#"^a\d+\""#.into()),
+                Token::Str(r"\n".into()),
+                Token::Ident("rx".into()),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_carry_positions() {
+        assert_eq!(
+            tokenize("df = df\ndf = \"open").unwrap_err(),
+            "line 2, column 6: unterminated string"
+        );
+        assert_eq!(
+            tokenize("df = df.filter(pl.col(\"a\") ? 1)").unwrap_err(),
+            "line 1, column 28: unexpected character `?`"
+        );
+    }
+}
@@ -1,6 +1,76 @@
-pub mod api;
-pub mod executor;
-pub mod metrics;
-pub mod parser;
-pub mod scheduler;
-pub mod utils;
+//! A server running Polars queries written in a small Python-like DSL,
+//! over HTTP or in process.
+//!
+//! [`QueryServer`] is the entry point for embedding: build one from a
+//! [`Config`], mount its [`QueryServer::router`] in an axum app or call
+//! [`QueryServer::execute`] to get a `DataFrame` back, or build queries
+//! with [`Plan`] instead of formatting them. [`Cli`] is the command line of
+//! the `rdata-server` binary.
+
+pub(crate) mod access;
+pub(crate) mod api;
+pub(crate) mod audit;
+pub(crate) mod bench;
+pub(crate) mod cache;
+pub(crate) mod catalog;
+pub(crate) mod chaos;
+pub(crate) mod cli;
+pub(crate) mod cloud;
+pub(crate) mod cluster;
+pub(crate) mod compaction;
+pub(crate) mod config;
+pub(crate) mod convert;
+pub(crate) mod cron;
+pub(crate) mod diff;
+pub(crate) mod discovery;
+pub(crate) mod doctor;
+pub(crate) mod download;
+pub(crate) mod estimate;
+pub(crate) mod executor;
+pub(crate) mod explain;
+pub(crate) mod expr;
+pub(crate) mod health;
+pub(crate) mod ingest;
+pub(crate) mod introspect;
+pub(crate) mod jobs;
+pub(crate) mod lexer;
+pub(crate) mod lineage;
+pub(crate) mod lint;
+pub(crate) mod masking;
+pub(crate) mod metrics;
+pub(crate) mod parser;
+pub(crate) mod partition;
+pub(crate) mod plan;
+pub(crate) mod quality;
+pub(crate) mod quota;
+pub(crate) mod ratelimit;
+pub(crate) mod replay;
+pub(crate) mod resources;
+pub(crate) mod retention;
+pub(crate) mod sandbox;
+pub(crate) mod scheduler;
+pub(crate) mod schema;
+pub(crate) mod server;
+pub(crate) mod sessions;
+pub(crate) mod sql;
+pub(crate) mod state;
+pub(crate) mod stats;
+pub(crate) mod storage;
+pub(crate) mod streaming;
+pub(crate) mod subscriptions;
+pub(crate) mod synthetic;
+pub(crate) mod systemd;
+pub(crate) mod telemetry;
+pub(crate) mod templates;
+pub(crate) mod temporary;
+pub(crate) mod utils;
+pub(crate) mod views;
+
+pub use cli::Cli;
+pub use config::Config;
+pub use executor::ExecContext;
+pub use expr::{DType, Expr, Literal};
+pub use parser::{CsvOptions, FillStrategy, JoinKind, QueryPlan};
+pub use plan::{col, lit, Plan};
+pub use scheduler::{JobError, JobOptions, JobPriority, Scheduler, ShutdownReport};
+pub use server::{QueryServer, QueryServerBuilder};
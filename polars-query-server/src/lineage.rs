@@ -0,0 +1,337 @@
+//! Lineage of query outputs and materialized views: the query and the
+//! inputs, as they were when it ran, that each output was derived from.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::catalog::{Catalog, VersionSelector};
+use crate::executor::ExecContext;
+use crate::parser::{self, QueryPlan};
+
+/// Most job lineage records kept; the oldest are dropped first.
+pub const MAX_LINEAGE_RECORDS: usize = 10_000;
+
+/// An input read by a query, as it was when the query ran.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Source {
+    /// A catalog dataset read at `version`.
+    Table { name: String, version: u64 },
+    /// A file read directly, last modified at `modified` (Unix seconds).
+    File { path: String, modified: u64 },
+}
+
+/// Snapshot the current state of every input of `query`.
+pub fn snapshot(exec: &ExecContext, query: &str) -> Result<Vec<Source>, String> {
+    let mut sources = Vec::new();
+    for step in parser::parse_query(query)? {
+        match step.source() {
+            Some(QueryPlan::ReadParquet(paths)) => {
+                for path in paths {
+                    let path = exec.resolve_path(path);
+                    sources.push(Source::File {
+                        modified: modified_secs(Path::new(&path)),
+                        path,
+                    });
+                }
+            }
+            Some(QueryPlan::ReadCsv { path, .. }) => {
+                let path = exec.resolve_path(path);
+                sources.push(Source::File {
+                    modified: modified_secs(Path::new(&path)),
+                    path,
+                });
+            }
+            Some(QueryPlan::ReadTable { name, version, .. }) => {
+                let version = match version {
+                    Some(v) => *v,
+                    None => {
+                        exec.catalog
+                            .as_ref()
+                            .ok_or("no dataset catalog configured")?
+                            .resolve(name, VersionSelector::Latest)?
+                            .1
+                            .version
+                    }
+                };
+                sources.push(Source::Table {
+                    name: name.clone(),
+                    version,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(sources)
+}
+
+/// Inputs in `sources`, snapshotted when `query` ran, that changed since.
+/// Tables pinned to a version or point in time never count as changed.
+pub fn changed_sources(catalog: &Catalog, query: &str, sources: &[Source]) -> Vec<String> {
+    let pinned: Vec<String> = parser::parse_query(query)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|step| match step.source() {
+            Some(QueryPlan::ReadTable {
+                name,
+                version,
+                as_of,
+            }) if version.is_some() || as_of.is_some() => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    sources
+        .iter()
+        .filter_map(|source| match source {
+            Source::Table { name, version } => {
+                let current = catalog.get(name).map(|d| d.version);
+                (!pinned.contains(name) && current != Some(*version)).then(|| name.clone())
+            }
+            Source::File { path, modified } => {
+                (modified_secs(Path::new(path)) != *modified).then(|| path.clone())
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn modified_secs(path: &Path) -> u64 {
+    fs::metadata(path)
+        .ok()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// How a job's output files were produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobLineage {
+    pub job_id: u64,
+    pub user: String,
+    pub query: String,
+    pub sources: Vec<Source>,
+    /// Result files written by the job.
+    pub outputs: Vec<String>,
+    /// Unix seconds the job ran at.
+    pub created_at: u64,
+}
+
+/// Lineage of an output together with its current staleness.
+#[derive(Debug, Clone, Serialize)]
+pub struct Lineage {
+    /// Job that wrote the output, for query results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+    /// View the output materializes, for views.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view: Option<String>,
+    /// Version of the view the output is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    pub query: String,
+    pub sources: Vec<Source>,
+    pub outputs: Vec<String>,
+    pub created_at: Option<u64>,
+    /// `true` when any input changed since the output was written.
+    pub stale: bool,
+    pub changed_sources: Vec<String>,
+}
+
+impl Lineage {
+    /// Lineage of the outputs of the job `record` describes.
+    pub fn of_job(record: JobLineage, catalog: &Catalog) -> Self {
+        let changed_sources = changed_sources(catalog, &record.query, &record.sources);
+        Lineage {
+            job_id: Some(record.job_id),
+            view: None,
+            version: None,
+            query: record.query,
+            sources: record.sources,
+            outputs: record.outputs,
+            created_at: Some(record.created_at),
+            stale: !changed_sources.is_empty(),
+            changed_sources,
+        }
+    }
+
+    /// Lineage of the current version of the view `name`.
+    pub fn of_view(catalog: &Catalog, name: &str) -> Result<Self, String> {
+        let dataset = catalog
+            .get(name)
+            .ok_or_else(|| format!("unknown dataset {}", name))?;
+        let view = dataset
+            .view
+            .ok_or_else(|| format!("dataset {} is not a view", name))?;
+        let outputs = dataset
+            .versions
+            .last()
+            .map(|v| v.files.iter().map(|f| f.path.clone()).collect())
+            .unwrap_or_default();
+        let changed_sources = changed_sources(catalog, &view.query, &view.sources);
+        Ok(Lineage {
+            job_id: None,
+            view: Some(dataset.name),
+            version: Some(dataset.version),
+            query: view.query,
+            sources: view.sources,
+            outputs,
+            created_at: view.refreshed_at,
+            stale: !changed_sources.is_empty(),
+            changed_sources,
+        })
+    }
+}
+
+/// Lineage records of job outputs, optionally persisted to a JSON file.
+#[derive(Debug, Default)]
+pub struct LineageStore {
+    path: Option<PathBuf>,
+    records: RwLock<BTreeMap<u64, JobLineage>>,
+}
+
+impl LineageStore {
+    /// A store that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Open the store persisted at `path`, starting empty if it does not exist.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let records = if path.exists() {
+            let list: Vec<JobLineage> = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            list.into_iter().map(|r| (r.job_id, r)).collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(LineageStore {
+            path: Some(path),
+            records: RwLock::new(records),
+        })
+    }
+
+    fn persist(&self, records: &BTreeMap<u64, JobLineage>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let list: Vec<&JobLineage> = records.values().collect();
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&list)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Record how a job's outputs were produced.
+    pub fn record(&self, record: JobLineage) -> Result<(), String> {
+        let mut records = self.records.write().unwrap();
+        records.insert(record.job_id, record);
+        while records.len() > MAX_LINEAGE_RECORDS {
+            records.pop_first();
+        }
+        self.persist(&records).map_err(|e| e.to_string())
+    }
+
+    /// The lineage record of job `id`.
+    pub fn get(&self, id: u64) -> Option<JobLineage> {
+        self.records.read().unwrap().get(&id).cloned()
+    }
+
+    /// Lineage of every job output and current view version that includes
+    /// the file `path`, newest job first.
+    pub fn file(&self, catalog: &Catalog, path: &str) -> Vec<Lineage> {
+        let jobs: Vec<JobLineage> = self
+            .records
+            .read()
+            .unwrap()
+            .values()
+            .rev()
+            .filter(|r| r.outputs.iter().any(|o| o == path))
+            .cloned()
+            .collect();
+        let mut found: Vec<Lineage> = jobs
+            .into_iter()
+            .map(|r| Lineage::of_job(r, catalog))
+            .collect();
+        found.extend(
+            catalog
+                .list()
+                .into_iter()
+                .filter(|d| d.view.is_some())
+                .filter_map(|d| Lineage::of_view(catalog, &d.name).ok())
+                .filter(|l| l.outputs.iter().any(|o| o == path)),
+        );
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{DatasetFormat, DatasetSpec};
+    use polars::prelude::*;
+    use std::fs::File;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn job_output_goes_stale_when_source_changes() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("people.parquet");
+        let write = |ages: &[i32]| {
+            let mut df = df!["age" => ages].unwrap();
+            ParquetWriter::new(File::create(&data).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        };
+        write(&[20, 40]);
+        let catalog = Arc::new(Catalog::in_memory());
+        let spec = DatasetSpec {
+            name: "people".into(),
+            location: data.to_string_lossy().to_string(),
+            format: DatasetFormat::Parquet,
+            ..Default::default()
+        };
+        catalog.register(spec.clone(), "alice").unwrap();
+        let exec = ExecContext {
+            catalog: Some(catalog.clone()),
+            ..Default::default()
+        };
+
+        let query = "df = pl.read_table(\"people\")";
+        let store = LineageStore::open(dir.path().join("lineage.json")).unwrap();
+        store
+            .record(JobLineage {
+                job_id: 7,
+                user: "alice".into(),
+                query: query.into(),
+                sources: snapshot(&exec, query).unwrap(),
+                outputs: vec!["out.parquet".into()],
+                created_at: 0,
+            })
+            .unwrap();
+        let lineage = Lineage::of_job(store.get(7).unwrap(), &catalog);
+        assert_eq!(
+            lineage.sources,
+            vec![Source::Table {
+                name: "people".into(),
+                version: 1
+            }]
+        );
+        assert!(!lineage.stale);
+
+        write(&[20, 40, 60]);
+        catalog.register(spec, "alice").unwrap();
+        let reopened = LineageStore::open(dir.path().join("lineage.json")).unwrap();
+        let found = reopened.file(&catalog, "out.parquet");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].stale);
+        assert_eq!(found[0].changed_sources, vec!["people".to_string()]);
+    }
+}
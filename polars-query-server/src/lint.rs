@@ -0,0 +1,241 @@
+//! Lint pass flagging query antipatterns, with suggested rewrites.
+
+use polars::prelude::*;
+use serde::Serialize;
+
+use crate::executor::ExecContext;
+use crate::parser::QueryPlan;
+
+/// Reads of more than this many bytes without a filter are flagged.
+pub const LARGE_SCAN_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// A flagged antipattern.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LintWarning {
+    /// Identifier of the rule, e.g. `sort_before_filter`.
+    pub rule: &'static str,
+    /// 1-based index of the offending step.
+    pub step: usize,
+    pub message: String,
+    /// Rewrite addressing the warning.
+    pub suggestion: String,
+}
+
+/// What the linter knows about the frame a read produced.
+struct Source {
+    label: String,
+    bytes: Option<u64>,
+    /// Column names and whether they are numeric.
+    columns: Vec<(String, bool)>,
+    partition_by: Option<String>,
+}
+
+fn describe(step: &QueryPlan, ctx: &ExecContext) -> Option<Source> {
+    match step {
+        QueryPlan::ReadTable { name, .. } => {
+            let dataset = ctx.catalog.as_ref()?.get(name)?;
+            let bytes = dataset
+                .versions
+                .last()
+                .map(|v| v.files.iter().map(|f| f.size).sum());
+            let columns = dataset
+                .schema
+                .iter()
+                .map(|c| {
+                    let numeric = matches!(
+                        c.dtype.as_str(),
+                        "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" | "f32" | "f64"
+                    );
+                    (c.name.clone(), numeric)
+                })
+                .collect();
+            Some(Source {
+                label: format!("dataset {}", name),
+                bytes,
+                columns,
+                partition_by: dataset.partition_by,
+            })
+        }
+        QueryPlan::ReadParquet(paths) => {
+            let files = ctx.parquet_files(paths).ok()?;
+            let columns = LazyFrame::scan_parquet(&files[0].path, Default::default())
+                .and_then(|lf| lf.schema())
+                .map(|schema| {
+                    schema
+                        .iter()
+                        .map(|(name, dtype)| (name.to_string(), dtype.is_numeric()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(Source {
+                label: paths
+                    .iter()
+                    .map(|p| ctx.resolve_path(p))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                bytes: Some(files.iter().map(|f| f.size).sum()),
+                columns,
+                partition_by: None,
+            })
+        }
+        QueryPlan::ReadCsv { path, .. } => {
+            let path = ctx.resolve_path(path);
+            let size = std::fs::metadata(&path).ok()?.len();
+            Some(Source {
+                label: path,
+                bytes: Some(size),
+                columns: Vec::new(),
+                partition_by: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Warning for a read of more than [`LARGE_SCAN_BYTES`] at step `at` that no
+/// filter follows.
+fn unfiltered(source: &Option<Source>, filtered: bool, at: usize) -> Option<LintWarning> {
+    let src = source.as_ref()?;
+    let bytes = src.bytes.filter(|b| *b > LARGE_SCAN_BYTES)?;
+    if filtered {
+        return None;
+    }
+    let suggestion = match &src.partition_by {
+        Some(key) => format!(
+            "filter on the partition column, e.g. df = df.filter(pl.col(\"{}\") == ...), so only matching partitions are read",
+            key
+        ),
+        None => "add a filter after the read to reduce the rows processed".to_string(),
+    };
+    Some(LintWarning {
+        rule: "unfiltered_large_read",
+        step: at,
+        message: format!(
+            "{} is {} MiB and is read without a filter",
+            src.label,
+            bytes / (1024 * 1024)
+        ),
+        suggestion,
+    })
+}
+
+/// Flag antipatterns in `steps`, looking up datasets and files in `ctx`.
+pub fn lint(steps: &[QueryPlan], ctx: &ExecContext) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    let mut source: Option<Source> = None;
+    let mut filtered = false;
+    let mut sorted_at: Option<usize> = None;
+
+    let mut read_at = 0;
+    for (i, step) in steps.iter().enumerate() {
+        let n = i + 1;
+        match step {
+            QueryPlan::ReadParquet(_) | QueryPlan::ReadCsv { .. } | QueryPlan::ReadTable { .. } => {
+                warnings.extend(unfiltered(&source, filtered, read_at));
+                source = describe(step, ctx);
+                filtered = false;
+                sorted_at = None;
+                read_at = n;
+            }
+            QueryPlan::Filter(expr) => {
+                filtered = true;
+                if let Some(at) = sorted_at {
+                    warnings.push(LintWarning {
+                        rule: "sort_before_filter",
+                        step: n,
+                        message: format!("filter runs after the sort at step {}", at),
+                        suggestion: "move the filter before the sort so fewer rows are sorted"
+                            .to_string(),
+                    });
+                }
+                for (column, op, value) in expr.conjuncts().iter().filter_map(|e| e.comparison()) {
+                    let numeric = source
+                        .as_ref()
+                        .and_then(|s| s.columns.iter().find(|(c, _)| *c == column))
+                        .is_some_and(|(_, numeric)| *numeric);
+                    let unquoted = value.trim_matches('"');
+                    if numeric && value.starts_with('"') && unquoted.parse::<f64>().is_ok() {
+                        warnings.push(LintWarning {
+                            rule: "string_comparison_on_numeric",
+                            step: n,
+                            message: format!("numeric column {} is compared with a string", column),
+                            suggestion: format!(
+                                "df = df.filter(pl.col(\"{}\") {} {})",
+                                column, op, unquoted
+                            ),
+                        });
+                    }
+                }
+            }
+            QueryPlan::Sort(_) => sorted_at = Some(n),
+            QueryPlan::Select(_) => {
+                if let Some(at) = sorted_at {
+                    warnings.push(LintWarning {
+                        rule: "select_after_heavy_compute",
+                        step: n,
+                        message: format!(
+                            "columns are selected after the sort at step {}, which carried every column",
+                            at
+                        ),
+                        suggestion: "select the needed columns right after the read".to_string(),
+                    });
+                }
+            }
+            QueryPlan::GroupBy(_)
+            | QueryPlan::Agg(_)
+            | QueryPlan::Join { .. }
+            | QueryPlan::Head(_)
+            | QueryPlan::Tail(_)
+            | QueryPlan::Slice { .. }
+            | QueryPlan::Unique { .. }
+            | QueryPlan::DropNulls(_)
+            | QueryPlan::FillNull(_)
+            | QueryPlan::WithColumns(_)
+            | QueryPlan::Pivot { .. }
+            | QueryPlan::Melt { .. } => {}
+        }
+    }
+    warnings.extend(unfiltered(&source, filtered, read_at));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn antipatterns_are_flagged() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("people.parquet");
+        let mut df = df!["name" => ["a"], "age" => [20]].unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let query = format!(
+            "df = pl.read_parquet(\"{}\")\ndf = df.sort(\"age\")\ndf = df.filter(pl.col(\"age\") > \"30\")\ndf = df.select([\"name\"])",
+            data.display()
+        );
+        let steps = parse_query(&query).unwrap();
+        let rules: Vec<_> = lint(&steps, &ExecContext::default())
+            .into_iter()
+            .map(|w| (w.rule, w.step))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("sort_before_filter", 3),
+                ("string_comparison_on_numeric", 3),
+                ("select_after_heavy_compute", 4),
+            ]
+        );
+
+        let clean = format!(
+            "df = pl.read_parquet(\"{}\")\ndf = df.filter(pl.col(\"age\") > 30)",
+            data.display()
+        );
+        assert!(lint(&parse_query(&clean).unwrap(), &ExecContext::default()).is_empty());
+    }
+}
@@ -1,18 +1,7 @@
-mod api;
-mod executor;
-mod metrics;
-mod parser;
-mod scheduler;
-mod utils;
+use clap::Parser;
+use polars_query_server::Cli;
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
-    if std::env::var("SKIP_SERVER").is_ok() {
-        // Used in tests to avoid starting the server
-        return;
-    }
-
-    api::start_server().await;
+    Cli::parse().run().await;
 }
@@ -1,47 +1,642 @@
 use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::fs::File;
-use std::io::Result as IoResult;
-use std::path::Path;
-
-/// Append a single metric row to `metrics/query_metrics.parquet`.
-///
-/// If the file already exists it will be loaded, the row appended and then
-/// written back. Otherwise a new file is created.
-pub fn record_metrics(
-    query: &str,
-    duration_ms: u128,
-    cost: usize,
-    output_size: u64,
-) -> IoResult<()> {
-    let mut df = df![
-        "query" => [query.to_string()],
-        "duration_ms" => [duration_ms as i64],
-        "cost" => [cost as i64],
-        "output_size" => [output_size as i64]
-    ]
-    .map_err(|e| std::io::Error::other(e.to_string()))?;
-
-    let path = Path::new("metrics/query_metrics.parquet");
-    let mut df_to_write = if path.exists() {
-        let file = File::open(path)?;
-        let mut existing = ParquetReader::new(file)
-            .finish()
-            .map_err(|e| std::io::Error::other(e.to_string()))?;
-        existing
-            .vstack_mut(&mut df)
-            .map_err(|e| std::io::Error::other(e.to_string()))?;
-        existing
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// One row of the metrics store, describing a job that ran.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobMetrics {
+    pub job_id: u64,
+    pub query: String,
+    /// Caller the job ran for, from the `X-User-Id` header.
+    pub user: String,
+    /// `completed`, `failed`, `cancelled` or `timeout`.
+    pub status: String,
+    /// Kind of the error of a job that did not complete.
+    pub error_kind: Option<String>,
+    /// Unix milliseconds the job arrived, so workloads can be replayed.
+    pub submitted_at_ms: u64,
+    /// Milliseconds the job waited in the queue before it started.
+    pub queue_wait_ms: u64,
+    /// Milliseconds the job ran for, storing its result included.
+    pub duration_ms: u64,
+    pub cost: usize,
+    /// Rows of the result, unknown when the job failed.
+    pub rows: Option<u64>,
+    /// Bytes of the result.
+    pub output_size: u64,
+}
+
+impl JobMetrics {
+    /// The row as a one-row frame.
+    fn to_frame(&self) -> PolarsResult<DataFrame> {
+        df![
+            "job_id" => [self.job_id as i64],
+            "query" => [self.query.as_str()],
+            "user" => [self.user.as_str()],
+            "status" => [self.status.as_str()],
+            "error_kind" => [self.error_kind.as_deref()],
+            "submitted_at_ms" => [self.submitted_at_ms as i64],
+            "queue_wait_ms" => [self.queue_wait_ms as i64],
+            "duration_ms" => [self.duration_ms as i64],
+            "cost" => [self.cost as i64],
+            "rows" => [self.rows.map(|r| r as i64)],
+            "output_size" => [self.output_size as i64],
+        ]
+    }
+}
+
+/// Buffering of the metrics store: rows of finished jobs are kept in memory
+/// and written as new files, one directory per day, so recording a job never
+/// rewrites earlier rows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Directory of the metrics store.
+    pub dir: PathBuf,
+    /// Milliseconds recorded rows wait at most before they are written.
+    pub flush_interval_ms: u64,
+    /// Rows buffered before the job recording them writes them itself.
+    pub max_batch_rows: usize,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            dir: PathBuf::from("metrics"),
+            flush_interval_ms: 5000,
+            max_batch_rows: 1000,
+        }
+    }
+}
+
+impl MetricsConfig {
+    /// Problems with the configured limits.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.flush_interval_ms == 0 {
+            errors.push("storage.metrics.flush_interval_ms must be at least 1".to_string());
+        }
+        if self.max_batch_rows == 0 {
+            errors.push("storage.metrics.max_batch_rows must be at least 1".to_string());
+        }
+        errors
+    }
+}
+
+/// Rows recorded and not yet written, with when the first of them was.
+#[derive(Default)]
+struct Pending {
+    rows: Vec<JobMetrics>,
+    since: Option<Instant>,
+}
+
+/// The metrics store, appended to in batches. Each batch is written to a
+/// file of its own under `day=YYYY-MM-DD`, the UTC day the jobs arrived,
+/// named after the writing process so servers sharing the directory never
+/// write the same file.
+pub struct MetricsLog {
+    config: MetricsConfig,
+    pending: Mutex<Pending>,
+    /// Files written by this process, numbering the next one.
+    written: AtomicU64,
+}
+
+impl MetricsLog {
+    pub fn new(config: MetricsConfig) -> Self {
+        MetricsLog {
+            config,
+            pending: Mutex::new(Pending::default()),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &MetricsConfig {
+        &self.config
+    }
+
+    /// Buffer the row of a finished job, writing the buffered rows right
+    /// away once `max_batch_rows` are waiting.
+    pub fn record(&self, row: JobMetrics) -> Result<(), String> {
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.since.get_or_insert_with(Instant::now);
+            pending.rows.push(row);
+            pending.rows.len() >= self.config.max_batch_rows
+        };
+        if full {
+            self.flush(true)?;
+        }
+        Ok(())
+    }
+
+    /// Rows recorded and not yet written.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().rows.len()
+    }
+
+    /// Write the buffered rows once they have waited for the flush interval,
+    /// or right away when `all` is set, returning the files written. Rows
+    /// that fail to be written are buffered again.
+    pub fn flush(&self, all: bool) -> Result<Vec<PathBuf>, String> {
+        let interval = Duration::from_millis(self.config.flush_interval_ms);
+        let rows = {
+            let mut pending = self.pending.lock().unwrap();
+            let due = pending
+                .since
+                .is_some_and(|since| all || since.elapsed() >= interval);
+            if !due {
+                return Ok(Vec::new());
+            }
+            pending.since = None;
+            std::mem::take(&mut pending.rows)
+        };
+        let mut days: BTreeMap<String, Vec<JobMetrics>> = BTreeMap::new();
+        for row in rows {
+            days.entry(day_of(row.submitted_at_ms))
+                .or_default()
+                .push(row);
+        }
+        let mut written = Vec::new();
+        let mut failed = Vec::new();
+        let mut error = None;
+        for (day, rows) in days {
+            match self.write(&day, &rows) {
+                Ok(path) => written.push(path),
+                Err(e) => {
+                    error = Some(e);
+                    failed.extend(rows);
+                }
+            }
+        }
+        match error {
+            Some(e) => {
+                let mut pending = self.pending.lock().unwrap();
+                pending.since.get_or_insert_with(Instant::now);
+                failed.append(&mut pending.rows);
+                pending.rows = failed;
+                Err(e)
+            }
+            None => Ok(written),
+        }
+    }
+
+    /// Write `rows` as a new file of the directory of `day`. The file is
+    /// written under a temporary name and renamed, so readers never see it
+    /// half written.
+    fn write(&self, day: &str, rows: &[JobMetrics]) -> Result<PathBuf, String> {
+        let mut df = to_frame(rows).map_err(|e| e.to_string())?;
+        let dir = self.config.dir.join(format!("day={}", day));
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        let name = format!(
+            "part-{}-{}-{}",
+            chrono::Utc::now().timestamp_millis(),
+            std::process::id(),
+            self.written.fetch_add(1, Ordering::Relaxed)
+        );
+        let tmp = dir.join(format!(".{}.tmp", name));
+        let path = dir.join(format!("{}.parquet", name));
+        let file =
+            File::create(&tmp).map_err(|e| format!("failed to create {}: {}", tmp.display(), e))?;
+        ParquetWriter::new(file)
+            .finish(&mut df)
+            .map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, &path)
+            .map_err(|e| format!("failed to rename {}: {}", tmp.display(), e))?;
+        Ok(path)
+    }
+}
+
+/// UTC day, as `YYYY-MM-DD`, of the Unix milliseconds `ms`.
+fn day_of(ms: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(ms as i64)
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// `rows` as a frame, one row each.
+fn to_frame(rows: &[JobMetrics]) -> PolarsResult<DataFrame> {
+    let mut frames = rows.iter().map(JobMetrics::to_frame);
+    let mut df = match frames.next() {
+        Some(first) => first?,
+        None => JobMetrics::default().to_frame()?.head(Some(0)),
+    };
+    for frame in frames {
+        df.vstack_mut(&frame?)?;
+    }
+    df.align_chunks();
+    Ok(df)
+}
+
+/// Write buffered rows once they have waited for the flush interval.
+pub fn spawn_flusher(log: Arc<MetricsLog>) {
+    tokio::spawn(async move {
+        // Checking twice per interval bounds the wait at 1.5 intervals.
+        let period = Duration::from_millis((log.config.flush_interval_ms / 2).max(1));
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let log = log.clone();
+            match tokio::task::spawn_blocking(move || log.flush(false)).await {
+                Ok(Ok(written)) => {
+                    for path in &written {
+                        tracing::debug!(path = %path.display(), "flushed job metrics");
+                    }
+                }
+                Ok(Err(e)) => tracing::warn!("failed to flush job metrics: {}", e),
+                Err(_) => {}
+            }
+        }
+    });
+}
+
+/// Read the metrics store at `path`: a single file, or a directory holding
+/// the daily files and a `query_metrics.parquet` written by earlier
+/// versions. Columns missing from older files are filled with nulls.
+pub fn read_metrics(path: &Path) -> Result<DataFrame, String> {
+    let files = if path.is_dir() {
+        let mut files = Vec::new();
+        let legacy = path.join("query_metrics.parquet");
+        if legacy.is_file() {
+            files.push(legacy);
+        }
+        let pattern = path.join("day=*").join("*.parquet");
+        let mut parts: Vec<PathBuf> = glob::glob(&pattern.to_string_lossy())
+            .map_err(|e| e.to_string())?
+            .filter_map(Result::ok)
+            .collect();
+        parts.sort();
+        files.extend(parts);
+        if files.is_empty() {
+            return Err(format!("no metrics files in {}", path.display()));
+        }
+        files
     } else {
-        df
+        vec![path.to_path_buf()]
     };
+    let mut frames = Vec::with_capacity(files.len());
+    for file in &files {
+        let handle =
+            File::open(file).map_err(|e| format!("failed to open {}: {}", file.display(), e))?;
+        let df = ParquetReader::new(handle)
+            .finish()
+            .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+        frames.push(df);
+    }
+    let mut schema = Schema::new();
+    for df in &frames {
+        for field in df.schema().iter_fields() {
+            if schema.get(field.name()).is_none() {
+                schema.with_column(field.name().clone(), field.data_type().clone());
+            }
+        }
+    }
+    let names: Vec<&str> = schema.iter_names().map(|n| n.as_str()).collect();
+    let mut combined: Option<DataFrame> = None;
+    for mut df in frames {
+        for (name, dtype) in schema.iter() {
+            if df.column(name).is_err() {
+                let nulls = Series::full_null(name, df.height(), dtype);
+                df.with_column(nulls).map_err(|e| e.to_string())?;
+            }
+        }
+        let df = df.select(&names).map_err(|e| e.to_string())?;
+        match combined.as_mut() {
+            Some(all) => {
+                all.vstack_mut(&df).map_err(|e| e.to_string())?;
+            }
+            None => combined = Some(df),
+        }
+    }
+    let mut df = combined.unwrap_or_default();
+    df.align_chunks();
+    Ok(df)
+}
+
+/// Upper bounds, in seconds, of the job duration histogram buckets.
+pub const DURATION_BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// Counters and histograms of the scheduler, served at `GET /metrics` in the
+/// Prometheus text format.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    cancelled: AtomicU64,
+    timed_out: AtomicU64,
+    /// Attempts at jobs repeated after transient failures.
+    retried: AtomicU64,
+    rejected: AtomicU64,
+    /// Submissions refused by rate limits or a full queue.
+    throttled: AtomicU64,
+    /// Jobs accepted but not yet started.
+    queued: AtomicI64,
+    bytes_returned: AtomicU64,
+    /// Jobs per duration bucket, not cumulative; the last counts the jobs
+    /// slower than every bound.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_micros: AtomicU64,
+}
+
+impl ServerMetrics {
+    /// Count a job accepted into the queue.
+    pub fn job_submitted(&self) {
+        self.submitted.fetch_add(1, Ordering::Relaxed);
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a job rejected at submission, which never enters the queue.
+    pub fn job_rejected(&self) {
+        self.submitted.fetch_add(1, Ordering::Relaxed);
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a submission refused before it became a job.
+    pub fn job_throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Jobs accepted but not yet started.
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed).max(0) as u64
+    }
 
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+    /// Count a job leaving the queue, to run or cancelled.
+    pub fn job_dequeued(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
     }
 
-    let file = File::create(path)?;
-    ParquetWriter::new(file)
-        .finish(&mut df_to_write)
-        .map_err(|e| std::io::Error::other(e.to_string()))?;
-    Ok(())
+    /// Count a job run again after a transient failure.
+    pub fn job_retried(&self) {
+        self.retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a job finishing with `status`. `duration` is that of jobs that
+    /// ran, `bytes` the size of their result.
+    pub fn job_finished(&self, status: &str, duration: Option<Duration>, bytes: u64) {
+        let counter = match status {
+            "completed" => &self.completed,
+            "cancelled" => &self.cancelled,
+            "timeout" => &self.timed_out,
+            _ => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.bytes_returned.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(duration) = duration {
+            let secs = duration.as_secs_f64();
+            let bucket = DURATION_BUCKETS
+                .iter()
+                .position(|bound| secs <= *bound)
+                .unwrap_or(DURATION_BUCKETS.len());
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+            self.duration_micros
+                .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format, with the
+    /// scheduler's `active` jobs and `max_workers` limit as gauges.
+    pub fn render(&self, active: usize, max_workers: usize) -> String {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "rdata_jobs_submitted_total",
+            "counter",
+            "Jobs submitted, including rejected ones.",
+            &[("", load(&self.submitted).to_string())],
+        );
+        write_metric(
+            &mut out,
+            "rdata_jobs_finished_total",
+            "counter",
+            "Jobs finished, by status.",
+            &[
+                ("{status=\"completed\"}", load(&self.completed).to_string()),
+                ("{status=\"failed\"}", load(&self.failed).to_string()),
+                ("{status=\"cancelled\"}", load(&self.cancelled).to_string()),
+                ("{status=\"timeout\"}", load(&self.timed_out).to_string()),
+                ("{status=\"rejected\"}", load(&self.rejected).to_string()),
+            ],
+        );
+        write_metric(
+            &mut out,
+            "rdata_job_retries_total",
+            "counter",
+            "Attempts at jobs repeated after transient I/O failures.",
+            &[("", load(&self.retried).to_string())],
+        );
+        write_metric(
+            &mut out,
+            "rdata_submissions_throttled_total",
+            "counter",
+            "Submissions refused with 429 by rate limits or a full queue.",
+            &[("", load(&self.throttled).to_string())],
+        );
+        write_metric(
+            &mut out,
+            "rdata_queue_depth",
+            "gauge",
+            "Jobs waiting for a free worker.",
+            &[("", self.queued.load(Ordering::Relaxed).max(0).to_string())],
+        );
+        write_metric(
+            &mut out,
+            "rdata_active_workers",
+            "gauge",
+            "Jobs executing.",
+            &[("", active.to_string())],
+        );
+        write_metric(
+            &mut out,
+            "rdata_max_workers",
+            "gauge",
+            "Jobs allowed to execute at once.",
+            &[("", max_workers.to_string())],
+        );
+        let mut samples = Vec::new();
+        let mut cumulative = 0;
+        for (i, bucket) in self.duration_buckets.iter().enumerate() {
+            cumulative += load(bucket);
+            let bound = DURATION_BUCKETS
+                .get(i)
+                .map_or("+Inf".to_string(), |b| b.to_string());
+            samples.push((
+                format!("_bucket{{le=\"{}\"}}", bound),
+                cumulative.to_string(),
+            ));
+        }
+        let sum = load(&self.duration_micros) as f64 / 1e6;
+        samples.push(("_sum".to_string(), sum.to_string()));
+        samples.push(("_count".to_string(), cumulative.to_string()));
+        write_metric(
+            &mut out,
+            "rdata_job_duration_seconds",
+            "histogram",
+            "Time jobs took to run, from starting to their result being stored.",
+            &samples,
+        );
+        write_metric(
+            &mut out,
+            "rdata_bytes_returned_total",
+            "counter",
+            "Bytes of job results, returned inline or written to files.",
+            &[("", load(&self.bytes_returned).to_string())],
+        );
+        out
+    }
+}
+
+/// Write the `# HELP` and `# TYPE` lines of metric `name` and its samples,
+/// each a label set or name suffix and a value.
+fn write_metric<L: AsRef<str>>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(L, String)],
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels.as_ref(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_rows_are_buffered_and_written_per_day() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file from before the job columns were recorded.
+        let mut old = df!["query" => ["q0"], "duration_ms" => [1i64]].unwrap();
+        ParquetWriter::new(File::create(dir.path().join("query_metrics.parquet")).unwrap())
+            .finish(&mut old)
+            .unwrap();
+
+        let log = MetricsLog::new(MetricsConfig {
+            dir: dir.path().to_path_buf(),
+            flush_interval_ms: 60_000,
+            max_batch_rows: 3,
+        });
+        let completed = JobMetrics {
+            job_id: 1,
+            query: "q1".into(),
+            user: "alice".into(),
+            status: "completed".into(),
+            submitted_at_ms: 1_700_000_000_000,
+            queue_wait_ms: 5,
+            duration_ms: 20,
+            rows: Some(3),
+            output_size: 100,
+            ..Default::default()
+        };
+        let failed = JobMetrics {
+            job_id: 2,
+            status: "failed".into(),
+            error_kind: Some("execution_failed".into()),
+            ..completed.clone()
+        };
+        log.record(completed.clone()).unwrap();
+        log.record(failed).unwrap();
+        assert_eq!(log.pending(), 2);
+        assert!(log.flush(false).unwrap().is_empty());
+
+        // The batch fills up and is written by the job completing it.
+        log.record(JobMetrics {
+            job_id: 3,
+            submitted_at_ms: completed.submitted_at_ms + 86_400_000,
+            ..completed
+        })
+        .unwrap();
+        assert_eq!(log.pending(), 0);
+        let days: Vec<_> = glob::glob(&dir.path().join("day=*").to_string_lossy())
+            .unwrap()
+            .map(|d| {
+                d.unwrap()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(days, ["day=2023-11-14", "day=2023-11-15"]);
+
+        let df = read_metrics(dir.path()).unwrap();
+        assert_eq!(df.height(), 4);
+        let ids: Vec<_> = df
+            .column("job_id")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(ids, [None, Some(1), Some(2), Some(3)]);
+        let kinds: Vec<_> = df
+            .column("error_kind")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(kinds, [None, None, Some("execution_failed"), None]);
+        assert_eq!(df.column("rows").unwrap().i64().unwrap().get(1), Some(3));
+        assert_eq!(
+            df.column("queue_wait_ms").unwrap().i64().unwrap().get(2),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn render_prometheus_text() {
+        let metrics = ServerMetrics::default();
+        metrics.job_submitted();
+        metrics.job_submitted();
+        metrics.job_rejected();
+        metrics.job_throttled();
+        metrics.job_retried();
+        metrics.job_dequeued();
+        metrics.job_finished("completed", Some(Duration::from_millis(30)), 100);
+        metrics.job_finished("failed", Some(Duration::from_secs(400)), 0);
+
+        let text = metrics.render(1, 4);
+        for line in [
+            "rdata_jobs_submitted_total 3",
+            "rdata_jobs_finished_total{status=\"completed\"} 1",
+            "rdata_jobs_finished_total{status=\"failed\"} 1",
+            "rdata_jobs_finished_total{status=\"rejected\"} 1",
+            "rdata_submissions_throttled_total 1",
+            "rdata_job_retries_total 1",
+            "rdata_queue_depth 1",
+            "rdata_active_workers 1",
+            "rdata_max_workers 4",
+            "rdata_job_duration_seconds_bucket{le=\"0.01\"} 0",
+            "rdata_job_duration_seconds_bucket{le=\"0.05\"} 1",
+            "rdata_job_duration_seconds_bucket{le=\"300\"} 1",
+            "rdata_job_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "rdata_job_duration_seconds_sum 400.03",
+            "rdata_job_duration_seconds_count 2",
+            "rdata_bytes_returned_total 100",
+            "# TYPE rdata_job_duration_seconds histogram",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {}\n{}",
+                line,
+                text
+            );
+        }
+    }
 }
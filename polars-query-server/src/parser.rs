@@ -1,94 +1,1485 @@
+use crate::expr::{self, AggFunc, BinaryOp, DType, Expr, Literal, RankMethod, StrOp};
+use crate::lexer::{self, Spanned, Token};
+use chrono::NaiveDate;
+use std::fmt;
+
 /// Representation of a single query operation.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum QueryPlan {
-    ReadParquet(String),
-    Filter(String),
-    Select(Vec<String>),
-    GroupBy(String),
-    Agg(String),
+    /// Read one or more parquet files, directories or globs as one frame.
+    ReadParquet(Vec<String>),
+    /// Read a CSV file.
+    ReadCsv {
+        path: String,
+        options: CsvOptions,
+    },
+    /// Read a catalog dataset, optionally pinned to a version or point in time.
+    ReadTable {
+        name: String,
+        version: Option<u64>,
+        as_of: Option<String>,
+    },
+    Filter(Expr),
+    /// Columns kept, or computed from expressions, in order.
+    Select(Vec<Expr>),
+    /// Group by one or more key columns.
+    GroupBy(Vec<String>),
+    /// Aggregations computed per group, each an output column.
+    Agg(Vec<Expr>),
+    /// Columns computed from expressions, added to the frame or replacing
+    /// those of the same name.
+    WithColumns(Vec<Expr>),
     Sort(String),
+    /// Keep the first `n` rows.
+    Head(u64),
+    /// Keep the last `n` rows.
+    Tail(u64),
+    /// Keep `length` rows (all when `None`) from `offset`, which counts from
+    /// the end when negative.
+    Slice {
+        offset: i64,
+        length: Option<u64>,
+    },
+    /// Drop duplicate rows, comparing the `subset` columns or every column,
+    /// keeping the rows `keep` picks. Rows keep their order.
+    Unique {
+        subset: Option<Vec<String>>,
+        keep: UniqueKeep,
+    },
+    /// Drop rows with a null in any of the `subset` columns, or in any
+    /// column.
+    DropNulls(Option<Vec<String>>),
+    /// Replace nulls with a value or by a strategy.
+    FillNull(FillNull),
+    /// Spread the `values` columns, or every other column when empty, into
+    /// a column per value of the `columns` columns, with a row per value of
+    /// the `index` columns. Polars only pivots eagerly, so the frame up to
+    /// here is collected first.
+    Pivot {
+        values: Vec<String>,
+        index: Vec<String>,
+        columns: Vec<String>,
+        aggregate: PivotAggregate,
+        sort_columns: bool,
+    },
+    /// Unpivot the `value_vars` columns, or every column not in `id_vars`
+    /// when empty, into rows of a variable and a value column.
+    Melt {
+        id_vars: Vec<String>,
+        value_vars: Vec<String>,
+        variable_name: Option<String>,
+        value_name: Option<String>,
+    },
+    /// Join the frame with another source on a column of both.
+    Join {
+        /// A `ReadParquet`, `ReadCsv` or `ReadTable` step.
+        source: Box<QueryPlan>,
+        on: String,
+        how: JoinKind,
+    },
+}
+
+/// Options of `pl.read_csv`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub has_header: bool,
+    /// Rows read to infer column types; every row when `None`.
+    pub infer_schema_length: Option<usize>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            has_header: true,
+            infer_schema_length: Some(100),
+        }
+    }
+}
+
+/// Rows kept by a join.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JoinKind {
+    #[default]
+    Inner,
+    Left,
+    Outer,
+}
+
+impl JoinKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            JoinKind::Inner => "inner",
+            JoinKind::Left => "left",
+            JoinKind::Outer => "outer",
+        }
+    }
+}
+
+/// Which row of each set of duplicates `unique` keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UniqueKeep {
+    /// Any one of them, whichever is cheapest.
+    #[default]
+    Any,
+    First,
+    Last,
+    /// None of them: only rows without duplicates are kept.
+    None,
+}
+
+impl UniqueKeep {
+    pub fn name(self) -> &'static str {
+        match self {
+            UniqueKeep::Any => "any",
+            UniqueKeep::First => "first",
+            UniqueKeep::Last => "last",
+            UniqueKeep::None => "none",
+        }
+    }
+}
+
+/// How `pivot` combines the values falling into the same cell.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PivotAggregate {
+    #[default]
+    First,
+    Last,
+    Sum,
+    Min,
+    Max,
+    Mean,
+    Median,
+    Count,
+}
+
+impl PivotAggregate {
+    pub fn name(self) -> &'static str {
+        match self {
+            PivotAggregate::First => "first",
+            PivotAggregate::Last => "last",
+            PivotAggregate::Sum => "sum",
+            PivotAggregate::Min => "min",
+            PivotAggregate::Max => "max",
+            PivotAggregate::Mean => "mean",
+            PivotAggregate::Median => "median",
+            PivotAggregate::Count => "count",
+        }
+    }
+}
+
+/// What `fill_null` replaces nulls with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FillNull {
+    /// A value, filling the columns of its type.
+    Value(Literal),
+    Strategy(FillStrategy),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillStrategy {
+    /// The previous non-null value of the column.
+    Forward,
+    /// The next non-null value of the column.
+    Backward,
+    Min,
+    Max,
+    Mean,
+    /// 0 in numeric columns.
+    Zero,
+    /// 1 in numeric columns.
+    One,
 }
 
-/// Parse a simple query string into a sequence of `QueryPlan` steps.
+impl FillStrategy {
+    pub fn name(self) -> &'static str {
+        match self {
+            FillStrategy::Forward => "forward",
+            FillStrategy::Backward => "backward",
+            FillStrategy::Min => "min",
+            FillStrategy::Max => "max",
+            FillStrategy::Mean => "mean",
+            FillStrategy::Zero => "zero",
+            FillStrategy::One => "one",
+        }
+    }
+}
+
+impl QueryPlan {
+    /// Name of the step's operation, as it is written in queries.
+    pub fn op(&self) -> &'static str {
+        match self {
+            QueryPlan::ReadParquet(_) => "read_parquet",
+            QueryPlan::ReadCsv { .. } => "read_csv",
+            QueryPlan::ReadTable { .. } => "read_table",
+            QueryPlan::Filter(_) => "filter",
+            QueryPlan::Select(_) => "select",
+            QueryPlan::GroupBy(_) => "groupby",
+            QueryPlan::Agg(_) => "agg",
+            QueryPlan::WithColumns(_) => "with_columns",
+            QueryPlan::Sort(_) => "sort",
+            QueryPlan::Head(_) => "head",
+            QueryPlan::Tail(_) => "tail",
+            QueryPlan::Slice { .. } => "slice",
+            QueryPlan::Unique { .. } => "unique",
+            QueryPlan::DropNulls(_) => "drop_nulls",
+            QueryPlan::FillNull(_) => "fill_null",
+            QueryPlan::Pivot { .. } => "pivot",
+            QueryPlan::Melt { .. } => "melt",
+            QueryPlan::Join { .. } => "join",
+        }
+    }
+
+    /// The step reading data for this one: itself for reads, the joined
+    /// source for joins.
+    pub fn source(&self) -> Option<&QueryPlan> {
+        match self {
+            QueryPlan::ReadParquet(_) | QueryPlan::ReadCsv { .. } | QueryPlan::ReadTable { .. } => {
+                Some(self)
+            }
+            QueryPlan::Join { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a `select` computing a column rather than only
+    /// keeping them, so that later filters may not see source values.
+    pub fn computes_columns(&self) -> bool {
+        match self {
+            QueryPlan::Select(exprs) => exprs.iter().any(|e| !matches!(e, Expr::Column(_))),
+            _ => false,
+        }
+    }
+
+    /// `(offset, length)` of the rows a `head`, `tail` or `slice` step keeps,
+    /// with negative offsets counting from the end.
+    pub fn slice_bounds(&self) -> Option<(i64, Option<u64>)> {
+        match self {
+            QueryPlan::Head(n) => Some((0, Some(*n))),
+            QueryPlan::Tail(n) => Some((-i64::try_from(*n).unwrap_or(i64::MAX), None)),
+            QueryPlan::Slice { offset, length } => Some((*offset, *length)),
+            _ => None,
+        }
+    }
+}
+
+/// Write `names` as a list of strings.
+fn write_names(f: &mut fmt::Formatter<'_>, names: &[String]) -> fmt::Result {
+    f.write_str("[")?;
+    for (i, name) in names.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        expr::write_str(f, name)?;
+    }
+    f.write_str("]")
+}
+
+/// Write `exprs` as a list.
+fn write_exprs(f: &mut fmt::Formatter<'_>, exprs: &[Expr]) -> fmt::Result {
+    f.write_str("[")?;
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", expr)?;
+    }
+    f.write_str("]")
+}
+
+/// Renders the step as it would be written in a query: reads as
+/// `pl.read_parquet(...)`, operations as the call following `df.`, leaving
+/// out default arguments. Parsing the rendering gives the step back.
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryPlan::ReadParquet(paths) => {
+                f.write_str("pl.read_parquet(")?;
+                match paths.as_slice() {
+                    [path] => expr::write_str(f, path)?,
+                    paths => write_names(f, paths)?,
+                }
+            }
+            QueryPlan::ReadCsv { path, options } => {
+                f.write_str("pl.read_csv(")?;
+                expr::write_str(f, path)?;
+                let defaults = CsvOptions::default();
+                if options.delimiter != defaults.delimiter {
+                    f.write_str(", delimiter=")?;
+                    expr::write_str(f, &char::from(options.delimiter).to_string())?;
+                }
+                if !options.has_header {
+                    f.write_str(", has_header=False")?;
+                }
+                if options.infer_schema_length != defaults.infer_schema_length {
+                    match options.infer_schema_length {
+                        Some(rows) => write!(f, ", infer_schema_length={}", rows)?,
+                        None => f.write_str(", infer_schema_length=None")?,
+                    }
+                }
+            }
+            QueryPlan::ReadTable {
+                name,
+                version,
+                as_of,
+            } => {
+                f.write_str("pl.read_table(")?;
+                expr::write_str(f, name)?;
+                if let Some(version) = version {
+                    write!(f, ", version={}", version)?;
+                }
+                if let Some(as_of) = as_of {
+                    f.write_str(", as_of=")?;
+                    expr::write_str(f, as_of)?;
+                }
+            }
+            QueryPlan::Filter(expr) => write!(f, "filter({}", expr)?,
+            QueryPlan::Select(exprs) => {
+                f.write_str("select(")?;
+                write_exprs(f, exprs)?;
+            }
+            QueryPlan::GroupBy(keys) => {
+                f.write_str("groupby(")?;
+                write_names(f, keys)?;
+            }
+            QueryPlan::Agg(exprs) => {
+                f.write_str("agg(")?;
+                write_exprs(f, exprs)?;
+            }
+            QueryPlan::WithColumns(exprs) => {
+                f.write_str("with_columns(")?;
+                write_exprs(f, exprs)?;
+            }
+            QueryPlan::Sort(column) => {
+                f.write_str("sort(")?;
+                expr::write_str(f, column)?;
+            }
+            QueryPlan::Head(n) => write!(f, "head({}", n)?,
+            QueryPlan::Tail(n) => write!(f, "tail({}", n)?,
+            QueryPlan::Slice { offset, length } => match length {
+                Some(length) => write!(f, "slice({}, {}", offset, length)?,
+                None => write!(f, "slice({}", offset)?,
+            },
+            QueryPlan::Unique { subset, keep } => {
+                f.write_str("unique(")?;
+                let mut sep = "";
+                if let Some(subset) = subset {
+                    f.write_str("subset=")?;
+                    write_names(f, subset)?;
+                    sep = ", ";
+                }
+                if *keep != UniqueKeep::default() {
+                    write!(f, "{}keep=\"{}\"", sep, keep.name())?;
+                }
+            }
+            QueryPlan::DropNulls(subset) => {
+                f.write_str("drop_nulls(")?;
+                if let Some(subset) = subset {
+                    write_names(f, subset)?;
+                }
+            }
+            QueryPlan::FillNull(FillNull::Value(value)) => write!(f, "fill_null({}", value)?,
+            QueryPlan::FillNull(FillNull::Strategy(strategy)) => {
+                write!(f, "fill_null(strategy=\"{}\"", strategy.name())?
+            }
+            QueryPlan::Pivot {
+                values,
+                index,
+                columns,
+                aggregate,
+                sort_columns,
+            } => {
+                f.write_str("pivot(")?;
+                if !values.is_empty() {
+                    f.write_str("values=")?;
+                    write_names(f, values)?;
+                    f.write_str(", ")?;
+                }
+                f.write_str("index=")?;
+                write_names(f, index)?;
+                f.write_str(", columns=")?;
+                write_names(f, columns)?;
+                if *aggregate != PivotAggregate::default() {
+                    write!(f, ", aggregate_function=\"{}\"", aggregate.name())?;
+                }
+                if *sort_columns {
+                    f.write_str(", sort_columns=True")?;
+                }
+            }
+            QueryPlan::Melt {
+                id_vars,
+                value_vars,
+                variable_name,
+                value_name,
+            } => {
+                f.write_str("melt(")?;
+                let mut sep = "";
+                for (key, names) in [("id_vars", id_vars), ("value_vars", value_vars)] {
+                    if !names.is_empty() {
+                        write!(f, "{}{}=", sep, key)?;
+                        write_names(f, names)?;
+                        sep = ", ";
+                    }
+                }
+                for (key, name) in [("variable_name", variable_name), ("value_name", value_name)] {
+                    if let Some(name) = name {
+                        write!(f, "{}{}=", sep, key)?;
+                        expr::write_str(f, name)?;
+                        sep = ", ";
+                    }
+                }
+            }
+            QueryPlan::Join { source, on, how } => {
+                write!(f, "join({}, on=", source)?;
+                expr::write_str(f, on)?;
+                if *how != JoinKind::default() {
+                    write!(f, ", how=\"{}\"", how.name())?;
+                }
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+/// Parse a query string into a sequence of `QueryPlan` steps.
 ///
-/// The parser expects lines in the form `df = df.<op>(...)` or the initial
-/// `df = pl.read_parquet("path")`. Supported operations are:
-/// `read_parquet`, `filter`, `select`, `groupby`, `agg` and `sort`.
+/// A query is a series of statements, one per line: a read such as
+/// `df = pl.read_parquet("path")`, or `df = df.<op>(...)`. Calls can be
+/// chained, as in `df = df.filter(...).select(...)`, and may span lines
+/// inside brackets. Supported reads are `read_parquet`, `read_csv` and
+/// `read_table` (or `read_dataset`); supported operations are `filter`,
+/// `select`, `groupby` (or `group_by`), `agg`, `sort`, `head`, `tail`,
+/// `slice`, `join`, `pivot` and `melt`.
 ///
 /// On success a vector of steps is returned in the order they were parsed.
+/// Errors give the line and column they were found at.
 pub fn parse_query(query: &str) -> Result<Vec<QueryPlan>, String> {
+    let mut parser = Parser {
+        src: query,
+        tokens: lexer::tokenize(query)?,
+        pos: 0,
+    };
     let mut plan = Vec::new();
+    while parser.peek() != &Token::Eof {
+        parser.statement(&mut plan)?;
+    }
+    Ok(plan)
+}
+
+/// Recursive descent parser over the tokens of a query.
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].token
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].offset
+    }
+
+    /// Consume the next token. The final `Eof` is never consumed.
+    fn advance(&mut self) -> Spanned {
+        let token = self.tokens[self.pos].clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error_at(&self, offset: usize, message: impl std::fmt::Display) -> String {
+        lexer::error_at(self.src, offset, message)
+    }
+
+    /// Error at the next token, which is not `what` was expected.
+    fn expected(&self, what: &str) -> String {
+        self.error_at(
+            self.offset(),
+            format!("expected {}, found {}", what, self.peek()),
+        )
+    }
+
+    fn at(&self, punct: &str) -> bool {
+        matches!(self.peek(), Token::Punct(p) if *p == punct)
+    }
 
-    for line in query.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.at(punct);
+        if found {
+            self.advance();
         }
+        found
+    }
 
-        if let Some(rest) = line.strip_prefix("df = pl.read_parquet(") {
-            if let Some(path) = rest.strip_suffix(')') {
-                let path = path.trim().trim_matches('"');
-                plan.push(QueryPlan::ReadParquet(path.to_string()));
-                continue;
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{}`", punct)))
+        }
+    }
+
+    /// The `)` ending a call with arguments, allowing a trailing comma.
+    fn close(&mut self) -> Result<(), String> {
+        if self.at(",") && self.tokens[self.pos + 1].token == Token::Punct(")") {
+            self.advance();
+        }
+        self.expect(")")
+    }
+
+    fn at_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Token::Ident(n) if n == name)
+    }
+
+    fn name(&mut self) -> Result<(String, usize), String> {
+        match self.peek() {
+            Token::Ident(name) => {
+                let name = name.clone();
+                Ok((name, self.advance().offset))
             }
+            _ => Err(self.expected("a name")),
         }
+    }
 
-        if let Some(rest) = line.strip_prefix("df = df.filter(") {
-            if let Some(expr) = rest.strip_suffix(')') {
-                plan.push(QueryPlan::Filter(expr.trim().to_string()));
-                continue;
+    fn string(&mut self, what: &str) -> Result<String, String> {
+        match self.peek() {
+            Token::Str(s) => {
+                let s = s.clone();
+                self.advance();
+                Ok(s)
             }
+            _ => Err(self.expected(what)),
         }
+    }
 
-        if let Some(rest) = line.strip_prefix("df = df.select(") {
-            if let Some(cols) = rest.strip_suffix(')') {
-                let cols = cols.trim().trim_start_matches('[').trim_end_matches(']');
-                let columns = cols
-                    .split(',')
-                    .map(|c| c.trim().trim_matches('"').to_string())
-                    .filter(|c| !c.is_empty())
-                    .collect();
-                plan.push(QueryPlan::Select(columns));
-                continue;
+    fn count(&mut self, what: &str) -> Result<u64, String> {
+        match self.peek() {
+            Token::Int(v) if *v >= 0 => {
+                let v = *v as u64;
+                self.advance();
+                Ok(v)
             }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<f64, String> {
+        let value = match self.peek() {
+            Token::Int(v) => *v as f64,
+            Token::Float(v) => *v,
+            _ => return Err(self.expected(what)),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    fn boolean(&mut self) -> Result<bool, String> {
+        let value = match self.peek() {
+            Token::Ident(n) if n == "True" || n == "true" => true,
+            Token::Ident(n) if n == "False" || n == "false" => false,
+            _ => return Err(self.expected("True or False")),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    /// `, name=value` arguments up to the closing parenthesis, each value
+    /// parsed by `arg` given the name and its offset.
+    fn keywords(
+        &mut self,
+        mut arg: impl FnMut(&mut Self, &str, usize) -> Result<(), String>,
+    ) -> Result<(), String> {
+        while self.eat(",") {
+            if self.at(")") {
+                break;
+            }
+            let (name, offset) = self.name()?;
+            self.expect("=")?;
+            arg(self, &name, offset)?;
+        }
+        Ok(())
+    }
+
+    /// `df = pl.read_*(...)` or `df = df`, followed by any chain of
+    /// operations and the end of the line.
+    fn statement(&mut self, plan: &mut Vec<QueryPlan>) -> Result<(), String> {
+        if !self.at_name("df") {
+            return Err(self.expected("`df = ...`"));
+        }
+        self.advance();
+        self.expect("=")?;
+        let mut steps = 0;
+        if self.at_name("pl") {
+            self.advance();
+            plan.push(self.read()?);
+            steps += 1;
+        } else if self.at_name("df") {
+            self.advance();
+        } else {
+            return Err(self.expected("`df` or `pl`"));
+        }
+        while self.eat(".") {
+            plan.push(self.operation()?);
+            steps += 1;
+        }
+        if steps == 0 {
+            return Err(self.expected("`.` and an operation"));
+        }
+        match self.peek() {
+            Token::Newline => {
+                self.advance();
+                Ok(())
+            }
+            Token::Eof => Ok(()),
+            _ => Err(self.expected("end of line")),
+        }
+    }
+
+    /// `.read_parquet(...)`, `.read_csv(...)` or `.read_table(...)` after `pl`.
+    fn read(&mut self) -> Result<QueryPlan, String> {
+        self.expect(".")?;
+        let (name, offset) = self.name()?;
+        self.expect("(")?;
+        let step = match name.as_str() {
+            "read_parquet" => self.read_parquet()?,
+            "read_csv" => self.read_csv()?,
+            "read_table" | "read_dataset" => self.read_table()?,
+            _ => return Err(self.error_at(offset, format!("unknown source `pl.{}`", name))),
+        };
+        self.close()?;
+        Ok(step)
+    }
+
+    /// Argument of `pl.read_parquet("path")` or `pl.read_parquet(["a", "b"])`.
+    fn read_parquet(&mut self) -> Result<QueryPlan, String> {
+        let offset = self.offset();
+        let paths = if self.at("[") {
+            self.list(|p| p.string("a path"))?
+        } else {
+            vec![self.string("a path")?]
+        };
+        if paths.is_empty() {
+            return Err(self.error_at(offset, "read_parquet requires at least one path"));
         }
+        Ok(QueryPlan::ReadParquet(paths))
+    }
 
-        if let Some(rest) = line.strip_prefix("df = df.groupby(") {
-            if let Some(rest) = rest.split_once(')') {
-                let col = rest.0.trim().trim_matches('"');
-                plan.push(QueryPlan::GroupBy(col.to_string()));
+    /// Arguments of `pl.read_table("name", version=N, as_of="...")`, also
+    /// spelled `pl.read_dataset`.
+    fn read_table(&mut self) -> Result<QueryPlan, String> {
+        let offset = self.offset();
+        let name = self.string("a dataset name")?;
+        if name.is_empty() {
+            return Err(self.error_at(offset, "read_table requires a dataset name"));
+        }
+        let mut version = None;
+        let mut as_of = None;
+        self.keywords(|p, key, offset| {
+            match key {
+                "version" => version = Some(p.count("a version number")?),
+                "as_of" => as_of = Some(p.string("a timestamp")?),
+                _ => {
+                    return Err(p.error_at(offset, format!("unknown read_table argument `{}`", key)))
+                }
+            }
+            Ok(())
+        })?;
+        Ok(QueryPlan::ReadTable {
+            name,
+            version,
+            as_of,
+        })
+    }
 
-                let remaining = rest.1.trim();
-                if remaining.is_empty() {
-                    continue;
+    /// Arguments of `pl.read_csv("path", delimiter=";", has_header=False,
+    /// infer_schema_length=1000)`. `separator` is accepted for `delimiter`,
+    /// as in current Polars.
+    fn read_csv(&mut self) -> Result<QueryPlan, String> {
+        let offset = self.offset();
+        let path = self.string("a path")?;
+        if path.is_empty() {
+            return Err(self.error_at(offset, "read_csv requires a path"));
+        }
+        let mut options = CsvOptions::default();
+        self.keywords(|p, key, offset| {
+            match key {
+                "delimiter" | "separator" => {
+                    let at = p.offset();
+                    options.delimiter = match p.string("a delimiter")?.as_bytes() {
+                        [d] => *d,
+                        _ => return Err(p.error_at(at, format!("{} must be one byte", key))),
+                    };
                 }
-                if let Some(arg) = remaining.strip_prefix(".agg(") {
-                    if let Some(arg) = arg.strip_suffix(')') {
-                        plan.push(QueryPlan::Agg(arg.trim().to_string()));
-                        continue;
-                    }
+                "has_header" => options.has_header = p.boolean()?,
+                "infer_schema_length" if p.at_name("None") => {
+                    p.advance();
+                    options.infer_schema_length = None;
+                }
+                "infer_schema_length" => {
+                    options.infer_schema_length = Some(p.count("a row count or None")? as usize)
                 }
+                _ => return Err(p.error_at(offset, format!("unknown read_csv argument `{}`", key))),
             }
+            Ok(())
+        })?;
+        Ok(QueryPlan::ReadCsv { path, options })
+    }
+
+    /// An operation `name(...)` on the frame.
+    fn operation(&mut self) -> Result<QueryPlan, String> {
+        let (name, offset) = self.name()?;
+        self.expect("(")?;
+        let step = match name.as_str() {
+            "filter" => QueryPlan::Filter(self.expr()?),
+            "select" => QueryPlan::Select(self.list(Self::projection)?),
+            "groupby" | "group_by" => {
+                let offset = self.offset();
+                let keys = self.list(Self::column)?;
+                if keys.is_empty() {
+                    return Err(self.error_at(offset, "groupby requires at least one column"));
+                }
+                QueryPlan::GroupBy(keys)
+            }
+            "agg" => {
+                let offset = self.offset();
+                let aggs = self.list(Self::expr)?;
+                if aggs.is_empty() {
+                    return Err(self.error_at(offset, "agg requires at least one expression"));
+                }
+                QueryPlan::Agg(aggs)
+            }
+            "with_columns" => {
+                let offset = self.offset();
+                let exprs = self.list(Self::expr)?;
+                if exprs.is_empty() {
+                    return Err(
+                        self.error_at(offset, "with_columns requires at least one expression")
+                    );
+                }
+                QueryPlan::WithColumns(exprs)
+            }
+            "sort" => QueryPlan::Sort(self.column()?),
+            "head" | "limit" => QueryPlan::Head(self.row_count()?),
+            "tail" => QueryPlan::Tail(self.row_count()?),
+            "slice" => self.slice()?,
+            "join" => self.join()?,
+            "unique" | "distinct" => self.unique()?,
+            "drop_nulls" => self.drop_nulls()?,
+            "fill_null" => self.fill_null()?,
+            "pivot" => self.pivot(offset)?,
+            "melt" | "unpivot" => self.melt()?,
+            _ => return Err(self.error_at(offset, format!("unknown operation `{}`", name))),
+        };
+        self.close()?;
+        Ok(step)
+    }
+
+    /// A column written as `"name"` or `pl.col("name")`.
+    fn column(&mut self) -> Result<String, String> {
+        let offset = self.offset();
+        match self.expr()? {
+            Expr::Column(name) | Expr::Literal(Literal::Str(name)) => Ok(name),
+            _ => Err(self.error_at(offset, "expected a column name")),
         }
+    }
 
-        if let Some(rest) = line.strip_prefix("df = df.sort(") {
-            if let Some(col) = rest.strip_suffix(')') {
-                plan.push(QueryPlan::Sort(col.trim().trim_matches('"').to_string()));
-                continue;
+    /// A column of `select`: a name, as `"name"` or `pl.col("name")`, or an
+    /// expression.
+    fn projection(&mut self) -> Result<Expr, String> {
+        Ok(match self.expr()? {
+            Expr::Literal(Literal::Str(name)) => Expr::Column(name),
+            expr => expr,
+        })
+    }
+
+    /// Arguments parsed by `item`, given as a list or as separate arguments,
+    /// as `select`, `groupby` and `agg` take them.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let close = if self.eat("[") { "]" } else { ")" };
+        let mut items = Vec::new();
+        while !self.at(close) {
+            items.push(item(self)?);
+            if !self.eat(",") {
+                break;
             }
         }
+        if close == "]" {
+            self.expect("]")?;
+        }
+        Ok(items)
+    }
 
-        if let Some(rest) = line.strip_prefix("df = df.agg(") {
-            if let Some(arg) = rest.strip_suffix(')') {
-                plan.push(QueryPlan::Agg(arg.trim().to_string()));
+    /// Optional row count of `head(n)` and `tail(n)`, 5 when omitted as in
+    /// Polars.
+    fn row_count(&mut self) -> Result<u64, String> {
+        if self.at(")") {
+            return Ok(5);
+        }
+        self.count("a row count")
+    }
+
+    /// Arguments of `df.slice(offset, length)`, where the length may be
+    /// omitted or `None` to keep every row from `offset`.
+    fn slice(&mut self) -> Result<QueryPlan, String> {
+        let negative = self.eat("-");
+        let offset = i64::try_from(self.count("an offset")?)
+            .map_err(|_| self.expected("a smaller offset"))?;
+        let offset = if negative { -offset } else { offset };
+        let mut length = None;
+        if self.eat(",") && !self.at(")") {
+            if self.at_name("None") {
+                self.advance();
+            } else {
+                length = Some(self.count("a row count or None")?);
+            }
+        }
+        Ok(QueryPlan::Slice { offset, length })
+    }
+
+    /// Whether the next tokens are `name=`, starting a keyword argument.
+    fn at_keyword(&self) -> bool {
+        matches!(self.peek(), Token::Ident(_))
+            && self.tokens[self.pos + 1].token == Token::Punct("=")
+    }
+
+    /// Arguments up to the closing parenthesis, of which the first may be
+    /// given without its name `positional`, each parsed by `arg` as in
+    /// [`Self::keywords`].
+    fn arguments(
+        &mut self,
+        positional: &str,
+        mut arg: impl FnMut(&mut Self, &str, usize) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if self.at_keyword() {
+            let (name, offset) = self.name()?;
+            self.expect("=")?;
+            arg(self, &name, offset)?;
+        } else if !self.at(")") {
+            let offset = self.offset();
+            arg(self, positional, offset)?;
+        }
+        self.keywords(arg)
+    }
+
+    /// A column or a non-empty list of them, compared by `op`.
+    fn subset(&mut self, op: &str) -> Result<Vec<String>, String> {
+        let offset = self.offset();
+        let columns = if self.at("[") {
+            self.list(Self::column)?
+        } else {
+            vec![self.column()?]
+        };
+        if columns.is_empty() {
+            return Err(self.error_at(offset, format!("{} requires at least one column", op)));
+        }
+        Ok(columns)
+    }
+
+    /// Arguments of `df.unique(subset=["id"], keep="first")`. The subset, a
+    /// column or a list of them, may also be given first without its name.
+    fn unique(&mut self) -> Result<QueryPlan, String> {
+        let mut subset = None;
+        let mut keep = UniqueKeep::default();
+        self.arguments("subset", |p, key, offset| {
+            match key {
+                "subset" => subset = Some(p.subset("unique")?),
+                "keep" => {
+                    let at = p.offset();
+                    keep = match p.string("a keep strategy")?.as_str() {
+                        "any" => UniqueKeep::Any,
+                        "first" => UniqueKeep::First,
+                        "last" => UniqueKeep::Last,
+                        "none" => UniqueKeep::None,
+                        other => {
+                            return Err(p.error_at(
+                                at,
+                                format!(
+                                    "unknown keep strategy {:?}, expected first, last, any or none",
+                                    other
+                                ),
+                            ))
+                        }
+                    };
+                }
+                _ => return Err(p.error_at(offset, format!("unknown unique argument `{}`", key))),
+            }
+            Ok(())
+        })?;
+        Ok(QueryPlan::Unique { subset, keep })
+    }
+
+    /// Arguments of `df.drop_nulls(["id"])`, optionally named `subset`.
+    fn drop_nulls(&mut self) -> Result<QueryPlan, String> {
+        let mut subset = None;
+        self.arguments("subset", |p, key, offset| {
+            match key {
+                "subset" => subset = Some(p.subset("drop_nulls")?),
+                _ => {
+                    return Err(p.error_at(offset, format!("unknown drop_nulls argument `{}`", key)))
+                }
+            }
+            Ok(())
+        })?;
+        Ok(QueryPlan::DropNulls(subset))
+    }
+
+    /// Arguments of `df.fill_null(0)` or `df.fill_null(strategy="forward")`.
+    fn fill_null(&mut self) -> Result<QueryPlan, String> {
+        let start = self.offset();
+        let mut fill = None;
+        self.arguments("value", |p, key, offset| {
+            if fill.is_some() {
+                return Err(p.error_at(offset, "fill_null takes a value or a strategy, not both"));
+            }
+            match key {
+                "value" => fill = Some(FillNull::Value(p.literal()?)),
+                "strategy" => {
+                    let at = p.offset();
+                    let strategy = match p.string("a fill strategy")?.as_str() {
+                        "forward" => FillStrategy::Forward,
+                        "backward" => FillStrategy::Backward,
+                        "min" => FillStrategy::Min,
+                        "max" => FillStrategy::Max,
+                        "mean" => FillStrategy::Mean,
+                        "zero" => FillStrategy::Zero,
+                        "one" => FillStrategy::One,
+                        other => {
+                            return Err(p.error_at(
+                                at,
+                                format!(
+                                    "unknown fill strategy {:?}, expected forward, backward, min, max, mean, zero or one",
+                                    other
+                                ),
+                            ))
+                        }
+                    };
+                    fill = Some(FillNull::Strategy(strategy));
+                }
+                _ => {
+                    return Err(p.error_at(offset, format!("unknown fill_null argument `{}`", key)))
+                }
+            }
+            Ok(())
+        })?;
+        let fill = fill.ok_or_else(|| {
+            self.error_at(start, "fill_null requires a value or strategy=\"...\"")
+        })?;
+        Ok(QueryPlan::FillNull(fill))
+    }
+
+    /// Arguments of `df.pivot(values="v", index="id", columns="k")`, the
+    /// columns also accepted as `on`, starting at `start`.
+    fn pivot(&mut self, start: usize) -> Result<QueryPlan, String> {
+        let mut values = Vec::new();
+        let mut index = None;
+        let mut columns = None;
+        let mut aggregate = PivotAggregate::default();
+        let mut sort_columns = false;
+        self.arguments("values", |p, key, offset| {
+            match key {
+                "values" => values = p.subset("pivot")?,
+                "index" => index = Some(p.subset("pivot")?),
+                "columns" | "on" => columns = Some(p.subset("pivot")?),
+                "aggregate_function" => {
+                    let at = p.offset();
+                    aggregate = match p.string("an aggregate function")?.as_str() {
+                        "first" => PivotAggregate::First,
+                        "last" => PivotAggregate::Last,
+                        "sum" => PivotAggregate::Sum,
+                        "min" => PivotAggregate::Min,
+                        "max" => PivotAggregate::Max,
+                        "mean" => PivotAggregate::Mean,
+                        "median" => PivotAggregate::Median,
+                        "count" | "len" => PivotAggregate::Count,
+                        other => {
+                            return Err(p.error_at(
+                                at,
+                                format!(
+                                    "unknown aggregate function {:?}, expected first, last, sum, min, max, mean, median or count",
+                                    other
+                                ),
+                            ))
+                        }
+                    };
+                }
+                "sort_columns" => sort_columns = p.boolean()?,
+                _ => return Err(p.error_at(offset, format!("unknown pivot argument `{}`", key))),
+            }
+            Ok(())
+        })?;
+        let (Some(index), Some(columns)) = (index, columns) else {
+            return Err(self.error_at(start, "pivot requires index=... and columns=..."));
+        };
+        Ok(QueryPlan::Pivot {
+            values,
+            index,
+            columns,
+            aggregate,
+            sort_columns,
+        })
+    }
+
+    /// Arguments of `df.melt(id_vars=["id"], value_vars=["a", "b"])`, also
+    /// accepted as `index` and `on` as `unpivot` names them.
+    fn melt(&mut self) -> Result<QueryPlan, String> {
+        let mut id_vars = Vec::new();
+        let mut value_vars = Vec::new();
+        let mut variable_name = None;
+        let mut value_name = None;
+        self.arguments("id_vars", |p, key, offset| {
+            match key {
+                "id_vars" | "index" => id_vars = p.subset("melt")?,
+                "value_vars" | "on" => value_vars = p.subset("melt")?,
+                "variable_name" => variable_name = Some(p.string("a column name")?),
+                "value_name" => value_name = Some(p.string("a column name")?),
+                _ => return Err(p.error_at(offset, format!("unknown melt argument `{}`", key))),
+            }
+            Ok(())
+        })?;
+        Ok(QueryPlan::Melt {
+            id_vars,
+            value_vars,
+            variable_name,
+            value_name,
+        })
+    }
+
+    /// Arguments of `df.join(pl.read_parquet("path"), on="id", how="left")`.
+    fn join(&mut self) -> Result<QueryPlan, String> {
+        if !self.at_name("pl") {
+            return Err(self.expected("a read_parquet, read_csv or read_table source"));
+        }
+        self.advance();
+        let source = self.read()?;
+        let mut on = None;
+        let mut how = JoinKind::default();
+        self.keywords(|p, key, offset| {
+            match key {
+                "on" => on = Some(p.string("a column name")?),
+                "how" => {
+                    let at = p.offset();
+                    how = match p.string("a join kind")?.as_str() {
+                        "inner" => JoinKind::Inner,
+                        "left" => JoinKind::Left,
+                        "outer" => JoinKind::Outer,
+                        other => {
+                            return Err(p.error_at(
+                                at,
+                                format!(
+                                    "unknown join kind {:?}, expected inner, left or outer",
+                                    other
+                                ),
+                            ))
+                        }
+                    };
+                }
+                _ => return Err(p.error_at(offset, format!("unknown join argument `{}`", key))),
+            }
+            Ok(())
+        })?;
+        let on = on.ok_or_else(|| self.error_at(self.offset(), "join requires on=\"column\""))?;
+        Ok(QueryPlan::Join {
+            source: Box::new(source),
+            on,
+            how,
+        })
+    }
+
+    /// An expression. Operators bind as in Python, so comparisons joined
+    /// with `&` or `|` need parentheses, and comparisons do not chain.
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.binary(1)
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        let Token::Punct(p) = self.peek() else {
+            return None;
+        };
+        Some(match *p {
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::NotEq,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::LtEq,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::GtEq,
+            "&" => BinaryOp::And,
+            "|" => BinaryOp::Or,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            _ => return None,
+        })
+    }
+
+    /// Operators binding at least as tightly as `precedence`.
+    fn binary(&mut self, precedence: u8) -> Result<Expr, String> {
+        if precedence > 5 {
+            return self.unary();
+        }
+        let mut left = self.binary(precedence + 1)?;
+        let mut chained = false;
+        while let Some(op) = self.binary_op().filter(|op| op.precedence() == precedence) {
+            if precedence == 1 && std::mem::replace(&mut chained, true) {
+                return Err(self.error_at(
+                    self.offset(),
+                    "comparisons cannot be chained; combine them with & or | and parentheses",
+                ));
+            }
+            self.advance();
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let offset = self.offset();
+        if self.eat("~") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return match self.unary()? {
+                Expr::Literal(Literal::Int(v)) => Ok(Expr::Literal(Literal::Int(-v))),
+                Expr::Literal(Literal::Float(v)) => Ok(Expr::Literal(Literal::Float(-v))),
+                _ => Err(self.error_at(offset, "`-` can only negate a number")),
+            };
+        }
+        let mut expr = self.primary()?;
+        while self.eat(".") {
+            let (name, offset) = self.name()?;
+            if name == "str" {
+                expr = self.str_method(expr)?;
                 continue;
             }
+            self.expect("(")?;
+            expr = if let Some(func) = AggFunc::from_name(&name) {
+                Expr::Agg {
+                    func: self.agg_args(func)?,
+                    expr: Box::new(expr),
+                }
+            } else if name == "alias" {
+                Expr::Alias {
+                    expr: Box::new(expr),
+                    name: self.string("an alias")?,
+                }
+            } else if name == "is_in" {
+                Expr::IsIn {
+                    expr: Box::new(expr),
+                    values: self.values()?,
+                }
+            } else if name == "is_null" || name == "is_not_null" {
+                Expr::IsNull {
+                    expr: Box::new(expr),
+                    negated: name == "is_not_null",
+                }
+            } else if name == "cast" {
+                let dtype = self.dtype()?;
+                let mut strict = true;
+                self.keywords(|p, key, offset| {
+                    match key {
+                        "strict" => strict = p.boolean()?,
+                        _ => {
+                            return Err(
+                                p.error_at(offset, format!("unknown cast argument `{}`", key))
+                            )
+                        }
+                    }
+                    Ok(())
+                })?;
+                Expr::Cast {
+                    expr: Box::new(expr),
+                    dtype,
+                    strict,
+                }
+            } else if name == "cum_sum" || name == "cumsum" {
+                Expr::CumSum(Box::new(expr))
+            } else if name == "rank" {
+                let (method, descending) = self.rank_args()?;
+                Expr::Rank {
+                    expr: Box::new(expr),
+                    method,
+                    descending,
+                }
+            } else if name == "over" {
+                let offset = self.offset();
+                let partition_by = self.list(Self::column)?;
+                if partition_by.is_empty() {
+                    return Err(self.error_at(offset, "over requires at least one column"));
+                }
+                Expr::Over {
+                    expr: Box::new(expr),
+                    partition_by,
+                }
+            } else if name == "is_between" {
+                let low = self.expr()?;
+                self.expect(",")?;
+                Expr::Between {
+                    expr: Box::new(expr),
+                    low: Box::new(low),
+                    high: Box::new(self.expr()?),
+                }
+            } else {
+                return Err(self.error_at(offset, format!("unknown expression method `{}`", name)));
+            };
+            self.expect(")")?;
         }
+        Ok(expr)
+    }
 
-        return Err(format!("Invalid operation: {}", line));
+    /// The optional method, positional or as `method=`, and `descending=`
+    /// arguments of `rank`.
+    fn rank_args(&mut self) -> Result<(RankMethod, bool), String> {
+        let mut method = RankMethod::default();
+        let mut descending = false;
+        let mut first = true;
+        while !self.at(")") {
+            if !first {
+                self.expect(",")?;
+                if self.at(")") {
+                    break;
+                }
+            }
+            let key = if first && matches!(self.peek(), Token::Str(_)) {
+                ("method".to_string(), self.offset())
+            } else {
+                let key = self.name()?;
+                self.expect("=")?;
+                key
+            };
+            first = false;
+            match key.0.as_str() {
+                "method" => {
+                    let offset = self.offset();
+                    let name = self.string("a rank method")?;
+                    method = RankMethod::from_name(&name).ok_or_else(|| {
+                        self.error_at(
+                            offset,
+                            format!(
+                                "unknown rank method {:?}, expected average, min, max, dense or ordinal",
+                                name
+                            ),
+                        )
+                    })?;
+                }
+                "descending" => descending = self.boolean()?,
+                _ => return Err(self.error_at(key.1, format!("unknown rank argument `{}`", key.0))),
+            }
+        }
+        Ok((method, descending))
     }
 
-    Ok(plan)
+    /// The `.contains("pattern")` after `expr.str`, or another string method.
+    fn str_method(&mut self, expr: Expr) -> Result<Expr, String> {
+        self.expect(".")?;
+        let (name, offset) = self.name()?;
+        self.expect("(")?;
+        let pattern = self.string("a pattern")?;
+        let mut op = match name.as_str() {
+            "contains" => StrOp::Contains,
+            "starts_with" => StrOp::StartsWith,
+            "ends_with" => StrOp::EndsWith,
+            _ => return Err(self.error_at(offset, format!("unknown string method `str.{}`", name))),
+        };
+        self.keywords(|p, key, offset| {
+            match key {
+                "literal" if name == "contains" => {
+                    if p.boolean()? {
+                        op = StrOp::ContainsLiteral;
+                    }
+                }
+                _ => {
+                    return Err(
+                        p.error_at(offset, format!("unknown str.{} argument `{}`", name, key))
+                    )
+                }
+            }
+            Ok(())
+        })?;
+        self.expect(")")?;
+        Ok(Expr::Str {
+            expr: Box::new(expr),
+            op,
+            pattern,
+        })
+    }
+
+    /// The arguments of `pl.date(year, month, day)` or `pl.datetime(year,
+    /// month, day, hour, minute, second, microsecond)`, whose time parts
+    /// default to 0.
+    fn temporal(&mut self, func: &str, at: usize) -> Result<Literal, String> {
+        let most = if func == "date" { 3 } else { 7 };
+        let mut parts = vec![self.count("a year")?];
+        while parts.len() < most
+            && self.at(",")
+            && self.tokens[self.pos + 1].token != Token::Punct(")")
+        {
+            self.advance();
+            parts.push(self.count("a number")?);
+        }
+        let invalid = || self.error_at(at, format!("invalid pl.{} arguments", func));
+        let parts: Vec<u32> = parts
+            .into_iter()
+            .map(u32::try_from)
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        if parts.len() < 3 {
+            return Err(self.error_at(at, format!("pl.{} requires a year, month and day", func)));
+        }
+        let date =
+            NaiveDate::from_ymd_opt(parts[0] as i32, parts[1], parts[2]).ok_or_else(invalid)?;
+        if func == "date" {
+            return Ok(Literal::Date(date));
+        }
+        let part = |i: usize| parts.get(i).copied().unwrap_or(0);
+        date.and_hms_micro_opt(part(3), part(4), part(5), part(6))
+            .map(Literal::Datetime)
+            .ok_or_else(invalid)
+    }
+
+    /// A data type such as `pl.Int64`.
+    fn dtype(&mut self) -> Result<DType, String> {
+        if !self.at_name("pl") {
+            return Err(self.expected("a data type such as `pl.Int64`"));
+        }
+        self.advance();
+        self.expect(".")?;
+        let (name, offset) = self.name()?;
+        DType::from_name(&name).ok_or_else(|| {
+            self.error_at(
+                offset,
+                format!(
+                    "unknown data type `pl.{}`, expected Int32, Int64, UInt32, UInt64, Float32, Float64, Utf8, Boolean, Date or Datetime",
+                    name
+                ),
+            )
+        })
+    }
+
+    /// A literal other than `None`, such as `0` or `"NY"`.
+    fn literal(&mut self) -> Result<Literal, String> {
+        let offset = self.offset();
+        match self.unary()? {
+            Expr::Literal(value) if value != Literal::Null => Ok(value),
+            _ => Err(self.error_at(offset, "expected a value")),
+        }
+    }
+
+    /// The list of `is_in(["NY", "LA"])`: numbers, strings or booleans.
+    fn values(&mut self) -> Result<Vec<Literal>, String> {
+        let offset = self.offset();
+        if !self.at("[") {
+            return Err(self.expected("a list of values"));
+        }
+        let values = self.list(Self::literal)?;
+        let kind = |value: &Literal| match value {
+            Literal::Int(_) | Literal::Float(_) => "numbers",
+            Literal::Str(_) => "strings",
+            Literal::Date(_) => "dates",
+            Literal::Datetime(_) => "datetimes",
+            _ => "booleans",
+        };
+        match values.first() {
+            None => Err(self.error_at(offset, "is_in requires at least one value")),
+            Some(first) if values.iter().any(|v| kind(v) != kind(first)) => {
+                Err(self.error_at(offset, format!("is_in values must all be {}", kind(first))))
+            }
+            _ => Ok(values),
+        }
+    }
+
+    /// The arguments of aggregation `func`, given positionally or by name:
+    /// the quantile of `quantile` and the optional `ddof` of `std` and `var`.
+    fn agg_args(&mut self, func: AggFunc) -> Result<AggFunc, String> {
+        let name = func.name();
+        let keyword = if name == "quantile" {
+            "quantile"
+        } else {
+            "ddof"
+        };
+        if self.at_name(keyword) && self.tokens[self.pos + 1].token == Token::Punct("=") {
+            self.advance();
+            self.advance();
+        }
+        let offset = self.offset();
+        Ok(match func {
+            AggFunc::Quantile(_) => {
+                let q = self.number("a quantile between 0 and 1")?;
+                if !(0.0..=1.0).contains(&q) {
+                    return Err(self.error_at(offset, "quantile must be between 0 and 1"));
+                }
+                AggFunc::Quantile(q)
+            }
+            AggFunc::Std(_) | AggFunc::Var(_) if !self.at(")") => {
+                let ddof = u8::try_from(self.count("ddof")?)
+                    .map_err(|_| self.error_at(offset, "ddof must be at most 255"))?;
+                if name == "std" {
+                    AggFunc::Std(ddof)
+                } else {
+                    AggFunc::Var(ddof)
+                }
+            }
+            other => other,
+        })
+    }
+
+    /// A literal, `pl.col(...)`, `pl.lit(...)` or a parenthesized expression.
+    fn primary(&mut self) -> Result<Expr, String> {
+        let Spanned { token, offset } = self.advance();
+        let literal = match token {
+            Token::Int(v) => Literal::Int(v),
+            Token::Float(v) => Literal::Float(v),
+            Token::Str(s) => Literal::Str(s),
+            Token::Ident(name) if name == "True" => Literal::Bool(true),
+            Token::Ident(name) if name == "False" => Literal::Bool(false),
+            Token::Ident(name) if name == "None" => Literal::Null,
+            Token::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            Token::Ident(name) if name == "pl" => {
+                self.expect(".")?;
+                let (func, at) = self.name()?;
+                self.expect("(")?;
+                let expr = match func.as_str() {
+                    "col" => Expr::Column(self.string("a column name")?),
+                    "date" | "datetime" => Expr::Literal(self.temporal(&func, at)?),
+                    "lit" => {
+                        let at = self.offset();
+                        match self.unary()? {
+                            expr @ Expr::Literal(_) => expr,
+                            _ => return Err(self.error_at(at, "pl.lit takes a literal")),
+                        }
+                    }
+                    _ => return Err(self.error_at(at, format!("unknown function `pl.{}`", func))),
+                };
+                self.close()?;
+                return Ok(expr);
+            }
+            Token::Ident(name) => {
+                return Err(self.error_at(offset, format!("unknown name `{}`", name)))
+            }
+            other => {
+                return Err(
+                    self.error_at(offset, format!("expected an expression, found {}", other))
+                )
+            }
+        };
+        Ok(Expr::Literal(literal))
+    }
 }
 
 #[cfg(test)]
@@ -107,17 +1498,627 @@ mod tests {
         assert_eq!(
             plan,
             vec![
-                QueryPlan::ReadParquet("data/sample.parquet".into()),
-                QueryPlan::Filter("pl.col(\"age\") > 30".into()),
-                QueryPlan::GroupBy("city".into()),
-                QueryPlan::Agg("pl.col(\"age\").mean()".into()),
+                QueryPlan::ReadParquet(vec!["data/sample.parquet".into()]),
+                QueryPlan::Filter(Expr::Binary {
+                    left: Box::new(Expr::Column("age".into())),
+                    op: BinaryOp::Gt,
+                    right: Box::new(Expr::Literal(Literal::Int(30))),
+                }),
+                QueryPlan::GroupBy(vec!["city".into()]),
+                QueryPlan::Agg(vec![Expr::Agg {
+                    func: AggFunc::Mean,
+                    expr: Box::new(Expr::Column("age".into())),
+                }]),
             ]
         );
     }
 
+    #[test]
+    fn parse_nested_expressions_and_chained_calls() {
+        let q = "df = df.filter((pl.col(\"a, b\") == \"x)\") | ~(pl.col(\"n\") * 2 >= -1.5)).select(\n    [\"a, b\", pl.col(\"n\")],\n).sort(\"n\")";
+        let plan = parse_query(q).unwrap();
+        assert_eq!(plan.len(), 3);
+        let QueryPlan::Filter(filter) = &plan[0] else {
+            panic!("expected a filter: {:?}", plan[0]);
+        };
+        assert_eq!(
+            filter.to_string(),
+            "(pl.col(\"a, b\") == \"x)\") | ~(pl.col(\"n\") * 2 >= -1.5)"
+        );
+        assert!(matches!(
+            filter,
+            Expr::Binary {
+                op: BinaryOp::Or,
+                ..
+            }
+        ));
+        assert_eq!(
+            plan[1],
+            QueryPlan::Select(vec![Expr::Column("a, b".into()), Expr::Column("n".into())])
+        );
+        assert_eq!(plan[2], QueryPlan::Sort("n".into()));
+
+        let plan = parse_query("df = pl.read_parquet(\"a.parquet\").group_by(\"c\").agg(pl.col(\"x\").sum().alias(\"total\"))").unwrap();
+        assert_eq!(plan.len(), 3);
+        assert_eq!(
+            plan[2],
+            QueryPlan::Agg(vec![Expr::Alias {
+                expr: Box::new(Expr::Agg {
+                    func: AggFunc::Sum,
+                    expr: Box::new(Expr::Column("x".into())),
+                }),
+                name: "total".into(),
+            }])
+        );
+    }
+
+    #[test]
+    fn parse_filter_predicates() {
+        let filter = |q: &str| match parse_query(q).unwrap().remove(0) {
+            QueryPlan::Filter(expr) => expr,
+            other => panic!("expected a filter: {:?}", other),
+        };
+        let q = "df = df.filter(pl.col(\"city\").is_in([\"NY\", \"LA\"]) & pl.col(\"x\").is_not_null())";
+        let expr = filter(q);
+        assert_eq!(
+            expr.to_string(),
+            "pl.col(\"city\").is_in([\"NY\", \"LA\"]) & pl.col(\"x\").is_not_null()"
+        );
+        assert_eq!(
+            expr.conjuncts()[0],
+            &Expr::IsIn {
+                expr: Box::new(Expr::Column("city".into())),
+                values: vec![Literal::Str("NY".into()), Literal::Str("LA".into())],
+            }
+        );
+        let expr = filter(
+            "df = df.filter((pl.col(\"x\") * 2).is_between(-1, 2.5) | pl.col(\"x\").is_null())",
+        );
+        assert_eq!(
+            expr.to_string(),
+            "(pl.col(\"x\") * 2).is_between(-1, 2.5) | pl.col(\"x\").is_null()"
+        );
+        assert_eq!(
+            filter("df = df.filter(pl.col(\"n\").is_in([1, 2.5]))").to_string(),
+            "pl.col(\"n\").is_in([1, 2.5])"
+        );
+        assert!(parse_query("df = df.filter(pl.col(\"n\").is_in([]))").is_err());
+        assert!(parse_query("df = df.filter(pl.col(\"n\").is_in(1, 2))").is_err());
+        assert!(parse_query("df = df.filter(pl.col(\"n\").is_in([1, \"a\"]))").is_err());
+        assert!(parse_query("df = df.filter(pl.col(\"n\").is_in([None]))").is_err());
+        assert!(parse_query("df = df.filter(pl.col(\"n\").is_between(1))").is_err());
+    }
+
+    #[test]
+    fn parse_string_predicates() {
+        let filter = |q: &str| match parse_query(q).unwrap().remove(0) {
+            QueryPlan::Filter(expr) => expr,
+            other => panic!("expected a filter: {:?}", other),
+        };
+        let expr =
+            filter("df = df.filter(pl.col(\"name\").str.contains(r\"^a.*\\d\", literal=False))");
+        assert_eq!(
+            expr,
+            Expr::Str {
+                expr: Box::new(Expr::Column("name".into())),
+                op: StrOp::Contains,
+                pattern: "^a.*\\d".into(),
+            }
+        );
+        assert_eq!(
+            expr.to_string(),
+            "pl.col(\"name\").str.contains(\"^a.*\\\\d\")"
+        );
+        let q = "df = df.filter(pl.col(\"path\").str.starts_with(\"/api\") & ~pl.col(\"path\").str.contains(\".\", literal=True))";
+        assert_eq!(
+            filter(q).to_string(),
+            "pl.col(\"path\").str.starts_with(\"/api\") & ~pl.col(\"path\").str.contains(\".\", literal=True)"
+        );
+        assert!(
+            parse_query("df = df.filter(pl.col(\"a\").str.ends_with(\"x\", literal=True))")
+                .is_err()
+        );
+        assert!(parse_query("df = df.filter(pl.col(\"a\").str.split(\"x\"))").is_err());
+        assert!(parse_query("df = df.filter(pl.col(\"a\").str.contains(1))").is_err());
+    }
+
+    #[test]
+    fn parse_multiple_aggregations() {
+        let sum = |c: &str| Expr::Agg {
+            func: AggFunc::Sum,
+            expr: Box::new(Expr::Column(c.into())),
+        };
+        let q = "df = df.groupby(\"city\").agg([pl.col(\"a\").sum(), pl.col(\"b\").sum()])";
+        assert_eq!(
+            parse_query(q).unwrap(),
+            vec![
+                QueryPlan::GroupBy(vec!["city".into()]),
+                QueryPlan::Agg(vec![sum("a"), sum("b")]),
+            ]
+        );
+        let q = "df = df.agg(pl.col(\"a\").sum(), pl.col(\"b\").sum())";
+        assert_eq!(
+            parse_query(q).unwrap(),
+            vec![QueryPlan::Agg(vec![sum("a"), sum("b")])]
+        );
+        assert_eq!(
+            parse_query("df = df.agg([])").unwrap_err(),
+            "line 1, column 13: agg requires at least one expression"
+        );
+    }
+
+    #[test]
+    fn parse_aggregation_arguments() {
+        let agg = |q: &str| match parse_query(&format!("df = df.agg(pl.col(\"a\").{})", q)) {
+            Ok(plan) => match &plan[..] {
+                [QueryPlan::Agg(exprs)] => match &exprs[..] {
+                    [Expr::Agg { func, .. }] => Ok(*func),
+                    other => panic!("unexpected aggregations {:?}", other),
+                },
+                other => panic!("unexpected plan {:?}", other),
+            },
+            Err(e) => Err(e),
+        };
+        assert_eq!(agg("median()"), Ok(AggFunc::Median));
+        assert_eq!(agg("n_unique()"), Ok(AggFunc::NUnique));
+        assert_eq!(agg("quantile(0.95)"), Ok(AggFunc::Quantile(0.95)));
+        assert_eq!(agg("quantile(quantile=1)"), Ok(AggFunc::Quantile(1.0)));
+        assert_eq!(agg("std()"), Ok(AggFunc::Std(1)));
+        assert_eq!(agg("var(ddof=0)"), Ok(AggFunc::Var(0)));
+        assert_eq!(agg("std(2)"), Ok(AggFunc::Std(2)));
+        assert_eq!(
+            agg("quantile()"),
+            Err("line 1, column 34: expected a quantile between 0 and 1, found `)`".into())
+        );
+        assert_eq!(
+            agg("quantile(1.5)"),
+            Err("line 1, column 34: quantile must be between 0 and 1".into())
+        );
+        assert_eq!(
+            agg("sum(1)"),
+            Err("line 1, column 29: expected `)`, found `1`".into())
+        );
+    }
+
+    #[test]
+    fn parse_multiple_group_keys() {
+        let keys = || QueryPlan::GroupBy(vec!["city".into(), "year".into()]);
+        for q in [
+            "df = df.groupby([\"city\", \"year\"])",
+            "df = df.group_by(\"city\", pl.col(\"year\"))",
+        ] {
+            assert_eq!(parse_query(q).unwrap(), vec![keys()]);
+        }
+        assert_eq!(
+            parse_query("df = df.groupby([])").unwrap_err(),
+            "line 1, column 17: groupby requires at least one column"
+        );
+    }
+
+    #[test]
+    fn errors_report_line_and_column() {
+        let err = |q: &str| parse_query(q).unwrap_err();
+        assert_eq!(
+            err("df = pl.read_parquet(\"a.parquet\")\ndf = df.filter(pl.col(\"a\") > 1 2)"),
+            "line 2, column 32: expected `)`, found `2`"
+        );
+        assert_eq!(
+            err("df = df.filter(pl.col(\"a\") > 1 > 0)"),
+            "line 1, column 32: comparisons cannot be chained; combine them with & or | and parentheses"
+        );
+        assert_eq!(
+            err("df = df.filter(pl.col(\"a\").mode())"),
+            "line 1, column 28: unknown expression method `mode`"
+        );
+        assert_eq!(
+            err("df = df.sort(\"a\") df"),
+            "line 1, column 19: expected end of line, found `df`"
+        );
+        assert_eq!(
+            err("df = df.filter("),
+            "line 1, column 16: expected an expression, found end of query"
+        );
+    }
+
     #[test]
     fn reject_invalid_operation() {
         let q = "df = df.foo()";
         assert!(parse_query(q).is_err());
     }
+
+    #[test]
+    fn parse_read_parquet_paths() {
+        let plan =
+            parse_query("df = pl.read_parquet([\"data/2023/*.parquet\", \"data/2024\"])").unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::ReadParquet(vec![
+                "data/2023/*.parquet".into(),
+                "data/2024".into()
+            ])]
+        );
+        assert_eq!(
+            parse_query("df = pl.read_parquet([])").unwrap_err(),
+            "line 1, column 22: read_parquet requires at least one path"
+        );
+        assert!(parse_query("df = pl.read_parquet(\"a\", \"b\")").is_err());
+    }
+
+    #[test]
+    fn parse_slices() {
+        let plan = parse_query(
+            "df = df.head(100)\ndf = df.tail()\ndf = df.slice(-10, 5)\ndf = df.slice(20, None)",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::Head(100),
+                QueryPlan::Tail(5),
+                QueryPlan::Slice {
+                    offset: -10,
+                    length: Some(5)
+                },
+                QueryPlan::Slice {
+                    offset: 20,
+                    length: None
+                },
+            ]
+        );
+        assert_eq!(plan[1].slice_bounds(), Some((-5, None)));
+        assert_eq!(
+            parse_query("df = df.head(-1)").unwrap_err(),
+            "line 1, column 14: expected a row count, found `-`"
+        );
+        assert!(parse_query("df = df.slice()").is_err());
+    }
+
+    #[test]
+    fn parse_read_table_arguments() {
+        let plan = parse_query("df = pl.read_table(\"sales\", version=12)").unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::ReadTable {
+                name: "sales".into(),
+                version: Some(12),
+                as_of: None,
+            }]
+        );
+        let plan = parse_query("df = pl.read_table(\"sales\", as_of=\"2024-06-01\")").unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::ReadTable {
+                name: "sales".into(),
+                version: None,
+                as_of: Some("2024-06-01".into()),
+            }]
+        );
+        assert!(parse_query("df = pl.read_table(\"sales\", v=1)").is_err());
+        assert_eq!(
+            parse_query("df = pl.read_dataset(\"sales\")").unwrap(),
+            parse_query("df = pl.read_table(\"sales\")").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_join_arguments() {
+        let plan = parse_query(
+            "df = df.join(pl.read_parquet(\"other (1).parquet\"), on=\"id\", how=\"left\")",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::Join {
+                source: Box::new(QueryPlan::ReadParquet(vec!["other (1).parquet".into()])),
+                on: "id".into(),
+                how: JoinKind::Left,
+            }]
+        );
+        let plan =
+            parse_query("df = df.join(pl.read_table(\"users\", version=2), on=\"id\")").unwrap();
+        assert!(matches!(
+            &plan[0],
+            QueryPlan::Join { source, how: JoinKind::Inner, .. }
+                if matches!(**source, QueryPlan::ReadTable { version: Some(2), .. })
+        ));
+        assert!(parse_query("df = df.join(pl.read_parquet(\"a.parquet\"))").is_err());
+        assert!(parse_query(
+            "df = df.join(pl.read_parquet(\"a.parquet\"), on=\"id\", how=\"cross\")"
+        )
+        .is_err());
+        assert!(parse_query("df = df.join(df.sort(\"id\"), on=\"id\")").is_err());
+    }
+
+    #[test]
+    fn parse_unique_arguments() {
+        let plan = parse_query("df = df.unique()\ndf = df.distinct(\"id\")").unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::Unique {
+                    subset: None,
+                    keep: UniqueKeep::Any,
+                },
+                QueryPlan::Unique {
+                    subset: Some(vec!["id".into()]),
+                    keep: UniqueKeep::Any,
+                },
+            ]
+        );
+        let plan = parse_query("df = df.unique(subset=[\"id\", pl.col(\"day\")], keep=\"first\")")
+            .unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::Unique {
+                subset: Some(vec!["id".into(), "day".into()]),
+                keep: UniqueKeep::First,
+            }]
+        );
+        let plan = parse_query("df = df.unique(keep=\"none\",)").unwrap();
+        assert!(matches!(
+            plan[0],
+            QueryPlan::Unique {
+                subset: None,
+                keep: UniqueKeep::None
+            }
+        ));
+        assert!(parse_query("df = df.unique(subset=[])").is_err());
+        assert!(parse_query("df = df.unique(keep=\"middle\")").is_err());
+        assert!(parse_query("df = df.unique(\"id\", maintain_order=True)").is_err());
+    }
+
+    #[test]
+    fn parse_pivot_and_melt() {
+        let plan = parse_query(
+            "df = df.pivot(values=\"v\", index=\"id\", columns=\"k\")\ndf = df.pivot(\"v\", index=[\"id\", \"day\"], on=\"k\", aggregate_function=\"sum\", sort_columns=True)",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::Pivot {
+                    values: vec!["v".into()],
+                    index: vec!["id".into()],
+                    columns: vec!["k".into()],
+                    aggregate: PivotAggregate::First,
+                    sort_columns: false,
+                },
+                QueryPlan::Pivot {
+                    values: vec!["v".into()],
+                    index: vec!["id".into(), "day".into()],
+                    columns: vec!["k".into()],
+                    aggregate: PivotAggregate::Sum,
+                    sort_columns: true,
+                },
+            ]
+        );
+        let err = parse_query("df = df.pivot(values=\"v\", index=\"id\")").unwrap_err();
+        assert!(err.contains("pivot requires index"), "{}", err);
+        assert!(parse_query(
+            "df = df.pivot(index=\"id\", columns=\"k\", aggregate_function=\"mode\")"
+        )
+        .is_err());
+
+        let plan = parse_query(
+            "df = df.melt(id_vars=[\"id\"])\ndf = df.unpivot(on=[\"a\", \"b\"], index=\"id\", variable_name=\"k\", value_name=\"v\")",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::Melt {
+                    id_vars: vec!["id".into()],
+                    value_vars: vec![],
+                    variable_name: None,
+                    value_name: None,
+                },
+                QueryPlan::Melt {
+                    id_vars: vec!["id".into()],
+                    value_vars: vec!["a".into(), "b".into()],
+                    variable_name: Some("k".into()),
+                    value_name: Some("v".into()),
+                },
+            ]
+        );
+        assert!(parse_query("df = df.melt(id_vars=\"id\", streamable=True)").is_err());
+    }
+
+    #[test]
+    fn parse_null_handling() {
+        let plan = parse_query(
+            "df = df.drop_nulls()\ndf = df.drop_nulls([\"id\", \"day\"])\ndf = df.drop_nulls(subset=\"id\")",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::DropNulls(None),
+                QueryPlan::DropNulls(Some(vec!["id".into(), "day".into()])),
+                QueryPlan::DropNulls(Some(vec!["id".into()])),
+            ]
+        );
+        let plan = parse_query(
+            "df = df.fill_null(-1.5)\ndf = df.fill_null(value=\"n/a\")\ndf = df.fill_null(strategy=\"forward\")",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::FillNull(FillNull::Value(Literal::Float(-1.5))),
+                QueryPlan::FillNull(FillNull::Value(Literal::Str("n/a".into()))),
+                QueryPlan::FillNull(FillNull::Strategy(FillStrategy::Forward)),
+            ]
+        );
+        assert!(parse_query("df = df.fill_null()").is_err());
+        assert!(parse_query("df = df.fill_null(None)").is_err());
+        assert!(parse_query("df = df.fill_null(pl.col(\"a\"))").is_err());
+        assert!(parse_query("df = df.fill_null(0, strategy=\"mean\")").is_err());
+        assert!(parse_query("df = df.fill_null(strategy=\"sideways\")").is_err());
+        assert!(parse_query("df = df.drop_nulls([])").is_err());
+    }
+
+    #[test]
+    fn parse_temporal_literals() {
+        let filter = |q: &str| match parse_query(q).map(|mut plan| plan.remove(0)) {
+            Ok(QueryPlan::Filter(Expr::Binary { right, .. })) => Ok(*right),
+            Ok(other) => panic!("{:?}", other),
+            Err(e) => Err(e),
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(
+            filter("df = df.filter(pl.col(\"day\") >= pl.date(2024, 2, 29))"),
+            Ok(Expr::Literal(Literal::Date(date)))
+        );
+        assert_eq!(
+            filter("df = df.filter(pl.col(\"ts\") < pl.datetime(2024, 2, 29, 12, 30,))"),
+            Ok(Expr::Literal(Literal::Datetime(
+                date.and_hms_opt(12, 30, 0).unwrap()
+            )))
+        );
+        assert!(filter("df = df.filter(pl.col(\"day\") >= pl.date(2023, 2, 29))").is_err());
+        assert!(filter("df = df.filter(pl.col(\"day\") >= pl.date(2024, 2))").is_err());
+        assert!(filter("df = df.filter(pl.col(\"day\") >= pl.date(2024, 2, 1, 3))").is_err());
+        assert!(parse_query(
+            "df = df.filter(pl.col(\"day\").is_in([pl.date(2024, 1, 1), \"2024-01-02\"]))"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_casts() {
+        let plan = parse_query(
+            "df = df.with_columns(pl.col(\"ts\").cast(pl.Datetime), pl.col(\"n\").cast(pl.Utf8, strict=False))\ndf = df.filter(pl.col(\"x\").cast(pl.Float64) > 1.5)",
+        )
+        .unwrap();
+        let cast = |name: &str, dtype, strict| Expr::Cast {
+            expr: Box::new(Expr::Column(name.into())),
+            dtype,
+            strict,
+        };
+        assert_eq!(
+            plan,
+            vec![
+                QueryPlan::WithColumns(vec![
+                    cast("ts", DType::Datetime, true),
+                    cast("n", DType::Utf8, false),
+                ]),
+                QueryPlan::Filter(Expr::Binary {
+                    left: Box::new(cast("x", DType::Float64, true)),
+                    op: BinaryOp::Gt,
+                    right: Box::new(Expr::Literal(Literal::Float(1.5))),
+                }),
+            ]
+        );
+        let err = parse_query("df = df.with_columns(pl.col(\"a\").cast(pl.Decimal))").unwrap_err();
+        assert!(err.contains("unknown data type `pl.Decimal`"), "{}", err);
+        assert!(parse_query("df = df.with_columns(pl.col(\"a\").cast(\"Int64\"))").is_err());
+        assert!(parse_query("df = df.with_columns()").is_err());
+    }
+
+    #[test]
+    fn parse_window_expressions() {
+        let plan = parse_query(
+            "df = df.select(\"city\", pl.col(\"sales\").cum_sum().over(\"city\").alias(\"running\"), pl.col(\"sales\").rank(\"dense\", descending=True).over([\"city\", \"year\"]))",
+        )
+        .unwrap();
+        let sales = || Box::new(Expr::Column("sales".into()));
+        assert_eq!(
+            plan,
+            vec![QueryPlan::Select(vec![
+                Expr::Column("city".into()),
+                Expr::Alias {
+                    expr: Box::new(Expr::Over {
+                        expr: Box::new(Expr::CumSum(sales())),
+                        partition_by: vec!["city".into()],
+                    }),
+                    name: "running".into(),
+                },
+                Expr::Over {
+                    expr: Box::new(Expr::Rank {
+                        expr: sales(),
+                        method: RankMethod::Dense,
+                        descending: true,
+                    }),
+                    partition_by: vec!["city".into(), "year".into()],
+                },
+            ])]
+        );
+        let plan = parse_query(
+            "df = df.with_columns(pl.col(\"x\").rank(method=\"min\").over(pl.col(\"g\")))",
+        )
+        .unwrap();
+        assert!(matches!(
+            &plan[0],
+            QueryPlan::WithColumns(exprs) if exprs[0].to_string() == "pl.col(\"x\").rank(\"min\").over(\"g\")"
+        ));
+        assert!(parse_query("df = df.select(pl.col(\"x\").sum().over())").is_err());
+        let err = parse_query("df = df.select(pl.col(\"x\").rank(\"top\"))").unwrap_err();
+        assert!(err.contains("unknown rank method"), "{}", err);
+        assert!(parse_query("df = df.select(pl.col(\"x\").rank(seed=1))").is_err());
+    }
+
+    #[test]
+    fn parse_read_csv_arguments() {
+        let plan = parse_query("df = pl.read_csv(\"data/a,b.csv\")").unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::ReadCsv {
+                path: "data/a,b.csv".into(),
+                options: CsvOptions::default(),
+            }]
+        );
+        let plan = parse_query(
+            "df = pl.read_csv(\"a.csv\", delimiter=\";\", has_header=False, infer_schema_length=None)",
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![QueryPlan::ReadCsv {
+                path: "a.csv".into(),
+                options: CsvOptions {
+                    delimiter: b';',
+                    has_header: false,
+                    infer_schema_length: None,
+                },
+            }]
+        );
+        let plan = parse_query("df = pl.read_csv(\"a.tsv\", separator=\"\\t\")").unwrap();
+        assert!(matches!(
+            &plan[0],
+            QueryPlan::ReadCsv { options, .. } if options.delimiter == b'\t'
+        ));
+        assert!(parse_query("df = pl.read_csv(\"a.csv\", delimiter=\"||\")").is_err());
+        assert!(parse_query("df = pl.read_csv(\"a.csv\", has_header=maybe)").is_err());
+    }
+
+    #[test]
+    fn steps_render_as_written() {
+        let q = r#"
+            df = pl.read_csv("a.tsv", delimiter="\t", infer_schema_length=None)
+            df = df.join(pl.read_table("users", version=3), on="id", how="left")
+            df = df.filter((pl.col("age") > 30) & pl.col("city").is_in(["NY", "LA"]))
+            df = df.with_columns(pl.col("age").cast(pl.Float64).alias("a")).select(["a", "city"])
+            df = df.groupby("city").agg([pl.col("a").sum(), pl.col("a").quantile(0.9)])
+            df = df.unique(subset="city", keep="last").drop_nulls().fill_null(strategy="zero")
+            df = df.pivot(index="city", on="a", aggregate_function="sum").melt(id_vars="city")
+            df = df.sort("city").slice(-3).head(2)
+        "#;
+        let plan = parse_query(q).unwrap();
+        let rendered: Vec<String> = plan.iter().map(|step| step.to_string()).collect();
+        assert_eq!(
+            rendered[1],
+            r#"join(pl.read_table("users", version=3), on="id", how="left")"#
+        );
+        assert_eq!(rendered[5], r#"groupby(["city"])"#);
+        assert_eq!(rendered[7], r#"unique(subset=["city"], keep="last")"#);
+        assert_eq!(rendered[13], "slice(-3)");
+        let query: String = plan
+            .iter()
+            .map(|step| match step.source() {
+                Some(read) if read == step => format!("df = {}\n", step),
+                _ => format!("df = df.{}\n", step),
+            })
+            .collect();
+        assert_eq!(parse_query(&query).unwrap(), plan);
+    }
 }
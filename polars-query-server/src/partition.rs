@@ -0,0 +1,308 @@
+//! Hive-style (`key=value` directory) partitioned datasets.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::catalog::{self, Catalog, Dataset, DatasetFormat, VersionFile};
+
+/// Directory partitions are moved to when dropped.
+pub const DROPPED_DIR: &str = "_dropped";
+
+/// Statistics of one partition of a dataset version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionStats {
+    pub value: String,
+    pub files: usize,
+    pub bytes: u64,
+    pub rows: u64,
+}
+
+/// The `key=value` directory components of `path`, outermost first.
+/// Directories starting with `_` are bookkeeping and never partitions.
+pub fn partition_values(path: &str) -> Vec<(String, String)> {
+    let mut values: Vec<(String, String)> = Path::new(path)
+        .ancestors()
+        .skip(1)
+        .filter_map(|dir| dir.file_name()?.to_str())
+        .filter(|name| !name.starts_with('_'))
+        .filter_map(|name| name.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    values.reverse();
+    values
+}
+
+/// Value of partition `key` for the file at `path`.
+pub fn partition_value(path: &str, key: &str) -> Option<String> {
+    partition_values(path)
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v)
+}
+
+/// Literal for a partition value: an integer where it parses as one.
+pub fn value_lit(value: &str) -> Expr {
+    match value.parse::<i64>() {
+        Ok(v) => lit(v),
+        Err(_) => lit(value),
+    }
+}
+
+/// Files with the format's extension in the `key=*` directories of `root`.
+pub fn list_files(root: &Path, key: &str, ext: &str) -> Result<Vec<PathBuf>, String> {
+    let prefix = format!("{}=", key);
+    let mut paths = Vec::new();
+    let entries =
+        fs::read_dir(root).map_err(|e| format!("failed to list {}: {}", root.display(), e))?;
+    for entry in entries.filter_map(Result::ok) {
+        let dir = entry.path();
+        let is_partition = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with(&prefix));
+        if !is_partition || !dir.is_dir() {
+            continue;
+        }
+        let files = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+        paths.extend(
+            files
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(ext)),
+        );
+    }
+    Ok(paths)
+}
+
+/// Per-partition file counts, sizes and row counts of `files`.
+pub fn collect_stats(
+    files: &[VersionFile],
+    format: DatasetFormat,
+    key: &str,
+) -> PolarsResult<Vec<PartitionStats>> {
+    let mut groups: BTreeMap<String, Vec<VersionFile>> = BTreeMap::new();
+    for file in files {
+        if let Some(value) = partition_value(&file.path, key) {
+            groups.entry(value).or_default().push(file.clone());
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(value, files)| {
+            let (lf, _) = catalog::scan_files(&files, format, Default::default())?;
+            Ok(PartitionStats {
+                value,
+                files: files.len(),
+                bytes: files.iter().map(|f| f.size).sum(),
+                rows: crate::quality::row_count(lf)?,
+            })
+        })
+        .collect()
+}
+
+/// Whether partition `value` can satisfy `value <op> literal`.
+fn may_match(value: &str, op: &str, literal: &str) -> bool {
+    let ordering = match (value.parse::<f64>(), literal.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b),
+        _ => Some(value.cmp(literal)),
+    };
+    let Some(ordering) = ordering else {
+        return true;
+    };
+    use std::cmp::Ordering::*;
+    match op {
+        "==" => ordering == Equal,
+        "!=" => ordering != Equal,
+        ">" => ordering == Greater,
+        ">=" => ordering != Less,
+        "<" => ordering == Less,
+        "<=" => ordering != Greater,
+        _ => true,
+    }
+}
+
+/// Drop files whose partition cannot satisfy every `(op, literal)` filter on
+/// the partition column.
+pub fn prune(files: Vec<VersionFile>, key: &str, filters: &[(String, String)]) -> Vec<VersionFile> {
+    if filters.is_empty() {
+        return files;
+    }
+    files
+        .into_iter()
+        .filter(|f| match partition_value(&f.path, key) {
+            Some(value) => filters.iter().all(|(op, lit)| may_match(&value, op, lit)),
+            None => true,
+        })
+        .collect()
+}
+
+fn partitioned(catalog: &Catalog, name: &str) -> Result<(Dataset, String), String> {
+    let dataset = catalog
+        .get(name)
+        .ok_or_else(|| format!("unknown dataset {}", name))?;
+    let key = dataset
+        .partition_by
+        .clone()
+        .ok_or_else(|| format!("dataset {} is not partitioned", name))?;
+    Ok((dataset, key))
+}
+
+fn partition_dir(dataset: &Dataset, key: &str, value: &str) -> Result<PathBuf, String> {
+    if value.is_empty() || value.contains(['/', '\\']) || value.starts_with('.') {
+        return Err(format!("invalid partition value {}", value));
+    }
+    Ok(Path::new(&dataset.location).join(format!("{}={}", key, value)))
+}
+
+/// Copy the parquet file, or the parquet files in the directory, at `source`
+/// into partition `value` of `name` and register the result.
+pub fn add_partition(
+    catalog: &Catalog,
+    name: &str,
+    value: &str,
+    source: &Path,
+) -> Result<Dataset, String> {
+    let (dataset, key) = partitioned(catalog, name)?;
+    let dir = partition_dir(&dataset, &key, value)?;
+    let ext = match dataset.format {
+        DatasetFormat::Parquet => "parquet",
+        DatasetFormat::Ipc => "ipc",
+    };
+    let sources: Vec<PathBuf> = if source.is_dir() {
+        fs::read_dir(source)
+            .map_err(|e| e.to_string())?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some(ext))
+            .collect()
+    } else {
+        vec![source.to_path_buf()]
+    };
+    if sources.is_empty() {
+        return Err(format!("no {} files at {}", ext, source.display()));
+    }
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    for file in sources {
+        let target = dir.join(file.file_name().unwrap_or_default());
+        if target.exists() {
+            return Err(format!("{} already exists", target.display()));
+        }
+        fs::copy(&file, &target)
+            .map_err(|e| format!("failed to copy {}: {}", file.display(), e))?;
+    }
+    catalog.register(dataset.spec(), &dataset.owner)
+}
+
+/// Move partition `value` of `name` aside and register the dataset without
+/// it. Earlier versions are updated to read the moved files.
+pub fn drop_partition(catalog: &Catalog, name: &str, value: &str) -> Result<Dataset, String> {
+    let (dataset, key) = partitioned(catalog, name)?;
+    let dir = partition_dir(&dataset, &key, value)?;
+    if !dir.is_dir() {
+        return Err(format!("unknown partition {}={}", key, value));
+    }
+    let archive = Path::new(&dataset.location)
+        .join(DROPPED_DIR)
+        .join(catalog::now_secs().to_string());
+    fs::create_dir_all(&archive).map_err(|e| e.to_string())?;
+    let to = archive.join(dir.file_name().unwrap_or_default());
+    fs::rename(&dir, &to).map_err(|e| e.to_string())?;
+
+    let dir_str = dir.to_string_lossy().to_string();
+    let to_str = to.to_string_lossy().to_string();
+    let moves: HashMap<String, String> = dataset
+        .versions
+        .iter()
+        .flat_map(|v| v.files.iter())
+        .filter_map(|f| {
+            let rest = f.path.strip_prefix(&dir_str)?;
+            Some((f.path.clone(), format!("{}{}", to_str, rest)))
+        })
+        .collect();
+    catalog.relocate_files(name, &moves)?;
+    catalog.register(dataset.spec(), &dataset.owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{DatasetSpec, VersionSelector};
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    fn write(dir: &Path, year: i32, rows: &[i32]) {
+        let dir = dir.join(format!("year={}", year));
+        fs::create_dir_all(&dir).unwrap();
+        let mut df = df!["amount" => rows].unwrap();
+        ParquetWriter::new(File::create(dir.join("part.parquet")).unwrap())
+            .finish(&mut df)
+            .unwrap();
+    }
+
+    #[test]
+    fn partitions_are_tracked_added_and_dropped() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("sales");
+        write(&root, 2023, &[1, 2]);
+        write(&root, 2024, &[3]);
+
+        let catalog = Catalog::in_memory();
+        let ds = catalog
+            .register(
+                DatasetSpec {
+                    name: "sales".into(),
+                    location: root.to_string_lossy().to_string(),
+                    partition_by: Some("year".into()),
+                    ..Default::default()
+                },
+                "alice",
+            )
+            .unwrap();
+        let stats = &ds.versions[0].partitions;
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].value.as_str(), stats[0].rows), ("2023", 2));
+        assert!(ds.schema.iter().any(|c| c.name == "year"));
+
+        let staged = dir.path().join("staged");
+        write(&staged, 2025, &[4, 5, 6]);
+        let ds = add_partition(
+            &catalog,
+            "sales",
+            "2025",
+            &staged.join("year=2025/part.parquet"),
+        )
+        .unwrap();
+        assert_eq!(ds.versions.last().unwrap().partitions.len(), 3);
+
+        let ds = drop_partition(&catalog, "sales", "2023").unwrap();
+        assert_eq!(ds.versions.last().unwrap().partitions.len(), 2);
+        let (format, v1) = catalog
+            .resolve("sales", VersionSelector::Version(1))
+            .unwrap();
+        let (lf, _) = catalog::scan_files(&v1.files, format, Default::default()).unwrap();
+        let df = lf.collect().unwrap();
+        assert_eq!(df.height(), 3);
+        assert_eq!(df.column("year").unwrap().dtype(), &DataType::Int64);
+    }
+
+    #[test]
+    fn pruning_by_partition_value() {
+        let file = |path: &str| VersionFile {
+            path: path.into(),
+            size: 1,
+            modified: 0,
+        };
+        let files = vec![
+            file("d/year=2023/a.parquet"),
+            file("d/year=2024/b.parquet"),
+            file("d/year=2024/_compacted/1/c.parquet"),
+        ];
+        let kept = prune(files, "year", &[(">".into(), "2023".into())]);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(
+            partition_value("d/year=2024/_compacted/1/c.parquet", "year").as_deref(),
+            Some("2024")
+        );
+    }
+}
@@ -0,0 +1,560 @@
+//! A typed builder for query plans, for Rust callers to construct queries
+//! without formatting strings:
+//!
+//! ```
+//! use polars_query_server::{col, Plan};
+//!
+//! let plan = Plan::read_parquet("people.parquet")
+//!     .filter(col("age").gt(30))
+//!     .group_by(["city"])
+//!     .agg([col("balance").sum().alias("total")]);
+//! assert_eq!(
+//!     plan.to_string(),
+//!     "df = pl.read_parquet(\"people.parquet\")\n\
+//!      df = df.filter(pl.col(\"age\") > 30)\n\
+//!      df = df.groupby([\"city\"])\n\
+//!      df = df.agg([pl.col(\"balance\").sum().alias(\"total\")])\n"
+//! );
+//! ```
+//!
+//! A plan holds the same [`QueryPlan`] steps [`parse_query`] produces from
+//! the text it renders to, so it can be executed directly or submitted as a
+//! query.
+
+use std::fmt;
+use std::ops;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use polars::prelude::{DataFrame, PolarsResult};
+
+use crate::executor::{self, ExecContext};
+use crate::expr::{AggFunc, BinaryOp, DType, Expr, Literal};
+use crate::parser::{
+    parse_query, CsvOptions, FillNull, FillStrategy, JoinKind, PivotAggregate, QueryPlan,
+    UniqueKeep,
+};
+
+/// A query as a sequence of steps, built by chaining operations onto a read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    steps: Vec<QueryPlan>,
+}
+
+impl Plan {
+    /// `pl.read_parquet(path)`, where `path` may be a file, directory or glob.
+    pub fn read_parquet(path: impl Into<String>) -> Plan {
+        Plan::read(QueryPlan::ReadParquet(vec![path.into()]))
+    }
+
+    /// `pl.read_parquet([paths])`, reading them as one frame.
+    pub fn read_parquet_many<S: Into<String>>(paths: impl IntoIterator<Item = S>) -> Plan {
+        Plan::read(QueryPlan::ReadParquet(names(paths)))
+    }
+
+    /// `pl.read_csv(path)` with the default options.
+    pub fn read_csv(path: impl Into<String>) -> Plan {
+        Plan::read_csv_with(path, CsvOptions::default())
+    }
+
+    pub fn read_csv_with(path: impl Into<String>, options: CsvOptions) -> Plan {
+        Plan::read(QueryPlan::ReadCsv {
+            path: path.into(),
+            options,
+        })
+    }
+
+    /// `pl.read_table(name)`, the latest version of a catalog dataset.
+    pub fn read_table(name: impl Into<String>) -> Plan {
+        Plan::read(QueryPlan::ReadTable {
+            name: name.into(),
+            version: None,
+            as_of: None,
+        })
+    }
+
+    /// `pl.read_table(name, version=version)`
+    pub fn read_table_version(name: impl Into<String>, version: u64) -> Plan {
+        Plan::read(QueryPlan::ReadTable {
+            name: name.into(),
+            version: Some(version),
+            as_of: None,
+        })
+    }
+
+    fn read(step: QueryPlan) -> Plan {
+        Plan { steps: vec![step] }
+    }
+
+    /// The plan of a textual query.
+    pub fn parse(query: &str) -> Result<Plan, String> {
+        Ok(Plan {
+            steps: parse_query(query)?,
+        })
+    }
+
+    pub fn steps(&self) -> &[QueryPlan] {
+        &self.steps
+    }
+
+    pub fn into_steps(self) -> Vec<QueryPlan> {
+        self.steps
+    }
+
+    /// Append `step`, for operations without a method of their own.
+    pub fn then(mut self, step: QueryPlan) -> Plan {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn filter(self, predicate: Expr) -> Plan {
+        self.then(QueryPlan::Filter(predicate))
+    }
+
+    pub fn select(self, exprs: impl IntoIterator<Item = Expr>) -> Plan {
+        self.then(QueryPlan::Select(exprs.into_iter().collect()))
+    }
+
+    pub fn with_columns(self, exprs: impl IntoIterator<Item = Expr>) -> Plan {
+        self.then(QueryPlan::WithColumns(exprs.into_iter().collect()))
+    }
+
+    /// Group by `keys`, to be followed by [`Plan::agg`].
+    pub fn group_by<S: Into<String>>(self, keys: impl IntoIterator<Item = S>) -> Plan {
+        self.then(QueryPlan::GroupBy(names(keys)))
+    }
+
+    pub fn agg(self, exprs: impl IntoIterator<Item = Expr>) -> Plan {
+        self.then(QueryPlan::Agg(exprs.into_iter().collect()))
+    }
+
+    pub fn sort(self, column: impl Into<String>) -> Plan {
+        self.then(QueryPlan::Sort(column.into()))
+    }
+
+    pub fn head(self, n: u64) -> Plan {
+        self.then(QueryPlan::Head(n))
+    }
+
+    pub fn tail(self, n: u64) -> Plan {
+        self.then(QueryPlan::Tail(n))
+    }
+
+    /// Keep `length` rows from `offset`, every row when `None`. Negative
+    /// offsets count from the end.
+    pub fn slice(self, offset: i64, length: Option<u64>) -> Plan {
+        self.then(QueryPlan::Slice { offset, length })
+    }
+
+    /// Drop duplicate rows, comparing every column.
+    pub fn unique(self) -> Plan {
+        self.then(QueryPlan::Unique {
+            subset: None,
+            keep: UniqueKeep::default(),
+        })
+    }
+
+    /// Drop rows duplicating others in the `subset` columns, keeping the
+    /// row `keep` picks.
+    pub fn unique_by<S: Into<String>>(
+        self,
+        subset: impl IntoIterator<Item = S>,
+        keep: UniqueKeep,
+    ) -> Plan {
+        self.then(QueryPlan::Unique {
+            subset: Some(names(subset)),
+            keep,
+        })
+    }
+
+    /// Drop rows with a null in any column.
+    pub fn drop_nulls(self) -> Plan {
+        self.then(QueryPlan::DropNulls(None))
+    }
+
+    pub fn fill_null(self, value: impl Into<Literal>) -> Plan {
+        self.then(QueryPlan::FillNull(FillNull::Value(value.into())))
+    }
+
+    pub fn fill_null_strategy(self, strategy: FillStrategy) -> Plan {
+        self.then(QueryPlan::FillNull(FillNull::Strategy(strategy)))
+    }
+
+    /// Spread the values of `columns` into columns of their own, one row per
+    /// `index`, combining values falling into the same cell by `aggregate`.
+    pub fn pivot<S: Into<String>>(
+        self,
+        values: impl IntoIterator<Item = S>,
+        index: impl IntoIterator<Item = S>,
+        columns: impl IntoIterator<Item = S>,
+        aggregate: PivotAggregate,
+    ) -> Plan {
+        self.then(QueryPlan::Pivot {
+            values: names(values),
+            index: names(index),
+            columns: names(columns),
+            aggregate,
+            sort_columns: false,
+        })
+    }
+
+    /// Turn `value_vars`, or every column but `id_vars` when empty, into
+    /// `variable` and `value` rows.
+    pub fn melt<S: Into<String>>(
+        self,
+        id_vars: impl IntoIterator<Item = S>,
+        value_vars: impl IntoIterator<Item = S>,
+    ) -> Plan {
+        self.then(QueryPlan::Melt {
+            id_vars: names(id_vars),
+            value_vars: names(value_vars),
+            variable_name: None,
+            value_name: None,
+        })
+    }
+
+    /// Join the frame read by `other` on the `on` column. Fails unless
+    /// `other` is a plan of a single read, as queries only join frames read
+    /// as they are.
+    pub fn join(self, other: Plan, on: impl Into<String>, how: JoinKind) -> Result<Plan, String> {
+        let source = match <[QueryPlan; 1]>::try_from(other.steps) {
+            Ok([source]) if source.source() == Some(&source) => source,
+            _ => return Err("only a plan of a single read can be joined".to_string()),
+        };
+        Ok(self.then(QueryPlan::Join {
+            source: Box::new(source),
+            on: on.into(),
+            how,
+        }))
+    }
+
+    /// Execute the plan in the current thread, with relative paths resolved
+    /// against the working directory.
+    pub fn execute(&self) -> PolarsResult<DataFrame> {
+        self.execute_with(&ExecContext::default())
+    }
+
+    pub fn execute_with(&self, ctx: &ExecContext) -> PolarsResult<DataFrame> {
+        executor::execute_steps_with(self.steps.clone(), ctx)
+    }
+}
+
+impl From<Vec<QueryPlan>> for Plan {
+    fn from(steps: Vec<QueryPlan>) -> Plan {
+        Plan { steps }
+    }
+}
+
+/// Renders the plan as a query, one statement per step.
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            if step.source() == Some(step) {
+                writeln!(f, "df = {}", step)?;
+            } else {
+                writeln!(f, "df = df.{}", step)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn names<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Vec<String> {
+    names.into_iter().map(Into::into).collect()
+}
+
+/// `pl.col(name)`
+pub fn col(name: impl Into<String>) -> Expr {
+    Expr::Column(name.into())
+}
+
+/// `pl.lit(value)`
+pub fn lit(value: impl Into<Literal>) -> Expr {
+    Expr::Literal(value.into())
+}
+
+macro_rules! literal_from {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Literal {
+                fn from(value: $ty) -> Literal {
+                    Literal::$variant(value.into())
+                }
+            }
+
+            impl From<$ty> for Expr {
+                fn from(value: $ty) -> Expr {
+                    Expr::Literal(value.into())
+                }
+            }
+        )*
+    };
+}
+
+literal_from! {
+    i32 => Int,
+    i64 => Int,
+    u32 => Int,
+    f32 => Float,
+    f64 => Float,
+    bool => Bool,
+    &str => Str,
+    String => Str,
+    NaiveDate => Date,
+    NaiveDateTime => Datetime,
+}
+
+impl From<Literal> for Expr {
+    fn from(value: Literal) -> Expr {
+        Expr::Literal(value)
+    }
+}
+
+fn binary(left: Expr, op: BinaryOp, right: impl Into<Expr>) -> Expr {
+    Expr::Binary {
+        left: Box::new(left),
+        op,
+        right: Box::new(right.into()),
+    }
+}
+
+/// Methods building expressions as their Polars namesakes do, taking
+/// operands as expressions or as values converting to literals.
+impl Expr {
+    pub fn eq(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::Eq, other)
+    }
+
+    pub fn neq(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::NotEq, other)
+    }
+
+    pub fn lt(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::Lt, other)
+    }
+
+    pub fn lt_eq(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::LtEq, other)
+    }
+
+    pub fn gt(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::Gt, other)
+    }
+
+    pub fn gt_eq(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::GtEq, other)
+    }
+
+    pub fn and(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::And, other)
+    }
+
+    pub fn or(self, other: impl Into<Expr>) -> Expr {
+        binary(self, BinaryOp::Or, other)
+    }
+
+    fn agg(self, func: AggFunc) -> Expr {
+        Expr::Agg {
+            func,
+            expr: Box::new(self),
+        }
+    }
+
+    pub fn sum(self) -> Expr {
+        self.agg(AggFunc::Sum)
+    }
+
+    pub fn mean(self) -> Expr {
+        self.agg(AggFunc::Mean)
+    }
+
+    pub fn median(self) -> Expr {
+        self.agg(AggFunc::Median)
+    }
+
+    pub fn min(self) -> Expr {
+        self.agg(AggFunc::Min)
+    }
+
+    pub fn max(self) -> Expr {
+        self.agg(AggFunc::Max)
+    }
+
+    pub fn count(self) -> Expr {
+        self.agg(AggFunc::Count)
+    }
+
+    pub fn n_unique(self) -> Expr {
+        self.agg(AggFunc::NUnique)
+    }
+
+    pub fn first(self) -> Expr {
+        self.agg(AggFunc::First)
+    }
+
+    pub fn last(self) -> Expr {
+        self.agg(AggFunc::Last)
+    }
+
+    pub fn std(self, ddof: u8) -> Expr {
+        self.agg(AggFunc::Std(ddof))
+    }
+
+    pub fn var(self, ddof: u8) -> Expr {
+        self.agg(AggFunc::Var(ddof))
+    }
+
+    pub fn quantile(self, quantile: f64) -> Expr {
+        self.agg(AggFunc::Quantile(quantile))
+    }
+
+    pub fn alias(self, name: impl Into<String>) -> Expr {
+        Expr::Alias {
+            expr: Box::new(self),
+            name: name.into(),
+        }
+    }
+
+    pub fn is_in<L: Into<Literal>>(self, values: impl IntoIterator<Item = L>) -> Expr {
+        Expr::IsIn {
+            expr: Box::new(self),
+            values: values.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn is_null(self) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(self),
+            negated: false,
+        }
+    }
+
+    pub fn is_not_null(self) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(self),
+            negated: true,
+        }
+    }
+
+    /// Whether values lie between `low` and `high`, both included.
+    pub fn is_between(self, low: impl Into<Expr>, high: impl Into<Expr>) -> Expr {
+        Expr::Between {
+            expr: Box::new(self),
+            low: Box::new(low.into()),
+            high: Box::new(high.into()),
+        }
+    }
+
+    pub fn cast(self, dtype: DType) -> Expr {
+        Expr::Cast {
+            expr: Box::new(self),
+            dtype,
+            strict: true,
+        }
+    }
+
+    /// Evaluate the expression within each group of `partition_by`.
+    pub fn over<S: Into<String>>(self, partition_by: impl IntoIterator<Item = S>) -> Expr {
+        Expr::Over {
+            expr: Box::new(self),
+            partition_by: names(partition_by),
+        }
+    }
+}
+
+impl ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+}
+
+macro_rules! arithmetic {
+    ($($trait:ident :: $method:ident => $op:ident),* $(,)?) => {
+        $(
+            impl<T: Into<Expr>> ops::$trait<T> for Expr {
+                type Output = Expr;
+
+                fn $method(self, other: T) -> Expr {
+                    binary(self, BinaryOp::$op, other)
+                }
+            }
+        )*
+    };
+}
+
+arithmetic! {
+    Add::add => Add,
+    Sub::sub => Sub,
+    Mul::mul => Mul,
+    Div::div => Div,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_plans_parse_back_to_themselves() {
+        let plan = Plan::read_csv_with(
+            "a.csv",
+            CsvOptions {
+                delimiter: b';',
+                ..Default::default()
+            },
+        )
+        .join(Plan::read_table_version("users", 2), "id", JoinKind::Left)
+        .unwrap()
+        .filter(
+            col("age")
+                .gt_eq(18)
+                .and(col("city").is_in(["NY", "LA"]).or(!col("score").gt(5))),
+        )
+        .with_columns([(col("a") + 1) * col("b").cast(DType::Float64)])
+        .group_by(["city"])
+        .agg([
+            col("a").sum().alias("total"),
+            col("a").quantile(0.9),
+            col("b").std(0),
+        ])
+        .unique_by(["city"], UniqueKeep::First)
+        .fill_null(0)
+        .sort("city")
+        .slice(-10, Some(5));
+        assert_eq!(Plan::parse(&plan.to_string()).unwrap(), plan);
+    }
+
+    #[test]
+    fn plans_execute_directly() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "city,x\nNY,1\nLA,2\nNY,3\nSF,4\n").unwrap();
+        let ctx = ExecContext {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let df = Plan::read_csv("a.csv")
+            .filter(col("x").gt(1))
+            .group_by(["city"])
+            .agg([col("x").sum().alias("total")])
+            .sort("city")
+            .execute_with(&ctx)
+            .unwrap();
+        assert_eq!(df.height(), 3);
+        let totals: Vec<_> = df
+            .column("total")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(totals, [Some(2), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn joined_plans_must_be_reads() {
+        let err = Plan::read_csv("a.csv")
+            .join(Plan::read_csv("b.csv").head(5), "id", JoinKind::Inner)
+            .unwrap_err();
+        assert!(err.contains("single read"));
+    }
+}
@@ -0,0 +1,198 @@
+//! Declarative data quality checks run against dataset versions.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A check evaluated against every row of a dataset version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CheckSpec {
+    /// The column contains no nulls.
+    NotNull { column: String },
+    /// The combination of `columns` identifies each row.
+    Unique { columns: Vec<String> },
+    /// Every non-null value lies within `[min, max]`.
+    Range {
+        column: String,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    /// The row count changed by at most `max_change_pct` percent since the
+    /// previous run.
+    RowCountDelta { max_change_pct: f64 },
+}
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub check: CheckSpec,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of running all of a dataset's checks against one version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckRun {
+    pub version: u64,
+    /// Unix seconds.
+    pub ran_at: u64,
+    pub passed: bool,
+    pub rows: u64,
+    pub results: Vec<CheckResult>,
+}
+
+/// Number of check runs kept per dataset.
+pub const MAX_CHECK_RUNS: usize = 50;
+
+pub(crate) fn row_count(lf: LazyFrame) -> PolarsResult<u64> {
+    let df = lf.select([count()]).collect()?;
+    let n = df.get_columns()[0].cast(&DataType::UInt64)?;
+    Ok(n.u64()?.get(0).unwrap_or(0))
+}
+
+fn evaluate(
+    check: &CheckSpec,
+    lf: &LazyFrame,
+    rows: u64,
+    previous_rows: Option<u64>,
+) -> PolarsResult<(bool, String)> {
+    Ok(match check {
+        CheckSpec::NotNull { column } => {
+            let nulls = row_count(lf.clone().filter(col(column).is_null()))?;
+            (nulls == 0, format!("{} null values in {}", nulls, column))
+        }
+        CheckSpec::Unique { columns } => {
+            let keys: Vec<Expr> = columns.iter().map(|c| col(c)).collect();
+            let distinct = row_count(
+                lf.clone()
+                    .select(keys)
+                    .unique(None, UniqueKeepStrategy::Any),
+            )?;
+            (
+                distinct == rows,
+                format!("{} duplicate rows", rows - distinct),
+            )
+        }
+        CheckSpec::Range { column, min, max } => {
+            let value = col(column).cast(DataType::Float64);
+            let mut outside = lit(false);
+            if let Some(min) = min {
+                outside = outside.or(value.clone().lt(lit(*min)));
+            }
+            if let Some(max) = max {
+                outside = outside.or(value.gt(lit(*max)));
+            }
+            let violations = row_count(lf.clone().filter(outside))?;
+            (
+                violations == 0,
+                format!("{} values of {} out of range", violations, column),
+            )
+        }
+        CheckSpec::RowCountDelta { max_change_pct } => match previous_rows {
+            Some(prev) => {
+                let change = if prev == 0 {
+                    if rows == 0 {
+                        0.0
+                    } else {
+                        f64::INFINITY
+                    }
+                } else {
+                    (rows as f64 - prev as f64).abs() / prev as f64 * 100.0
+                };
+                (
+                    change <= *max_change_pct,
+                    format!("row count changed {:.1}% ({} -> {})", change, prev, rows),
+                )
+            }
+            None => (true, "no previous run to compare against".to_string()),
+        },
+    })
+}
+
+/// Run `checks` against `lf`, the data of `version`.
+///
+/// A check that cannot be evaluated (for example because the column does not
+/// exist) fails with the error as its detail.
+pub fn run_checks(
+    checks: &[CheckSpec],
+    lf: LazyFrame,
+    version: u64,
+    previous_rows: Option<u64>,
+    ran_at: u64,
+) -> PolarsResult<CheckRun> {
+    let rows = row_count(lf.clone())?;
+    let results: Vec<CheckResult> = checks
+        .iter()
+        .map(|check| {
+            let (passed, detail) = evaluate(check, &lf, rows, previous_rows)
+                .unwrap_or_else(|e| (false, e.to_string()));
+            CheckResult {
+                check: check.clone(),
+                passed,
+                detail,
+            }
+        })
+        .collect();
+    Ok(CheckRun {
+        version,
+        ran_at,
+        passed: results.iter().all(|r| r.passed),
+        rows,
+        results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_report_violations() {
+        let df = df![
+            "id" => [1, 2, 2],
+            "age" => [Some(20), None, Some(150)],
+        ]
+        .unwrap();
+        let checks = vec![
+            CheckSpec::NotNull {
+                column: "age".into(),
+            },
+            CheckSpec::Unique {
+                columns: vec!["id".into()],
+            },
+            CheckSpec::Range {
+                column: "age".into(),
+                min: Some(0.0),
+                max: Some(120.0),
+            },
+            CheckSpec::RowCountDelta {
+                max_change_pct: 10.0,
+            },
+            CheckSpec::NotNull {
+                column: "missing".into(),
+            },
+        ];
+        let run = run_checks(&checks, df.lazy(), 1, Some(2), 0).unwrap();
+        assert_eq!(run.rows, 3);
+        assert!(!run.passed);
+        let passed: Vec<bool> = run.results.iter().map(|r| r.passed).collect();
+        assert_eq!(passed, vec![false, false, false, false, false]);
+        assert_eq!(run.results[1].detail, "1 duplicate rows");
+        assert_eq!(run.results[2].detail, "1 values of age out of range");
+    }
+
+    #[test]
+    fn passing_checks() {
+        let df = df!["id" => [1, 2, 3]].unwrap();
+        let checks = vec![
+            CheckSpec::Unique {
+                columns: vec!["id".into()],
+            },
+            CheckSpec::RowCountDelta {
+                max_change_pct: 50.0,
+            },
+        ];
+        let run = run_checks(&checks, df.lazy(), 1, Some(3), 0).unwrap();
+        assert!(run.passed);
+    }
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// What to do when storing an output would take a user over their quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPolicy {
+    /// Refuse the new output.
+    Reject,
+    /// Evict the user's oldest outputs until the new one fits.
+    EvictOldest,
+}
+
+/// Per-user storage quota configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    /// Maximum stored-output bytes per user. `None` disables quotas.
+    pub limit_bytes: Option<u64>,
+    pub policy: QuotaPolicy,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            limit_bytes: None,
+            policy: QuotaPolicy::Reject,
+        }
+    }
+}
+
+/// Returned when an output does not fit in the user's quota.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub user: String,
+    pub used: u64,
+    pub requested: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "storage quota exceeded for user {}: {} bytes used, {} requested, limit {}",
+            self.user, self.used, self.requested, self.limit
+        )
+    }
+}
+
+/// Tracks cumulative stored-output bytes per user.
+#[derive(Default)]
+pub struct QuotaTracker {
+    config: QuotaConfig,
+    usage: Mutex<HashMap<String, VecDeque<(String, u64)>>>,
+}
+
+impl QuotaTracker {
+    pub fn new(config: QuotaConfig) -> Self {
+        QuotaTracker {
+            config,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Bytes currently charged to `user`.
+    pub fn usage(&self, user: &str) -> u64 {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(user)
+            .map(|files| files.iter().map(|(_, size)| size).sum())
+            .unwrap_or(0)
+    }
+
+    /// Charge a stored file of `size` bytes to `user`.
+    ///
+    /// On success returns the paths evicted to make room (always empty with
+    /// [`QuotaPolicy::Reject`]); the caller is responsible for releasing them.
+    pub fn charge(&self, user: &str, path: &str, size: u64) -> Result<Vec<String>, QuotaExceeded> {
+        let mut usage = self.usage.lock().unwrap();
+        let files = usage.entry(user.to_string()).or_default();
+        let mut evicted = Vec::new();
+
+        if let Some(limit) = self.config.limit_bytes {
+            let mut used: u64 = files.iter().map(|(_, s)| s).sum();
+            if used + size > limit {
+                if self.config.policy == QuotaPolicy::Reject || size > limit {
+                    return Err(QuotaExceeded {
+                        user: user.to_string(),
+                        used,
+                        requested: size,
+                        limit,
+                    });
+                }
+                while used + size > limit {
+                    match files.pop_front() {
+                        Some((old_path, old_size)) => {
+                            used -= old_size;
+                            evicted.push(old_path);
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        files.push_back((path.to_string(), size));
+        Ok(evicted)
+    }
+
+    /// Stop charging `path` to its owner once the file has been removed.
+    pub fn uncharge(&self, path: &str) {
+        for files in self.usage.lock().unwrap().values_mut() {
+            files.retain(|(p, _)| p != path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_over_quota() {
+        let quota = QuotaTracker::new(QuotaConfig {
+            limit_bytes: Some(100),
+            policy: QuotaPolicy::Reject,
+        });
+        assert!(quota.charge("alice", "a", 60).unwrap().is_empty());
+        assert!(quota.charge("alice", "b", 60).is_err());
+        assert!(quota.charge("bob", "c", 60).is_ok());
+        assert_eq!(quota.usage("alice"), 60);
+    }
+
+    #[test]
+    fn evict_oldest_to_make_room() {
+        let quota = QuotaTracker::new(QuotaConfig {
+            limit_bytes: Some(100),
+            policy: QuotaPolicy::EvictOldest,
+        });
+        quota.charge("alice", "a", 40).unwrap();
+        quota.charge("alice", "b", 40).unwrap();
+        let evicted = quota.charge("alice", "c", 50).unwrap();
+        assert_eq!(evicted, vec!["a".to_string()]);
+        assert_eq!(quota.usage("alice"), 90);
+        quota.uncharge("b");
+        assert_eq!(quota.usage("alice"), 50);
+    }
+}
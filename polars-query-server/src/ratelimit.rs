@@ -0,0 +1,136 @@
+//! Limits on how fast each client, and all clients together, may submit
+//! jobs, counted in the shared state backend so every replica enforces the
+//! same limits.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::state::StateBackend;
+
+/// Submission rates, each unlimited when unset.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Submissions per second each user may sustain.
+    pub per_client: Option<f64>,
+    /// Submissions per second across all users.
+    pub global: Option<f64>,
+    /// Submissions each limit allows at once, per window of `burst / rate`
+    /// seconds. Defaults to one second's worth of its rate.
+    pub burst: Option<u32>,
+}
+
+/// Why a submission was turned away, and when to try again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throttled {
+    pub message: String,
+    pub retry_after: Duration,
+}
+
+impl Throttled {
+    /// `retry_after` in whole seconds, at least one, for a `Retry-After`
+    /// header.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_millis().div_ceil(1000).max(1) as u64
+    }
+}
+
+/// Per-user and global submission counters. Each limit allows its burst of
+/// submissions per window of `burst / rate` seconds, which sustains `rate`
+/// per second on average.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    state: Arc<dyn StateBackend>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, state: Arc<dyn StateBackend>) -> Self {
+        RateLimiter { config, state }
+    }
+
+    fn capacity(&self, rate: f64) -> u64 {
+        match self.config.burst {
+            Some(burst) => burst.max(1) as u64,
+            None => rate.ceil().max(1.0) as u64,
+        }
+    }
+
+    /// Count a submission against the window of `key`, limited to `rate` per
+    /// second, returning how long to wait if it is over the limit. A state
+    /// backend that cannot be reached lets the submission through.
+    async fn take(&self, key: &str, rate: f64) -> Option<Duration> {
+        let capacity = self.capacity(rate);
+        let window = Duration::from_secs_f64(capacity as f64 / rate);
+        match self.state.incr_counter(key, window).await {
+            Ok(count) if count > capacity => Some(window),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!(key, "failed to count submission: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Count a submission by `user` against the per-user limit and then the
+    /// global one. A submission refused by the per-user limit is not counted
+    /// globally.
+    pub async fn check(&self, user: &str) -> Result<(), Throttled> {
+        if let Some(rate) = self.config.per_client.filter(|r| *r > 0.0) {
+            if let Some(wait) = self.take(&format!("rate:user:{}", user), rate).await {
+                return Err(Throttled {
+                    message: format!("rate limit exceeded for {}", user),
+                    retry_after: wait,
+                });
+            }
+        }
+        if let Some(rate) = self.config.global.filter(|r| *r > 0.0) {
+            if let Some(wait) = self.take("rate:global", rate).await {
+                return Err(Throttled {
+                    message: "server rate limit exceeded".to_string(),
+                    retry_after: wait,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::MemoryState;
+
+    #[tokio::test]
+    async fn limits_reset_after_their_window() {
+        let state: Arc<dyn StateBackend> = Arc::new(MemoryState::default());
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                per_client: Some(2.0),
+                global: Some(3.0),
+                burst: None,
+            },
+            state.clone(),
+        );
+        assert!(limiter.check("alice").await.is_ok());
+        assert!(limiter.check("alice").await.is_ok());
+        let throttled = limiter.check("alice").await.unwrap_err();
+        assert_eq!(throttled.message, "rate limit exceeded for alice");
+        assert_eq!(throttled.retry_after, Duration::from_secs(1));
+        assert_eq!(throttled.retry_after_secs(), 1);
+
+        // alice's refused submission was not counted globally.
+        assert!(limiter.check("bob").await.is_ok());
+        let throttled = limiter.check("carol").await.unwrap_err();
+        assert_eq!(throttled.message, "server rate limit exceeded");
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(limiter.check("alice").await.is_ok());
+        assert!(limiter.check("carol").await.is_ok());
+
+        let unlimited = RateLimiter::new(RateLimitConfig::default(), state);
+        for _ in 0..100 {
+            assert!(unlimited.check("alice").await.is_ok());
+        }
+    }
+}
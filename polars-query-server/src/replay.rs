@@ -0,0 +1,182 @@
+//! Replay of workloads recorded in the metrics store against a running
+//! server, keeping the queries' original arrival pattern.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Args;
+use tokio::time::Instant;
+
+use crate::api::USER_HEADER;
+use crate::bench::BenchReport;
+use crate::metrics;
+
+/// Arguments of the `replay` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct ReplayArgs {
+    /// Metrics file, or directory of the metrics store.
+    #[arg(long, default_value = "metrics")]
+    pub from: PathBuf,
+    /// Base URL of the server the workload is submitted to.
+    #[arg(long, default_value = "http://127.0.0.1:3000")]
+    pub target: String,
+    /// Playback speed, e.g. `2x` to halve the gaps between submissions.
+    #[arg(long, default_value = "1x", value_parser = parse_speed)]
+    pub speed: f64,
+    /// Replay only the first N recorded queries.
+    #[arg(long)]
+    pub limit: Option<usize>,
+    /// Submit every query as this user instead of the recorded one.
+    #[arg(long)]
+    pub user: Option<String>,
+}
+
+/// A recorded query and when it arrived relative to the first one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedQuery {
+    pub query: String,
+    pub user: Option<String>,
+    pub offset: Duration,
+}
+
+/// Parse a speed such as `2x`, `0.5x` or `3`.
+fn parse_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value
+        .trim_end_matches(['x', 'X'])
+        .parse()
+        .map_err(|_| format!("invalid speed {}: use e.g. 2x", value))?;
+    if !(speed.is_finite() && speed > 0.0) {
+        return Err(format!("speed must be positive, got {}", value));
+    }
+    Ok(speed)
+}
+
+/// Load the recorded queries at `path` in arrival order. Rows recorded
+/// before arrival times were kept are taken to arrive with the row before.
+pub fn load(path: &Path, limit: Option<usize>) -> Result<Vec<RecordedQuery>, String> {
+    let df = metrics::read_metrics(path)?;
+    let queries = df
+        .column("query")
+        .and_then(|c| c.utf8().cloned())
+        .map_err(|e| e.to_string())?;
+    let users = df.column("user").ok().and_then(|c| c.utf8().ok().cloned());
+    let arrivals = df
+        .column("submitted_at_ms")
+        .ok()
+        .and_then(|c| c.i64().ok().cloned());
+
+    let mut rows = Vec::with_capacity(df.height());
+    let mut last = None;
+    for i in 0..df.height() {
+        let Some(query) = queries.get(i) else {
+            continue;
+        };
+        let arrived = arrivals.as_ref().and_then(|a| a.get(i)).or(last);
+        last = arrived;
+        let user = users.as_ref().and_then(|u| u.get(i)).map(str::to_string);
+        rows.push((arrived.unwrap_or(0), query.to_string(), user));
+    }
+    // Rows are appended as jobs finish; stable sorting restores arrival order.
+    rows.sort_by_key(|(arrived, _, _)| *arrived);
+    let first = rows.first().map_or(0, |(arrived, _, _)| *arrived);
+    Ok(rows
+        .into_iter()
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|(arrived, query, user)| RecordedQuery {
+            query,
+            user,
+            offset: Duration::from_millis((arrived - first).max(0) as u64),
+        })
+        .collect())
+}
+
+/// Submit the recorded workload to `args.target`, each query at its original
+/// offset divided by the speed, and report latencies.
+pub async fn run(args: &ReplayArgs) -> Result<BenchReport, String> {
+    let recorded = load(&args.from, args.limit)?;
+    if recorded.is_empty() {
+        return Err(format!("no queries recorded in {}", args.from.display()));
+    }
+    let http = reqwest::Client::new();
+    let url = format!("{}/run-query?wait=true", args.target.trim_end_matches('/'));
+
+    let start = Instant::now();
+    let mut handles = Vec::with_capacity(recorded.len());
+    for query in recorded {
+        let http = http.clone();
+        let url = url.clone();
+        let user = args.user.clone().or(query.user);
+        let at = start + query.offset.div_f64(args.speed);
+        handles.push(tokio::spawn(async move {
+            tokio::time::sleep_until(at).await;
+            let submitted = Instant::now();
+            let mut request = http.post(&url).body(query.query);
+            if let Some(user) = user {
+                request = request.header(USER_HEADER, user);
+            }
+            let ok = match request.send().await {
+                Ok(resp) if resp.status().is_success() => resp
+                    .json::<serde_json::Value>()
+                    .await
+                    .is_ok_and(|v| v["error"].is_null()),
+                _ => false,
+            };
+            (submitted.elapsed(), ok)
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(handles.len());
+    let mut failures = 0;
+    for handle in handles {
+        let (latency, ok) = handle.await.map_err(|e| e.to_string())?;
+        latencies.push(latency);
+        if !ok {
+            failures += 1;
+        }
+    }
+    Ok(BenchReport::from_latencies(
+        latencies,
+        failures,
+        start.elapsed(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::*;
+    use std::fs::File;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn speed_accepts_multiplier_suffix() {
+        assert_eq!(parse_speed("2x").unwrap(), 2.0);
+        assert_eq!(parse_speed("0.5").unwrap(), 0.5);
+        assert!(parse_speed("0x").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+
+    #[test]
+    fn queries_are_loaded_in_arrival_order() {
+        let dir = tempdir().unwrap();
+        let mut df = df![
+            "query" => ["b", "a", "old"],
+            "user" => [Some("bob"), Some("alice"), None],
+            "submitted_at_ms" => [Some(1_500i64), Some(1_000), None],
+        ]
+        .unwrap();
+        ParquetWriter::new(File::create(dir.path().join("query_metrics.parquet")).unwrap())
+            .finish(&mut df)
+            .unwrap();
+
+        let recorded = load(dir.path(), None).unwrap();
+        let order: Vec<_> = recorded.iter().map(|r| r.query.as_str()).collect();
+        // "old" has no arrival time and follows the row recorded before it.
+        assert_eq!(order, vec!["a", "old", "b"]);
+        assert_eq!(recorded[1].offset, Duration::ZERO);
+        assert_eq!(recorded[2].offset, Duration::from_millis(500));
+        assert_eq!(recorded[0].user.as_deref(), Some("alice"));
+        assert_eq!(load(dir.path(), Some(1)).unwrap().len(), 1);
+    }
+}
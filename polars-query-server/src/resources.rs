@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::Path;
+
+/// CPU and memory limits imposed on the process by its cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Resources {
+    /// Number of CPUs the process may use, possibly fractional.
+    pub cpus: Option<f64>,
+    /// Memory limit in bytes.
+    pub memory_bytes: Option<u64>,
+}
+
+/// Detect limits from `/sys/fs/cgroup`, falling back to the host CPU count.
+pub fn detect() -> Resources {
+    let mut resources = detect_in(Path::new("/sys/fs/cgroup"));
+    if resources.cpus.is_none() {
+        resources.cpus = std::thread::available_parallelism()
+            .ok()
+            .map(|n| n.get() as f64);
+    }
+    resources
+}
+
+/// Detect limits from a cgroup filesystem mounted at `root`, understanding
+/// both the unified (v2) and legacy (v1) hierarchies.
+pub fn detect_in(root: &Path) -> Resources {
+    let read = |p: &str| fs::read_to_string(root.join(p)).ok();
+    let cpus = read("cpu.max").and_then(|s| parse_cpu_max(&s)).or_else(|| {
+        let quota = read("cpu/cpu.cfs_quota_us")?;
+        let period = read("cpu/cpu.cfs_period_us")?;
+        parse_cfs(&quota, &period)
+    });
+    let memory_bytes = read("memory.max")
+        .and_then(|s| parse_memory_limit(&s))
+        .or_else(|| read("memory/memory.limit_in_bytes").and_then(|s| parse_memory_limit(&s)));
+    Resources { cpus, memory_bytes }
+}
+
+/// Parse cgroup v2 `cpu.max` (`"<quota> <period>"` or `"max <period>"`).
+fn parse_cpu_max(s: &str) -> Option<f64> {
+    let mut fields = s.split_whitespace();
+    let quota = fields.next()?;
+    let period = fields.next()?;
+    parse_cfs(quota, period)
+}
+
+/// Parse a CFS quota/period pair; a negative or `max` quota means unlimited.
+fn parse_cfs(quota: &str, period: &str) -> Option<f64> {
+    let quota: i64 = quota.trim().parse().ok()?;
+    let period: i64 = period.trim().parse().ok()?;
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+    Some(quota as f64 / period as f64)
+}
+
+/// Parse a memory limit; `max` and the huge v1 sentinel mean unlimited.
+fn parse_memory_limit(s: &str) -> Option<u64> {
+    let limit: u64 = s.trim().parse().ok()?;
+    // cgroup v1 reports "unlimited" as a page-aligned i64::MAX.
+    if limit >= (i64::MAX as u64) & !0xfff {
+        return None;
+    }
+    Some(limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn cgroup_v2_limits() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("cpu.max"), "250000 100000\n").unwrap();
+        fs::write(dir.path().join("memory.max"), "2147483648\n").unwrap();
+        let r = detect_in(dir.path());
+        assert_eq!(r.cpus, Some(2.5));
+        assert_eq!(r.memory_bytes, Some(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn unlimited_cgroup() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("cpu.max"), "max 100000\n").unwrap();
+        fs::write(dir.path().join("memory.max"), "max\n").unwrap();
+        assert_eq!(detect_in(dir.path()), Resources::default());
+    }
+
+    #[test]
+    fn cgroup_v1_limits() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("cpu")).unwrap();
+        fs::create_dir_all(dir.path().join("memory")).unwrap();
+        fs::write(dir.path().join("cpu/cpu.cfs_quota_us"), "-1\n").unwrap();
+        fs::write(dir.path().join("cpu/cpu.cfs_period_us"), "100000\n").unwrap();
+        fs::write(
+            dir.path().join("memory/memory.limit_in_bytes"),
+            "9223372036854771712\n",
+        )
+        .unwrap();
+        assert_eq!(detect_in(dir.path()), Resources::default());
+    }
+}
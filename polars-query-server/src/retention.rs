@@ -0,0 +1,151 @@
+//! Removal of stored result files once they are older than a retention
+//! period or their total size exceeds a cap. The jobs whose results were
+//! removed are reported as `expired`.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::scheduler::Scheduler;
+
+/// How long result files are kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Seconds a result file is kept after it was last written or reused.
+    /// Kept indefinitely when unset.
+    pub max_age_secs: Option<u64>,
+    /// Most bytes of result files kept; the oldest are removed beyond it.
+    pub max_total_bytes: Option<u64>,
+    /// Seconds between sweeps of the output directory.
+    pub interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_age_secs: None,
+            max_total_bytes: None,
+            interval_secs: 60,
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn enabled(&self) -> bool {
+        self.max_age_secs.is_some() || self.max_total_bytes.is_some()
+    }
+}
+
+/// Result files in `dir` to remove at `now`: those older than
+/// `max_age_secs`, then the oldest of the rest until the remainder fits in
+/// `max_total_bytes`.
+pub fn expired_files(
+    dir: &Path,
+    config: &RetentionConfig,
+    now: SystemTime,
+) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with("output_") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+    files.sort();
+
+    let max_age = config.max_age_secs.map(Duration::from_secs);
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let mut expired = Vec::new();
+    // Oldest first, so once a file is kept every later one is too.
+    for (modified, size, path) in files {
+        let age = now.duration_since(modified).unwrap_or_default();
+        let too_old = max_age.is_some_and(|max| age > max);
+        let over_cap = config.max_total_bytes.is_some_and(|max| total > max);
+        if !too_old && !over_cap {
+            break;
+        }
+        total -= size;
+        expired.push(path);
+    }
+    Ok(expired)
+}
+
+/// Remove expired result files every `interval_secs`, when a retention
+/// period or size cap is configured.
+pub fn spawn_sweep(scheduler: Scheduler, config: RetentionConfig) {
+    if !config.enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            let expired = scheduler.expire_results(&config).await;
+            if !expired.is_empty() {
+                tracing::info!(jobs = ?expired, "expired job results");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn old_and_excess_files_expire_oldest_first() {
+        let dir = tempdir().unwrap();
+        let now = SystemTime::now();
+        for (name, age_secs) in [
+            ("output_a.feather", 300),
+            ("output_b.feather", 200),
+            ("output_c.feather", 100),
+            ("lineage.json", 1000),
+        ] {
+            let path = dir.path().join(name);
+            fs::write(&path, [0u8; 10]).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age_secs))
+                .unwrap();
+        }
+        let names = |config: &RetentionConfig| -> Vec<String> {
+            expired_files(dir.path(), config, now)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+                .collect()
+        };
+
+        assert!(names(&RetentionConfig::default()).is_empty());
+        let by_age = RetentionConfig {
+            max_age_secs: Some(250),
+            ..Default::default()
+        };
+        assert_eq!(names(&by_age), ["output_a.feather"]);
+        let by_size = RetentionConfig {
+            max_total_bytes: Some(15),
+            ..Default::default()
+        };
+        assert_eq!(names(&by_size), ["output_a.feather", "output_b.feather"]);
+        assert!(expired_files(&dir.path().join("missing"), &by_age, now)
+            .unwrap()
+            .is_empty());
+    }
+}
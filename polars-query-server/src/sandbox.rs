@@ -0,0 +1,106 @@
+//! Confinement of the local files queries may read to configured data
+//! directories.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::cloud;
+
+/// Directories local source paths must lie within. Every path is allowed
+/// when none are configured.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    /// Each root both as written and with symlinks resolved, so paths are
+    /// compared in either form.
+    roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    pub fn new(roots: &[PathBuf]) -> Self {
+        let mut all = Vec::new();
+        for root in roots {
+            let lexical = normalize(&absolute(root));
+            if let Ok(real) = fs::canonicalize(&lexical) {
+                all.push(real);
+            }
+            all.push(lexical);
+        }
+        all.dedup();
+        Sandbox { roots: all }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.roots.is_empty()
+    }
+
+    /// Whether `path`, already resolved against the data directory, lies
+    /// within a root. `..` components are applied before comparing, and
+    /// existing paths are also followed through symlinks; globs are judged
+    /// by the directories they name. Object store URLs are always allowed.
+    pub fn allows(&self, path: &str) -> bool {
+        if !self.enabled() || cloud::is_url(path) {
+            return true;
+        }
+        let lexical = normalize(&absolute(Path::new(path)));
+        let real = fs::canonicalize(&lexical).unwrap_or_else(|_| lexical.clone());
+        let inside = |p: &Path| self.roots.iter().any(|root| p.starts_with(root));
+        inside(&lexical) && inside(&real)
+    }
+}
+
+/// `path` relative to the working directory when it is not absolute.
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    std::env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// `path` with `.` dropped and each `..` removing the component before it.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn paths_outside_the_roots_are_refused() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sales")).unwrap();
+        fs::write(dir.path().join("secret.csv"), "x\n1\n").unwrap();
+        let sandbox = Sandbox::new(std::slice::from_ref(&data));
+        let at = |p: &str| data.join(p).to_string_lossy().to_string();
+
+        assert!(sandbox.allows(&at("sales/2024.parquet")));
+        assert!(sandbox.allows(&at("sales/*.parquet")));
+        assert!(sandbox.allows(&at("sales/../a.csv")));
+        assert!(sandbox.allows("s3://bucket/a.parquet"));
+        assert!(!sandbox.allows(&at("../secret.csv")));
+        assert!(!sandbox.allows(&at("sales/*/../../../secret.csv")));
+        assert!(!sandbox.allows("/etc/passwd"));
+        assert!(Sandbox::default().allows("/etc/passwd"));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("secret.csv"), data.join("link.csv"))
+                .unwrap();
+            assert!(!sandbox.allows(&at("link.csv")));
+        }
+    }
+}
@@ -1,19 +1,71 @@
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::{
-    atomic::{AtomicU64, AtomicUsize, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
 };
 
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use tokio::sync::{mpsc, oneshot};
+use base64::engine::general_purpose::STANDARD as B64_ENGINE;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::time::Instant;
-use tracing::info;
+use tracing::{info, Instrument, Span};
 
-use crate::metrics;
+use crate::metrics::{JobMetrics, MetricsLog, ServerMetrics};
 
-use crate::executor;
+use polars::prelude::{DataFrame, PolarsError};
+
+use crate::access::{AccessControl, Permission};
+use crate::audit::AuditEvent;
+use crate::cache::{self, ResultCache};
+use crate::catalog::{Catalog, Dataset};
+use crate::chaos::Chaos;
+use crate::cluster::{Dispatcher, Role, WorkItem};
+use crate::compaction::CompactionConfig;
+use crate::config::{Config, RetryConfig};
+use crate::convert::{self, ConvertFormat, Converted};
+use crate::diff::{self, ResultDiff};
+use crate::discovery::DiscoveryConfig;
+use crate::estimate::{self, Estimate};
+use crate::executor::{self, CancelToken, ExecContext};
+use crate::explain::{self, PlanGraph};
+use crate::health::{self, Readiness};
+use crate::introspect::{self, SourceSchema};
+use crate::jobs::{self, JobEntry, JobEvent, JobFilter, JobRegistry};
+use crate::lineage::{self, JobLineage, Lineage, LineageStore};
+use crate::lint::{self, LintWarning};
 use crate::parser::{self, QueryPlan};
+use crate::quota::QuotaTracker;
+use crate::ratelimit::{RateLimiter, Throttled};
+use crate::retention::{self, RetentionConfig};
+use crate::sandbox::Sandbox;
+use crate::sessions::Sessions;
+use crate::state::{self, JobRecord, StateBackend};
+use crate::storage::ResultStore;
+use crate::streaming::Appender;
+use crate::subscriptions::{Subscription, SubscriptionSpec, Subscriptions};
+use crate::templates::QueryLibrary;
+use crate::temporary::{self, ResultSource, TemporaryConfig};
+use crate::utils::{OutputConfig, OutputFormat, OutputPart, PreparedOutput};
+use crate::views::Views;
+
+/// Fallback id source used if the shared state backend is unavailable.
+/// Starts high to stay clear of ids handed out by the backend.
+static LOCAL_IDS: AtomicU64 = AtomicU64::new(1 << 48);
+
+/// Error of jobs cancelled by [`Scheduler::abort_all`].
+pub const CANCELLED: &str = "job cancelled by an administrator";
+
+/// Error of jobs stopped for running longer than their timeout.
+pub const TIMED_OUT: &str = "job timed out";
+
+/// Directory under the output directory converted results are cached in.
+pub const CONVERTED_DIR: &str = "converted";
 
 /// A job submitted to the scheduler.
 struct Job {
@@ -21,14 +73,239 @@ struct Job {
     query: String,
     resp: oneshot::Sender<JobResult>,
     cost: usize,
+    options: JobOptions,
+    /// Unix milliseconds at which the job was submitted.
+    submitted_at_ms: u64,
+    /// Times a cheaper job submitted later started before this one.
+    bypassed: usize,
+    /// Span of the job, a child of the request submitting it.
+    span: Span,
+    /// Span of the job's wait in the queue, closed as it starts.
+    queue_span: Span,
+}
+
+/// Times a queued job may be overtaken by cheaper ones of its priority
+/// before it starts next regardless of cost.
+const MAX_BYPASSED: usize = 8;
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// How soon a queued job starts. Queued jobs start highest priority first,
+/// and cheapest first within a priority, see [`next_job`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Per-job submission options.
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Identity the job is run on behalf of, used for quota accounting.
+    pub user: String,
+    /// Serialization of the job's result.
+    pub format: OutputFormat,
+    /// Longest the job may run, overriding the scheduler's default.
+    pub timeout: Option<Duration>,
+    /// Largest compressed result returned inline, lowering the server's
+    /// `inline_limit`.
+    pub inline_limit: Option<usize>,
+    pub priority: JobPriority,
+    /// Whether to run on Polars' streaming engine, overriding the server's
+    /// `streaming_engine`.
+    pub streaming: Option<bool>,
+    /// Attempts at the job when it fails with transient I/O errors,
+    /// overriding the scheduler's `retry.max_attempts`.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        JobOptions {
+            user: "anonymous".to_string(),
+            format: OutputFormat::default(),
+            timeout: None,
+            inline_limit: None,
+            priority: JobPriority::default(),
+            streaming: None,
+            max_attempts: None,
+        }
+    }
+}
+
+/// Take the next queued job that `fits`: highest priority first, then jobs
+/// overtaken [`MAX_BYPASSED`] times, then the cheapest, the earliest
+/// submitted among equally cheap ones. Jobs that do not fit are passed
+/// over, except one overtaken [`MAX_BYPASSED`] times, which holds back the
+/// jobs after it until it fits, so that expensive jobs are not starved.
+fn next_job(queue: &mut VecDeque<Job>, fits: impl Fn(&Job) -> bool) -> Option<Job> {
+    let mut order: Vec<usize> = (0..queue.len()).collect();
+    order.sort_by_key(|&i| {
+        let job = &queue[i];
+        (
+            Reverse(job.options.priority),
+            job.bypassed < MAX_BYPASSED,
+            job.cost,
+            i,
+        )
+    });
+    let mut chosen = None;
+    for i in order {
+        let job = &queue[i];
+        if fits(job) {
+            chosen = Some(i);
+            break;
+        }
+        if job.bypassed >= MAX_BYPASSED {
+            return None;
+        }
+    }
+    let i = chosen?;
+    let priority = queue[i].options.priority;
+    for job in queue.iter_mut().take(i) {
+        if job.options.priority >= priority {
+            job.bypassed += 1;
+        }
+    }
+    queue.remove(i)
+}
+
+/// Whether a job of `cost` may start while `active` jobs of total cost
+/// `running_cost` execute: a slot is free and the cost fits in what they
+/// leave of `cost_budget`. A job costing more than the whole budget starts
+/// once nothing else runs.
+fn can_start(
+    active: usize,
+    running_cost: usize,
+    cost: usize,
+    max_concurrency: usize,
+    cost_budget: Option<usize>,
+) -> bool {
+    active < max_concurrency
+        && cost_budget
+            .is_none_or(|budget| active == 0 || running_cost.saturating_add(cost) <= budget)
+}
+
+/// State shared between the scheduler loop and running jobs.
+#[derive(Clone)]
+struct JobContext {
+    active: Arc<AtomicUsize>,
+    /// Total estimated cost of the jobs executing.
+    running_cost: Arc<AtomicUsize>,
+    max_concurrency: usize,
+    /// Most jobs waiting to start.
+    queue_capacity: usize,
+    cost_budget: Option<usize>,
+    retry: RetryConfig,
+    store: Arc<ResultStore>,
+    quota: Arc<QuotaTracker>,
+    output: OutputConfig,
+    exec: ExecContext,
+    state: Arc<dyn StateBackend>,
+    /// Set on coordinators, which hand jobs to workers instead of running them.
+    dispatcher: Option<Arc<Dispatcher>>,
+    lineage: Arc<LineageStore>,
+    metrics: Arc<ServerMetrics>,
+    metrics_log: Arc<MetricsLog>,
+    /// Ids of the jobs currently running, with the tokens that cancel them.
+    running: Arc<Mutex<BTreeMap<u64, CancelToken>>>,
+    /// Bumped to signal every running job to stop.
+    abort: Arc<watch::Sender<u64>>,
+    jobs: Arc<JobRegistry>,
+    /// Longest a job may run when its options set no timeout.
+    timeout: Option<Duration>,
+    cache: Arc<ResultCache>,
+}
+
+/// Jobs cancelled by [`Scheduler::abort_all`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AbortReport {
+    /// Queued jobs, cancelled before they started.
+    pub queued: Vec<u64>,
+    /// Running jobs, signalled to stop. Work already executing finishes in
+    /// the background and its result is discarded.
+    pub running: Vec<u64>,
+}
+
+/// Outcome of [`Scheduler::shutdown`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShutdownReport {
+    /// Whether every queued and running job finished within the grace
+    /// period.
+    pub drained: bool,
+    /// Jobs cancelled once the grace period ran out.
+    pub cancelled: AbortReport,
+}
+
+/// How long jobs cancelled at shutdown get to record their results.
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+/// Outcome of [`Scheduler::cancel_job`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CancelReport {
+    pub job_id: u64,
+    /// `cancelled` for a queued job, removed before it started, or
+    /// `cancelling` for a running job, which stops at its next check.
+    pub status: &'static str,
+}
+
+/// Concurrency limits of a scheduler, reported with each submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SchedulerLimits {
+    /// Jobs executing at once; later jobs wait in the queue.
+    pub max_concurrency: usize,
+    /// Total estimated cost of the jobs executing at once, when limited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_budget: Option<usize>,
+    /// Jobs waiting to start before callers wait to submit.
+    pub queue_capacity: usize,
 }
 
 /// Scheduler managing job execution with a maximum number of concurrent jobs.
 #[derive(Clone)]
 pub struct Scheduler {
     tx: mpsc::Sender<Job>,
+    abort_tx: mpsc::Sender<oneshot::Sender<AbortReport>>,
+    cancel_tx: mpsc::Sender<(u64, oneshot::Sender<Option<CancelReport>>)>,
+    admin_token: Option<String>,
     active: Arc<AtomicUsize>,
-    next_id: Arc<AtomicU64>,
+    running_cost: Arc<AtomicUsize>,
+    store: Arc<ResultStore>,
+    quota: Arc<QuotaTracker>,
+    state: Arc<dyn StateBackend>,
+    dispatcher: Option<Arc<Dispatcher>>,
+    catalog: Arc<Catalog>,
+    views: Arc<Views>,
+    lineage: Arc<LineageStore>,
+    access: Arc<AccessControl>,
+    queries: Arc<QueryLibrary>,
+    sessions: Arc<Sessions>,
+    subscriptions: Arc<Subscriptions>,
+    appender: Arc<Appender>,
+    jobs: Arc<JobRegistry>,
+    metrics: Arc<ServerMetrics>,
+    metrics_log: Arc<MetricsLog>,
+    limiter: Arc<RateLimiter>,
+    compaction: CompactionConfig,
+    discovery: DiscoveryConfig,
+    temporary: TemporaryConfig,
+    exec: ExecContext,
+    ingest_dir: PathBuf,
+    max_concurrency: usize,
+    cost_budget: Option<usize>,
+    queue_capacity: usize,
+    ready_queue_limit: usize,
+    max_queued: Option<usize>,
+    draining: Arc<AtomicBool>,
+    config: Arc<Config>,
 }
 
 impl Default for Scheduler {
@@ -37,55 +314,1101 @@ impl Default for Scheduler {
     }
 }
 
+/// Job step a query that does not parse fails at.
+pub const PARSE_STEP: &str = "parse";
+
+/// Why a job failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JobError {
+    /// What went wrong, for clients to branch on: `invalid_query`,
+    /// `access_denied`, `unknown_dataset`, `cancelled`, `timeout`,
+    /// `insufficient_storage`, `transient_io` or `execution_failed`.
+    pub kind: &'static str,
+    pub message: String,
+    /// Step of the job that failed: `authorize`, `parse`, `execute` or
+    /// `store`. Unknown for jobs run by a worker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<&'static str>,
+}
+
+/// Every kind a [`JobError`] may have.
+const ERROR_KINDS: [&str; 10] = [
+    "invalid_query",
+    "access_denied",
+    "unknown_dataset",
+    "cancelled",
+    "timeout",
+    "insufficient_storage",
+    "transient_io",
+    "execution_failed",
+    "rate_limited",
+    "shutting_down",
+];
+
+impl JobError {
+    /// Failure of kind `kind` at `step` with `message`.
+    pub fn new(kind: &'static str, step: Option<&'static str>, message: impl Into<String>) -> Self {
+        JobError {
+            kind,
+            message: message.into(),
+            step,
+        }
+    }
+
+    /// Failure of the kind called `kind`, as reported by a worker, or
+    /// `execution_failed` when it is not one this server knows.
+    pub fn named(kind: &str, step: Option<&'static str>, message: impl Into<String>) -> Self {
+        let kind = ERROR_KINDS
+            .into_iter()
+            .find(|k| *k == kind)
+            .unwrap_or("execution_failed");
+        JobError::new(kind, step, message)
+    }
+
+    /// A failure `executor` raised at `step`, of the kind it was raised with.
+    fn execution(step: &'static str, error: &PolarsError) -> Self {
+        let kind = executor::error_kind(error).unwrap_or("execution_failed");
+        JobError::new(kind, Some(step), error.to_string())
+    }
+
+    /// The error as kind `transient_io` if `transient` and it has no more
+    /// specific kind, so that the job is retried.
+    fn transient_if(mut self, transient: bool) -> Self {
+        if transient && self.kind == "execution_failed" {
+            self.kind = "transient_io";
+        }
+        self
+    }
+
+    /// Whether retrying the job may succeed.
+    pub fn is_transient(&self) -> bool {
+        self.kind == "transient_io"
+    }
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for JobError {}
+
 #[derive(Clone)]
 pub struct JobResult {
-    pub bytes: Option<Vec<u8>>, // compressed
-    pub path: Option<String>,
+    pub output: Result<PreparedOutput, JobError>,
     pub duration: Duration,
     pub cost: usize,
+    /// Set when the output was served from the result cache.
+    pub cached: bool,
+    /// Times the job was run, more than once when transient failures were
+    /// retried.
+    pub attempts: u32,
+}
+
+/// Directories dataset locations must lie within: the allowed data roots,
+/// the discovery roots and the directories the server writes datasets to.
+/// Every location is allowed when no data roots are configured.
+fn dataset_sandbox(config: &Config) -> Sandbox {
+    if config.data.allowed_roots.is_empty() {
+        return Sandbox::default();
+    }
+    let mut roots = config.data.allowed_roots.clone();
+    roots.extend(config.catalog.discovery.roots.iter().cloned());
+    roots.push(config.data.ingest_dir.clone());
+    roots.push(config.catalog.views_dir.clone());
+    roots.push(config.catalog.temporary.dir.clone());
+    Sandbox::new(&roots)
+}
+
+/// Where `GET /jobs/:id/download` serves the stored result of job `id`, or
+/// its part `part`, relative to the server's base URL.
+pub fn download_url(id: u64, part: Option<usize>) -> String {
+    match part {
+        Some(part) => format!("/jobs/{}/download?part={}", id, part),
+        None => format!("/jobs/{}/download", id),
+    }
+}
+
+/// JSON manifest describing the result of job `id` split into several part
+/// files.
+fn part_manifest(id: u64, parts: &[OutputPart]) -> Value {
+    json!({
+        "total_rows": parts.iter().map(|p| p.rows).sum::<usize>(),
+        "parts": parts
+            .iter()
+            .enumerate()
+            .map(|(i, p)| json!({"url": download_url(id, Some(i)), "rows": p.rows, "bytes": p.size}))
+            .collect::<Vec<_>>(),
+    })
+}
+
+impl JobResult {
+    /// A job that failed without producing output.
+    fn failed(error: JobError, duration: Duration, cost: usize) -> Self {
+        JobResult {
+            output: Err(error),
+            duration,
+            cost,
+            cached: false,
+            attempts: 0,
+        }
+    }
+
+    pub fn error(&self) -> Option<&JobError> {
+        self.output.as_ref().err()
+    }
+
+    /// Format of the output of a successful job.
+    pub fn format(&self) -> Option<OutputFormat> {
+        self.output.as_ref().ok().map(|o| o.format)
+    }
+
+    /// The result of job `id` as `/run-query` returns it: base64 of the
+    /// compressed IPC bytes, the download URL of its stored file, or a
+    /// manifest of the URLs of its parts. Server paths are never exposed.
+    pub fn output_json(&self, id: u64) -> Option<Value> {
+        let output = self.output.as_ref().ok()?;
+        if let Some(bytes) = &output.bytes {
+            Some(json!(B64_ENGINE.encode(bytes)))
+        } else if let Some(parts) = &output.parts {
+            Some(part_manifest(id, parts))
+        } else {
+            output.path.as_ref().map(|_| json!(download_url(id, None)))
+        }
+    }
+
+    /// Size in bytes of the compressed output or of its files.
+    fn output_size(&self) -> u64 {
+        let Ok(output) = &self.output else {
+            return 0;
+        };
+        if let Some(bytes) = &output.bytes {
+            bytes.len() as u64
+        } else if let Some(path) = &output.path {
+            std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+        } else if let Some(parts) = &output.parts {
+            parts.iter().map(|p| p.size).sum()
+        } else {
+            0
+        }
+    }
 }
 
 impl Scheduler {
     /// Create a new scheduler and spawn the background worker.
+    ///
+    /// Settings are taken from the environment, see [`Config::from_env`].
     pub fn new() -> Self {
-        let (tx, mut rx) = mpsc::channel::<Job>(100);
-        let (complete_tx, mut complete_rx) = mpsc::channel::<()>(100);
+        Self::from_config(&Config::from_env())
+    }
+
+    /// Create a scheduler running at most `max_concurrent` jobs at once and
+    /// holding up to `queue_capacity` waiting ones, with the other settings taken
+    /// from the environment.
+    pub fn with_config(max_concurrent: usize, queue_capacity: usize) -> Self {
+        let mut config = Config::from_env();
+        config.scheduler.max_concurrency = max_concurrent;
+        config.scheduler.queue_capacity = queue_capacity;
+        Self::from_config(&config)
+    }
+
+    /// Create a scheduler from the scheduler, storage, data, state, cluster,
+    /// catalog and access sections of `config`.
+    ///
+    /// Falls back to in-memory state (with an error logged) when the
+    /// configured state backend cannot be created.
+    pub fn from_config(config: &Config) -> Self {
+        let state = state::backend(&config.state).unwrap_or_else(|e| {
+            tracing::error!("using in-memory job state: {}", e);
+            Arc::new(state::MemoryState::default())
+        });
+        if let Err(e) = std::fs::create_dir_all(&config.storage.output_dir) {
+            tracing::error!(
+                "failed to create output directory {}: {}",
+                config.storage.output_dir.display(),
+                e
+            );
+        }
+        let mut store = ResultStore::new(&config.storage.output_dir)
+            .with_min_free_bytes(config.storage.min_free_bytes);
+        if let Some(dir) = &config.storage.scratch_dir {
+            store = store.with_scratch_dir(dir);
+        }
+        let store = Arc::new(store);
+        let lineage_path = config.storage.output_dir.join("lineage.json");
+        let lineage = Arc::new(LineageStore::open(&lineage_path).unwrap_or_else(|e| {
+            tracing::error!("failed to open lineage {}: {}", lineage_path.display(), e);
+            LineageStore::in_memory()
+        }));
+        let catalog = Arc::new(
+            Catalog::from_config(&config.catalog)
+                .with_schema_mode(config.data.schema_mode)
+                .with_stats_on_register(config.catalog.stats.on_register)
+                .with_sandbox(dataset_sandbox(config)),
+        );
+        let access = Arc::new(AccessControl::new(config.access.clone()));
+        let queries = match &config.catalog.queries_path {
+            Some(path) => QueryLibrary::open(path).unwrap_or_else(|e| {
+                tracing::error!("failed to open saved queries {}: {}", path.display(), e);
+                QueryLibrary::in_memory()
+            }),
+            None => QueryLibrary::in_memory(),
+        };
+        let subscriptions = match &config.catalog.subscriptions_path {
+            Some(path) => Subscriptions::open(path).unwrap_or_else(|e| {
+                tracing::error!("failed to open subscriptions {}: {}", path.display(), e);
+                Subscriptions::in_memory()
+            }),
+            None => Subscriptions::in_memory(),
+        };
+        let quota = Arc::new(QuotaTracker::new(config.storage.quota.clone()));
+        let dispatcher = (config.cluster.role == Role::Coordinator)
+            .then(|| Arc::new(Dispatcher::new(&config.cluster)));
+
+        let max_concurrency = config.scheduler.max_concurrency.max(1);
+        let queue_capacity = config.scheduler.queue_capacity.max(1);
+        // Jobs wait in the loop's queue, which takes no more than
+        // `queue_capacity` of them; the channel only hands them over.
+        let (tx, mut rx) = mpsc::channel::<Job>(1);
+        let cost_budget = config.scheduler.cost_budget;
+        let (complete_tx, mut complete_rx) = mpsc::channel::<usize>(max_concurrency);
+        let (abort_tx, mut abort_rx) = mpsc::channel::<oneshot::Sender<AbortReport>>(1);
+        let (cancel_tx, mut cancel_rx) =
+            mpsc::channel::<(u64, oneshot::Sender<Option<CancelReport>>)>(16);
         let active = Arc::new(AtomicUsize::new(0));
-        let next_id = Arc::new(AtomicU64::new(1));
-        let active_bg = active.clone();
+        let running_cost = Arc::new(AtomicUsize::new(0));
+        let exec = ExecContext {
+            data_dir: config.data.data_dir.clone(),
+            catalog: Some(catalog.clone()),
+            schema_mode: config.data.schema_mode,
+            user: None,
+            access: Some(access.clone()),
+            chaos: Chaos::from_config(&config.chaos),
+            cancel: None,
+            cloud: Arc::new(config.data.cloud.clone()),
+            sandbox: Arc::new(Sandbox::new(&config.data.allowed_roots)),
+            streaming: config.resources.streaming_engine,
+        };
+        let views = Arc::new(Views::new(
+            catalog.clone(),
+            exec.clone(),
+            &config.catalog.views_dir,
+        ));
+        // The memory budget bounds what the server keeps between queries: a
+        // quarter of it for cached results and half for session frames.
+        let budget = config.resources.memory_budget_bytes;
+        let sessions = Arc::new(
+            Sessions::new(config.sessions.clone(), exec.clone())
+                .with_max_total_bytes(budget.map(|b| b / 2)),
+        );
+        let mut cache = config.cache.clone();
+        if let Some(budget) = budget {
+            cache.max_bytes = cache.max_bytes.min(budget / 4);
+        }
+        let jobs = Arc::new(JobRegistry::new(config.scheduler.retained_jobs));
+        let metrics = Arc::new(ServerMetrics::default());
+        let metrics_log = Arc::new(MetricsLog::new(config.storage.metrics.clone()));
+        let ctx = JobContext {
+            active: active.clone(),
+            running_cost: running_cost.clone(),
+            max_concurrency,
+            queue_capacity,
+            cost_budget,
+            retry: config.scheduler.retry.clone(),
+            store: store.clone(),
+            quota: quota.clone(),
+            output: config.storage.output.clone(),
+            exec: exec.clone(),
+            state: state.clone(),
+            dispatcher: dispatcher.clone(),
+            lineage: lineage.clone(),
+            metrics: metrics.clone(),
+            metrics_log: metrics_log.clone(),
+            running: Arc::new(Mutex::new(BTreeMap::new())),
+            abort: Arc::new(watch::channel(0).0),
+            jobs: jobs.clone(),
+            timeout: config.scheduler.job_timeout_ms.map(Duration::from_millis),
+            cache: Arc::new(ResultCache::new(cache)),
+        };
 
         tokio::spawn(async move {
+            // Results of jobs persisted before a restart are still referenced,
+            // so a shared file outlives the release of any one of them.
+            match ctx.state.list_jobs().await {
+                Ok(records) => ctx.store.restore_refs(
+                    records
+                        .into_iter()
+                        .flat_map(|r| r.output_location.into_iter().chain(r.output_parts)),
+                ),
+                Err(e) => tracing::warn!("failed to list persisted jobs: {}", e),
+            }
             let mut queue: VecDeque<Job> = VecDeque::new();
             loop {
                 tokio::select! {
-                    Some(job) = rx.recv() => {
-                        if active_bg.load(Ordering::SeqCst) < 4 {
-                            spawn_job(job, complete_tx.clone(), active_bg.clone());
-                        } else {
+                    Some(job) = rx.recv(), if queue.len() < ctx.queue_capacity => {
+                        queue.push_back(job);
+                        start_queued(&mut queue, &complete_tx, &ctx);
+                    }
+                    Some(cost) = complete_rx.recv() => {
+                        ctx.active.fetch_sub(1, Ordering::SeqCst);
+                        ctx.running_cost.fetch_sub(cost, Ordering::SeqCst);
+                        start_queued(&mut queue, &complete_tx, &ctx);
+                    }
+                    Some(reply) = abort_rx.recv() => {
+                        // Include jobs submitted but not yet picked up.
+                        while let Ok(job) = rx.try_recv() {
                             queue.push_back(job);
                         }
+                        let report = abort_all(queue.drain(..).collect(), &ctx).await;
+                        let _ = reply.send(report);
                     }
-                    Some(_) = complete_rx.recv() => {
-                        active_bg.fetch_sub(1, Ordering::SeqCst);
-                        if let Some(job) = queue.pop_front() {
-                            spawn_job(job, complete_tx.clone(), active_bg.clone());
+                    Some((id, reply)) = cancel_rx.recv() => {
+                        // Include jobs submitted but not yet picked up.
+                        while let Ok(job) = rx.try_recv() {
+                            queue.push_back(job);
                         }
+                        start_queued(&mut queue, &complete_tx, &ctx);
+                        let report = match queue.iter().position(|job| job.id == id) {
+                            Some(i) => {
+                                let job = queue.remove(i).expect("position is in the queue");
+                                cancel_queued(job, executor::CANCELLED, &ctx).await;
+                                Some(CancelReport { job_id: id, status: "cancelled" })
+                            }
+                            None => cancel_running(id, &ctx),
+                        };
+                        let _ = reply.send(report);
                     }
                     else => break,
                 }
             }
         });
 
+        let appender = Arc::new(Appender::new(
+            config.data.streaming.clone(),
+            catalog.clone(),
+            &config.data.ingest_dir,
+        ));
         Scheduler {
             tx,
+            abort_tx,
+            cancel_tx,
+            admin_token: config.scheduler.admin_token.clone(),
             active,
-            next_id,
+            running_cost,
+            store,
+            quota,
+            state: state.clone(),
+            dispatcher,
+            catalog,
+            views,
+            lineage,
+            access,
+            queries: Arc::new(queries),
+            sessions,
+            subscriptions: Arc::new(subscriptions),
+            appender,
+            jobs,
+            metrics,
+            metrics_log,
+            limiter: Arc::new(RateLimiter::new(config.scheduler.rate_limit.clone(), state)),
+            compaction: config.catalog.compaction.clone(),
+            discovery: config.catalog.discovery.clone(),
+            temporary: config.catalog.temporary.clone(),
+            exec,
+            ingest_dir: config.data.ingest_dir.clone(),
+            max_concurrency,
+            cost_budget,
+            queue_capacity,
+            ready_queue_limit: config.scheduler.ready_queue_limit.unwrap_or(queue_capacity),
+            max_queued: config.scheduler.max_queued,
+            draining: Arc::new(AtomicBool::new(false)),
+            config: Arc::new(config.clone()),
+        }
+    }
+
+    /// The configuration the scheduler was created from.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Result store used for outputs too large to return inline.
+    pub fn store(&self) -> &Arc<ResultStore> {
+        &self.store
+    }
+
+    /// Per-user output quota tracker.
+    pub fn quota(&self) -> &Arc<QuotaTracker> {
+        &self.quota
+    }
+
+    /// Backend holding job records shared between replicas.
+    pub fn state(&self) -> &Arc<dyn StateBackend> {
+        &self.state
+    }
+
+    /// Queue of jobs waiting for workers, when running as a coordinator.
+    pub fn dispatcher(&self) -> Option<&Arc<Dispatcher>> {
+        self.dispatcher.as_ref()
+    }
+
+    /// Catalog of registered datasets.
+    pub fn catalog(&self) -> &Arc<Catalog> {
+        &self.catalog
+    }
+
+    /// Materialized views defined in the catalog.
+    pub fn views(&self) -> &Arc<Views> {
+        &self.views
+    }
+
+    /// Temporary dataset settings.
+    pub fn temporary(&self) -> &TemporaryConfig {
+        &self.temporary
+    }
+
+    /// Lineage of job outputs.
+    pub fn lineage(&self) -> &Arc<LineageStore> {
+        &self.lineage
+    }
+
+    /// Grants and roles deciding who may use which datasets.
+    pub fn access(&self) -> &Arc<AccessControl> {
+        &self.access
+    }
+
+    /// Saved queries and the snippets they include.
+    pub fn queries(&self) -> &Arc<QueryLibrary> {
+        &self.queries
+    }
+
+    /// Settings for compacting dataset files.
+    pub fn compaction(&self) -> &CompactionConfig {
+        &self.compaction
+    }
+
+    /// Data roots scanned for datasets to register.
+    pub fn discovery(&self) -> &DiscoveryConfig {
+        &self.discovery
+    }
+
+    /// Directory ingested datasets are written to.
+    pub fn ingest_dir(&self) -> &Path {
+        &self.ingest_dir
+    }
+
+    /// Buffers of rows streamed into datasets, flushed in micro-batches.
+    pub fn appender(&self) -> &Arc<Appender> {
+        &self.appender
+    }
+
+    /// Rows of finished jobs waiting to be written to the metrics store.
+    pub fn metrics_log(&self) -> &Arc<MetricsLog> {
+        &self.metrics_log
+    }
+
+    /// Jobs submitted to this scheduler, with the results of recent ones.
+    pub fn jobs(&self) -> &Arc<JobRegistry> {
+        &self.jobs
+    }
+
+    /// The configured concurrency limits.
+    pub fn limits(&self) -> SchedulerLimits {
+        SchedulerLimits {
+            max_concurrency: self.max_concurrency,
+            cost_budget: self.cost_budget,
+            queue_capacity: self.queue_capacity,
+        }
+    }
+
+    /// Whether `user` may submit a job now: the scheduler is not shutting
+    /// down, and the submission is within the per-user and global rate
+    /// limits, with the queue below `max_queued`. Refusals are counted in
+    /// the throttled metric.
+    pub async fn admit(&self, user: &str) -> Result<(), Throttled> {
+        let queued = self.metrics.queued();
+        let admitted = match self.max_queued {
+            _ if self.is_draining() => Err(Throttled {
+                message: "server is shutting down".to_string(),
+                retry_after: Duration::from_secs(5),
+            }),
+            Some(limit) if queued >= limit as u64 => Err(Throttled {
+                message: format!("server is saturated: {} jobs queued", queued),
+                retry_after: Duration::from_secs(1),
+            }),
+            _ => self.limiter.check(user).await,
+        };
+        if admitted.is_err() {
+            self.metrics.job_throttled();
+        }
+        admitted
+    }
+
+    /// Whether the scheduler loop is still running and taking jobs.
+    pub fn is_running(&self) -> bool {
+        !self.tx.is_closed()
+    }
+
+    /// Whether [`Scheduler::shutdown`] has started, so new submissions are
+    /// refused.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Whether the scheduler can take new jobs: its loop is running and not
+    /// draining, the queue is within its readiness limit and results can be
+    /// written. Writes a probe file, so call it off the async runtime.
+    pub fn readiness(&self) -> Readiness {
+        Readiness::new(vec![
+            health::check_scheduler(self.is_running(), self.is_draining()),
+            health::check_queue(self.metrics.queued(), self.ready_queue_limit),
+            health::check_output_dir(self.store.dir()),
+        ])
+    }
+
+    /// Counters and histograms of submitted and finished jobs.
+    pub fn metrics(&self) -> &Arc<ServerMetrics> {
+        &self.metrics
+    }
+
+    /// The scheduler's metrics in the Prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let active = self.active.load(Ordering::SeqCst);
+        self.metrics.render(active, self.max_concurrency)
+    }
+
+    /// Cost of `plan` as [`estimate::estimate`] reckons it, from the sizes
+    /// and footers of the files it reads. Plans whose sources cannot be
+    /// listed are charged for their steps alone.
+    async fn estimate_cost(&self, plan: &[QueryPlan]) -> usize {
+        let (steps, exec) = (plan.to_vec(), self.exec.clone());
+        tokio::task::spawn_blocking(move || match estimate::estimate(&steps, &exec) {
+            Ok(estimate) => estimate.cost,
+            Err(_) => estimate::cost_score(&steps, 0),
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Estimate the rows, bytes scanned and cost of `query` for `user`
+    /// without running it.
+    pub fn estimate(&self, query: &str, user: &str) -> Result<Estimate, String> {
+        let plan = parser::parse_query(query)?;
+        self.access.authorize_query(&self.catalog, user, query)?;
+        self.exec.check_sources(&plan)?;
+        let exec = ExecContext {
+            user: Some(user.to_string()),
+            ..self.exec.clone()
+        };
+        estimate::estimate(&plan, &exec)
+    }
+
+    /// Columns, estimated rows and size of the read step `source` for
+    /// `user`, from file metadata. Datasets need read permission and paths
+    /// must lie within the sandbox.
+    pub fn source_schema(&self, source: &QueryPlan, user: &str) -> Result<SourceSchema, String> {
+        if let QueryPlan::ReadTable { name, .. } = source {
+            self.access
+                .authorize(&self.catalog, user, name, Permission::Read, "get_schema")?;
+        }
+        self.exec.check_sources(std::slice::from_ref(source))?;
+        let exec = ExecContext {
+            user: Some(user.to_string()),
+            ..self.exec.clone()
+        };
+        introspect::describe(source, &exec)
+    }
+
+    /// Parse `query` and describe it as a graph of operators with estimated
+    /// rows, without running it.
+    pub fn explain(&self, query: &str, user: &str) -> Result<PlanGraph, String> {
+        let plan = parser::parse_query(query)?;
+        self.access.authorize_query(&self.catalog, user, query)?;
+        self.exec.check_sources(&plan)?;
+        let exec = ExecContext {
+            user: Some(user.to_string()),
+            ..self.exec.clone()
+        };
+        explain::explain(&plan, &exec)
+    }
+
+    /// Interactive sessions holding intermediate frames in memory.
+    pub fn sessions(&self) -> &Arc<Sessions> {
+        &self.sessions
+    }
+
+    /// Saved queries subscribed to datasets.
+    pub fn subscriptions(&self) -> &Arc<Subscriptions> {
+        &self.subscriptions
+    }
+
+    /// Subscribe a saved query to a dataset on behalf of `owner`. The query
+    /// is re-run whenever the dataset's files change from now on.
+    pub fn subscribe(&self, spec: SubscriptionSpec, owner: &str) -> Result<Subscription, String> {
+        // Rendering checks the query exists, is not a snippet and has every
+        // variable it needs.
+        self.queries.render(&spec.query, &spec.vars)?;
+        let version = self
+            .catalog
+            .get(&spec.dataset)
+            .ok_or_else(|| format!("unknown dataset {}", spec.dataset))?
+            .version;
+        self.subscriptions.add(spec, owner, version)
+    }
+
+    /// Whether `token` grants access to the admin endpoints. Always `false`
+    /// when no admin token is configured.
+    pub fn authorize_admin(&self, token: Option<&str>) -> bool {
+        self.admin_token.is_some() && token == self.admin_token.as_deref()
+    }
+
+    /// Cancel every queued job and signal every running job to stop, for
+    /// incident response. Cancelled jobs fail with [`CANCELLED`].
+    pub async fn abort_all(&self) -> AbortReport {
+        let (tx, rx) = oneshot::channel();
+        if self.abort_tx.send(tx).await.is_err() {
+            return AbortReport::default();
+        }
+        rx.await.unwrap_or_default()
+    }
+
+    /// Stop admitting submissions and wait up to `grace` for queued and
+    /// running jobs to finish, cancelling those still pending after it.
+    /// Then write buffered metrics and streamed rows and flush the job
+    /// records of the state backend, so nothing finished is lost on exit.
+    pub async fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        self.draining.store(true, Ordering::SeqCst);
+        let mut report = ShutdownReport {
+            drained: self.wait_idle(grace).await,
+            ..ShutdownReport::default()
+        };
+        if !report.drained {
+            report.cancelled = self.abort_all().await;
+            // Cancelled jobs record their results as they stop.
+            self.wait_idle(CANCEL_GRACE).await;
+        }
+        let metrics_log = self.metrics_log.clone();
+        let appender = self.appender.clone();
+        let flushed = tokio::task::spawn_blocking(move || {
+            appender.flush(true);
+            metrics_log.flush(true)
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+        if let Err(e) = flushed {
+            tracing::warn!("failed to write job metrics at shutdown: {}", e);
+        }
+        if let Err(e) = self.state.flush().await {
+            tracing::warn!("failed to flush job records at shutdown: {}", e);
+        }
+        report
+    }
+
+    /// Wait up to `limit` for no job to be queued or running, returning
+    /// whether none is.
+    async fn wait_idle(&self, limit: Duration) -> bool {
+        let deadline = Instant::now() + limit;
+        while self.jobs.pending() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+
+    /// Cancel job `id`: a queued job is removed and fails with
+    /// [`executor::CANCELLED`], a running one is signalled to stop. With
+    /// access control enabled only the job's owner and configured admins may
+    /// cancel it.
+    pub async fn cancel_job(&self, id: u64, user: &str) -> Result<CancelReport, String> {
+        let owner = match self.jobs.get(id) {
+            Some(entry) => entry.user,
+            None => {
+                self.state
+                    .get_job(id)
+                    .await?
+                    .ok_or_else(|| format!("unknown job {}", id))?
+                    .user
+            }
+        };
+        self.check_job_access(id, &owner, user, "cancel", "cancel")?;
+        let (tx, rx) = oneshot::channel();
+        if self.cancel_tx.send((id, tx)).await.is_err() {
+            return Err("scheduler has shut down".to_string());
+        }
+        match rx.await.ok().flatten() {
+            Some(report) => Ok(report),
+            None => {
+                let status = match self.jobs.get(id) {
+                    Some(entry) => entry.status.to_string(),
+                    None => self
+                        .state
+                        .get_job(id)
+                        .await?
+                        .map_or_else(|| "unknown".to_string(), |r| r.status),
+                };
+                Err(format!(
+                    "job {} is not queued or running here (status {})",
+                    id, status
+                ))
+            }
+        }
+    }
+
+    /// The record of job `id`, if `user` may read its result: with access
+    /// control enabled only the job's owner and configured admins may.
+    async fn readable_job(&self, id: u64, user: &str, action: &str) -> Result<JobRecord, String> {
+        let record = self
+            .state
+            .get_job(id)
+            .await?
+            .ok_or_else(|| format!("unknown job {}", id))?;
+        self.check_job_access(id, &record.user, user, action, "read the result of")?;
+        Ok(record)
+    }
+
+    /// With access control enabled, only the job's `owner` and configured
+    /// admins may `verb` it.
+    fn check_job_access(
+        &self,
+        id: u64,
+        owner: &str,
+        user: &str,
+        action: &str,
+        verb: &str,
+    ) -> Result<(), String> {
+        if self.access.enabled() && owner != user && !self.access.is_admin(user) {
+            let reason = format!("access denied: {} may not {} job {}", user, verb, id);
+            self.access.audit().record(
+                AuditEvent::new(user, action, &format!("job {}", id), false)
+                    .with_detail(reason.clone()),
+            );
+            return Err(reason);
+        }
+        Ok(())
+    }
+
+    /// Page of the jobs held by this scheduler matching `filter`, most
+    /// recently submitted first. With access control enabled, callers other
+    /// than admins only see their own jobs.
+    pub fn list_jobs(
+        &self,
+        user: &str,
+        mut filter: JobFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Value, String> {
+        if let Some(status) = &filter.status {
+            if !jobs::STATUSES.contains(&status.as_str()) {
+                return Err(format!("unknown job status {}", status));
+            }
+        }
+        if self.access.enabled() && !self.access.is_admin(user) {
+            match &filter.user {
+                Some(owner) if owner != user => {
+                    return Err(format!(
+                        "access denied: {} may not list the jobs of {}",
+                        user, owner
+                    ));
+                }
+                _ => filter.user = Some(user.to_string()),
+            }
+        }
+        let (total, page) = self.jobs.list(&filter, offset, limit);
+        Ok(json!({
+            "jobs": page.iter().map(JobEntry::summary_json).collect::<Vec<_>>(),
+            "total": total,
+            "offset": offset,
+            "limit": limit,
+        }))
+    }
+
+    /// Status of job `id` and its result once finished. Jobs no longer held
+    /// by this scheduler, or submitted to another replica, are answered from
+    /// the job state with the location of their stored result.
+    pub async fn job_status(&self, id: u64, user: &str) -> Result<Value, String> {
+        if let Some(entry) = self.jobs.get(id) {
+            self.check_job_access(id, &entry.user, user, "status", "read the result of")?;
+            return Ok(entry.to_json());
+        }
+        let record = self.readable_job(id, user, "status").await?;
+        let output = match (&record.output_location, record.output_parts.len()) {
+            (Some(_), _) => Some(json!(download_url(id, None))),
+            (None, 0) => None,
+            (None, parts) => Some(json!({
+                "parts": (0..parts)
+                    .map(|i| json!({ "url": download_url(id, Some(i)) }))
+                    .collect::<Vec<_>>(),
+            })),
+        };
+        Ok(json!({
+            "job_id": record.id,
+            "status": record.status,
+            "submitted_at": record.submitted_at_ms,
+            "started_at": record.started_at_ms,
+            "duration_ms": record.duration_ms,
+            "cost": record.cost,
+            "output": output,
+            "error": record.error,
+        }))
+    }
+
+    /// Lineage of the outputs of job `id`, if any is recorded and `user` may
+    /// read the job's result.
+    pub fn job_lineage(&self, id: u64, user: &str) -> Result<Option<Lineage>, String> {
+        let Some(record) = self.lineage.get(id) else {
+            return Ok(None);
+        };
+        self.check_job_access(id, &record.user, user, "lineage", "read the lineage of")?;
+        Ok(Some(Lineage::of_job(record, &self.catalog)))
+    }
+
+    /// Lineage of the job outputs and views including the file `path` that
+    /// `user` may see: with access control enabled, only their own jobs and
+    /// views they may read, unless they are an admin.
+    pub fn file_lineage(&self, path: &str, user: &str) -> Vec<Lineage> {
+        let visible = |lineage: &Lineage| match (lineage.job_id, &lineage.view) {
+            (Some(id), _) => self.lineage.get(id).is_some_and(|record| {
+                !self.access.enabled() || record.user == user || self.access.is_admin(user)
+            }),
+            (None, Some(view)) => self
+                .catalog
+                .get(view)
+                .is_some_and(|d| self.access.permission(&d, user) >= Some(Permission::Read)),
+            (None, None) => false,
+        };
+        self.lineage
+            .file(&self.catalog, path)
+            .into_iter()
+            .filter(visible)
+            .collect()
+    }
+
+    /// The status of job `id` as [`Self::job_status`] reports it, with a
+    /// receiver of the events of every job from then on.
+    pub async fn watch_job(
+        &self,
+        id: u64,
+        user: &str,
+    ) -> Result<(Value, broadcast::Receiver<JobEvent>), String> {
+        // Listen first, so no change after the status is read is missed.
+        let events = self.jobs.listen();
+        let status = self.job_status(id, user).await?;
+        Ok((status, events))
+    }
+
+    /// Remove the result files that have outlived `config` and mark the jobs
+    /// that produced them as `expired`, returning their ids.
+    pub async fn expire_results(&self, config: &RetentionConfig) -> Vec<u64> {
+        let store = self.store.clone();
+        let quota = self.quota.clone();
+        let config = config.clone();
+        let removed = tokio::task::spawn_blocking(move || {
+            let files = retention::expired_files(store.dir(), &config, SystemTime::now())
+                .unwrap_or_else(|e| {
+                    tracing::warn!("failed to list {}: {}", store.dir().display(), e);
+                    Vec::new()
+                });
+            let mut removed = HashSet::new();
+            for path in files {
+                let path = path.to_string_lossy().to_string();
+                match store.remove(&path) {
+                    Ok(_) => {
+                        quota.uncharge(&path);
+                        removed.insert(path);
+                    }
+                    Err(e) => {
+                        tracing::warn!(path = %path, "failed to remove expired result: {}", e)
+                    }
+                }
+            }
+            removed
+        })
+        .await
+        .unwrap_or_default();
+        if removed.is_empty() {
+            return Vec::new();
+        }
+        let expired = self.jobs.expire(&removed);
+        for &id in &expired {
+            let Ok(Some(mut record)) = self.state.get_job(id).await else {
+                continue;
+            };
+            record.status = "expired".to_string();
+            record.output_location = None;
+            record.output_parts.clear();
+            if let Err(e) = self.state.put_job(&record).await {
+                tracing::warn!(job_id = id, "failed to record expired job: {}", e);
+            }
+        }
+        expired
+    }
+
+    /// Path of the stored result file of job `id`, or of its part `part`
+    /// when the result was split. With access control enabled only the job's
+    /// owner and configured admins may download it.
+    pub async fn job_file(
+        &self,
+        id: u64,
+        part: Option<usize>,
+        user: &str,
+    ) -> Result<PathBuf, String> {
+        let record = self.readable_job(id, user, "download").await?;
+        let path = match part {
+            None => record.output_location.as_ref().ok_or_else(|| {
+                if record.output_parts.is_empty() {
+                    format!(
+                        "unknown file: job {} has no stored result (status {})",
+                        id, record.status
+                    )
+                } else {
+                    format!(
+                        "job {} result is split into {} parts, download one with ?part=",
+                        id,
+                        record.output_parts.len()
+                    )
+                }
+            }),
+            Some(part) => record
+                .output_parts
+                .get(part)
+                .ok_or_else(|| format!("unknown file: job {} has no part {}", id, part)),
+        }?;
+        Ok(PathBuf::from(path))
+    }
+
+    /// Compare the stored results of jobs `left` and `right`, matching rows on
+    /// `key` when given. With access control enabled only the jobs' owner and
+    /// configured admins may compare them.
+    pub async fn diff_jobs(
+        &self,
+        left: u64,
+        right: u64,
+        key: Option<String>,
+        user: &str,
+    ) -> Result<ResultDiff, String> {
+        let mut records = Vec::new();
+        for id in [left, right] {
+            records.push(self.readable_job(id, user, "diff").await?);
+        }
+        tokio::task::spawn_blocking(move || {
+            let left = diff::load(&records[0])?;
+            let right = diff::load(&records[1])?;
+            diff::diff(&left, &right, key.as_deref())
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+    }
+
+    /// Convert the stored result of job `id` to `format`, reusing an earlier
+    /// conversion of the same result. With access control enabled only the
+    /// job's owner and configured admins may convert it.
+    pub async fn convert_job(
+        &self,
+        id: u64,
+        format: ConvertFormat,
+        user: &str,
+    ) -> Result<Converted, String> {
+        let record = self.readable_job(id, user, "convert").await?;
+        let dir = self.store.dir().join(CONVERTED_DIR);
+        tokio::task::spawn_blocking(move || convert::convert(&dir, &record, format))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+    }
+
+    /// Register the stored result of job `id` as the temporary dataset
+    /// `name`, readable with `pl.read_table` until `ttl_secs` pass. With
+    /// access control enabled only the job's owner and configured admins may
+    /// register it.
+    pub async fn register_job_result(
+        &self,
+        id: u64,
+        name: String,
+        ttl_secs: Option<u64>,
+        user: &str,
+    ) -> Result<Dataset, String> {
+        let record = self.readable_job(id, user, "register").await?;
+        let files = match &record.output_location {
+            Some(path) => vec![path.clone()],
+            None => record.output_parts.clone(),
+        };
+        if files.is_empty() {
+            return Err(format!(
+                "job {} has no stored result (status {}); submit it with the register header to keep an inline result",
+                id, record.status
+            ));
+        }
+        self.register_result(id, name, ResultSource::Files(files), ttl_secs, user)
+            .await
+    }
+
+    /// Register `result`, just returned for job `id`, as the temporary
+    /// dataset `name`.
+    pub async fn register_output(
+        &self,
+        id: u64,
+        name: String,
+        result: &JobResult,
+        ttl_secs: Option<u64>,
+        user: &str,
+    ) -> Result<Dataset, String> {
+        let Ok(output) = &result.output else {
+            return Err(format!("job {} produced no result", id));
+        };
+        if output.format != OutputFormat::Ipc {
+            return Err(format!(
+                "only ipc results can be registered as datasets, job {} returned {}",
+                id,
+                output.format.extension()
+            ));
         }
+        let source = if let Some(bytes) = &output.bytes {
+            ResultSource::Inline(bytes.clone())
+        } else if let Some(parts) = &output.parts {
+            ResultSource::Files(parts.iter().map(|p| p.path.clone()).collect())
+        } else if let Some(path) = &output.path {
+            ResultSource::Files(vec![path.clone()])
+        } else {
+            return Err(format!("job {} produced no result", id));
+        };
+        self.register_result(id, name, source, ttl_secs, user).await
+    }
+
+    async fn register_result(
+        &self,
+        id: u64,
+        name: String,
+        source: ResultSource,
+        ttl_secs: Option<u64>,
+        user: &str,
+    ) -> Result<Dataset, String> {
+        let catalog = self.catalog.clone();
+        let config = self.temporary.clone();
+        let user = user.to_string();
+        tokio::task::spawn_blocking(move || {
+            temporary::register(&catalog, &config, &name, id, source, ttl_secs, &user)
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
     }
 
-    fn estimate_cost(plan: &[QueryPlan]) -> usize {
-        // very rough heuristic: each step costs 10 units
-        plan.len() * 10
+    /// Parse `query` and flag antipatterns in it without running it. Fails
+    /// with `invalid_query` if it does not parse and `access_denied` if it
+    /// reads paths outside the sandbox.
+    pub fn lint(&self, query: &str) -> Result<Vec<LintWarning>, JobError> {
+        let plan = parser::parse_query(query)
+            .map_err(|e| JobError::new("invalid_query", Some(PARSE_STEP), e))?;
+        self.exec
+            .check_sources(&plan)
+            .map_err(|e| JobError::new("access_denied", Some("authorize"), e))?;
+        Ok(lint::lint(&plan, &self.exec))
+    }
+
+    /// Local `path` resolved against the data directory, or an "access
+    /// denied" error if it lies outside the sandbox.
+    pub fn sandboxed_path(&self, path: &str) -> Result<String, String> {
+        self.exec.sandboxed_path(path)
     }
 
     /// Enqueue a new job and return its id, status and channel to await results.
@@ -93,20 +1416,101 @@ impl Scheduler {
         &self,
         query: String,
     ) -> (u64, &'static str, oneshot::Receiver<JobResult>) {
-        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.enqueue_with(query, JobOptions::default()).await
+    }
+
+    /// Enqueue a new job with explicit [`JobOptions`]. Jobs reading datasets
+    /// the user may not read are rejected without running.
+    pub async fn enqueue_with(
+        &self,
+        query: String,
+        options: JobOptions,
+    ) -> (u64, &'static str, oneshot::Receiver<JobResult>) {
+        let id = match self.state.next_job_id().await {
+            Ok(id) => id,
+            Err(e) => {
+                tracing::error!("failed to allocate job id from shared state: {}", e);
+                LOCAL_IDS.fetch_add(1, Ordering::SeqCst)
+            }
+        };
+        let submitted_at_ms = now_ms();
         let plan = parser::parse_query(&query).unwrap_or_default();
-        let cost = Self::estimate_cost(&plan);
         let (tx, rx) = oneshot::channel();
-        let status = if self.active.load(Ordering::SeqCst) < 4 {
+        if let Err(e) = self
+            .access
+            .authorize_query(&self.catalog, &options.user, &query)
+            .and_then(|()| self.exec.check_sources(&plan))
+        {
+            // Sources the user may not read are not looked at.
+            let cost = estimate::cost_score(&plan, 0);
+            let record = JobRecord {
+                id,
+                user: options.user.clone(),
+                query: query.clone(),
+                status: "rejected".to_string(),
+                submitted_at_ms: Some(submitted_at_ms),
+                started_at_ms: None,
+                duration_ms: None,
+                cost,
+                output_location: None,
+                output_parts: Vec::new(),
+                error: Some(e.clone()),
+            };
+            if let Err(e) = self.state.put_job(&record).await {
+                tracing::warn!(job_id = id, "failed to record job state: {}", e);
+            }
+            let error = JobError::new("access_denied", Some("authorize"), e);
+            let result = JobResult::failed(error, Duration::ZERO, cost);
+            self.jobs
+                .submitted(id, &options.user, options.priority, "rejected", cost);
+            self.jobs.finished(id, "rejected", result.clone());
+            self.metrics.job_rejected();
+            let _ = tx.send(result);
+            return (id, "rejected", rx);
+        }
+        let cost = self.estimate_cost(&plan).await;
+        let status = if can_start(
+            self.active.load(Ordering::SeqCst),
+            self.running_cost.load(Ordering::SeqCst),
+            cost,
+            self.max_concurrency,
+            self.cost_budget,
+        ) {
             "running"
         } else {
             "queued"
         };
+        let record = JobRecord {
+            id,
+            user: options.user.clone(),
+            query: query.clone(),
+            status: status.to_string(),
+            submitted_at_ms: Some(submitted_at_ms),
+            started_at_ms: None,
+            duration_ms: None,
+            cost,
+            output_location: None,
+            output_parts: Vec::new(),
+            error: None,
+        };
+        if let Err(e) = self.state.put_job(&record).await {
+            tracing::warn!(job_id = id, "failed to record job state: {}", e);
+        }
+        self.jobs
+            .submitted(id, &options.user, options.priority, status, cost);
+        self.metrics.job_submitted();
+        let span = tracing::info_span!("job", job_id = id, cost);
+        let queue_span = tracing::info_span!(parent: &span, "queue_wait");
         let job = Job {
             id,
             query,
             resp: tx,
             cost,
+            options,
+            submitted_at_ms,
+            bypassed: 0,
+            span,
+            queue_span,
         };
         // Ignore send errors - only possible if scheduler loop has shut down.
         let _ = self.tx.send(job).await;
@@ -114,53 +1518,462 @@ impl Scheduler {
     }
 }
 
+/// Store a prepared output in the requested format, charging any files
+/// against the job owner's quota.
+fn store_output(
+    ctx: &JobContext,
+    options: &JobOptions,
+    df: &DataFrame,
+) -> Result<PreparedOutput, JobError> {
+    let user = options.user.as_str();
+    let mut config = ctx.output.clone();
+    if let Some(limit) = options.inline_limit {
+        config.inline_limit = config.inline_limit.min(limit);
+    }
+    let output =
+        crate::utils::prepare_output_as(&ctx.store, df, &config, options.format).map_err(|e| {
+            let kind = match e.kind() {
+                std::io::ErrorKind::StorageFull => "insufficient_storage",
+                _ => "execution_failed",
+            };
+            let transient = executor::is_transient_message(&e.to_string());
+            JobError::new(kind, Some("store"), e.to_string()).transient_if(transient)
+        })?;
+    let mut files: Vec<(String, u64)> = Vec::new();
+    if let Some(path) = &output.path {
+        let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        files.push((path.clone(), size));
+    }
+    for part in output.parts.iter().flatten() {
+        files.push((part.path.clone(), part.size));
+    }
+
+    for (i, (path, size)) in files.iter().enumerate() {
+        match ctx.quota.charge(user, path, *size) {
+            Ok(evicted) => {
+                for old in evicted {
+                    info!(user, path = %old, "evicting output to satisfy quota");
+                    let _ = ctx.store.release(&old);
+                }
+            }
+            Err(e) => {
+                // Only the files not yet charged are released here; any parts
+                // already charged stay accounted until evicted.
+                for (path, _) in &files[i..] {
+                    let _ = ctx.store.release(path);
+                }
+                return Err(JobError::new(
+                    "execution_failed",
+                    Some("store"),
+                    e.to_string(),
+                ));
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Fail the `queued` jobs with [`CANCELLED`] and signal every running job
+/// to stop.
+async fn abort_all(queued: Vec<Job>, ctx: &JobContext) -> AbortReport {
+    let running: Vec<u64> = {
+        let running = ctx.running.lock().unwrap();
+        for token in running.values() {
+            token.cancel();
+        }
+        running.keys().copied().collect()
+    };
+    ctx.abort.send_modify(|epoch| *epoch += 1);
+    if let Some(dispatcher) = &ctx.dispatcher {
+        dispatcher.cancel_all();
+    }
+    let mut report = AbortReport {
+        queued: Vec::new(),
+        running,
+    };
+    for job in queued {
+        report.queued.push(job.id);
+        cancel_queued(job, CANCELLED, ctx).await;
+    }
+    tracing::warn!(queued = ?report.queued, running = ?report.running, "aborted all jobs");
+    report
+}
+
+/// Fail a job removed from the queue with `error`.
+async fn cancel_queued(job: Job, error: &str, ctx: &JobContext) {
+    let record = JobRecord {
+        id: job.id,
+        user: job.options.user.clone(),
+        query: job.query.clone(),
+        status: "cancelled".to_string(),
+        submitted_at_ms: Some(job.submitted_at_ms),
+        started_at_ms: None,
+        duration_ms: None,
+        cost: job.cost,
+        output_location: None,
+        output_parts: Vec::new(),
+        error: Some(error.to_string()),
+    };
+    if let Err(e) = ctx.state.put_job(&record).await {
+        tracing::warn!(job_id = job.id, "failed to record job state: {}", e);
+    }
+    let error = JobError::new("cancelled", None, error);
+    let result = JobResult::failed(error, Duration::ZERO, job.cost);
+    ctx.metrics.job_dequeued();
+    ctx.metrics.job_finished("cancelled", None, 0);
+    ctx.jobs.finished(job.id, "cancelled", result.clone());
+    let _ = job.resp.send(result);
+}
+
+/// Signal the running job `id` to stop, if there is one.
+fn cancel_running(id: u64, ctx: &JobContext) -> Option<CancelReport> {
+    let token = ctx.running.lock().unwrap().get(&id).cloned()?;
+    token.cancel();
+    if let Some(dispatcher) = &ctx.dispatcher {
+        dispatcher.cancel(id);
+    }
+    info!(job_id = id, "cancelling running job");
+    Some(CancelReport {
+        job_id: id,
+        status: "cancelling",
+    })
+}
+
 /// Spawn a task to execute a job and notify when complete.
-fn spawn_job(job: Job, complete: mpsc::Sender<()>, active: Arc<AtomicUsize>) {
-    active.fetch_add(1, Ordering::SeqCst);
-    tokio::spawn(async move {
+///
+/// The task itself only waits: parsing, executing and storing the result run
+/// on Tokio's blocking pool, as do the file writes recording the job's
+/// lineage and metrics, so HTTP handling stays responsive while queries run.
+/// At most `max_concurrency` jobs hold a slot, which bounds the blocking
+/// threads in use by jobs. The job's cost is reported on `complete` when it
+/// releases its slot.
+fn spawn_job(mut job: Job, complete: mpsc::Sender<usize>, ctx: JobContext) {
+    job.queue_span = Span::none();
+    let span = job.span.clone();
+    ctx.active.fetch_add(1, Ordering::SeqCst);
+    ctx.running_cost.fetch_add(job.cost, Ordering::SeqCst);
+    let token = CancelToken::default();
+    ctx.running.lock().unwrap().insert(job.id, token.clone());
+    let mut abort = ctx.abort.subscribe();
+    let task = async move {
         let start = Instant::now();
+        let started_at_ms = now_ms();
         info!(job_id = job.id, "job started");
-        let result = executor::execute_plan(&job.query);
+        ctx.jobs.started(job.id);
+        ctx.metrics.job_dequeued();
+        let record = JobRecord {
+            id: job.id,
+            user: job.options.user.clone(),
+            query: job.query.clone(),
+            status: "running".to_string(),
+            submitted_at_ms: Some(job.submitted_at_ms),
+            started_at_ms: Some(started_at_ms),
+            duration_ms: None,
+            cost: job.cost,
+            output_location: None,
+            output_parts: Vec::new(),
+            error: None,
+        };
+        if let Err(e) = ctx.state.put_job(&record).await {
+            tracing::warn!(job_id = job.id, "failed to record job state: {}", e);
+        }
+        let (sources, cache_key) = {
+            let exec = ctx.exec.clone();
+            let query = job.query.clone();
+            let user = job.options.user.clone();
+            let format = job.options.format;
+            // Results are cached as prepared under the server's inline limit.
+            let caching = ctx.cache.enabled() && job.options.inline_limit.is_none();
+            tokio::task::spawn_blocking(move || {
+                let sources = lineage::snapshot(&exec, &query).ok();
+                let exec = ExecContext {
+                    user: Some(user),
+                    ..exec
+                };
+                let key = caching.then(|| cache::key(&query, &exec, format).ok());
+                (sources, key.flatten())
+            })
+            .await
+            .unwrap_or((None, None))
+        };
+        let hit = cache_key.as_deref().and_then(|key| ctx.cache.get(key));
+        let cached = hit.is_some();
+        if cached {
+            info!(job_id = job.id, "serving cached result");
+        }
+        // Jobs served from the cache or run by a worker count as one attempt
+        // here; workers retry their own.
+        let attempts = Arc::new(AtomicU32::new(1));
+        let mut work = match (hit, ctx.dispatcher.clone()) {
+            (Some(output), _) => tokio::spawn(async move { Ok(output) }),
+            (None, Some(dispatcher)) => {
+                let item = WorkItem {
+                    id: job.id,
+                    query: job.query.clone(),
+                    user: job.options.user.clone(),
+                    format: job.options.format,
+                    inline_limit: job.options.inline_limit,
+                    streaming: job.options.streaming,
+                    max_attempts: job.options.max_attempts,
+                    lease_ms: None,
+                };
+                tokio::spawn(async move {
+                    dispatcher
+                        .dispatch(item)
+                        .await
+                        .map_err(|e| JobError::new("execution_failed", None, e))
+                        .and_then(|r| r.into_output())
+                })
+            }
+            (None, None) => {
+                let exec = ExecContext {
+                    user: Some(job.options.user.clone()),
+                    cancel: Some(token.clone()),
+                    streaming: job.options.streaming.unwrap_or(ctx.exec.streaming),
+                    ..ctx.exec.clone()
+                };
+                let query = job.query.clone();
+                let options = job.options.clone();
+                let ctx = ctx.clone();
+                let abort = abort.clone();
+                let token = token.clone();
+                let attempts = attempts.clone();
+                let id = job.id;
+                let max_attempts = options.max_attempts.unwrap_or(ctx.retry.max_attempts);
+                let span = Span::current();
+                tokio::task::spawn_blocking(move || {
+                    let _span = span.enter();
+                    ctx.jobs.progress(id, PARSE_STEP);
+                    tracing::info_span!("parse")
+                        .in_scope(|| parser::parse_query(&query))
+                        .map_err(|e| JobError::new("invalid_query", Some(PARSE_STEP), e))?;
+                    let mut attempt = 1;
+                    loop {
+                        attempts.store(attempt, Ordering::SeqCst);
+                        let error =
+                            match run_attempt(&ctx, id, &query, &exec, &options, &abort, &token) {
+                                Err(e) if e.is_transient() && attempt < max_attempts => e,
+                                outcome => return outcome,
+                            };
+                        let delay = ctx.retry.delay(attempt);
+                        tracing::warn!(
+                            job_id = id,
+                            attempt,
+                            ?delay,
+                            "retrying after transient failure: {}",
+                            error.message
+                        );
+                        ctx.metrics.job_retried();
+                        if let Some(stopped) = wait_to_retry(delay, &abort, &token) {
+                            return Err(stopped);
+                        }
+                        attempt += 1;
+                    }
+                })
+            }
+        };
+        let timeout = job.options.timeout.or(ctx.timeout);
+        let deadline = async move {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        let (output, cancelled) = tokio::select! {
+            biased;
+            Ok(()) = abort.changed() => (Err(JobError::new("cancelled", None, CANCELLED)), true),
+            () = token.cancelled() => {
+                (Err(JobError::new("cancelled", None, executor::CANCELLED)), true)
+            }
+            () = deadline => {
+                token.cancel();
+                if let Some(dispatcher) = &ctx.dispatcher {
+                    dispatcher.cancel(job.id);
+                }
+                let limit = timeout.unwrap_or_default().as_millis();
+                tracing::warn!(job_id = job.id, limit_ms = %limit, "job timed out");
+                let message = format!("{} after {} ms", TIMED_OUT, limit);
+                (Err(JobError::new("timeout", None, message)), true)
+            }
+            output = &mut work => (
+                output.unwrap_or_else(|e| {
+                    Err(JobError::new("execution_failed", Some("execute"), e.to_string()))
+                }),
+                false,
+            ),
+        };
+        ctx.running.lock().unwrap().remove(&job.id);
         let duration = start.elapsed();
         info!(job_id = job.id, ?duration, "job finished");
+        if let (Some(key), Ok(output), false) = (cache_key, &output, cached) {
+            ctx.cache.insert(key, output);
+        }
+        let job_result = JobResult {
+            output,
+            duration,
+            cost: job.cost,
+            cached,
+            attempts: attempts.load(Ordering::SeqCst),
+        };
+        let output_size = job_result.output_size();
 
-        let job_result = if let Ok(df) = result {
-            match crate::utils::prepare_output(job.id, &df) {
-                Ok(o) => JobResult {
-                    bytes: o.bytes,
-                    path: o.path,
-                    duration,
-                    cost: job.cost,
-                },
-                Err(_) => JobResult {
-                    bytes: None,
-                    path: None,
-                    duration,
-                    cost: job.cost,
-                },
+        let files = job_result.output.as_ref().ok();
+        let output_location = files.and_then(|o| o.path.clone());
+        let output_parts: Vec<String> = files
+            .and_then(|o| o.parts.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|p| p.path.clone())
+            .collect();
+        let outputs: Vec<String> = output_location
+            .iter()
+            .chain(&output_parts)
+            .cloned()
+            .collect();
+        let status = match job_result.error() {
+            Some(e) if e.kind == "cancelled" => "cancelled",
+            Some(e) if e.kind == "timeout" => "timeout",
+            Some(_) => "failed",
+            None => "completed",
+        };
+        let row = JobMetrics {
+            job_id: job.id,
+            query: job.query.clone(),
+            user: job.options.user.clone(),
+            status: status.to_string(),
+            error_kind: job_result.error().map(|e| e.kind.to_string()),
+            submitted_at_ms: job.submitted_at_ms,
+            queue_wait_ms: started_at_ms.saturating_sub(job.submitted_at_ms),
+            duration_ms: duration.as_millis() as u64,
+            cost: job.cost,
+            rows: files.and_then(|o| o.rows),
+            output_size,
+        };
+        let lineage = ctx.lineage.clone();
+        let metrics_log = ctx.metrics_log.clone();
+        let (id, query, user) = (job.id, job.query.clone(), job.options.user.clone());
+        let recorded = tokio::task::spawn_blocking(move || {
+            if let Err(e) = metrics_log.record(row) {
+                tracing::warn!(job_id = id, "failed to record metrics: {}", e);
             }
-        } else {
-            JobResult {
-                bytes: None,
-                path: None,
-                duration,
-                cost: job.cost,
+            if let (Some(sources), false) = (sources, outputs.is_empty()) {
+                let record = JobLineage {
+                    job_id: id,
+                    user,
+                    query,
+                    sources,
+                    outputs,
+                    created_at: crate::catalog::now_secs(),
+                };
+                if let Err(e) = lineage.record(record) {
+                    tracing::warn!(job_id = id, "failed to record lineage: {}", e);
+                }
             }
-        };
+        });
+        let _ = recorded.await;
 
-        let output_size = if let Some(ref bytes) = job_result.bytes {
-            bytes.len() as u64
-        } else if let Some(ref path) = job_result.path {
-            std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
-        } else {
-            0
+        let record = JobRecord {
+            id: job.id,
+            user: job.options.user.clone(),
+            query: job.query.clone(),
+            status: status.to_string(),
+            submitted_at_ms: Some(job.submitted_at_ms),
+            started_at_ms: Some(started_at_ms),
+            duration_ms: Some(duration.as_millis() as u64),
+            cost: job.cost,
+            output_location,
+            output_parts,
+            error: job_result.error().map(|e| e.message.clone()),
         };
+        if let Err(e) = ctx.state.put_job(&record).await {
+            tracing::warn!(job_id = job.id, "failed to record job state: {}", e);
+        }
 
-        let _ = metrics::record_metrics(&job.query, duration.as_millis(), job.cost, output_size);
-
+        ctx.metrics
+            .job_finished(status, Some(duration), output_size);
+        ctx.jobs.finished(job.id, status, job_result.clone());
         let _ = job.resp.send(job_result);
-        let _ = complete.send(()).await;
-    });
+        // A cancelled or timed out job keeps its slot until work already
+        // executing ends.
+        if cancelled {
+            work.abort();
+            let _ = work.await;
+        }
+        let _ = complete.send(job.cost).await;
+    };
+    tokio::spawn(task.instrument(span));
+}
+
+/// Run a job's query once and store its result, failing with `transient_io`
+/// when the error may pass on retrying.
+fn run_attempt(
+    ctx: &JobContext,
+    id: u64,
+    query: &str,
+    exec: &ExecContext,
+    options: &JobOptions,
+    abort: &watch::Receiver<u64>,
+    token: &CancelToken,
+) -> Result<PreparedOutput, JobError> {
+    ctx.jobs.progress(id, "execute");
+    let df = executor::execute_plan_with(query, exec);
+    // Don't store the result of a job cancelled while it ran.
+    if let Some(stopped) = stop_requested(abort, token) {
+        return Err(stopped);
+    }
+    let df = df
+        .map_err(|e| JobError::execution("execute", &e).transient_if(executor::is_transient(&e)))?;
+    ctx.jobs.progress(id, "store");
+    tracing::info_span!("serialize").in_scope(|| store_output(ctx, options, &df))
+}
+
+/// The error of a job aborted or cancelled while it ran, if it was.
+fn stop_requested(abort: &watch::Receiver<u64>, token: &CancelToken) -> Option<JobError> {
+    if abort.has_changed().unwrap_or(false) {
+        return Some(JobError::new("cancelled", None, CANCELLED));
+    }
+    if token.is_cancelled() {
+        return Some(JobError::new("cancelled", None, executor::CANCELLED));
+    }
+    None
+}
+
+/// Wait `delay` before retrying a job, on its blocking thread, in short
+/// slices so that a job aborted or cancelled meanwhile stops waiting. The
+/// error of such a job is returned.
+fn wait_to_retry(
+    delay: Duration,
+    abort: &watch::Receiver<u64>,
+    token: &CancelToken,
+) -> Option<JobError> {
+    let until = std::time::Instant::now() + delay;
+    loop {
+        if let Some(stopped) = stop_requested(abort, token) {
+            return Some(stopped);
+        }
+        let left = until.saturating_duration_since(std::time::Instant::now());
+        if left.is_zero() {
+            return None;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(50)));
+    }
+}
+
+/// Start queued jobs, in [`next_job`] order, while they fit the free slots
+/// and cost budget.
+fn start_queued(queue: &mut VecDeque<Job>, complete: &mpsc::Sender<usize>, ctx: &JobContext) {
+    let fits = |job: &Job| {
+        can_start(
+            ctx.active.load(Ordering::SeqCst),
+            ctx.running_cost.load(Ordering::SeqCst),
+            job.cost,
+            ctx.max_concurrency,
+            ctx.cost_budget,
+        )
+    };
+    while let Some(job) = next_job(queue, fits) {
+        spawn_job(job, complete.clone(), ctx.clone());
+    }
 }
 
 #[cfg(test)]
@@ -169,8 +1982,131 @@ mod tests {
     use polars::prelude::ParquetWriter;
     use polars::prelude::*;
     use std::fs::File;
+    use std::time::Duration;
     use tempfile::NamedTempFile;
 
+    #[test]
+    fn queued_jobs_start_by_priority() {
+        let job = |id, priority| Job {
+            id,
+            query: String::new(),
+            resp: oneshot::channel().0,
+            cost: 0,
+            options: JobOptions {
+                priority,
+                ..Default::default()
+            },
+            submitted_at_ms: 0,
+            bypassed: 0,
+            span: Span::none(),
+            queue_span: Span::none(),
+        };
+        let mut queue = VecDeque::from([
+            job(1, JobPriority::Normal),
+            job(2, JobPriority::Low),
+            job(3, JobPriority::High),
+            job(4, JobPriority::Normal),
+            job(5, JobPriority::High),
+        ]);
+        let order: Vec<u64> = std::iter::from_fn(|| next_job(&mut queue, |_| true))
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(order, vec![3, 5, 1, 4, 2]);
+    }
+
+    #[test]
+    fn queued_jobs_start_cheapest_first_without_starving() {
+        let job = |id, cost| Job {
+            id,
+            query: String::new(),
+            resp: oneshot::channel().0,
+            cost,
+            options: JobOptions::default(),
+            submitted_at_ms: 0,
+            bypassed: 0,
+            span: Span::none(),
+            queue_span: Span::none(),
+        };
+        let mut queue = VecDeque::from([job(1, 500), job(2, 3), job(3, 40), job(4, 3)]);
+        let order: Vec<u64> = std::iter::from_fn(|| next_job(&mut queue, |_| true))
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(order, vec![2, 4, 3, 1]);
+
+        let mut queue = VecDeque::from([job(1, 500)]);
+        queue.extend((2..=20).map(|id| job(id, 1)));
+        let order: Vec<u64> = std::iter::from_fn(|| next_job(&mut queue, |_| true))
+            .map(|job| job.id)
+            .collect();
+        assert_eq!(order[MAX_BYPASSED], 1);
+    }
+
+    #[test]
+    fn jobs_start_within_the_cost_budget() {
+        assert!(can_start(0, 0, 50, 4, Some(10)));
+        assert!(can_start(2, 6, 4, 4, Some(10)));
+        assert!(!can_start(2, 6, 5, 4, Some(10)));
+        assert!(!can_start(4, 4, 1, 4, Some(10)));
+        assert!(can_start(3, 900, 100, 4, None));
+
+        let job = |id, cost| Job {
+            id,
+            query: String::new(),
+            resp: oneshot::channel().0,
+            cost,
+            options: JobOptions::default(),
+            submitted_at_ms: 0,
+            bypassed: 0,
+            span: Span::none(),
+            queue_span: Span::none(),
+        };
+        // With 3 of a budget of 4 in use, small jobs are started around a
+        // large one.
+        let fits = |job: &Job| can_start(1, 3, job.cost, 4, Some(4));
+        let mut queue = VecDeque::from([job(1, 10), job(2, 1), job(3, 1)]);
+        assert_eq!(next_job(&mut queue, fits).map(|j| j.id), Some(2));
+        assert_eq!(queue[0].bypassed, 1);
+
+        // Until the large one has waited long enough to hold them back.
+        queue[0].bypassed = MAX_BYPASSED;
+        assert!(next_job(&mut queue, fits).is_none());
+        let idle = |job: &Job| can_start(0, 0, job.cost, 4, Some(4));
+        assert_eq!(next_job(&mut queue, idle).map(|j| j.id), Some(1));
+    }
+
+    #[tokio::test]
+    async fn with_config_sets_limits() {
+        let sched = Scheduler::with_config(64, 1000);
+        assert_eq!(
+            sched.limits(),
+            SchedulerLimits {
+                max_concurrency: 64,
+                cost_budget: None,
+                queue_capacity: 1000,
+            }
+        );
+        assert!(sched.render_metrics().contains("rdata_max_workers 64\n"));
+        assert_eq!(Scheduler::with_config(0, 0).limits().queue_capacity, 1);
+    }
+
+    #[tokio::test]
+    async fn submissions_wait_once_the_queue_is_full() {
+        // Coordinator jobs wait for a worker that never comes.
+        let mut config = Config::default();
+        config.cluster.role = Role::Coordinator;
+        config.scheduler.max_concurrency = 1;
+        config.scheduler.queue_capacity = 1;
+        let sched = Scheduler::from_config(&config);
+        let query = "df = pl.read_parquet(\"a.parquet\")".to_string();
+        // One job runs, one waits in the queue and one is being handed over.
+        for _ in 0..3 {
+            sched.enqueue(query.clone()).await;
+        }
+        let blocked = tokio::time::timeout(Duration::from_millis(200), sched.enqueue(query));
+        assert!(blocked.await.is_err());
+        sched.abort_all().await;
+    }
+
     #[tokio::test]
     async fn enqueue_and_complete() {
         let sched = Scheduler::new();
@@ -185,7 +2121,337 @@ mod tests {
         );
         let (_id, _status, rx) = sched.enqueue(query).await;
         let res = rx.await.unwrap();
-        assert!(res.bytes.is_some() || res.path.is_some());
+        assert!(res.output_json(1).is_some());
         assert!(res.cost > 0);
     }
+
+    #[tokio::test]
+    async fn injected_faults_fail_only_their_job() {
+        let mut df = df!["name" => ["a"], "age" => [10]].unwrap();
+        let file = NamedTempFile::new().unwrap();
+        ParquetWriter::new(File::create(file.path()).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let query = format!(
+            "df = pl.read_parquet(\"{}\")",
+            file.path().to_str().unwrap()
+        );
+        let mut config = Config::default();
+        config.chaos.enabled = true;
+        config.chaos.panic_rate = 1.0;
+        let sched = Scheduler::from_config(&config);
+        let (_, _, rx) = sched.enqueue(query.clone()).await;
+        let error = rx.await.unwrap().error().cloned().unwrap();
+        assert!(error.message.contains("panic"));
+        assert_eq!(error.kind, "execution_failed");
+        // The scheduler survives the panic and keeps running jobs.
+        let (_, _, rx) = sched.enqueue(query.clone()).await;
+        assert!(rx.await.unwrap().error().is_some());
+
+        // Injected scan failures are transient and retried.
+        config.chaos.panic_rate = 0.0;
+        config.chaos.scan_failure_rate = 1.0;
+        config.scheduler.retry.max_attempts = 3;
+        config.scheduler.retry.backoff_ms = 1;
+        let sched = Scheduler::from_config(&config);
+        let (_, _, rx) = sched.enqueue(query.clone()).await;
+        let result = rx.await.unwrap();
+        let error = result.error().cloned().unwrap();
+        assert!(error.message.contains(crate::chaos::INJECTED));
+        assert_eq!(error.step, Some("execute"));
+        assert_eq!(error.kind, "transient_io");
+        assert_eq!(result.attempts, 3);
+        assert!(sched
+            .render_metrics()
+            .contains("rdata_job_retries_total 2\n"));
+
+        let options = JobOptions {
+            max_attempts: Some(1),
+            ..Default::default()
+        };
+        let (_, _, rx) = sched.enqueue_with(query, options).await;
+        assert_eq!(rx.await.unwrap().attempts, 1);
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let retry = RetryConfig {
+            max_attempts: 5,
+            backoff_ms: 100,
+            max_backoff_ms: 300,
+        };
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(200));
+        assert_eq!(retry.delay(3), Duration::from_millis(300));
+        assert_eq!(retry.delay(60), Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn abort_all_cancels_queued_and_running_jobs() {
+        // Coordinator jobs wait for a worker that never comes.
+        let mut config = Config::default();
+        config.cluster.role = Role::Coordinator;
+        config.scheduler.max_concurrency = 1;
+        config.scheduler.admin_token = Some("secret".into());
+        let sched = Scheduler::from_config(&config);
+        assert!(sched.authorize_admin(Some("secret")));
+        assert!(!sched.authorize_admin(None));
+
+        let query = "df = pl.read_parquet(\"a.parquet\")".to_string();
+        let (first, _, first_rx) = sched.enqueue(query.clone()).await;
+        let (second, _, second_rx) = sched.enqueue(query).await;
+        let report = sched.abort_all().await;
+        let mut ids: Vec<u64> = report
+            .queued
+            .iter()
+            .chain(&report.running)
+            .copied()
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![first, second]);
+        for rx in [first_rx, second_rx] {
+            assert_eq!(
+                rx.await.unwrap().error().map(|e| e.message.as_str()),
+                Some(CANCELLED)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_jobs_and_writes_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n").unwrap();
+        let mut config = Config::default();
+        config.data.data_dir = Some(dir.path().to_path_buf());
+        config.storage.output_dir = dir.path().join("out");
+        config.storage.metrics.dir = dir.path().join("metrics");
+        let sched = Scheduler::from_config(&config);
+
+        let (_, _, rx) = sched.enqueue("df = pl.read_csv(\"a.csv\")".into()).await;
+        assert!(rx.await.unwrap().output.is_ok());
+        assert_eq!(sched.metrics_log().pending(), 1);
+        let report = sched.shutdown(Duration::from_secs(1)).await;
+        assert!(report.drained);
+        assert_eq!(sched.metrics_log().pending(), 0);
+        assert!(config.storage.metrics.dir.exists());
+        let refused = sched.admit("anonymous").await.unwrap_err();
+        assert_eq!(refused.message, "server is shutting down");
+
+        // Coordinator jobs wait for a worker that never comes, so they are
+        // cancelled once the grace period runs out.
+        config.cluster.role = Role::Coordinator;
+        let sched = Scheduler::from_config(&config);
+        let (id, _, rx) = sched.enqueue("df = pl.read_csv(\"a.csv\")".into()).await;
+        let report = sched.shutdown(Duration::from_millis(20)).await;
+        assert!(!report.drained);
+        let mut cancelled = report.cancelled.queued;
+        cancelled.extend(report.cancelled.running);
+        assert_eq!(cancelled, [id]);
+        assert_eq!(
+            rx.await.unwrap().error().map(|e| e.message.as_str()),
+            Some(CANCELLED)
+        );
+        assert_eq!(sched.jobs().pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn metrics_of_several_jobs_are_appended_and_aggregated() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n3\n").unwrap();
+        let mut config = Config::default();
+        config.data.data_dir = Some(dir.path().to_path_buf());
+        config.storage.output_dir = dir.path().join("out");
+        config.storage.metrics.dir = dir.path().join("metrics");
+        let sched = Scheduler::from_config(&config);
+
+        let queries = [
+            "df = pl.read_csv(\"a.csv\")",
+            "df = pl.read_csv(\"a.csv\")\ndf = df.head(2)",
+            "df = pl.read_csv(\"missing.csv\")",
+        ];
+        let mut files = Vec::new();
+        for query in queries {
+            let (_, _, rx) = sched.enqueue(query.into()).await;
+            rx.await.unwrap();
+            // Each flush appends a file of its own next to the earlier ones.
+            files.extend(sched.metrics_log().flush(true).unwrap());
+        }
+        assert_eq!(files.len(), 3);
+        assert_eq!(files.iter().collect::<HashSet<_>>().len(), 3);
+
+        let df = crate::metrics::read_metrics(&config.storage.metrics.dir).unwrap();
+        assert_eq!(df.height(), 3);
+        let totals = executor::execute_plan_on(
+            df,
+            "df = df.groupby(\"status\").agg([pl.col(\"job_id\").count().alias(\"jobs\"), pl.col(\"rows\").sum()])\ndf = df.sort(\"status\")",
+            &ExecContext::default(),
+        )
+        .unwrap();
+        let status: Vec<_> = totals
+            .column("status")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(status, ["completed", "failed"]);
+        let jobs: Vec<_> = totals
+            .column("jobs")
+            .unwrap()
+            .cast(&DataType::Int64)
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(jobs, [2, 1]);
+        let rows = totals.column("rows").unwrap().i64().unwrap();
+        assert_eq!(rows.get(0), Some(5));
+    }
+
+    #[tokio::test]
+    async fn errors_have_the_kind_they_were_raised_with() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.output_dir = dir.path().to_path_buf();
+        config.storage.output.inline_limit = 0;
+        config.storage.min_free_bytes = u64::MAX;
+        let sched = Scheduler::from_config(&config);
+
+        let (_, _, rx) = sched
+            .enqueue("df = pl.read_table(\"missing\")".to_string())
+            .await;
+        let error = rx.await.unwrap().error().cloned().unwrap();
+        assert_eq!(
+            (error.kind, error.step),
+            ("unknown_dataset", Some("execute"))
+        );
+
+        let mut df = df!["name" => ["a"], "age" => [10]].unwrap();
+        let data = dir.path().join("people.parquet");
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let query = format!("df = pl.read_parquet(\"{}\")", data.display());
+        let (_, _, rx) = sched.enqueue(query).await;
+        let error = rx.await.unwrap().error().cloned().unwrap();
+        assert_eq!(
+            (error.kind, error.step),
+            ("insufficient_storage", Some("store"))
+        );
+    }
+
+    #[tokio::test]
+    async fn jobs_past_their_timeout_are_stopped() {
+        let mut config = Config::default();
+        config.cluster.role = Role::Coordinator;
+        config.scheduler.max_concurrency = 1;
+        config.scheduler.job_timeout_ms = Some(20);
+        let sched = Scheduler::from_config(&config);
+
+        let query = "df = pl.read_parquet(\"a.parquet\")".to_string();
+        let (first, _, first_rx) = sched.enqueue(query.clone()).await;
+        let options = JobOptions {
+            timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let (_, _, second_rx) = sched.enqueue_with(query, options).await;
+        let error = first_rx.await.unwrap().error().cloned().unwrap();
+        assert_eq!(error.kind, "timeout");
+        assert_eq!(error.message, format!("{} after 20 ms", TIMED_OUT));
+        assert_eq!(sched.jobs().get(first).unwrap().status, "timeout");
+        // The slot is freed for the queued job, which has its own limit.
+        let error = second_rx.await.unwrap().error().cloned().unwrap();
+        assert_eq!(error.message, format!("{} after 10 ms", TIMED_OUT));
+    }
+
+    #[tokio::test]
+    async fn repeated_queries_are_served_from_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n").unwrap();
+        let mut config = Config::default();
+        config.data.data_dir = Some(dir.path().to_path_buf());
+        config.storage.output_dir = dir.path().join("out");
+        config.cache.enabled = true;
+        let sched = Scheduler::from_config(&config);
+
+        let query = "df = pl.read_csv(\"a.csv\")".to_string();
+        let (_, _, rx) = sched.enqueue(query.clone()).await;
+        assert!(!rx.await.unwrap().cached);
+        let (_, _, rx) = sched.enqueue(format!("{}\n", query)).await;
+        let result = rx.await.unwrap();
+        assert!(result.cached);
+        assert!(result.output.is_ok());
+
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n3\n").unwrap();
+        let (_, _, rx) = sched.enqueue(query).await;
+        assert!(!rx.await.unwrap().cached);
+    }
+
+    #[tokio::test]
+    async fn results_beyond_the_size_cap_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n").unwrap();
+        let mut config = Config::default();
+        config.data.data_dir = Some(dir.path().to_path_buf());
+        config.storage.output_dir = dir.path().join("out");
+        config.storage.output.inline_limit = 0;
+        let sched = Scheduler::from_config(&config);
+
+        let (id, _, rx) = sched.enqueue("df = pl.read_csv(\"a.csv\")".into()).await;
+        assert!(rx.await.unwrap().output.unwrap().path.is_some());
+        let retention = RetentionConfig {
+            max_total_bytes: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(sched.expire_results(&retention).await, [id]);
+        assert_eq!(sched.jobs().get(id).unwrap().status, "expired");
+        let record = sched.state.get_job(id).await.unwrap().unwrap();
+        assert_eq!(record.status, "expired");
+        assert!(record.output_location.is_none());
+        assert_eq!(
+            std::fs::read_dir(&config.storage.output_dir)
+                .unwrap()
+                .filter(|e| e
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("output_"))
+                .count(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_job_removes_queued_and_stops_running_jobs() {
+        let mut config = Config::default();
+        config.cluster.role = Role::Coordinator;
+        config.scheduler.max_concurrency = 1;
+        let sched = Scheduler::from_config(&config);
+
+        let query = "df = pl.read_parquet(\"a.parquet\")".to_string();
+        let (first, _, first_rx) = sched.enqueue(query.clone()).await;
+        let (second, _, second_rx) = sched.enqueue(query).await;
+        let report = sched.cancel_job(second, "anonymous").await.unwrap();
+        assert_eq!(report.status, "cancelled");
+        assert_eq!(
+            second_rx.await.unwrap().error().map(|e| e.message.as_str()),
+            Some(executor::CANCELLED)
+        );
+
+        let report = sched.cancel_job(first, "anonymous").await.unwrap();
+        assert_eq!(report.status, "cancelling");
+        assert_eq!(
+            first_rx.await.unwrap().error().map(|e| e.message.as_str()),
+            Some(executor::CANCELLED)
+        );
+        assert_eq!(sched.jobs().get(first).unwrap().status, "cancelled");
+        assert!(sched.cancel_job(first, "anonymous").await.is_err());
+        assert!(sched
+            .cancel_job(u64::MAX, "anonymous")
+            .await
+            .unwrap_err()
+            .starts_with("unknown job"));
+    }
 }
@@ -0,0 +1,159 @@
+//! Aligning the schemas of files scanned together.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How files with differing schemas are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaMode {
+    /// All files must share one schema; the scan fails otherwise.
+    #[default]
+    Strict,
+    /// Take the union of all columns, filling missing ones with nulls and
+    /// casting differing types to a common type.
+    Relaxed,
+}
+
+/// Adjustments made to one file to fit the aligned schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileCoercion {
+    pub path: String,
+    /// Columns missing from the file, filled with nulls.
+    pub added: Vec<String>,
+    /// Columns cast to a wider type, as `column: from -> to`.
+    pub cast: Vec<String>,
+}
+
+fn is_signed(dtype: &DataType) -> bool {
+    matches!(
+        dtype,
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64
+    )
+}
+
+/// The common type of `a` and `b`: the wider numeric type where both are
+/// numeric, otherwise a string.
+pub fn widen(a: &DataType, b: &DataType) -> DataType {
+    match (a, b) {
+        _ if a == b => a.clone(),
+        (DataType::Null, other) | (other, DataType::Null) => other.clone(),
+        _ if a.is_float() || b.is_float() => {
+            if a.is_numeric() && b.is_numeric() {
+                DataType::Float64
+            } else {
+                DataType::Utf8
+            }
+        }
+        _ if a.is_integer() && b.is_integer() => {
+            if is_signed(a) || is_signed(b) {
+                DataType::Int64
+            } else {
+                DataType::UInt64
+            }
+        }
+        _ => DataType::Utf8,
+    }
+}
+
+/// Combine `frames` (each with the path it was read from) into one frame.
+///
+/// In [`SchemaMode::Relaxed`] columns are aligned first and every file that
+/// needed changes is reported.
+pub fn combine(
+    frames: Vec<(String, LazyFrame)>,
+    mode: SchemaMode,
+) -> PolarsResult<(LazyFrame, Vec<FileCoercion>)> {
+    if frames.len() == 1 || mode == SchemaMode::Strict {
+        let mut frames: Vec<LazyFrame> = frames.into_iter().map(|(_, lf)| lf).collect();
+        let lf = if frames.len() == 1 {
+            frames.pop().unwrap()
+        } else {
+            concat(frames, UnionArgs::default())?
+        };
+        return Ok((lf, Vec::new()));
+    }
+
+    let mut schemas = Vec::with_capacity(frames.len());
+    for (_, lf) in &frames {
+        schemas.push(lf.schema()?);
+    }
+    let mut unified = Schema::new();
+    for schema in &schemas {
+        for (name, dtype) in schema.iter() {
+            let dtype = match unified.get(name) {
+                Some(existing) => widen(existing, dtype),
+                None => dtype.clone(),
+            };
+            unified.with_column(name.clone(), dtype);
+        }
+    }
+
+    let mut aligned = Vec::with_capacity(frames.len());
+    let mut coercions = Vec::new();
+    for ((path, lf), schema) in frames.into_iter().zip(&schemas) {
+        let mut coercion = FileCoercion {
+            path,
+            added: Vec::new(),
+            cast: Vec::new(),
+        };
+        let exprs: Vec<Expr> = unified
+            .iter()
+            .map(|(name, dtype)| match schema.get(name) {
+                None => {
+                    coercion.added.push(name.to_string());
+                    lit(NULL).cast(dtype.clone()).alias(name)
+                }
+                Some(own) if own != dtype => {
+                    coercion
+                        .cast
+                        .push(format!("{}: {} -> {}", name, own, dtype));
+                    col(name).cast(dtype.clone())
+                }
+                Some(_) => col(name),
+            })
+            .collect();
+        if !coercion.added.is_empty() || !coercion.cast.is_empty() {
+            coercions.push(coercion);
+        }
+        aligned.push(lf.select(exprs));
+    }
+    Ok((concat(aligned, UnionArgs::default())?, coercions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widening() {
+        assert_eq!(widen(&DataType::Int32, &DataType::Int64), DataType::Int64);
+        assert_eq!(
+            widen(&DataType::Int32, &DataType::Float32),
+            DataType::Float64
+        );
+        assert_eq!(widen(&DataType::UInt8, &DataType::UInt16), DataType::UInt64);
+        assert_eq!(widen(&DataType::Int32, &DataType::Utf8), DataType::Utf8);
+    }
+
+    #[test]
+    fn relaxed_mode_aligns_columns() {
+        let a = df!["id" => [1i32, 2], "name" => ["a", "b"]].unwrap();
+        let b = df!["id" => [3i64], "score" => [0.5]].unwrap();
+        let frames = vec![("a".to_string(), a.lazy()), ("b".to_string(), b.lazy())];
+
+        let (lf, coercions) = combine(frames.clone(), SchemaMode::Relaxed).unwrap();
+        let out = lf.collect().unwrap();
+        assert_eq!(out.shape(), (3, 3));
+        assert_eq!(out.column("id").unwrap().dtype(), &DataType::Int64);
+        assert_eq!(out.column("score").unwrap().null_count(), 2);
+        assert_eq!(coercions.len(), 2);
+        assert_eq!(coercions[0].added, vec!["score".to_string()]);
+        assert_eq!(coercions[0].cast, vec!["id: i32 -> i64".to_string()]);
+        assert_eq!(coercions[1].added, vec!["name".to_string()]);
+
+        assert!(combine(frames, SchemaMode::Strict)
+            .and_then(|(lf, _)| lf.collect())
+            .is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! The server as a library: a [`QueryServer`] owns the scheduler built from
+//! a [`Config`], hands out the HTTP API as an axum [`Router`] to mount in
+//! another application, and runs queries in process without HTTP.
+//!
+//! ```no_run
+//! use polars_query_server::{Config, QueryServer};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let server = QueryServer::builder()
+//!     .with_config(Config::from_env())
+//!     .build()
+//!     .map_err(|errors| errors.join("; "))?;
+//! let df = server
+//!     .execute("df = pl.read_parquet(\"people.parquet\")")
+//!     .await?;
+//! println!("{}", df);
+//! let app = axum::Router::new().nest("/rdata", server.router());
+//! # let _ = app;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use polars::prelude::DataFrame;
+
+use crate::api::{self, AppState};
+use crate::compaction;
+use crate::config::Config;
+use crate::discovery;
+use crate::metrics;
+use crate::plan::Plan;
+use crate::retention;
+use crate::scheduler::{JobError, JobOptions, Scheduler, ShutdownReport};
+use crate::sessions;
+use crate::stats;
+use crate::streaming;
+use crate::subscriptions;
+use crate::systemd;
+use crate::temporary;
+use crate::utils::{self, OutputFormat};
+
+/// Builds a [`QueryServer`].
+#[derive(Debug, Clone)]
+pub struct QueryServerBuilder {
+    config: Config,
+    background_tasks: bool,
+}
+
+impl Default for QueryServerBuilder {
+    fn default() -> Self {
+        QueryServerBuilder {
+            config: Config::default(),
+            background_tasks: true,
+        }
+    }
+}
+
+impl QueryServerBuilder {
+    /// Configure the server with `config` instead of the defaults.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Whether to start the maintenance tasks the standalone server runs:
+    /// view refreshes, compaction, discovery, statistics, sweeps of sessions,
+    /// temporary tables and expired results, subscriptions and the flushing
+    /// of streamed rows and job metrics. On by default.
+    pub fn with_background_tasks(mut self, enabled: bool) -> Self {
+        self.background_tasks = enabled;
+        self
+    }
+
+    /// Validate the configuration and start the scheduler. Must be called
+    /// within a Tokio runtime.
+    pub fn build(self) -> Result<QueryServer, Vec<String>> {
+        self.config.validate()?;
+        let server = QueryServer {
+            scheduler: Scheduler::from_config(&self.config),
+        };
+        if self.background_tasks {
+            server.spawn_background_tasks();
+        }
+        Ok(server)
+    }
+}
+
+/// A query server embedded in an application. Clones share the scheduler.
+#[derive(Clone)]
+pub struct QueryServer {
+    scheduler: Scheduler,
+}
+
+impl QueryServer {
+    pub fn builder() -> QueryServerBuilder {
+        QueryServerBuilder::default()
+    }
+
+    /// The configuration the server was built from.
+    pub fn config(&self) -> &Config {
+        self.scheduler.config()
+    }
+
+    /// The scheduler running the server's jobs, for finer control over
+    /// submissions, the catalog and the other stores.
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// The HTTP API, to serve or to nest in another axum app.
+    pub fn router(&self) -> Router {
+        api::app(AppState {
+            scheduler: self.scheduler.clone(),
+        })
+    }
+
+    /// Run `query` as the anonymous user and return its result.
+    pub async fn execute(&self, query: &str) -> Result<DataFrame, JobError> {
+        self.execute_with(query, JobOptions::default()).await
+    }
+
+    /// Run a built [`Plan`] as the anonymous user and return its result.
+    pub async fn execute_plan(&self, plan: &Plan) -> Result<DataFrame, JobError> {
+        self.execute(&plan.to_string()).await
+    }
+
+    /// Run `query` with `options` through the scheduler, subject to its
+    /// limits and access control as a submission over HTTP is, and return
+    /// its result. Results too large to return inline are read back from
+    /// the result store, where they stay until retention removes them.
+    pub async fn execute_with(
+        &self,
+        query: &str,
+        options: JobOptions,
+    ) -> Result<DataFrame, JobError> {
+        if let Err(throttled) = self.scheduler.admit(&options.user).await {
+            let kind = if self.scheduler.is_draining() {
+                "shutting_down"
+            } else {
+                "rate_limited"
+            };
+            return Err(JobError {
+                kind,
+                message: throttled.message,
+                step: None,
+            });
+        }
+        let options = JobOptions {
+            format: OutputFormat::Ipc,
+            ..options
+        };
+        let (_, _, rx) = self
+            .scheduler
+            .enqueue_with(query.to_string(), options)
+            .await;
+        let result = rx.await.map_err(|_| {
+            JobError::new(
+                "execution_failed",
+                None,
+                "job was dropped before it finished",
+            )
+        })?;
+        let output = result.output?;
+        tokio::task::spawn_blocking(move || {
+            utils::read_output(
+                output.bytes.as_deref(),
+                output.path.as_deref(),
+                output.parts.as_deref().unwrap_or_default(),
+            )
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .map_err(|e| JobError::new("execution_failed", None, e))
+    }
+
+    /// Serve the HTTP API on the configured address until SIGTERM or
+    /// Ctrl-C, then stop taking jobs and drain them as
+    /// [`QueryServer::shutdown`] does.
+    pub async fn serve(self) -> Result<ShutdownReport, String> {
+        let addr = self.config().server.addr();
+        self.serve_on(addr).await
+    }
+
+    /// Serve the HTTP API on `addr` rather than the configured address,
+    /// otherwise as [`QueryServer::serve`] does.
+    pub async fn serve_on(self, addr: SocketAddr) -> Result<ShutdownReport, String> {
+        let server =
+            axum::Server::try_bind(&addr).map_err(|e| format!("failed to bind {}: {}", addr, e))?;
+        tracing::info!("listening on {}", addr);
+        systemd::notify_ready();
+        systemd::spawn_watchdog();
+        // The listener keeps serving while jobs drain, so clients can still
+        // poll for their results; it stops when the process exits.
+        let mut serving = tokio::spawn(server.serve(self.router().into_make_service()));
+        tokio::select! {
+            () = api::shutdown_signal() => {}
+            served = &mut serving => {
+                return Err(format!("server stopped: {:?}", served));
+            }
+        }
+        systemd::notify_stopping();
+        Ok(self.shutdown().await)
+    }
+
+    /// Stop taking jobs, wait up to the configured
+    /// `server.shutdown_timeout_secs` for running ones and flush what is
+    /// buffered. See [`Scheduler::shutdown`].
+    pub async fn shutdown(&self) -> ShutdownReport {
+        let grace = self.config().server.shutdown_timeout();
+        tracing::info!("shutting down, waiting up to {:?} for jobs", grace);
+        self.scheduler.shutdown(grace).await
+    }
+
+    fn spawn_background_tasks(&self) {
+        let scheduler = &self.scheduler;
+        let config = scheduler.config();
+        let refresh = Duration::from_secs(config.catalog.refresh_interval_secs.max(1));
+        scheduler.views().clone().spawn_refresh_loop(refresh);
+        compaction::spawn_schedule(scheduler.catalog().clone(), scheduler.compaction().clone());
+        discovery::spawn(
+            scheduler.catalog().clone(),
+            config.catalog.discovery.clone(),
+        );
+        stats::spawn_schedule(scheduler.catalog().clone(), config.catalog.stats.clone());
+        sessions::spawn_sweep(scheduler.sessions().clone());
+        temporary::spawn_sweep(scheduler.catalog().clone(), scheduler.temporary().clone());
+        retention::spawn_sweep(scheduler.clone(), config.storage.retention.clone());
+        streaming::spawn_flusher(scheduler.appender().clone());
+        metrics::spawn_flusher(scheduler.metrics_log().clone());
+        subscriptions::spawn_loop(scheduler.clone(), refresh);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::col;
+
+    #[tokio::test]
+    async fn queries_run_in_process() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n3\n").unwrap();
+        let mut config = Config::default();
+        config.data.data_dir = Some(dir.path().to_path_buf());
+        config.storage.output_dir = dir.path().join("out");
+        config.storage.output.inline_limit = 0;
+        config.storage.metrics.dir = dir.path().join("metrics");
+        let server = QueryServer::builder()
+            .with_config(config)
+            .with_background_tasks(false)
+            .build()
+            .unwrap();
+
+        let df = server
+            .execute("df = pl.read_csv(\"a.csv\")\ndf = df.filter(pl.col(\"x\") > 1)")
+            .await
+            .unwrap();
+        assert_eq!(df.height(), 2);
+        let plan = Plan::read_csv("a.csv").filter(col("x").lt(3)).head(1);
+        assert_eq!(server.execute_plan(&plan).await.unwrap().height(), 1);
+
+        let error = server.execute("df = df.head(").await.unwrap_err();
+        assert_eq!(error.kind, "invalid_query");
+
+        server.shutdown().await;
+        let error = server
+            .execute("df = pl.read_csv(\"a.csv\")")
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, "shutting_down");
+    }
+
+    #[tokio::test]
+    async fn api_is_served_on_the_given_address() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.output_dir = dir.path().join("out");
+        let server = QueryServer::builder()
+            .with_config(config)
+            .with_background_tasks(false)
+            .build()
+            .unwrap();
+        let serving = tokio::spawn(server.serve_on(addr));
+
+        let url = format!("http://{}/healthz", addr);
+        let mut response = None;
+        for _ in 0..50 {
+            if let Ok(r) = reqwest::get(&url).await {
+                response = Some(r);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        serving.abort();
+        assert_eq!(response.unwrap().status(), reqwest::StatusCode::OK);
+    }
+}
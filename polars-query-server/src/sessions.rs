@@ -0,0 +1,411 @@
+//! Interactive sessions keeping intermediate frames in memory between steps,
+//! so exploration does not re-scan sources at every step.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::catalog::{self, ColumnInfo};
+use crate::executor::{self, ExecContext};
+
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
+/// Interactive session settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Seconds a session is kept after it was last used.
+    pub ttl_secs: u64,
+    /// Most sessions open at once.
+    pub max_sessions: usize,
+    /// Most bytes of frames one session may hold.
+    pub max_bytes: u64,
+    /// Rows of a step's result returned as a preview.
+    pub preview_rows: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            ttl_secs: 900,
+            max_sessions: 64,
+            max_bytes: 1024 * 1024 * 1024,
+            preview_rows: 20,
+        }
+    }
+}
+
+/// A frame held by a session.
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameInfo {
+    pub name: String,
+    pub rows: usize,
+    /// Estimated in-memory size.
+    pub bytes: u64,
+    pub columns: Vec<ColumnInfo>,
+}
+
+impl FrameInfo {
+    fn of(name: &str, df: &DataFrame) -> Self {
+        FrameInfo {
+            name: name.to_string(),
+            rows: df.height(),
+            bytes: df.estimated_size() as u64,
+            columns: df
+                .schema()
+                .iter()
+                .map(|(name, dtype)| ColumnInfo {
+                    name: name.to_string(),
+                    dtype: dtype.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A session and the frames it holds.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user: String,
+    /// Unix seconds.
+    pub created_at: u64,
+    /// Unix seconds at which the session expires unless used again.
+    pub expires_at: u64,
+    pub frames: Vec<FrameInfo>,
+}
+
+/// Outcome of a step.
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResult {
+    pub frame: FrameInfo,
+    /// First rows of the frame as an array of JSON objects.
+    pub preview: Value,
+}
+
+struct Session {
+    user: String,
+    created_at: u64,
+    last_used: Instant,
+    frames: BTreeMap<String, DataFrame>,
+}
+
+fn new_id() -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(NEXT_SESSION.fetch_add(1, Ordering::SeqCst).to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(format!("{:?}", std::time::SystemTime::now()).as_bytes());
+    format!("{:x}", hasher.finalize())[..32].to_string()
+}
+
+fn preview(df: &DataFrame, rows: usize) -> Result<Value, String> {
+    let mut head = df.head(Some(rows));
+    let mut buf = Vec::new();
+    JsonWriter::new(&mut buf)
+        .with_json_format(JsonFormat::Json)
+        .finish(&mut head)
+        .map_err(|e| e.to_string())?;
+    serde_json::from_slice(&buf).map_err(|e| e.to_string())
+}
+
+/// Open sessions, each owned by the user who created it.
+pub struct Sessions {
+    config: SessionConfig,
+    exec: ExecContext,
+    sessions: Mutex<HashMap<String, Session>>,
+    /// Most bytes of frames all sessions together may hold.
+    max_total_bytes: Option<u64>,
+}
+
+impl Sessions {
+    /// Sessions whose steps run in `exec`.
+    pub fn new(config: SessionConfig, exec: ExecContext) -> Self {
+        Sessions {
+            config,
+            exec,
+            sessions: Mutex::new(HashMap::new()),
+            max_total_bytes: None,
+        }
+    }
+
+    /// Refuse steps that would take the frames of all sessions together
+    /// over `bytes`.
+    pub fn with_max_total_bytes(mut self, bytes: Option<u64>) -> Self {
+        self.max_total_bytes = bytes;
+        self
+    }
+
+    /// Bytes of the frames held by sessions other than `id`.
+    fn held_elsewhere(&self, id: &str) -> u64 {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .iter()
+            .filter(|(other, _)| other.as_str() != id)
+            .flat_map(|(_, s)| s.frames.values())
+            .map(|df| df.estimated_size() as u64)
+            .sum()
+    }
+
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.config.ttl_secs)
+    }
+
+    fn info(&self, id: &str, session: &Session) -> SessionInfo {
+        let left = self.ttl().saturating_sub(session.last_used.elapsed());
+        SessionInfo {
+            id: id.to_string(),
+            user: session.user.clone(),
+            created_at: session.created_at,
+            expires_at: catalog::now_secs() + left.as_secs(),
+            frames: session
+                .frames
+                .iter()
+                .map(|(name, df)| FrameInfo::of(name, df))
+                .collect(),
+        }
+    }
+
+    /// Drop sessions unused for longer than the TTL, returning how many.
+    pub fn evict_expired(&self) -> usize {
+        let ttl = self.ttl();
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, s| s.last_used.elapsed() < ttl);
+        before - sessions.len()
+    }
+
+    /// Open a session for `user`.
+    pub fn create(&self, user: &str) -> Result<SessionInfo, String> {
+        self.evict_expired();
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= self.config.max_sessions {
+            return Err(format!(
+                "too many open sessions (limit {}); close one or wait for one to expire",
+                self.config.max_sessions
+            ));
+        }
+        let id = new_id();
+        let session = Session {
+            user: user.to_string(),
+            created_at: catalog::now_secs(),
+            last_used: Instant::now(),
+            frames: BTreeMap::new(),
+        };
+        let info = self.info(&id, &session);
+        sessions.insert(id, session);
+        Ok(info)
+    }
+
+    /// Run `f` on session `id`, marking it used, if `user` owns it.
+    fn with_session<T>(
+        &self,
+        id: &str,
+        user: &str,
+        f: impl FnOnce(&mut Session) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let ttl = self.ttl();
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(id)
+            .filter(|s| s.last_used.elapsed() < ttl)
+            .ok_or_else(|| format!("unknown session {}", id))?;
+        if session.user != user {
+            return Err(format!(
+                "access denied: session {} belongs to another user",
+                id
+            ));
+        }
+        session.last_used = Instant::now();
+        f(session)
+    }
+
+    pub fn get(&self, id: &str, user: &str) -> Result<SessionInfo, String> {
+        self.with_session(id, user, |s| Ok(self.info(id, s)))
+    }
+
+    /// Close session `id`, freeing its frames.
+    pub fn close(&self, id: &str, user: &str) -> Result<(), String> {
+        self.with_session(id, user, |_| Ok(()))?;
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    /// Drop the frame `name` from session `id`.
+    pub fn drop_frame(&self, id: &str, user: &str, name: &str) -> Result<(), String> {
+        self.with_session(id, user, |s| {
+            s.frames
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| format!("unknown frame {} in session {}", name, id))
+        })
+    }
+
+    /// Run `query` in session `id` and keep its result as the frame `into`.
+    /// The query starts from the frame `from` when given, so it may consist
+    /// of transformations only; otherwise it must read its data.
+    pub fn run(
+        &self,
+        id: &str,
+        user: &str,
+        query: &str,
+        from: Option<&str>,
+        into: &str,
+    ) -> Result<StepResult, String> {
+        if !catalog::valid_name(into) {
+            return Err(format!(
+                "invalid frame name {}: use letters, digits, '_' and '-'",
+                into
+            ));
+        }
+        let start = self.with_session(id, user, |s| match from {
+            Some(name) => s
+                .frames
+                .get(name)
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| format!("unknown frame {} in session {}", name, id)),
+            None => Ok(None),
+        })?;
+        if let (Some(access), Some(catalog)) = (&self.exec.access, &self.exec.catalog) {
+            access.authorize_query(catalog, user, query)?;
+        }
+        let exec = ExecContext {
+            user: Some(user.to_string()),
+            ..self.exec.clone()
+        };
+        let df = match start {
+            Some(df) => executor::execute_plan_on(df, query, &exec),
+            None => executor::execute_plan_with(query, &exec),
+        }
+        .map_err(|e| e.to_string())?;
+
+        let frame = FrameInfo::of(into, &df);
+        let preview = preview(&df, self.config.preview_rows)?;
+        let elsewhere = self.held_elsewhere(id);
+        self.with_session(id, user, |s| {
+            let held: u64 = s
+                .frames
+                .iter()
+                .filter(|(name, _)| name.as_str() != into)
+                .map(|(_, df)| df.estimated_size() as u64)
+                .sum();
+            if held + frame.bytes > self.config.max_bytes {
+                return Err(format!(
+                    "session {} would hold {} MiB, over its limit of {} MiB; drop frames first",
+                    id,
+                    (held + frame.bytes) / (1024 * 1024),
+                    self.config.max_bytes / (1024 * 1024)
+                ));
+            }
+            if let Some(max) = self.max_total_bytes {
+                if elsewhere + held + frame.bytes > max {
+                    return Err(format!(
+                        "sessions would hold {} MiB, over the server's limit of {} MiB; drop frames first",
+                        (elsewhere + held + frame.bytes) / (1024 * 1024),
+                        max / (1024 * 1024)
+                    ));
+                }
+            }
+            s.frames.insert(into.to_string(), df);
+            Ok(())
+        })?;
+        Ok(StepResult { frame, preview })
+    }
+}
+
+/// Evict expired sessions every minute.
+pub fn spawn_sweep(sessions: Arc<Sessions>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            let evicted = sessions.evict_expired();
+            if evicted > 0 {
+                tracing::info!(evicted, "evicted expired sessions");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn frames_persist_between_steps() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("people.parquet");
+        let mut df = df!["name" => ["a", "b", "c"], "age" => [20, 30, 40]].unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let sessions = Sessions::new(SessionConfig::default(), ExecContext::default());
+        let id = sessions.create("alice").unwrap().id;
+
+        let read = format!("df = pl.read_parquet(\"{}\")", data.display());
+        let step = sessions.run(&id, "alice", &read, None, "people").unwrap();
+        assert_eq!(step.frame.rows, 3);
+        // The source is gone; later steps work on the frame in memory.
+        std::fs::remove_file(&data).unwrap();
+        let step = sessions
+            .run(
+                &id,
+                "alice",
+                "df = df.filter(pl.col(\"age\") > 25)",
+                Some("people"),
+                "adults",
+            )
+            .unwrap();
+        assert_eq!(step.frame.rows, 2);
+        assert_eq!(step.preview.as_array().unwrap().len(), 2);
+
+        let info = sessions.get(&id, "alice").unwrap();
+        let names: Vec<_> = info.frames.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["adults", "people"]);
+        assert!(sessions.get(&id, "bob").is_err());
+        assert!(sessions
+            .run(&id, "alice", "df = df.select([\"name\"])", None, "x")
+            .is_err());
+
+        sessions.close(&id, "alice").unwrap();
+        assert!(sessions.get(&id, "alice").is_err());
+    }
+
+    #[test]
+    fn frames_of_all_sessions_share_a_limit() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("people.parquet");
+        let mut df = df!["name" => ["a", "b", "c"], "age" => [20, 30, 40]].unwrap();
+        ParquetWriter::new(File::create(&data).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        let size = df.estimated_size() as u64;
+        let sessions = Sessions::new(SessionConfig::default(), ExecContext::default())
+            .with_max_total_bytes(Some(size * 3 / 2));
+        let read = format!("df = pl.read_parquet(\"{}\")", data.display());
+
+        let first = sessions.create("alice").unwrap().id;
+        sessions
+            .run(&first, "alice", &read, None, "people")
+            .unwrap();
+        // Replacing a frame only counts the new one.
+        sessions
+            .run(&first, "alice", &read, None, "people")
+            .unwrap();
+        let second = sessions.create("bob").unwrap().id;
+        let err = sessions
+            .run(&second, "bob", &read, None, "people")
+            .unwrap_err();
+        assert!(err.contains("over the server's limit"), "{}", err);
+
+        sessions.close(&first, "alice").unwrap();
+        sessions.run(&second, "bob", &read, None, "people").unwrap();
+    }
+}
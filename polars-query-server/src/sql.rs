@@ -0,0 +1,688 @@
+//! SQL queries. A `SELECT` statement is lowered to a [`Plan`] of the steps
+//! the query language has, so it runs through the same scheduler, access
+//! control and executor as a query written in the DSL.
+//!
+//! Tables in `FROM` and `JOIN` are catalog datasets; a quoted name ending in
+//! `.parquet` or `.csv`, or containing a `/`, is read as a path instead.
+//! Columns may be qualified by their table, which is dropped: joined tables
+//! share one namespace, as in the DSL.
+
+use sqlparser::ast as sql;
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+
+use crate::expr::{AggFunc, BinaryOp, DType, Expr, Literal, StrOp};
+use crate::parser::JoinKind;
+use crate::plan::{col, Plan};
+
+/// The plan of the `SELECT` statement `query`.
+pub fn to_plan(query: &str) -> Result<Plan, String> {
+    let statements = Parser::parse_sql(&GenericDialect {}, query).map_err(|e| e.to_string())?;
+    match statements.as_slice() {
+        [sql::Statement::Query(query)] => lower_query(query),
+        [_] => Err("only SELECT statements are supported".to_string()),
+        _ => Err("expected a single SELECT statement".to_string()),
+    }
+}
+
+/// `query` in the query language.
+pub fn to_query(query: &str) -> Result<String, String> {
+    to_plan(query).map(|plan| plan.to_string())
+}
+
+fn unsupported(what: &str) -> String {
+    format!("{} is not supported", what)
+}
+
+fn lower_query(query: &sql::Query) -> Result<Plan, String> {
+    if query.with.is_some() {
+        return Err(unsupported("WITH"));
+    }
+    if query.fetch.is_some() {
+        return Err(unsupported("FETCH"));
+    }
+    let select = match &*query.body {
+        sql::SetExpr::Select(select) => select,
+        sql::SetExpr::Query(_) => return Err(unsupported("a parenthesized query")),
+        _ => return Err(unsupported("UNION, INTERSECT, EXCEPT or VALUES")),
+    };
+    if select.top.is_some() {
+        return Err(unsupported("TOP"));
+    }
+    if select.into.is_some() {
+        return Err(unsupported("SELECT INTO"));
+    }
+    if select.qualify.is_some() {
+        return Err(unsupported("QUALIFY"));
+    }
+
+    let mut plan = from(&select.from)?;
+    if let Some(selection) = &select.selection {
+        plan = plan.filter(lower(selection, None)?);
+    }
+    plan = project(plan, select)?;
+    match &select.distinct {
+        None => {}
+        Some(sql::Distinct::Distinct) => plan = plan.unique(),
+        Some(sql::Distinct::On(_)) => return Err(unsupported("DISTINCT ON")),
+    }
+    match query.order_by.as_slice() {
+        [] => {}
+        [order] if order.asc != Some(false) && order.nulls_first.is_none() => {
+            plan = plan.sort(column(&order.expr)?);
+        }
+        _ => return Err("ORDER BY supports one column in ascending order".to_string()),
+    }
+    let limit = query.limit.as_ref().map(count).transpose()?;
+    match &query.offset {
+        Some(offset) => {
+            let offset = i64::try_from(count(&offset.value)?)
+                .map_err(|_| "OFFSET is too large".to_string())?;
+            plan = plan.slice(offset, limit);
+        }
+        None => {
+            if let Some(limit) = limit {
+                plan = plan.head(limit);
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// The reads of `FROM` and its joins.
+fn from(from: &[sql::TableWithJoins]) -> Result<Plan, String> {
+    let table = match from {
+        [table] => table,
+        [] => return Err("SELECT requires FROM".to_string()),
+        _ => return Err("FROM takes one table; combine others with JOIN".to_string()),
+    };
+    let mut plan = read(&table.relation)?;
+    for join in &table.joins {
+        let (how, constraint) = match &join.join_operator {
+            sql::JoinOperator::Inner(constraint) => (JoinKind::Inner, constraint),
+            sql::JoinOperator::LeftOuter(constraint) => (JoinKind::Left, constraint),
+            sql::JoinOperator::FullOuter(constraint) => (JoinKind::Outer, constraint),
+            _ => return Err("only INNER, LEFT and FULL joins are supported".to_string()),
+        };
+        let on = match constraint {
+            sql::JoinConstraint::Using(columns) => match columns.as_slice() {
+                [column] => column.value.clone(),
+                _ => return Err("joins are on one column".to_string()),
+            },
+            sql::JoinConstraint::On(sql::Expr::BinaryOp {
+                left,
+                op: sql::BinaryOperator::Eq,
+                right,
+            }) => {
+                let (left, right) = (column(left)?, column(right)?);
+                if left != right {
+                    return Err(format!(
+                        "joins are on a column of the same name in both tables, not {} and {}",
+                        left, right
+                    ));
+                }
+                left
+            }
+            _ => return Err("joins require ON a = b or USING (column)".to_string()),
+        };
+        plan = plan.join(read(&join.relation)?, on, how)?;
+    }
+    Ok(plan)
+}
+
+/// The read of a table in `FROM` or `JOIN`.
+fn read(relation: &sql::TableFactor) -> Result<Plan, String> {
+    let name = match relation {
+        sql::TableFactor::Table { name, .. } => name,
+        sql::TableFactor::Derived { .. } => return Err(unsupported("a subquery in FROM")),
+        other => return Err(format!("unsupported table {}", other)),
+    };
+    let name = name
+        .0
+        .iter()
+        .map(|ident| ident.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".");
+    Ok(if name.ends_with(".csv") {
+        Plan::read_csv(name)
+    } else if name.ends_with(".parquet") || name.contains('/') {
+        Plan::read_parquet(name)
+    } else {
+        Plan::read_table(name)
+    })
+}
+
+/// A column reference, without the table qualifying it.
+fn column(expr: &sql::Expr) -> Result<String, String> {
+    match expr {
+        sql::Expr::Identifier(ident) => Ok(ident.value.clone()),
+        sql::Expr::CompoundIdentifier(idents) => match idents.last() {
+            Some(ident) => Ok(ident.value.clone()),
+            None => Err("expected a column".to_string()),
+        },
+        sql::Expr::Nested(expr) => column(expr),
+        other => Err(format!("expected a column, found {}", other)),
+    }
+}
+
+/// A non-negative whole number, as `LIMIT` and `OFFSET` take.
+fn count(expr: &sql::Expr) -> Result<u64, String> {
+    match expr {
+        sql::Expr::Value(sql::Value::Number(n, _)) => n
+            .parse()
+            .map_err(|_| format!("expected a row count, found {}", n)),
+        other => Err(format!("expected a row count, found {}", other)),
+    }
+}
+
+/// The select list, with `GROUP BY` and `HAVING`, as steps of `plan`.
+fn project(plan: Plan, select: &sql::Select) -> Result<Plan, String> {
+    let keys = match &select.group_by {
+        sql::GroupByExpr::Expressions(exprs) => {
+            exprs.iter().map(column).collect::<Result<Vec<_>, _>>()?
+        }
+        sql::GroupByExpr::All => return Err(unsupported("GROUP BY ALL")),
+    };
+    // `COUNT(*)` counts the values of a group key, or of the first column
+    // selected: the DSL counts values of columns only.
+    let count_column = keys.first().cloned().or_else(|| {
+        select.projection.iter().find_map(|item| match item {
+            sql::SelectItem::UnnamedExpr(expr) | sql::SelectItem::ExprWithAlias { expr, .. } => {
+                first_column(expr)
+            }
+            _ => None,
+        })
+    });
+    let mut wildcard = false;
+    let mut items = Vec::new();
+    for item in &select.projection {
+        match item {
+            sql::SelectItem::Wildcard(_) | sql::SelectItem::QualifiedWildcard(..) => {
+                wildcard = true
+            }
+            sql::SelectItem::UnnamedExpr(expr) => {
+                items.push((item.to_string(), lower(expr, count_column.as_deref())?))
+            }
+            sql::SelectItem::ExprWithAlias { expr, alias } => items.push((
+                item.to_string(),
+                lower(expr, count_column.as_deref())?.alias(alias.value.clone()),
+            )),
+        }
+    }
+
+    if keys.is_empty() && !items.iter().any(|(_, item)| is_aggregate(item)) {
+        if select.having.is_some() {
+            return Err("HAVING requires GROUP BY or an aggregate".to_string());
+        }
+        let items: Vec<Expr> = items.into_iter().map(|(_, item)| item).collect();
+        return Ok(match (wildcard, items.is_empty()) {
+            (true, true) => plan,
+            (true, false) => plan.with_columns(items),
+            (false, _) => plan.select(items),
+        });
+    }
+    if wildcard {
+        return Err("SELECT * cannot be combined with GROUP BY or aggregates".to_string());
+    }
+
+    // Aggregates are computed under their output names, then the columns
+    // are put in the order of the select list.
+    let mut aggs = Vec::new();
+    let mut outputs = Vec::new();
+    for (text, item) in items {
+        if is_aggregate(&item) {
+            let name =
+                output_name(&item).ok_or_else(|| format!("name the aggregate {} with AS", text))?;
+            aggs.push(item);
+            outputs.push(col(name));
+            continue;
+        }
+        let (key, alias) = match &item {
+            Expr::Alias { expr, name } => (&**expr, Some(name)),
+            expr => (expr, None),
+        };
+        match key {
+            Expr::Column(name) if keys.contains(name) => outputs.push(match alias {
+                Some(alias) => col(name.clone()).alias(alias.clone()),
+                None => col(name.clone()),
+            }),
+            _ => {
+                return Err(format!(
+                    "{} must be a GROUP BY column or an aggregate",
+                    text
+                ))
+            }
+        }
+    }
+    let grouped: Vec<Expr> = keys.iter().cloned().map(col).collect();
+    let natural: Vec<Expr> = grouped
+        .into_iter()
+        .chain(aggs.iter().filter_map(output_name).map(col))
+        .collect();
+    let mut plan = if keys.is_empty() {
+        plan.select(aggs.clone())
+    } else {
+        plan.group_by(keys).agg(aggs.clone())
+    };
+    if let Some(having) = &select.having {
+        let having = lower(having, count_column.as_deref())?;
+        plan = plan.filter(aggregated(having, &aggs)?);
+    }
+    if outputs != natural {
+        plan = plan.select(outputs);
+    }
+    Ok(plan)
+}
+
+/// The first column `expr` refers to.
+fn first_column(expr: &sql::Expr) -> Option<String> {
+    match expr {
+        sql::Expr::Identifier(_) | sql::Expr::CompoundIdentifier(_) => column(expr).ok(),
+        sql::Expr::Nested(expr)
+        | sql::Expr::UnaryOp { expr, .. }
+        | sql::Expr::Cast { expr, .. }
+        | sql::Expr::IsNull(expr)
+        | sql::Expr::IsNotNull(expr) => first_column(expr),
+        sql::Expr::BinaryOp { left, right, .. } => {
+            first_column(left).or_else(|| first_column(right))
+        }
+        sql::Expr::Function(function) => function.args.iter().find_map(|arg| match arg {
+            sql::FunctionArg::Unnamed(sql::FunctionArgExpr::Expr(expr))
+            | sql::FunctionArg::Named {
+                arg: sql::FunctionArgExpr::Expr(expr),
+                ..
+            } => first_column(expr),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Whether `expr` aggregates.
+fn is_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Agg { .. } => true,
+        Expr::Column(_) | Expr::Literal(_) => false,
+        Expr::Binary { left, right, .. } => is_aggregate(left) || is_aggregate(right),
+        Expr::Between { expr, low, high } => {
+            is_aggregate(expr) || is_aggregate(low) || is_aggregate(high)
+        }
+        Expr::Not(expr)
+        | Expr::CumSum(expr)
+        | Expr::Alias { expr, .. }
+        | Expr::IsIn { expr, .. }
+        | Expr::IsNull { expr, .. }
+        | Expr::Str { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::Rank { expr, .. }
+        | Expr::Over { expr, .. } => is_aggregate(expr),
+    }
+}
+
+/// The name of the column `expr` produces, as Polars names it: its alias
+/// or its first column.
+fn output_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Alias { name, .. } => Some(name.clone()),
+        Expr::Column(name) => Some(name.clone()),
+        Expr::Literal(_) => None,
+        Expr::Binary { left, .. } => output_name(left),
+        Expr::Not(expr)
+        | Expr::CumSum(expr)
+        | Expr::Agg { expr, .. }
+        | Expr::IsIn { expr, .. }
+        | Expr::IsNull { expr, .. }
+        | Expr::Str { expr, .. }
+        | Expr::Between { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::Rank { expr, .. }
+        | Expr::Over { expr, .. } => output_name(expr),
+    }
+}
+
+/// `having` with each aggregate replaced by the column of the same
+/// aggregate in `aggs`, as it filters the aggregated frame.
+fn aggregated(having: Expr, aggs: &[Expr]) -> Result<Expr, String> {
+    if let Expr::Agg { .. } = having {
+        return aggs
+            .iter()
+            .find_map(|agg| match agg {
+                Expr::Alias { expr, name } if **expr == having => Some(col(name.clone())),
+                agg if *agg == having => output_name(agg).map(col),
+                _ => None,
+            })
+            .ok_or_else(|| format!("{} in HAVING must also be selected", having));
+    }
+    Ok(match having {
+        Expr::Binary { left, op, right } => Expr::Binary {
+            left: Box::new(aggregated(*left, aggs)?),
+            op,
+            right: Box::new(aggregated(*right, aggs)?),
+        },
+        Expr::Not(expr) => Expr::Not(Box::new(aggregated(*expr, aggs)?)),
+        other if is_aggregate(&other) => {
+            return Err(format!("{} in HAVING must be selected as it is", other))
+        }
+        other => other,
+    })
+}
+
+/// `expr` as an expression of the query language, with `COUNT(*)` counting
+/// `count_column`.
+fn lower(expr: &sql::Expr, count_column: Option<&str>) -> Result<Expr, String> {
+    let lower_box = |expr: &sql::Expr| lower(expr, count_column).map(Box::new);
+    Ok(match expr {
+        sql::Expr::Identifier(_) | sql::Expr::CompoundIdentifier(_) => col(column(expr)?),
+        sql::Expr::Nested(expr) => lower(expr, count_column)?,
+        sql::Expr::Value(value) => Expr::Literal(literal(value)?),
+        sql::Expr::TypedString { value, .. } => Expr::Literal(
+            Literal::parse_temporal(value)
+                .ok_or_else(|| format!("expected a date or timestamp, found {:?}", value))?,
+        ),
+        sql::Expr::BinaryOp { left, op, right } => {
+            let op = match op {
+                sql::BinaryOperator::Eq => BinaryOp::Eq,
+                sql::BinaryOperator::NotEq => BinaryOp::NotEq,
+                sql::BinaryOperator::Lt => BinaryOp::Lt,
+                sql::BinaryOperator::LtEq => BinaryOp::LtEq,
+                sql::BinaryOperator::Gt => BinaryOp::Gt,
+                sql::BinaryOperator::GtEq => BinaryOp::GtEq,
+                sql::BinaryOperator::And => BinaryOp::And,
+                sql::BinaryOperator::Or => BinaryOp::Or,
+                sql::BinaryOperator::Plus => BinaryOp::Add,
+                sql::BinaryOperator::Minus => BinaryOp::Sub,
+                sql::BinaryOperator::Multiply => BinaryOp::Mul,
+                sql::BinaryOperator::Divide => BinaryOp::Div,
+                other => return Err(format!("unsupported operator {}", other)),
+            };
+            Expr::Binary {
+                left: lower_box(left)?,
+                op,
+                right: lower_box(right)?,
+            }
+        }
+        sql::Expr::UnaryOp { op, expr } => match (op, lower(expr, count_column)?) {
+            (sql::UnaryOperator::Not, expr) => !expr,
+            (sql::UnaryOperator::Plus, expr) => expr,
+            (sql::UnaryOperator::Minus, Expr::Literal(Literal::Int(v))) => {
+                Expr::Literal(Literal::Int(-v))
+            }
+            (sql::UnaryOperator::Minus, Expr::Literal(Literal::Float(v))) => {
+                Expr::Literal(Literal::Float(-v))
+            }
+            (sql::UnaryOperator::Minus, expr) => Expr::Literal(Literal::Int(0)) - expr,
+            (other, _) => return Err(format!("unsupported operator {}", other)),
+        },
+        sql::Expr::IsNull(expr) => lower(expr, count_column)?.is_null(),
+        sql::Expr::IsNotNull(expr) => lower(expr, count_column)?.is_not_null(),
+        sql::Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let values = list
+                .iter()
+                .map(|value| match lower(value, count_column)? {
+                    Expr::Literal(value) if value != Literal::Null => Ok(value),
+                    other => Err(format!("IN takes a list of values, found {}", other)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            negate(lower(expr, count_column)?.is_in(values), *negated)
+        }
+        sql::Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => negate(
+            lower(expr, count_column)?
+                .is_between(lower(low, count_column)?, lower(high, count_column)?),
+            *negated,
+        ),
+        sql::Expr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char: None,
+            ..
+        } => match &**pattern {
+            sql::Expr::Value(sql::Value::SingleQuotedString(pattern)) => {
+                let (op, pattern) = like(pattern);
+                negate(
+                    Expr::Str {
+                        expr: lower_box(expr)?,
+                        op,
+                        pattern,
+                    },
+                    *negated,
+                )
+            }
+            other => return Err(format!("LIKE takes a string pattern, found {}", other)),
+        },
+        sql::Expr::Cast {
+            expr, data_type, ..
+        } => lower(expr, count_column)?.cast(dtype(data_type)?),
+        sql::Expr::TryCast {
+            expr, data_type, ..
+        } => Expr::Cast {
+            expr: lower_box(expr)?,
+            dtype: dtype(data_type)?,
+            strict: false,
+        },
+        sql::Expr::Function(function) => aggregate(function, count_column)?,
+        other => return Err(format!("unsupported expression {}", other)),
+    })
+}
+
+fn negate(expr: Expr, negated: bool) -> Expr {
+    if negated {
+        !expr
+    } else {
+        expr
+    }
+}
+
+fn literal(value: &sql::Value) -> Result<Literal, String> {
+    Ok(match value {
+        sql::Value::Number(n, _) => match n.parse() {
+            Ok(v) => Literal::Int(v),
+            Err(_) => Literal::Float(
+                n.parse()
+                    .map_err(|_| format!("expected a number, found {}", n))?,
+            ),
+        },
+        sql::Value::SingleQuotedString(s) => Literal::Str(s.clone()),
+        sql::Value::Boolean(b) => Literal::Bool(*b),
+        sql::Value::Null => Literal::Null,
+        other => return Err(format!("unsupported value {}", other)),
+    })
+}
+
+/// The string predicate matching the `LIKE` pattern `pattern`.
+fn like(pattern: &str) -> (StrOp, String) {
+    let inner = |s: &str| !s.contains(['%', '_']);
+    if let Some(rest) = pattern.strip_suffix('%') {
+        if let Some(middle) = rest.strip_prefix('%') {
+            if inner(middle) {
+                return (StrOp::ContainsLiteral, middle.to_string());
+            }
+        } else if inner(rest) {
+            return (StrOp::StartsWith, rest.to_string());
+        }
+    } else if let Some(rest) = pattern.strip_prefix('%') {
+        if inner(rest) {
+            return (StrOp::EndsWith, rest.to_string());
+        }
+    }
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c if "\\.+*?()|[]{}^$".contains(c) => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex.push('$');
+    (StrOp::Contains, regex)
+}
+
+fn dtype(data_type: &sql::DataType) -> Result<DType, String> {
+    let name = data_type.to_string().to_uppercase();
+    let base = name.split('(').next().unwrap_or_default().trim();
+    Ok(match base {
+        "INT" | "INTEGER" | "INT4" => DType::Int32,
+        "BIGINT" | "INT8" | "INT64" => DType::Int64,
+        "INT UNSIGNED" | "INTEGER UNSIGNED" => DType::UInt32,
+        "BIGINT UNSIGNED" => DType::UInt64,
+        "REAL" | "FLOAT4" => DType::Float32,
+        "FLOAT" | "DOUBLE" | "DOUBLE PRECISION" | "FLOAT8" | "FLOAT64" | "NUMERIC" | "DECIMAL" => {
+            DType::Float64
+        }
+        "TEXT" | "STRING" | "VARCHAR" | "CHAR" | "CHARACTER VARYING" => DType::Utf8,
+        "BOOLEAN" | "BOOL" => DType::Boolean,
+        "DATE" => DType::Date,
+        "TIMESTAMP" | "DATETIME" => DType::Datetime,
+        _ => return Err(format!("unsupported type {}", data_type)),
+    })
+}
+
+/// An aggregate function call, the only functions SQL queries may call.
+fn aggregate(function: &sql::Function, count_column: Option<&str>) -> Result<Expr, String> {
+    let name = function.name.to_string().to_lowercase();
+    if function.over.is_some() {
+        return Err(unsupported("a window function"));
+    }
+    let arg = match function.args.as_slice() {
+        [sql::FunctionArg::Unnamed(sql::FunctionArgExpr::Expr(arg))] => Some(arg),
+        [sql::FunctionArg::Unnamed(sql::FunctionArgExpr::Wildcard)] if name == "count" => None,
+        _ => return Err(format!("{} takes one column", name.to_uppercase())),
+    };
+    let func = match name.as_str() {
+        "count" if function.distinct => AggFunc::NUnique,
+        "sum" => AggFunc::Sum,
+        "avg" | "mean" => AggFunc::Mean,
+        "median" => AggFunc::Median,
+        "min" => AggFunc::Min,
+        "max" => AggFunc::Max,
+        "count" => AggFunc::Count,
+        "first" => AggFunc::First,
+        "last" => AggFunc::Last,
+        "stddev" | "stddev_samp" | "std" => AggFunc::Std(1),
+        "stddev_pop" => AggFunc::Std(0),
+        "variance" | "var_samp" | "var" => AggFunc::Var(1),
+        "var_pop" => AggFunc::Var(0),
+        _ => return Err(format!("unknown function {}", name.to_uppercase())),
+    };
+    if function.distinct && func != AggFunc::NUnique {
+        return Err(format!(
+            "{}(DISTINCT ...) is not supported",
+            name.to_uppercase()
+        ));
+    }
+    Ok(match arg {
+        Some(arg) => Expr::Agg {
+            func,
+            expr: Box::new(lower(arg, count_column)?),
+        },
+        None => {
+            let column =
+                count_column.ok_or("COUNT(*) requires a column to count; use COUNT(column)")?;
+            col(column).count().alias("count")
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ExecContext;
+
+    #[test]
+    fn selects_lower_to_dsl_steps() {
+        let query = to_query(
+            "SELECT city, SUM(balance) AS total, COUNT(*) FROM people \
+             WHERE age > 30 AND name LIKE 'A%' GROUP BY city HAVING SUM(balance) > 100 \
+             ORDER BY total LIMIT 10",
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            "df = pl.read_table(\"people\")\n\
+             df = df.filter((pl.col(\"age\") > 30) & pl.col(\"name\").str.starts_with(\"A\"))\n\
+             df = df.groupby([\"city\"])\n\
+             df = df.agg([pl.col(\"balance\").sum().alias(\"total\"), pl.col(\"city\").count().alias(\"count\")])\n\
+             df = df.filter(pl.col(\"total\") > 100)\n\
+             df = df.sort(\"total\")\n\
+             df = df.head(10)\n"
+        );
+
+        let query = to_query(
+            "SELECT o.id, amount * 2 AS doubled FROM \"data/orders.parquet\" AS o \
+             LEFT JOIN users u ON o.id = u.id WHERE u.name IS NOT NULL OFFSET 5",
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            "df = pl.read_parquet(\"data/orders.parquet\")\n\
+             df = df.join(pl.read_table(\"users\"), on=\"id\", how=\"left\")\n\
+             df = df.filter(pl.col(\"name\").is_not_null())\n\
+             df = df.select([pl.col(\"id\"), (pl.col(\"amount\") * 2).alias(\"doubled\")])\n\
+             df = df.slice(5)\n"
+        );
+    }
+
+    #[test]
+    fn unsupported_sql_is_an_error() {
+        for (query, error) in [
+            ("DELETE FROM people", "only SELECT"),
+            ("SELECT * FROM a, b", "one table"),
+            (
+                "SELECT city, age FROM people GROUP BY city",
+                "age must be a GROUP BY column",
+            ),
+            ("SELECT * FROM people ORDER BY age DESC", "ascending"),
+            ("SELECT LOWER(name) FROM people", "unknown function LOWER"),
+            ("SELECT 1", "FROM"),
+        ] {
+            let message = to_plan(query).unwrap_err();
+            assert!(message.contains(error), "{}: {}", query, message);
+        }
+    }
+
+    #[test]
+    fn sql_runs_like_the_dsl() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), "city,x\nNY,1\nLA,2\nNY,3\nSF,4\n").unwrap();
+        let ctx = ExecContext {
+            data_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let df = to_plan(
+            "SELECT city, SUM(x) AS total FROM \"a.csv\" WHERE x IN (1, 3, 4) \
+             GROUP BY city ORDER BY city",
+        )
+        .unwrap()
+        .execute_with(&ctx)
+        .unwrap();
+        let cities: Vec<_> = df
+            .column("city")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(cities, [Some("NY"), Some("SF")]);
+        let totals: Vec<_> = df
+            .column("total")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(totals, [Some(4), Some(4)]);
+    }
+}
@@ -0,0 +1,644 @@
+//! Job state shared between server replicas.
+//!
+//! By default state lives in process memory. With the `redis` feature and a
+//! configured `state.redis_url`, job ids, job records (including where each
+//! result is stored) and rate-limit counters are kept in Redis so several
+//! stateless replicas behind a load balancer present one consistent API.
+//! A single replica can instead set `state.journal_path` to keep job records
+//! in a local file, so `GET /jobs/{id}` still answers after a restart.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// What is known about a job outside the replica running it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: u64,
+    pub user: String,
+    #[serde(default)]
+    pub query: String,
+    pub status: String,
+    /// Unix milliseconds at which the job was submitted.
+    pub submitted_at_ms: Option<u64>,
+    /// Unix milliseconds at which the job started running.
+    pub started_at_ms: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub cost: usize,
+    /// Where the result is stored when it was written to disk.
+    pub output_location: Option<String>,
+    /// Part files of a result split into several files, in row order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_parts: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl JobRecord {
+    /// Unix milliseconds of the last change recorded for the job.
+    fn updated_at_ms(&self) -> Option<u64> {
+        let finished = self
+            .started_at_ms
+            .map(|started| started + self.duration_ms.unwrap_or(0));
+        finished.max(self.submitted_at_ms)
+    }
+}
+
+/// Storage for state that must be consistent across replicas.
+#[async_trait]
+pub trait StateBackend: Send + Sync {
+    /// Allocate a job id unique across all replicas.
+    async fn next_job_id(&self) -> Result<u64, String>;
+    /// Insert or replace the record for `record.id`.
+    async fn put_job(&self, record: &JobRecord) -> Result<(), String>;
+    async fn get_job(&self, id: u64) -> Result<Option<JobRecord>, String>;
+    /// Every job record held.
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>, String>;
+    /// Increment the counter `key` and return its value within the current
+    /// `window`; the counter resets once the window has elapsed.
+    async fn incr_counter(&self, key: &str, window: Duration) -> Result<u64, String>;
+    /// Make the records put so far durable, before the process exits.
+    async fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Configuration of the shared state backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateConfig {
+    /// Redis connection URL, e.g. `redis://127.0.0.1/`. In-memory when unset.
+    pub redis_url: Option<String>,
+    /// File job records are appended to and reloaded from on startup.
+    /// Ignored when `redis_url` is set.
+    pub journal_path: Option<PathBuf>,
+    /// Seconds a job record is kept after it was last updated. Kept
+    /// indefinitely when unset.
+    pub job_ttl_secs: Option<u64>,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            redis_url: None,
+            journal_path: None,
+            job_ttl_secs: Some(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Build the backend described by `config`.
+pub fn backend(config: &StateConfig) -> Result<Arc<dyn StateBackend>, String> {
+    let ttl = config.job_ttl_secs.map(Duration::from_secs);
+    match &config.redis_url {
+        None => match &config.journal_path {
+            Some(path) => Ok(Arc::new(JournalState::open(path, ttl)?)),
+            None => Ok(Arc::new(MemoryState::default().with_job_ttl(ttl))),
+        },
+        #[cfg(feature = "redis")]
+        Some(url) => Ok(Arc::new(RedisState::open(url)?.with_job_ttl(ttl))),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => Err("state.redis_url requires building with the `redis` feature".to_string()),
+    }
+}
+
+/// Counters beyond which those whose window has elapsed are forgotten.
+const MAX_COUNTERS: usize = 10_000;
+
+/// Process-local state, suitable for a single replica.
+#[derive(Default)]
+pub struct MemoryState {
+    next_id: AtomicU64,
+    jobs: Mutex<Jobs>,
+    job_ttl: Option<Duration>,
+    /// Start, length and count of each counter's current window.
+    counters: Mutex<HashMap<String, (Instant, Duration, u64)>>,
+}
+
+/// Job records with when each was last updated, oldest update first.
+#[derive(Default)]
+struct Jobs {
+    records: HashMap<u64, (JobRecord, Instant)>,
+    updates: VecDeque<(Instant, u64)>,
+}
+
+impl MemoryState {
+    /// Forget job records `ttl` after their last update.
+    pub fn with_job_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.job_ttl = ttl;
+        self
+    }
+
+    /// Every job record held.
+    fn records(&self) -> Vec<JobRecord> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.records
+            .values()
+            .map(|(record, _)| record.clone())
+            .collect()
+    }
+
+    /// Store `record`, forgetting those not updated within the job TTL.
+    fn insert(&self, record: JobRecord) {
+        let now = Instant::now();
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = record.id;
+        jobs.records.insert(id, (record, now));
+        let Some(ttl) = self.job_ttl else {
+            return;
+        };
+        jobs.updates.push_back((now, id));
+        while let Some(&(updated, id)) = jobs.updates.front() {
+            if now.duration_since(updated) < ttl {
+                break;
+            }
+            jobs.updates.pop_front();
+            // Records updated since stay until their latest update expires.
+            if jobs.records.get(&id).is_some_and(|(_, at)| *at == updated) {
+                jobs.records.remove(&id);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StateBackend for MemoryState {
+    async fn next_job_id(&self) -> Result<u64, String> {
+        Ok(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    async fn put_job(&self, record: &JobRecord) -> Result<(), String> {
+        self.insert(record.clone());
+        Ok(())
+    }
+
+    async fn get_job(&self, id: u64) -> Result<Option<JobRecord>, String> {
+        let jobs = self.jobs.lock().unwrap();
+        Ok(jobs.records.get(&id).map(|(record, _)| record.clone()))
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>, String> {
+        Ok(self.records())
+    }
+
+    async fn incr_counter(&self, key: &str, window: Duration) -> Result<u64, String> {
+        let mut counters = self.counters.lock().unwrap();
+        let now = Instant::now();
+        if counters.len() > MAX_COUNTERS {
+            counters.retain(|_, (start, window, _)| now.duration_since(*start) < *window);
+        }
+        let entry = counters.entry(key.to_string()).or_insert((now, window, 0));
+        if now.duration_since(entry.0) >= entry.1 {
+            *entry = (now, window, 0);
+        }
+        entry.2 += 1;
+        Ok(entry.2)
+    }
+}
+
+/// Error recorded for jobs that were queued or running when the server
+/// stopped.
+pub const INTERRUPTED: &str = "server restarted while the job was queued or running";
+
+/// Lines a journal holds before it may be compacted. Past this it is
+/// rewritten once it holds more than twice as many lines as job records.
+const COMPACT_MIN_LINES: usize = 10_000;
+
+/// Process-local state whose job records are also appended to a journal
+/// file, one JSON record per line, and reloaded by [`JournalState::open`].
+pub struct JournalState {
+    memory: Arc<MemoryState>,
+    journal: Arc<Mutex<Journal>>,
+}
+
+/// An open journal file and the number of lines in it.
+struct Journal {
+    path: PathBuf,
+    file: File,
+    lines: usize,
+}
+
+impl Journal {
+    /// Append `line`, compacting the journal to the records `memory` holds
+    /// once it has grown past [`COMPACT_MIN_LINES`].
+    fn append(&mut self, line: &str, memory: &MemoryState) -> Result<(), String> {
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| e.to_string())?;
+        self.lines += 1;
+        if self.lines >= COMPACT_MIN_LINES {
+            let records = memory.records();
+            if self.lines > 2 * records.len() {
+                let held = records.len();
+                self.file = write_journal(&self.path, records)?;
+                self.lines = held;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replace the journal at `path` with one line per record, in id order, and
+/// open it for appending.
+fn write_journal(path: &Path, mut records: Vec<JobRecord>) -> Result<File, String> {
+    records.sort_by_key(|r| r.id);
+    let mut compacted = String::new();
+    for record in &records {
+        compacted.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+        compacted.push('\n');
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, compacted)
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+impl JournalState {
+    /// Load the journal at `path`, creating it if missing. Jobs left queued
+    /// or running are marked failed, and the file is rewritten with one line
+    /// per job, leaving out jobs last updated more than `job_ttl` ago. It is
+    /// rewritten the same way whenever it grows past [`COMPACT_MIN_LINES`]
+    /// and holds more than twice as many lines as jobs.
+    pub fn open(path: &Path, job_ttl: Option<Duration>) -> Result<Self, String> {
+        let memory = MemoryState::default().with_job_ttl(job_ttl);
+        let mut jobs = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for (n, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<JobRecord>(&line) {
+                        Ok(record) => {
+                            jobs.insert(record.id, record);
+                        }
+                        Err(e) => {
+                            tracing::warn!("skipping line {} of {}: {}", n + 1, path.display(), e)
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+        let next_id = jobs.keys().max().copied().unwrap_or(0);
+        if let Some(ttl) = job_ttl {
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            let cutoff = now_ms.saturating_sub(ttl.as_millis() as u64);
+            jobs.retain(|_, record| record.updated_at_ms().is_none_or(|t| t >= cutoff));
+        }
+        for record in jobs.values_mut() {
+            if record.status == "queued" || record.status == "running" {
+                record.status = "failed".to_string();
+                record.error = Some(INTERRUPTED.to_string());
+            }
+        }
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let mut records: Vec<JobRecord> = jobs.into_values().collect();
+        records.sort_by_key(|r| r.id);
+        let lines = records.len();
+        let file = write_journal(path, records.clone())?;
+
+        memory.next_id.store(next_id, Ordering::SeqCst);
+        for record in records {
+            memory.insert(record);
+        }
+        Ok(JournalState {
+            memory: Arc::new(memory),
+            journal: Arc::new(Mutex::new(Journal {
+                path: path.to_path_buf(),
+                file,
+                lines,
+            })),
+        })
+    }
+}
+
+#[async_trait]
+impl StateBackend for JournalState {
+    async fn next_job_id(&self) -> Result<u64, String> {
+        self.memory.next_job_id().await
+    }
+
+    async fn put_job(&self, record: &JobRecord) -> Result<(), String> {
+        let mut line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        line.push('\n');
+        // Held in memory first, so that a compaction racing this write keeps
+        // the record.
+        self.memory.put_job(record).await?;
+        let memory = self.memory.clone();
+        let journal = self.journal.clone();
+        tokio::task::spawn_blocking(move || journal.lock().unwrap().append(&line, &memory))
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
+    async fn get_job(&self, id: u64) -> Result<Option<JobRecord>, String> {
+        self.memory.get_job(id).await
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>, String> {
+        self.memory.list_jobs().await
+    }
+
+    async fn incr_counter(&self, key: &str, window: Duration) -> Result<u64, String> {
+        self.memory.incr_counter(key, window).await
+    }
+
+    async fn flush(&self) -> Result<(), String> {
+        let journal = self.journal.clone();
+        tokio::task::spawn_blocking(move || journal.lock().unwrap().file.sync_all())
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_state::RedisState;
+
+#[cfg(feature = "redis")]
+mod redis_state {
+    use super::*;
+    use redis::aio::MultiplexedConnection;
+    use redis::AsyncCommands;
+    use tokio::sync::OnceCell;
+
+    const KEY_PREFIX: &str = "rdata";
+
+    /// State stored in Redis, shared by every replica using the same server.
+    pub struct RedisState {
+        client: redis::Client,
+        conn: OnceCell<MultiplexedConnection>,
+        job_ttl: Option<Duration>,
+    }
+
+    impl RedisState {
+        pub fn open(url: &str) -> Result<Self, String> {
+            Ok(RedisState {
+                client: redis::Client::open(url).map_err(|e| e.to_string())?,
+                conn: OnceCell::new(),
+                job_ttl: None,
+            })
+        }
+
+        /// Expire job records `ttl` after their last update.
+        pub fn with_job_ttl(mut self, ttl: Option<Duration>) -> Self {
+            self.job_ttl = ttl;
+            self
+        }
+
+        async fn conn(&self) -> Result<MultiplexedConnection, String> {
+            self.conn
+                .get_or_try_init(|| self.client.get_multiplexed_tokio_connection())
+                .await
+                .cloned()
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    #[async_trait]
+    impl StateBackend for RedisState {
+        async fn next_job_id(&self) -> Result<u64, String> {
+            let mut conn = self.conn().await?;
+            conn.incr(format!("{}:job_id", KEY_PREFIX), 1)
+                .await
+                .map_err(|e| e.to_string())
+        }
+
+        async fn put_job(&self, record: &JobRecord) -> Result<(), String> {
+            let mut conn = self.conn().await?;
+            let value = serde_json::to_string(record).map_err(|e| e.to_string())?;
+            let key = format!("{}:job:{}", KEY_PREFIX, record.id);
+            let stored: redis::RedisResult<()> = match self.job_ttl {
+                Some(ttl) => conn.set_ex(key, value, ttl.as_secs().max(1) as usize).await,
+                None => conn.set(key, value).await,
+            };
+            stored.map_err(|e| e.to_string())
+        }
+
+        async fn get_job(&self, id: u64) -> Result<Option<JobRecord>, String> {
+            let mut conn = self.conn().await?;
+            let value: Option<String> = conn
+                .get(format!("{}:job:{}", KEY_PREFIX, id))
+                .await
+                .map_err(|e| e.to_string())?;
+            value
+                .map(|v| serde_json::from_str(&v).map_err(|e| e.to_string()))
+                .transpose()
+        }
+
+        async fn list_jobs(&self) -> Result<Vec<JobRecord>, String> {
+            let mut conn = self.conn().await?;
+            let keys = {
+                let mut iter: redis::AsyncIter<String> = conn
+                    .scan_match(format!("{}:job:*", KEY_PREFIX))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let mut keys = Vec::new();
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                keys
+            };
+            let mut records = Vec::with_capacity(keys.len());
+            for chunk in keys.chunks(256) {
+                // Records that expired since the scan come back as nil.
+                let values: Vec<Option<String>> = redis::cmd("MGET")
+                    .arg(chunk)
+                    .query_async(&mut conn)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                for value in values.into_iter().flatten() {
+                    records.push(serde_json::from_str(&value).map_err(|e| e.to_string())?);
+                }
+            }
+            Ok(records)
+        }
+
+        async fn incr_counter(&self, key: &str, window: Duration) -> Result<u64, String> {
+            let mut conn = self.conn().await?;
+            let key = format!("{}:counter:{}", KEY_PREFIX, key);
+            // PEXPIRE NX only sets the expiry when the window starts.
+            let (count,): (u64,) = redis::pipe()
+                .atomic()
+                .incr(&key, 1)
+                .cmd("PEXPIRE")
+                .arg(&key)
+                .arg(window.as_millis().max(1) as u64)
+                .arg("NX")
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(count)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_state_round_trip() {
+        let state = MemoryState::default();
+        assert_eq!(state.next_job_id().await.unwrap(), 1);
+        assert_eq!(state.next_job_id().await.unwrap(), 2);
+        let record = JobRecord {
+            id: 2,
+            user: "alice".into(),
+            query: "df = df".into(),
+            status: "completed".into(),
+            submitted_at_ms: Some(1),
+            started_at_ms: Some(2),
+            duration_ms: Some(5),
+            cost: 10,
+            output_location: None,
+            output_parts: Vec::new(),
+            error: None,
+        };
+        state.put_job(&record).await.unwrap();
+        assert_eq!(state.get_job(2).await.unwrap(), Some(record));
+        assert_eq!(state.get_job(3).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn journal_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("jobs.jsonl");
+        let record = |id, status: &str| JobRecord {
+            id,
+            user: "alice".into(),
+            query: "df = df.head(1)".into(),
+            status: status.into(),
+            submitted_at_ms: Some(1),
+            started_at_ms: None,
+            duration_ms: None,
+            cost: 1,
+            output_location: None,
+            output_parts: Vec::new(),
+            error: None,
+        };
+
+        let state = JournalState::open(&path, None).unwrap();
+        assert_eq!(state.next_job_id().await.unwrap(), 1);
+        assert_eq!(state.next_job_id().await.unwrap(), 2);
+        state.put_job(&record(1, "queued")).await.unwrap();
+        let done = JobRecord {
+            output_location: Some("/data/out/1.parquet".into()),
+            duration_ms: Some(7),
+            ..record(1, "completed")
+        };
+        state.put_job(&done).await.unwrap();
+        state.put_job(&record(2, "running")).await.unwrap();
+        drop(state);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"id\": 3, \"us").unwrap();
+
+        let state = JournalState::open(&path, None).unwrap();
+        assert_eq!(state.get_job(1).await.unwrap(), Some(done.clone()));
+        let mut listed = state.list_jobs().await.unwrap();
+        listed.sort_by_key(|record| record.id);
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0], done);
+        let interrupted = state.get_job(2).await.unwrap().unwrap();
+        assert_eq!(interrupted.status, "failed");
+        assert_eq!(interrupted.error.as_deref(), Some(INTERRUPTED));
+        assert_eq!(state.next_job_id().await.unwrap(), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn journal_is_compacted_as_it_grows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+        let state = JournalState::open(&path, None).unwrap();
+        let mut record = JobRecord {
+            id: 1,
+            user: "alice".into(),
+            query: "df = df.head(1)".into(),
+            status: "running".into(),
+            submitted_at_ms: Some(1),
+            started_at_ms: None,
+            duration_ms: None,
+            cost: 1,
+            output_location: None,
+            output_parts: Vec::new(),
+            error: None,
+        };
+        for n in 0..COMPACT_MIN_LINES {
+            record.cost = n;
+            state.put_job(&record).await.unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        record.status = "completed".into();
+        state.put_job(&record).await.unwrap();
+        drop(state);
+
+        let state = JournalState::open(&path, None).unwrap();
+        assert_eq!(state.get_job(1).await.unwrap(), Some(record));
+    }
+
+    #[tokio::test]
+    async fn job_records_expire_after_their_ttl() {
+        let ttl = Duration::from_millis(20);
+        let state = MemoryState::default().with_job_ttl(Some(ttl));
+        let record = |id| JobRecord {
+            id,
+            user: "alice".into(),
+            query: String::new(),
+            status: "completed".into(),
+            submitted_at_ms: Some(1),
+            started_at_ms: None,
+            duration_ms: None,
+            cost: 1,
+            output_location: None,
+            output_parts: Vec::new(),
+            error: None,
+        };
+        state.put_job(&record(1)).await.unwrap();
+        state.put_job(&record(2)).await.unwrap();
+        tokio::time::sleep(ttl).await;
+        state.put_job(&record(2)).await.unwrap();
+        state.put_job(&record(3)).await.unwrap();
+        assert_eq!(state.get_job(1).await.unwrap(), None);
+        assert!(state.get_job(2).await.unwrap().is_some());
+        assert!(state.get_job(3).await.unwrap().is_some());
+
+        // Journaled records too old to keep are dropped when it is reopened.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+        let journal = JournalState::open(&path, None).unwrap();
+        journal.put_job(&record(1)).await.unwrap();
+        drop(journal);
+        let journal = JournalState::open(&path, Some(Duration::from_secs(60))).unwrap();
+        assert_eq!(journal.get_job(1).await.unwrap(), None);
+        assert_eq!(journal.next_job_id().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn memory_counter_resets_after_window() {
+        let state = MemoryState::default();
+        let window = Duration::from_millis(20);
+        assert_eq!(state.incr_counter("k", window).await.unwrap(), 1);
+        assert_eq!(state.incr_counter("k", window).await.unwrap(), 2);
+        tokio::time::sleep(window).await;
+        assert_eq!(state.incr_counter("k", window).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,208 @@
+//! Per-column statistics collected for catalog datasets.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::catalog::{self, Catalog};
+use crate::cron::Schedule;
+
+/// Statistics collection settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatsConfig {
+    /// Collect statistics whenever registration adds a version.
+    pub on_register: bool,
+    /// Cron expression on which statistics missing or older than the current
+    /// version are collected.
+    pub schedule: Option<String>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            on_register: true,
+            schedule: None,
+        }
+    }
+}
+
+/// Statistics of one column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub name: String,
+    pub dtype: String,
+    /// Percentage of rows that are null.
+    pub null_pct: f64,
+    /// Number of distinct values, including null. Not computed for nested
+    /// types.
+    pub distinct: Option<u64>,
+    /// Smallest and largest non-null values, for orderable types.
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+/// Statistics of a dataset version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatasetStats {
+    pub version: u64,
+    /// Unix timestamp in seconds.
+    pub computed_at: u64,
+    pub rows: u64,
+    /// Total size of the version's files.
+    pub bytes: u64,
+    pub columns: Vec<ColumnStats>,
+}
+
+fn orderable(dtype: &DataType) -> bool {
+    dtype.is_numeric()
+        || matches!(
+            dtype,
+            DataType::Utf8
+                | DataType::Boolean
+                | DataType::Date
+                | DataType::Datetime(_, _)
+                | DataType::Time
+        )
+}
+
+fn nested(dtype: &DataType) -> bool {
+    matches!(dtype, DataType::List(_) | DataType::Struct(_))
+}
+
+fn first_u64(df: &DataFrame, name: &str) -> PolarsResult<u64> {
+    let s = df.column(name)?.cast(&DataType::UInt64)?;
+    Ok(s.u64()?.get(0).unwrap_or(0))
+}
+
+fn first_str(df: &DataFrame, name: &str) -> PolarsResult<Option<String>> {
+    let s = df.column(name)?.cast(&DataType::Utf8)?;
+    Ok(s.utf8()?.get(0).map(str::to_string))
+}
+
+/// Compute statistics of `lf`, a version of `bytes` bytes, in one pass.
+pub fn compute(
+    lf: LazyFrame,
+    bytes: u64,
+    version: u64,
+    computed_at: u64,
+) -> PolarsResult<DatasetStats> {
+    let schema = lf.schema()?;
+    let mut exprs = vec![count().alias("rows")];
+    for (i, (name, dtype)) in schema.iter().enumerate() {
+        let name = name.as_str();
+        exprs.push(col(name).null_count().alias(&format!("nulls{}", i)));
+        if !nested(dtype) {
+            exprs.push(col(name).n_unique().alias(&format!("distinct{}", i)));
+        }
+        if orderable(dtype) {
+            exprs.push(col(name).min().alias(&format!("min{}", i)));
+            exprs.push(col(name).max().alias(&format!("max{}", i)));
+        }
+    }
+    let df = lf.select(exprs).collect()?;
+    let rows = first_u64(&df, "rows")?;
+
+    let mut columns = Vec::with_capacity(schema.len());
+    for (i, (name, dtype)) in schema.iter().enumerate() {
+        let nulls = first_u64(&df, &format!("nulls{}", i))?;
+        let (min, max) = if orderable(dtype) {
+            (
+                first_str(&df, &format!("min{}", i))?,
+                first_str(&df, &format!("max{}", i))?,
+            )
+        } else {
+            (None, None)
+        };
+        columns.push(ColumnStats {
+            name: name.to_string(),
+            dtype: dtype.to_string(),
+            null_pct: if rows == 0 {
+                0.0
+            } else {
+                nulls as f64 * 100.0 / rows as f64
+            },
+            distinct: if nested(dtype) {
+                None
+            } else {
+                Some(first_u64(&df, &format!("distinct{}", i))?)
+            },
+            min,
+            max,
+        });
+    }
+    Ok(DatasetStats {
+        version,
+        computed_at,
+        rows,
+        bytes,
+        columns,
+    })
+}
+
+/// Collect statistics for every dataset whose statistics are missing or
+/// older than its current version.
+pub fn collect_stale(catalog: &Catalog) -> Vec<Result<DatasetStats, String>> {
+    catalog
+        .list()
+        .into_iter()
+        .filter(|d| d.stats.as_ref().is_none_or(|s| s.version != d.version))
+        .map(|d| catalog.collect_stats(&d.name))
+        .collect()
+}
+
+/// Collect stale statistics on `config.schedule`, if set.
+pub fn spawn_schedule(catalog: Arc<Catalog>, config: StatsConfig) {
+    let Some(expr) = config.schedule else {
+        return;
+    };
+    let schedule = match Schedule::parse(&expr) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("invalid statistics schedule {}: {}", expr, e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        loop {
+            let now = catalog::now_secs();
+            let Some(next) = schedule.next_after_secs(now) else {
+                return;
+            };
+            tokio::time::sleep(Duration::from_secs(next - now)).await;
+            let catalog = catalog.clone();
+            let results = tokio::task::spawn_blocking(move || collect_stale(&catalog))
+                .await
+                .unwrap_or_default();
+            for e in results.into_iter().filter_map(Result::err) {
+                tracing::error!("scheduled statistics collection failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_statistics() {
+        let df = df![
+            "city" => [Some("NY"), Some("LA"), None, Some("NY")],
+            "amount" => [Some(3), Some(1), Some(2), None],
+        ]
+        .unwrap();
+        let stats = compute(df.lazy(), 100, 1, 0).unwrap();
+        assert_eq!(stats.rows, 4);
+        assert_eq!(stats.bytes, 100);
+        let city = &stats.columns[0];
+        assert_eq!(city.null_pct, 25.0);
+        assert_eq!(city.distinct, Some(3));
+        assert_eq!(city.min.as_deref(), Some("LA"));
+        assert_eq!(city.max.as_deref(), Some("NY"));
+        let amount = &stats.columns[1];
+        assert_eq!(amount.min.as_deref(), Some("1"));
+        assert_eq!(amount.max.as_deref(), Some("3"));
+    }
+}
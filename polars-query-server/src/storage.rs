@@ -0,0 +1,293 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A result file held by the store.
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub path: String,
+    pub size: u64,
+    /// `true` when an identical file already existed and was reused.
+    pub reused: bool,
+}
+
+/// Content-addressed storage for result files.
+///
+/// Files are named after the SHA-256 of their bytes, so jobs producing
+/// identical results share a single file on disk. A reference count per file
+/// ensures a shared file is only removed once the last job releases it.
+pub struct ResultStore {
+    dir: PathBuf,
+    scratch_dir: Option<PathBuf>,
+    min_free_bytes: u64,
+    refs: Mutex<HashMap<String, usize>>,
+    /// Suffix keeping concurrent writes of the same content apart.
+    next_tmp: AtomicU64,
+}
+
+/// Error message prefix used when a write is refused for lack of disk space.
+pub const INSUFFICIENT_STORAGE: &str = "insufficient storage";
+
+impl Default for ResultStore {
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+impl ResultStore {
+    /// Create a store writing files into `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ResultStore {
+            dir: dir.into(),
+            scratch_dir: None,
+            min_free_bytes: 0,
+            refs: Mutex::new(HashMap::new()),
+            next_tmp: AtomicU64::new(0),
+        }
+    }
+
+    /// Write temporary files into `dir` instead of next to the final output.
+    pub fn with_scratch_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.scratch_dir = Some(dir.into());
+        self
+    }
+
+    /// Refuse writes that would leave less than `bytes` free on the target disk.
+    pub fn with_min_free_bytes(mut self, bytes: u64) -> Self {
+        self.min_free_bytes = bytes;
+        self
+    }
+
+    /// Directory temporary files are staged in.
+    pub fn scratch_dir(&self) -> &Path {
+        self.scratch_dir.as_deref().unwrap_or(&self.dir)
+    }
+
+    /// Fail with an "insufficient storage" error unless `needed` bytes (plus
+    /// the configured reserve) are available in `dir`.
+    pub fn ensure_free_space(&self, dir: &Path, needed: u64) -> io::Result<()> {
+        let available = fs2::available_space(dir)?;
+        let required = needed.saturating_add(self.min_free_bytes);
+        if available < required {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "{}: {} needs {} bytes but only {} are available",
+                    INSUFFICIENT_STORAGE,
+                    dir.display(),
+                    required,
+                    available
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Store `bytes` with the given extension, reusing an existing file with
+    /// the same content if there is one.
+    pub fn put(&self, bytes: &[u8], ext: &str) -> io::Result<StoredFile> {
+        let name = format!("output_{}.{}", content_hash(bytes), ext);
+        let path = self.dir.join(name);
+        let path_str = path.to_string_lossy().to_string();
+
+        // Take the reference before touching the disk, so a concurrent
+        // release of the same file cannot delete it under this write.
+        *self
+            .refs
+            .lock()
+            .unwrap()
+            .entry(path_str.clone())
+            .or_insert(0) += 1;
+        let reused = path.exists();
+        let written = if reused {
+            // Retention counts from a file's latest use, not its first.
+            let _ = fs::File::options()
+                .append(true)
+                .open(&path)
+                .and_then(|f| f.set_modified(SystemTime::now()));
+            Ok(())
+        } else {
+            self.write(&path, bytes)
+        };
+        if let Err(e) = written {
+            self.forget(&path_str);
+            return Err(e);
+        }
+
+        Ok(StoredFile {
+            path: path_str,
+            size: bytes.len() as u64,
+            reused,
+        })
+    }
+
+    /// Write `bytes` to `path` through a temporary file in the scratch
+    /// directory.
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let scratch = self.scratch_dir();
+        fs::create_dir_all(scratch)?;
+        self.ensure_free_space(&self.dir, bytes.len() as u64)?;
+        if scratch != self.dir {
+            self.ensure_free_space(scratch, bytes.len() as u64)?;
+        }
+        // Write to a temporary name first so a concurrent reader never
+        // observes a partially written file under the final name.
+        let n = self.next_tmp.fetch_add(1, Ordering::Relaxed);
+        let tmp = scratch.join(format!("{}.{}.tmp", content_hash(bytes), n));
+        if let Err(e) = fs::write(&tmp, bytes) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        if fs::rename(&tmp, path).is_err() {
+            // The scratch directory may live on another filesystem.
+            let copied = fs::copy(&tmp, path);
+            let _ = fs::remove_file(&tmp);
+            if let Err(e) = copied {
+                let _ = fs::remove_file(path);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop a reference taken by a write that failed.
+    fn forget(&self, path: &str) {
+        let mut refs = self.refs.lock().unwrap();
+        if let Some(count) = refs.get_mut(path) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                refs.remove(path);
+            }
+        }
+    }
+
+    /// Count one reference for each of `paths` that still exists, as
+    /// recorded by jobs persisted before a restart.
+    pub fn restore_refs<I>(&self, paths: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let existing: Vec<String> = paths
+            .into_iter()
+            .filter(|path| Path::new(path).exists())
+            .collect();
+        let mut refs = self.refs.lock().unwrap();
+        for path in existing {
+            *refs.entry(path).or_insert(0) += 1;
+        }
+    }
+
+    /// Drop one reference to `path`, deleting the file when no job refers to
+    /// it anymore. Returns `true` if the file was removed.
+    pub fn release(&self, path: &str) -> io::Result<bool> {
+        let mut refs = self.refs.lock().unwrap();
+        let remaining = match refs.get_mut(path) {
+            Some(count) => {
+                *count = count.saturating_sub(1);
+                *count
+            }
+            None => 0,
+        };
+        if remaining > 0 {
+            return Ok(false);
+        }
+        refs.remove(path);
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete `path` however many jobs refer to it, as when its retention
+    /// period has passed. Returns `true` if the file was removed.
+    pub fn remove(&self, path: &str) -> io::Result<bool> {
+        let mut refs = self.refs.lock().unwrap();
+        refs.remove(path);
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Number of jobs currently referencing `path`.
+    pub fn ref_count(&self, path: &str) -> usize {
+        self.refs.lock().unwrap().get(path).copied().unwrap_or(0)
+    }
+}
+
+/// Hex encoded SHA-256 digest of `bytes`.
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn identical_content_shares_file() {
+        let dir = tempdir().unwrap();
+        let store = ResultStore::new(dir.path());
+        let a = store.put(b"same bytes", "feather").unwrap();
+        let b = store.put(b"same bytes", "feather").unwrap();
+        assert_eq!(a.path, b.path);
+        assert!(!a.reused);
+        assert!(b.reused);
+        assert_eq!(store.ref_count(&a.path), 2);
+
+        assert!(!store.release(&a.path).unwrap());
+        assert!(Path::new(&a.path).exists());
+        assert!(store.release(&b.path).unwrap());
+        assert!(!Path::new(&a.path).exists());
+
+        let c = store.put(b"same bytes", "feather").unwrap();
+        store.put(b"same bytes", "feather").unwrap();
+        assert!(store.remove(&c.path).unwrap());
+        assert_eq!(store.ref_count(&c.path), 0);
+        assert!(!Path::new(&c.path).exists());
+    }
+
+    #[test]
+    fn restored_refs_keep_shared_files() {
+        let dir = tempdir().unwrap();
+        let path = ResultStore::new(dir.path())
+            .put(b"same bytes", "feather")
+            .unwrap()
+            .path;
+
+        let store = ResultStore::new(dir.path());
+        let missing = dir.path().join("output_gone.feather");
+        store.restore_refs([
+            path.clone(),
+            path.clone(),
+            missing.to_string_lossy().to_string(),
+        ]);
+        assert_eq!(store.ref_count(&path), 2);
+        assert_eq!(store.ref_count(&missing.to_string_lossy()), 0);
+        assert!(!store.release(&path).unwrap());
+        assert!(Path::new(&path).exists());
+        assert!(store.release(&path).unwrap());
+    }
+
+    #[test]
+    fn insufficient_storage_is_reported() {
+        let dir = tempdir().unwrap();
+        let store = ResultStore::new(dir.path()).with_min_free_bytes(u64::MAX);
+        let err = store.put(b"bytes", "feather").unwrap_err();
+        assert!(err.to_string().starts_with(INSUFFICIENT_STORAGE));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,330 @@
+//! Streaming ingestion: rows appended to a dataset are buffered in memory and
+//! flushed as new parquet files in micro-batches, so event data becomes
+//! queryable within seconds without writing a file per request.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::catalog::{self, Catalog};
+use crate::ingest::{self, IngestOptions, IngestReport};
+
+/// Prefix of the error returned when appends are refused until buffered rows
+/// have been flushed.
+pub const BUFFER_FULL: &str = "ingestion buffer full";
+
+/// Micro-batching settings of `POST /datasets/:name/append`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamingConfig {
+    /// Milliseconds appended rows wait at most before they are flushed.
+    pub flush_interval_ms: u64,
+    /// Rows buffered for one dataset before the appending request flushes
+    /// them itself.
+    pub max_batch_rows: usize,
+    /// Bytes buffered across all datasets before appends are refused.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        StreamingConfig {
+            flush_interval_ms: 2000,
+            max_batch_rows: 100_000,
+            max_buffered_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+impl StreamingConfig {
+    /// Problems with the configured limits.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.flush_interval_ms == 0 {
+            errors.push("data.streaming.flush_interval_ms must be at least 1".to_string());
+        }
+        if self.max_batch_rows == 0 {
+            errors.push("data.streaming.max_batch_rows must be at least 1".to_string());
+        }
+        errors
+    }
+}
+
+/// Outcome of appending rows to a dataset.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AppendReport {
+    pub dataset: String,
+    /// Rows accepted by the request.
+    pub rows: usize,
+    /// Rows of the dataset waiting to be flushed.
+    pub buffered_rows: usize,
+    /// Files written while the request was handled.
+    pub flushed: Vec<IngestReport>,
+}
+
+impl AppendReport {
+    /// Add the outcome of a later batch of the same request.
+    pub fn absorb(&mut self, later: AppendReport) {
+        self.rows += later.rows;
+        self.buffered_rows = later.buffered_rows;
+        self.flushed.extend(later.flushed);
+    }
+}
+
+/// Rows appended to one dataset and not yet flushed.
+struct Buffer {
+    frames: Vec<DataFrame>,
+    rows: usize,
+    bytes: usize,
+    /// User the dataset is created for if it does not exist yet.
+    owner: String,
+    since: Instant,
+}
+
+/// Cast the columns of `df` to `schema`, the schema of rows already buffered.
+fn conform(df: DataFrame, schema: &Schema) -> Result<DataFrame, String> {
+    if df.schema() == *schema {
+        return Ok(df);
+    }
+    let names: Vec<&str> = schema.iter_names().map(|n| n.as_str()).collect();
+    if df.width() != names.len() {
+        return Err(format!(
+            "appended rows have columns {:?}, expected {:?}",
+            df.get_column_names(),
+            names
+        ));
+    }
+    let columns = schema
+        .iter()
+        .map(|(name, dtype)| {
+            df.column(name)
+                .map_err(|_| format!("appended rows have no column {}", name))?
+                .strict_cast(dtype)
+                .map_err(|e| format!("cannot convert column {} to {}: {}", name, dtype, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    DataFrame::new(columns).map_err(|e| e.to_string())
+}
+
+/// Buffers of appended rows, flushed into datasets under the ingestion root.
+pub struct Appender {
+    config: StreamingConfig,
+    catalog: Arc<Catalog>,
+    root: PathBuf,
+    buffers: Mutex<HashMap<String, Buffer>>,
+}
+
+impl Appender {
+    pub fn new(config: StreamingConfig, catalog: Arc<Catalog>, root: impl Into<PathBuf>) -> Self {
+        Appender {
+            config,
+            catalog,
+            root: root.into(),
+            buffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &StreamingConfig {
+        &self.config
+    }
+
+    /// Buffer `df` for the dataset `name`, created for `owner` if needed.
+    /// The dataset's rows are flushed right away once `max_batch_rows` are
+    /// buffered, and appends are refused with [`BUFFER_FULL`] while
+    /// `max_buffered_bytes` are waiting.
+    pub fn append(&self, name: &str, df: DataFrame, owner: &str) -> Result<AppendReport, String> {
+        if !catalog::valid_name(name) {
+            return Err(format!(
+                "invalid dataset name {}: use letters, digits, '_' and '-'",
+                name
+            ));
+        }
+        let location = self.root.join(name).to_string_lossy().to_string();
+        if let Some(existing) = self.catalog.get(name) {
+            if existing.location != location {
+                return Err(format!(
+                    "dataset {} is registered at {} and not managed by ingestion",
+                    name, existing.location
+                ));
+            }
+        }
+        let mut report = AppendReport {
+            dataset: name.to_string(),
+            rows: df.height(),
+            ..Default::default()
+        };
+        let mut buffers = self.buffers.lock().unwrap();
+        if df.height() > 0 {
+            let size = df.estimated_size();
+            let buffered: usize = buffers.values().map(|b| b.bytes).sum();
+            if buffered > 0 && buffered + size > self.config.max_buffered_bytes {
+                return Err(format!(
+                    "{}: {} bytes are waiting to be flushed",
+                    BUFFER_FULL, buffered
+                ));
+            }
+            let buffer = buffers.entry(name.to_string()).or_insert_with(|| Buffer {
+                frames: Vec::new(),
+                rows: 0,
+                bytes: 0,
+                owner: owner.to_string(),
+                since: Instant::now(),
+            });
+            let df = match buffer.frames.first() {
+                Some(first) => conform(df, &first.schema())?,
+                None => df,
+            };
+            buffer.rows += df.height();
+            buffer.bytes += size;
+            buffer.frames.push(df);
+        }
+        let full = buffers
+            .get(name)
+            .is_some_and(|b| b.rows >= self.config.max_batch_rows);
+        let taken = if full { buffers.remove(name) } else { None };
+        report.buffered_rows = buffers.get(name).map_or(0, |b| b.rows);
+        drop(buffers);
+
+        if let Some(buffer) = taken {
+            report.flushed.push(self.write(name, buffer)?);
+        }
+        Ok(report)
+    }
+
+    /// Write `buffer` as a new file of the dataset `name`.
+    fn write(&self, name: &str, buffer: Buffer) -> Result<IngestReport, String> {
+        let mut frames = buffer.frames.into_iter();
+        let Some(mut df) = frames.next() else {
+            return Err(format!("nothing buffered for {}", name));
+        };
+        for frame in frames {
+            df.vstack_mut(&frame).map_err(|e| e.to_string())?;
+        }
+        df.align_chunks();
+        let options = IngestOptions {
+            dataset: name.to_string(),
+            ..Default::default()
+        };
+        ingest::ingest(&self.catalog, &self.root, df, options, &buffer.owner)
+    }
+
+    /// Flush the buffers waiting longer than the flush interval, or all of
+    /// them when `all` is set, returning the files written.
+    pub fn flush(&self, all: bool) -> Vec<IngestReport> {
+        let interval = Duration::from_millis(self.config.flush_interval_ms);
+        let due: Vec<(String, Buffer)> = {
+            let mut buffers = self.buffers.lock().unwrap();
+            let names: Vec<String> = buffers
+                .iter()
+                .filter(|(_, b)| all || b.since.elapsed() >= interval)
+                .map(|(name, _)| name.clone())
+                .collect();
+            names
+                .into_iter()
+                .filter_map(|name| buffers.remove(&name).map(|b| (name, b)))
+                .collect()
+        };
+        let mut written = Vec::new();
+        for (name, buffer) in due {
+            let rows = buffer.rows;
+            match self.write(&name, buffer) {
+                Ok(report) => written.push(report),
+                Err(e) => {
+                    tracing::error!(dataset = %name, rows, "failed to flush appended rows: {}", e)
+                }
+            }
+        }
+        written
+    }
+}
+
+/// Read an Arrow IPC stream of record batches.
+pub fn read_arrow_stream(bytes: Vec<u8>) -> Result<DataFrame, String> {
+    IpcStreamReader::new(Cursor::new(bytes))
+        .finish()
+        .map_err(|e| format!("failed to read Arrow stream: {}", e))
+}
+
+/// Flush buffered rows once they have waited for the flush interval.
+pub fn spawn_flusher(appender: Arc<Appender>) {
+    tokio::spawn(async move {
+        // Checking twice per interval bounds the wait at 1.5 intervals.
+        let period = Duration::from_millis((appender.config.flush_interval_ms / 2).max(1));
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let appender = appender.clone();
+            let Ok(written) = tokio::task::spawn_blocking(move || appender.flush(false)).await
+            else {
+                continue;
+            };
+            for report in &written {
+                tracing::debug!(dataset = %report.dataset, rows = report.rows, "flushed appended rows");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn appended_rows_are_flushed_in_batches() {
+        let dir = tempdir().unwrap();
+        let catalog = Arc::new(Catalog::in_memory());
+        let config = StreamingConfig {
+            max_batch_rows: 4,
+            ..Default::default()
+        };
+        let appender = Appender::new(config, catalog.clone(), dir.path());
+
+        let report = appender
+            .append("events", df!["id" => [1i64, 2]].unwrap(), "alice")
+            .unwrap();
+        assert_eq!(report.buffered_rows, 2);
+        assert!(report.flushed.is_empty());
+        assert!(catalog.get("events").is_none());
+
+        // Later batches are cast to the buffered schema.
+        assert!(appender
+            .append("events", df!["other" => [1i64]].unwrap(), "alice")
+            .is_err());
+        let report = appender
+            .append("events", df!["id" => [3i32, 4]].unwrap(), "alice")
+            .unwrap();
+        assert_eq!(report.buffered_rows, 0);
+        assert_eq!(report.flushed[0].rows, 4);
+        assert_eq!(catalog.get("events").unwrap().owner, "alice");
+
+        appender
+            .append("events", df!["id" => [5i64]].unwrap(), "alice")
+            .unwrap();
+        assert!(appender.flush(false).is_empty());
+        let written = appender.flush(true);
+        assert_eq!(written[0].rows, 1);
+        assert_eq!(written[0].version, 2);
+    }
+
+    #[test]
+    fn appends_are_refused_while_the_buffer_is_full() {
+        let dir = tempdir().unwrap();
+        let config = StreamingConfig {
+            max_buffered_bytes: 1,
+            ..Default::default()
+        };
+        let appender = Appender::new(config, Arc::new(Catalog::in_memory()), dir.path());
+        let df = df!["id" => [1i64, 2, 3]].unwrap();
+        // The first batch is always accepted, however large.
+        appender.append("events", df.clone(), "alice").unwrap();
+        let err = appender.append("events", df.clone(), "alice").unwrap_err();
+        assert!(err.starts_with(BUFFER_FULL));
+        appender.flush(true);
+        appender.append("events", df, "alice").unwrap();
+    }
+}
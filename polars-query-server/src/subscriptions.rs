@@ -0,0 +1,372 @@
+//! Subscriptions re-running a saved query whenever a dataset's files change,
+//! pushing each new result to a webhook and to event-stream listeners.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::catalog::{self, Catalog};
+use crate::scheduler::{JobOptions, Scheduler};
+
+/// Notifications buffered for slow event-stream listeners.
+const EVENT_BUFFER: usize = 64;
+
+/// Request to subscribe a saved query to a dataset.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SubscriptionSpec {
+    /// Saved query re-run on every change.
+    pub query: String,
+    /// Dataset whose files are watched.
+    pub dataset: String,
+    /// Values for the saved query's variables.
+    #[serde(default)]
+    pub vars: BTreeMap<String, Value>,
+    /// URL each notification is POSTed to as JSON.
+    pub webhook: Option<String>,
+}
+
+/// A saved query subscribed to a dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: u64,
+    pub query: String,
+    pub dataset: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+    pub owner: String,
+    /// Unix seconds.
+    pub created_at: u64,
+    /// Dataset version the query last ran against, or was subscribed at.
+    pub last_version: Option<u64>,
+    /// Unix seconds of the last run.
+    #[serde(default)]
+    pub last_run_at: Option<u64>,
+    #[serde(default)]
+    pub last_job_id: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+/// Result of re-running a subscribed query, pushed to listeners.
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub subscription: u64,
+    pub query: String,
+    pub dataset: String,
+    /// Dataset version the query ran against.
+    pub version: u64,
+    pub job_id: u64,
+    pub status: String,
+    /// The result in the same form `/run-query` returns it.
+    pub output: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// Subscriptions, persisted as JSON.
+pub struct Subscriptions {
+    path: Option<PathBuf>,
+    entries: RwLock<BTreeMap<u64, Subscription>>,
+    events: broadcast::Sender<Notification>,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Subscriptions {
+            path: None,
+            entries: RwLock::new(BTreeMap::new()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl Subscriptions {
+    /// Subscriptions that are not persisted.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Open the subscriptions persisted at `path`, starting empty if it does
+    /// not exist.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let entries = if path.exists() {
+            let list: Vec<Subscription> = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            list.into_iter().map(|s| (s.id, s)).collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Subscriptions {
+            path: Some(path),
+            entries: RwLock::new(entries),
+            ..Self::default()
+        })
+    }
+
+    fn persist(&self, entries: &BTreeMap<u64, Subscription>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let list: Vec<&Subscription> = entries.values().collect();
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&list)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn list(&self) -> Vec<Subscription> {
+        self.entries.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Subscription> {
+        self.entries.read().unwrap().get(&id).cloned()
+    }
+
+    /// Add a subscription for `owner`, first firing once `dataset` moves on
+    /// from `version`.
+    pub fn add(
+        &self,
+        spec: SubscriptionSpec,
+        owner: &str,
+        version: u64,
+    ) -> Result<Subscription, String> {
+        if let Some(url) = &spec.webhook {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("invalid webhook {}: use an http(s) URL", url));
+            }
+        }
+        let mut entries = self.entries.write().unwrap();
+        let subscription = Subscription {
+            id: entries.keys().next_back().map_or(1, |id| id + 1),
+            query: spec.query,
+            dataset: spec.dataset,
+            vars: spec.vars,
+            webhook: spec.webhook,
+            owner: owner.to_string(),
+            created_at: catalog::now_secs(),
+            last_version: Some(version),
+            last_run_at: None,
+            last_job_id: None,
+            last_error: None,
+        };
+        entries.insert(subscription.id, subscription.clone());
+        self.persist(&entries).map_err(|e| e.to_string())?;
+        Ok(subscription)
+    }
+
+    pub fn remove(&self, id: u64) -> Result<Subscription, String> {
+        let mut entries = self.entries.write().unwrap();
+        let removed = entries
+            .remove(&id)
+            .ok_or_else(|| format!("unknown subscription {}", id))?;
+        self.persist(&entries).map_err(|e| e.to_string())?;
+        Ok(removed)
+    }
+
+    /// Record a run of subscription `notification.subscription` and push it
+    /// to event-stream listeners.
+    fn record(&self, notification: &Notification) -> Result<(), String> {
+        let mut entries = self.entries.write().unwrap();
+        // The subscription may have been removed while its query ran.
+        if let Some(s) = entries.get_mut(&notification.subscription) {
+            s.last_version = Some(notification.version);
+            s.last_run_at = Some(catalog::now_secs());
+            s.last_job_id = Some(notification.job_id);
+            s.last_error = notification.error.clone();
+            self.persist(&entries).map_err(|e| e.to_string())?;
+        }
+        // Sending only fails when nobody is listening.
+        let _ = self.events.send(notification.clone());
+        Ok(())
+    }
+
+    /// Listen for notifications of every subscription.
+    pub fn listen(&self) -> broadcast::Receiver<Notification> {
+        self.events.subscribe()
+    }
+}
+
+/// Register a new version of the dataset `name` when the files at its
+/// location no longer match the current one, returning the current version.
+pub fn watch_dataset(catalog: &Catalog, name: &str) -> Result<u64, String> {
+    let dataset = catalog
+        .get(name)
+        .ok_or_else(|| format!("unknown dataset {}", name))?;
+    let files = catalog::snapshot_files(
+        &dataset.location,
+        dataset.format,
+        dataset.partition_by.as_deref(),
+    )?;
+    if dataset.versions.last().is_some_and(|v| v.files == files) {
+        return Ok(dataset.version);
+    }
+    let owner = dataset.owner.clone();
+    Ok(catalog.register(dataset.spec(), &owner)?.version)
+}
+
+/// Re-run the query of `subscription` against `version` of its dataset and
+/// notify its listeners.
+async fn fire(
+    scheduler: &Scheduler,
+    http: &reqwest::Client,
+    subscription: &Subscription,
+    version: u64,
+) -> Result<(), String> {
+    let query = scheduler
+        .queries()
+        .render(&subscription.query, &subscription.vars)?;
+    let options = JobOptions {
+        user: subscription.owner.clone(),
+        ..Default::default()
+    };
+    let (job_id, status, rx) = scheduler.enqueue_with(query, options).await;
+    let result = rx.await.ok();
+    let error = result
+        .as_ref()
+        .and_then(|r| r.error())
+        .map(|e| e.message.clone());
+    let notification = Notification {
+        subscription: subscription.id,
+        query: subscription.query.clone(),
+        dataset: subscription.dataset.clone(),
+        version,
+        job_id,
+        status: match (&result, &error) {
+            (Some(_), None) => "completed".to_string(),
+            (Some(_), Some(_)) => "failed".to_string(),
+            (None, _) => status.to_string(),
+        },
+        output: result.as_ref().and_then(|r| r.output_json(job_id)),
+        error,
+    };
+    scheduler.subscriptions().record(&notification)?;
+    if let Some(url) = &subscription.webhook {
+        http.post(url)
+            .json(&notification)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("webhook {} failed: {}", url, e))?;
+    }
+    Ok(())
+}
+
+/// Check every subscription for changes of its dataset, re-running its
+/// query when the dataset has a new version.
+pub async fn check(scheduler: &Scheduler, http: &reqwest::Client) {
+    for subscription in scheduler.subscriptions().list() {
+        let catalog = scheduler.catalog().clone();
+        let name = subscription.dataset.clone();
+        let version = tokio::task::spawn_blocking(move || watch_dataset(&catalog, &name))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        let version = match version {
+            Ok(v) if subscription.last_version != Some(v) => v,
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!(
+                    subscription = subscription.id,
+                    "failed to watch dataset: {}",
+                    e
+                );
+                continue;
+            }
+        };
+        tracing::info!(
+            subscription = subscription.id,
+            dataset = %subscription.dataset,
+            version,
+            "dataset changed, re-running subscribed query"
+        );
+        if let Err(e) = fire(scheduler, http, &subscription, version).await {
+            tracing::warn!(subscription = subscription.id, "failed to notify: {}", e);
+        }
+    }
+}
+
+/// Check subscriptions every `interval`.
+pub fn spawn_loop(scheduler: Scheduler, interval: Duration) {
+    tokio::spawn(async move {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap_or_default();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            check(&scheduler, &http).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::{DatasetFormat, DatasetSpec};
+    use polars::prelude::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn new_files_add_a_version() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str| {
+            let mut df = df!["id" => [1, 2]].unwrap();
+            ParquetWriter::new(File::create(dir.path().join(name)).unwrap())
+                .finish(&mut df)
+                .unwrap();
+        };
+        write("a.parquet");
+        let catalog = Catalog::in_memory();
+        catalog
+            .register(
+                DatasetSpec {
+                    name: "events".into(),
+                    location: dir.path().to_string_lossy().to_string(),
+                    format: DatasetFormat::Parquet,
+                    ..Default::default()
+                },
+                "alice",
+            )
+            .unwrap();
+        assert_eq!(watch_dataset(&catalog, "events").unwrap(), 1);
+        write("b.parquet");
+        assert_eq!(watch_dataset(&catalog, "events").unwrap(), 2);
+        assert_eq!(watch_dataset(&catalog, "events").unwrap(), 2);
+    }
+
+    #[test]
+    fn subscriptions_persist() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("subscriptions.json");
+        let subscriptions = Subscriptions::open(&path).unwrap();
+        let spec = SubscriptionSpec {
+            query: "daily".into(),
+            dataset: "events".into(),
+            vars: BTreeMap::new(),
+            webhook: Some("ftp://example".into()),
+        };
+        assert!(subscriptions.add(spec.clone(), "alice", 1).is_err());
+        let spec = SubscriptionSpec {
+            webhook: Some("http://localhost:9000/hook".into()),
+            ..spec
+        };
+        let added = subscriptions.add(spec, "alice", 1).unwrap();
+        assert_eq!(added.id, 1);
+
+        let reopened = Subscriptions::open(&path).unwrap();
+        assert_eq!(reopened.get(1), Some(added));
+        reopened.remove(1).unwrap();
+        assert!(reopened.remove(1).is_err());
+    }
+}
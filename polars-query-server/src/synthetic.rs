@@ -0,0 +1,343 @@
+//! Synthetic datasets and mixed query workloads for reproducible benchmarks
+//! and soak tests against a running server.
+
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Args;
+use polars::prelude::*;
+use tokio::time::Instant;
+
+use crate::bench::BenchReport;
+use crate::utils::{splitmix64, unit_f64, SPLITMIX_GAMMA};
+
+const CITIES: [&str; 5] = ["NY", "LA", "SF", "CHI", "HOU"];
+
+/// Days since the Unix epoch of 2020-01-01.
+const EPOCH_2020: i32 = 18_262;
+
+/// Arguments of the `generate` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct GenerateArgs {
+    /// Directory the parquet files are written to.
+    #[arg(long, default_value = "data")]
+    pub out: PathBuf,
+    /// Number of files to write.
+    #[arg(long, default_value_t = 1)]
+    pub files: usize,
+    /// Rows per file.
+    #[arg(long, default_value_t = 1_000_000)]
+    pub rows: usize,
+    /// Number of distinct values of the `name` column.
+    #[arg(long, default_value_t = 1000)]
+    pub cardinality: usize,
+    /// Zipf exponent of the `name` column; 0 draws names uniformly, larger
+    /// values concentrate rows on fewer names.
+    #[arg(long, default_value_t = 0.0)]
+    pub skew: f64,
+    /// Seed of the random draws; the same seed writes the same files.
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+    /// File name prefix, followed by `_N.parquet`.
+    #[arg(long, default_value = "synthetic")]
+    pub prefix: String,
+}
+
+/// Arguments of the `load` subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct LoadArgs {
+    /// Base URL of the server the workload is submitted to.
+    #[arg(long, default_value = "http://127.0.0.1:3000")]
+    pub target: String,
+    /// Path or glob of the parquet files queried, as seen by the server.
+    #[arg(long, default_value = "data/synthetic_*.parquet")]
+    pub dataset: String,
+    /// Seconds to keep submitting queries.
+    #[arg(long, default_value_t = 60)]
+    pub duration: u64,
+    /// Number of queries in flight at once.
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+    /// Relative weights of the query kinds, e.g. `scan=1,filter=3`. Kinds
+    /// are `scan`, `filter`, `group` and `sort`.
+    #[arg(long, default_value = "scan=1,filter=3,group=2,sort=1", value_parser = parse_mix)]
+    pub mix: Mix,
+    /// Seed of the query choices.
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+}
+
+/// Kinds of query issued by the load driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryKind {
+    /// Projection of a few columns.
+    Scan,
+    /// Range filter on `age`.
+    Filter,
+    /// Mean balance per city.
+    Group,
+    /// Full sort on `balance`.
+    Sort,
+}
+
+impl QueryKind {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "scan" => Ok(QueryKind::Scan),
+            "filter" => Ok(QueryKind::Filter),
+            "group" => Ok(QueryKind::Group),
+            "sort" => Ok(QueryKind::Sort),
+            _ => Err(format!(
+                "unknown query kind {}: use scan, filter, group or sort",
+                name
+            )),
+        }
+    }
+
+    /// Query of this kind over `dataset`, varied by the random `bits`.
+    pub fn query(self, dataset: &str, bits: u64) -> String {
+        let read = format!("df = pl.read_parquet(\"{}\")", dataset);
+        match self {
+            QueryKind::Scan => format!("{}\ndf = df.select([\"name\", \"age\"])", read),
+            QueryKind::Filter => format!(
+                "{}\ndf = df.filter(pl.col(\"age\") > {})",
+                read,
+                18 + bits % 62
+            ),
+            QueryKind::Group => format!(
+                "{}\ndf = df.groupby(\"city\")\ndf = df.agg(pl.col(\"balance\").mean())",
+                read
+            ),
+            QueryKind::Sort => format!("{}\ndf = df.sort(\"balance\")", read),
+        }
+    }
+}
+
+/// Query kinds with their relative weights.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mix(pub Vec<(QueryKind, u32)>);
+
+impl Mix {
+    /// Pick a query kind in proportion to the weights.
+    fn pick(&self, bits: u64) -> QueryKind {
+        let total: u64 = self.0.iter().map(|(_, w)| *w as u64).sum();
+        let mut at = bits % total;
+        for (kind, weight) in &self.0 {
+            if at < *weight as u64 {
+                return *kind;
+            }
+            at -= *weight as u64;
+        }
+        self.0[self.0.len() - 1].0
+    }
+}
+
+/// Parse a workload mix such as `scan=1,filter=3`.
+fn parse_mix(value: &str) -> Result<Mix, String> {
+    let mut mix = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, weight) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("invalid mix entry {}: use kind=weight", entry))?;
+        let weight = weight
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid weight in {}", entry))?;
+        mix.push((QueryKind::parse(name.trim())?, weight));
+    }
+    if mix.iter().all(|(_, weight)| *weight == 0) {
+        return Err("mix needs at least one positive weight".to_string());
+    }
+    Ok(Mix(mix))
+}
+
+/// Sequential SplitMix64 generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(SPLITMIX_GAMMA);
+        splitmix64(self.0)
+    }
+
+    fn unit(&mut self) -> f64 {
+        unit_f64(self.next_u64())
+    }
+}
+
+/// Cumulative Zipf weights of ranks `1..=cardinality`, normalised to end at 1.
+fn zipf_cdf(cardinality: usize, skew: f64) -> Vec<f64> {
+    let mut total = 0.0;
+    let mut cdf: Vec<f64> = (1..=cardinality.max(1))
+        .map(|rank| {
+            total += (rank as f64).powf(-skew);
+            total
+        })
+        .collect();
+    for c in &mut cdf {
+        *c /= total;
+    }
+    cdf
+}
+
+/// Generate `rows` rows with columns `name`, `age`, `city`, `signup_date`
+/// and `balance`.
+pub fn generate_frame(
+    rows: usize,
+    cardinality: usize,
+    skew: f64,
+    seed: u64,
+) -> PolarsResult<DataFrame> {
+    let cdf = zipf_cdf(cardinality, skew);
+    let mut rng = Rng(seed);
+    let mut names = Vec::with_capacity(rows);
+    let mut ages = Vec::with_capacity(rows);
+    let mut cities = Vec::with_capacity(rows);
+    let mut days = Vec::with_capacity(rows);
+    let mut balances = Vec::with_capacity(rows);
+    for _ in 0..rows {
+        let u = rng.unit();
+        let rank = cdf.partition_point(|c| *c <= u).min(cdf.len() - 1);
+        names.push(format!("user_{}", rank));
+        ages.push(18 + (rng.next_u64() % 62) as i64);
+        cities.push(CITIES[(rng.next_u64() % CITIES.len() as u64) as usize]);
+        days.push(EPOCH_2020 + (rng.next_u64() % 366) as i32);
+        balances.push((rng.unit() * 1_000_000.0).round() / 100.0);
+    }
+    let signup_date = Series::new("signup_date", days).cast(&DataType::Date)?;
+    DataFrame::new(vec![
+        Series::new("name", names),
+        Series::new("age", ages),
+        Series::new("city", cities),
+        signup_date,
+        Series::new("balance", balances),
+    ])
+}
+
+/// Write the files described by `args`, returning their paths.
+pub fn generate(args: &GenerateArgs) -> Result<Vec<PathBuf>, String> {
+    if !(args.skew.is_finite() && args.skew >= 0.0) {
+        return Err(format!("skew must be 0 or more, got {}", args.skew));
+    }
+    if args.cardinality == 0 {
+        return Err("cardinality must be at least 1".to_string());
+    }
+    fs::create_dir_all(&args.out)
+        .map_err(|e| format!("failed to create {}: {}", args.out.display(), e))?;
+    let mut written = Vec::with_capacity(args.files);
+    for i in 0..args.files {
+        // Each file gets its own stream so files can be regenerated alone.
+        let seed = splitmix64(args.seed.wrapping_add(i as u64));
+        let mut df = generate_frame(args.rows, args.cardinality, args.skew, seed)
+            .map_err(|e| e.to_string())?;
+        let path = args.out.join(format!("{}_{}.parquet", args.prefix, i));
+        let file = File::create(&path)
+            .map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+        ParquetWriter::new(file)
+            .finish(&mut df)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Submit queries drawn from `args.mix` to `args.target` from
+/// `args.concurrency` workers for `args.duration` seconds, and report
+/// latencies.
+pub async fn run(args: &LoadArgs) -> Result<BenchReport, String> {
+    if args.concurrency == 0 {
+        return Err("concurrency must be at least 1".to_string());
+    }
+    let http = reqwest::Client::new();
+    let url = format!("{}/run-query?wait=true", args.target.trim_end_matches('/'));
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(args.duration);
+
+    let mut handles = Vec::with_capacity(args.concurrency);
+    for worker in 0..args.concurrency {
+        let http = http.clone();
+        let url = url.clone();
+        let dataset = args.dataset.clone();
+        let mix = args.mix.clone();
+        let mut rng = Rng(splitmix64(args.seed.wrapping_add(worker as u64)));
+        handles.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            let mut failures = 0;
+            while Instant::now() < deadline {
+                let query = mix.pick(rng.next_u64()).query(&dataset, rng.next_u64());
+                let submitted = Instant::now();
+                let ok = match http.post(&url).body(query).send().await {
+                    Ok(resp) if resp.status().is_success() => resp
+                        .json::<serde_json::Value>()
+                        .await
+                        .is_ok_and(|v| v["error"].is_null()),
+                    _ => false,
+                };
+                latencies.push(submitted.elapsed());
+                if !ok {
+                    failures += 1;
+                }
+            }
+            (latencies, failures)
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    let mut failures = 0;
+    for handle in handles {
+        let (worker_latencies, worker_failures) = handle.await.map_err(|e| e.to_string())?;
+        latencies.extend(worker_latencies);
+        failures += worker_failures;
+    }
+    Ok(BenchReport::from_latencies(
+        latencies,
+        failures,
+        start.elapsed(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_query;
+
+    #[test]
+    fn frames_are_reproducible_and_skewed() {
+        let df = generate_frame(2_000, 50, 0.0, 1).unwrap();
+        assert_eq!(df.height(), 2_000);
+        assert_eq!(df.column("signup_date").unwrap().dtype(), &DataType::Date);
+        assert!(df.column("name").unwrap().n_unique().unwrap() <= 50);
+        assert!(df.frame_equal(&generate_frame(2_000, 50, 0.0, 1).unwrap()));
+
+        let count = |df: &DataFrame| {
+            df.column("name")
+                .unwrap()
+                .utf8()
+                .unwrap()
+                .into_iter()
+                .filter(|n| *n == Some("user_0"))
+                .count()
+        };
+        let skewed = generate_frame(2_000, 50, 1.5, 1).unwrap();
+        assert!(count(&skewed) > 4 * count(&df));
+    }
+
+    #[test]
+    fn mix_is_parsed_and_queries_are_valid() {
+        let mix = parse_mix("scan=1, sort=0,group=2").unwrap();
+        assert_eq!(mix.0.len(), 3);
+        assert!(parse_mix("scan=0").is_err());
+        assert!(parse_mix("join=1").is_err());
+        assert!((0..100).all(|bits| mix.pick(bits) != QueryKind::Sort));
+
+        for kind in [
+            QueryKind::Scan,
+            QueryKind::Filter,
+            QueryKind::Group,
+            QueryKind::Sort,
+        ] {
+            parse_query(&kind.query("data/synthetic_*.parquet", 7)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! systemd readiness and watchdog notifications.
+//!
+//! Without the `systemd` feature every function is a no-op.
+
+use std::time::Duration;
+
+#[cfg(feature = "systemd")]
+use sd_notify::NotifyState;
+
+/// Tell systemd the service is ready. A no-op when not started by systemd.
+pub fn notify_ready() {
+    #[cfg(feature = "systemd")]
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        tracing::warn!("failed to notify systemd readiness: {}", e);
+    }
+}
+
+/// Tell systemd the service is shutting down.
+pub fn notify_stopping() {
+    #[cfg(feature = "systemd")]
+    let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+}
+
+/// Interval at which watchdog keep-alives must be sent, if systemd enabled
+/// the watchdog for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    #[cfg(feature = "systemd")]
+    {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+            return Some(Duration::from_micros(usec));
+        }
+    }
+    None
+}
+
+/// Spawn a task sending watchdog keep-alives at half the configured interval.
+///
+/// The task runs on the async runtime, so a wedged runtime stops the
+/// keep-alives and systemd restarts the service.
+pub fn spawn_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    tracing::info!(?interval, "systemd watchdog enabled");
+    #[cfg(feature = "systemd")]
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval / 2);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                tracing::warn!("failed to send watchdog keep-alive: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,197 @@
+//! Logging and, with the `otel` feature, export of traces over OTLP. Each
+//! HTTP request runs in a span continuing the trace of its `traceparent`
+//! header, and each job in a child span with spans for its queue wait,
+//! parsing, plan steps, execution and serialization.
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
+use tracing::Span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Trace export settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// OTLP gRPC endpoint traces are exported to, e.g.
+    /// `http://localhost:4317`. Traces are not exported when unset.
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` of the exported spans.
+    pub service_name: String,
+    /// Share of traces started here that are exported, between 0 and 1.
+    /// Traces continued from a `traceparent` header follow its sampling
+    /// decision.
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            otlp_endpoint: None,
+            service_name: "rdata".to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    /// Problems with the configured export.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.otlp_endpoint.is_some() && !cfg!(feature = "otel") {
+            errors.push(
+                "telemetry.otlp_endpoint requires building with the `otel` feature".to_string(),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.sample_ratio) {
+            errors.push("telemetry.sample_ratio must be between 0 and 1".to_string());
+        }
+        errors
+    }
+}
+
+/// Install the global subscriber: logs to stdout and, when an OTLP endpoint
+/// is configured, trace export. Failing to set up the export is logged and
+/// leaves logging in place.
+pub fn init(config: &TelemetryConfig) {
+    let registry = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer());
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &config.otlp_endpoint {
+        match otel::tracer(config, endpoint) {
+            Ok(tracer) => {
+                registry
+                    .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                    .init();
+                tracing::info!(endpoint, "exporting traces over OTLP");
+            }
+            Err(e) => {
+                registry.init();
+                tracing::error!("failed to set up trace export to {}: {}", endpoint, e);
+            }
+        }
+        return;
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = config;
+    registry.init();
+}
+
+/// Export the spans still buffered, before the process exits.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Make `span` continue the trace of the `traceparent` header in `headers`,
+/// if there is one and traces are exported.
+pub fn set_parent(span: &Span, headers: &HeaderMap) {
+    #[cfg(feature = "otel")]
+    otel::set_parent(span, headers);
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers);
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use axum::http::HeaderMap;
+    use opentelemetry::propagation::Extractor;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::trace::{self, Sampler, Tracer};
+    use opentelemetry::sdk::Resource;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::TelemetryConfig;
+
+    /// A tracer batching spans to the OTLP collector at `endpoint`.
+    pub fn tracer(config: &TelemetryConfig, endpoint: &str) -> Result<Tracer, String> {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let sampler =
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+        let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(
+                trace::config()
+                    .with_sampler(sampler)
+                    .with_resource(resource),
+            )
+            .install_batch(opentelemetry::runtime::Tokio)
+            .map_err(|e| e.to_string())
+    }
+
+    struct Headers<'a>(&'a HeaderMap);
+
+    impl Extractor for Headers<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+
+    pub fn set_parent(span: &Span, headers: &HeaderMap) {
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&Headers(headers))
+        });
+        span.set_parent(parent);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use opentelemetry::trace::{TraceContextExt, TracerProvider};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        #[test]
+        fn requests_continue_the_incoming_trace() {
+            opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+            let provider = trace::TracerProvider::builder().build();
+            let subscriber = tracing_subscriber::registry()
+                .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                    .parse()
+                    .unwrap(),
+            );
+            tracing::subscriber::with_default(subscriber, || {
+                let span = tracing::info_span!("http_request");
+                set_parent(&span, &headers);
+                let trace_id = span.context().span().span_context().trace_id();
+                assert_eq!(trace_id.to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_ratio_is_a_probability() {
+        assert!(TelemetryConfig::default().validate().is_empty());
+        let config = TelemetryConfig {
+            sample_ratio: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            ["telemetry.sample_ratio must be between 0 and 1"]
+        );
+    }
+}
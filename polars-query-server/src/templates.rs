@@ -0,0 +1,320 @@
+//! Saved queries and reusable snippets, with `{{variable}}` substitution and
+//! `{{> snippet}}` includes expanded on the server.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use crate::catalog;
+
+/// A saved query template, or a snippet other templates include.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub name: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Snippets are only included by other templates, never run directly.
+    #[serde(default)]
+    pub snippet: bool,
+    pub owner: String,
+    /// Unix seconds of the last change.
+    pub updated_at: u64,
+}
+
+/// Request to save a query or snippet.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SavedQuerySpec {
+    pub text: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub snippet: bool,
+}
+
+/// Piece of a template.
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    Include(&'a str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        tokens.push(Token::Text(&rest[..start]));
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unclosed {{{{ in template: {}", &rest[start..]))?;
+        let inner = after[..end].trim();
+        let (token, name) = match inner.strip_prefix('>') {
+            Some(include) => (Token::Include(include.trim()), include.trim()),
+            None => (Token::Var(inner), inner),
+        };
+        if !catalog::valid_name(name) {
+            return Err(format!(
+                "invalid template placeholder {{{{{}}}}}: names use letters, digits, '_' and '-'",
+                inner
+            ));
+        }
+        tokens.push(token);
+        rest = &after[end + 2..];
+    }
+    tokens.push(Token::Text(rest));
+    Ok(tokens)
+}
+
+/// Text a variable's value is substituted as: strings as they are, numbers
+/// and booleans in their JSON form.
+fn render_value(name: &str, value: &Value) -> Result<String, String> {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => {
+            return Err(format!(
+                "variable {} must be a string, number or boolean",
+                name
+            ))
+        }
+    };
+    // A line break would let a value add steps to the query.
+    if text.contains(['\n', '\r']) {
+        return Err(format!("variable {} must not contain line breaks", name));
+    }
+    Ok(text)
+}
+
+/// Saved queries and snippets, persisted as JSON.
+#[derive(Debug, Default)]
+pub struct QueryLibrary {
+    path: Option<PathBuf>,
+    entries: RwLock<BTreeMap<String, SavedQuery>>,
+}
+
+impl QueryLibrary {
+    /// A library that is not persisted.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Open the library persisted at `path`, starting empty if it does not
+    /// exist.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let entries = if path.exists() {
+            let list: Vec<SavedQuery> = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            list.into_iter().map(|q| (q.name.clone(), q)).collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(QueryLibrary {
+            path: Some(path),
+            entries: RwLock::new(entries),
+        })
+    }
+
+    fn persist(&self, entries: &BTreeMap<String, SavedQuery>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let list: Vec<&SavedQuery> = entries.values().collect();
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&list)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn list(&self) -> Vec<SavedQuery> {
+        self.entries.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<SavedQuery> {
+        self.entries.read().unwrap().get(name).cloned()
+    }
+
+    /// Save `spec` as `name`, replacing any previous definition.
+    pub fn save(
+        &self,
+        name: &str,
+        spec: SavedQuerySpec,
+        owner: &str,
+    ) -> Result<SavedQuery, String> {
+        if !catalog::valid_name(name) {
+            return Err(format!(
+                "invalid query name {}: use letters, digits, '_' and '-'",
+                name
+            ));
+        }
+        tokenize(&spec.text)?;
+        let query = SavedQuery {
+            name: name.to_string(),
+            text: spec.text,
+            description: spec.description,
+            snippet: spec.snippet,
+            owner: owner.to_string(),
+            updated_at: catalog::now_secs(),
+        };
+        let mut entries = self.entries.write().unwrap();
+        entries.insert(name.to_string(), query.clone());
+        self.persist(&entries).map_err(|e| e.to_string())?;
+        Ok(query)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<SavedQuery, String> {
+        let mut entries = self.entries.write().unwrap();
+        let removed = entries
+            .remove(name)
+            .ok_or_else(|| format!("unknown query {}", name))?;
+        self.persist(&entries).map_err(|e| e.to_string())?;
+        Ok(removed)
+    }
+
+    /// Variables of the saved query `name` and the snippets it includes, in
+    /// order of first appearance.
+    pub fn variables(&self, name: &str) -> Result<Vec<String>, String> {
+        let mut found = Vec::new();
+        self.walk(name, &mut Vec::new(), &mut |token| {
+            if let Token::Var(var) = token {
+                if !found.iter().any(|f| f == var) {
+                    found.push(var.to_string());
+                }
+            }
+            Ok(())
+        })?;
+        Ok(found)
+    }
+
+    /// Expand the saved query `name`, substituting `vars` and including
+    /// snippets.
+    pub fn render(&self, name: &str, vars: &BTreeMap<String, Value>) -> Result<String, String> {
+        let query = self
+            .get(name)
+            .ok_or_else(|| format!("unknown query {}", name))?;
+        if query.snippet {
+            return Err(format!(
+                "{} is a snippet and cannot be run on its own",
+                name
+            ));
+        }
+        let mut out = String::new();
+        self.walk(name, &mut Vec::new(), &mut |token| {
+            match token {
+                Token::Text(text) => out.push_str(text),
+                Token::Var(var) => {
+                    let value = vars
+                        .get(*var)
+                        .ok_or_else(|| format!("missing value for variable {}", var))?;
+                    out.push_str(&render_value(var, value)?);
+                }
+                Token::Include(_) => {}
+            }
+            Ok(())
+        })?;
+        Ok(out)
+    }
+
+    /// Visit the tokens of `name` in order, descending into includes.
+    fn walk(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+        visit: &mut dyn FnMut(&Token) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if stack.iter().any(|s| s == name) {
+            return Err(format!(
+                "snippet {} includes itself via {}",
+                name,
+                stack.join(" > ")
+            ));
+        }
+        let query = match self.get(name) {
+            Some(q) => q,
+            None if stack.is_empty() => return Err(format!("unknown query {}", name)),
+            None => {
+                return Err(format!(
+                    "unknown snippet {} included by {}",
+                    name,
+                    stack.join(" > ")
+                ))
+            }
+        };
+        stack.push(name.to_string());
+        for token in tokenize(&query.text)? {
+            match token {
+                Token::Include(snippet) => self.walk(snippet, stack, visit)?,
+                other => visit(&other)?,
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn save(library: &QueryLibrary, name: &str, text: &str, snippet: bool) {
+        let spec = SavedQuerySpec {
+            text: text.to_string(),
+            description: None,
+            snippet,
+        };
+        library.save(name, spec, "alice").unwrap();
+    }
+
+    #[test]
+    fn variables_and_snippets_are_expanded() {
+        let library = QueryLibrary::in_memory();
+        save(
+            &library,
+            "adults",
+            "df = df.filter(pl.col(\"age\") > {{min_age}})\n",
+            true,
+        );
+        save(
+            &library,
+            "by_city",
+            "df = pl.read_table(\"{{table}}\")\n{{> adults}}df = df.groupby(\"city\")",
+            false,
+        );
+        assert_eq!(
+            library.variables("by_city").unwrap(),
+            vec!["table", "min_age"]
+        );
+        let vars = BTreeMap::from([
+            ("table".to_string(), json!("people")),
+            ("min_age".to_string(), json!(30)),
+        ]);
+        assert_eq!(
+            library.render("by_city", &vars).unwrap(),
+            "df = pl.read_table(\"people\")\ndf = df.filter(pl.col(\"age\") > 30)\ndf = df.groupby(\"city\")"
+        );
+        assert!(library.render("adults", &vars).is_err());
+
+        let injected = BTreeMap::from([
+            (
+                "table".to_string(),
+                json!("people\")\ndf = pl.read_table(\"secret"),
+            ),
+            ("min_age".to_string(), json!(30)),
+        ]);
+        assert!(library.render("by_city", &injected).is_err());
+
+        save(&library, "loop", "{{> loop}}", true);
+        save(&library, "uses_loop", "{{> loop}}", false);
+        assert!(library.render("uses_loop", &vars).is_err());
+        assert!(tokenize("{{ unclosed").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Temporary datasets: job results registered in the catalog for a limited
+//! time, so later queries can read them with `pl.read_table` instead of
+//! downloading and re-uploading them.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::catalog::{self, Catalog, Dataset, DatasetFormat, DatasetSpec};
+
+/// Tag carried by every temporary dataset.
+pub const TEMPORARY_TAG: &str = "temporary";
+
+/// Temporary dataset settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemporaryConfig {
+    /// Directory the results of temporary datasets are linked into.
+    pub dir: PathBuf,
+    /// Seconds a temporary dataset is kept when no TTL is requested.
+    pub default_ttl_secs: u64,
+    /// Longest TTL that may be requested.
+    pub max_ttl_secs: u64,
+}
+
+impl Default for TemporaryConfig {
+    fn default() -> Self {
+        TemporaryConfig {
+            dir: PathBuf::from("temporary"),
+            default_ttl_secs: 3600,
+            max_ttl_secs: 24 * 3600,
+        }
+    }
+}
+
+/// Where a job's result is read from.
+pub enum ResultSource {
+    /// Zstd-compressed IPC returned inline.
+    Inline(Vec<u8>),
+    /// Feather files in row order.
+    Files(Vec<String>),
+}
+
+fn materialize(dir: &Path, source: ResultSource) -> io::Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+    match source {
+        ResultSource::Inline(bytes) => {
+            let ipc = zstd::decode_all(Cursor::new(bytes))?;
+            fs::write(dir.join("part-00000.ipc"), ipc)
+        }
+        ResultSource::Files(paths) => {
+            for (i, path) in paths.iter().enumerate() {
+                // A link keeps the data readable if the result store evicts
+                // the original.
+                let target = dir.join(format!("part-{:05}.ipc", i));
+                fs::hard_link(path, &target).or_else(|_| fs::copy(path, &target).map(|_| ()))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Register the result of job `job_id` as the dataset `name`, owned by
+/// `owner` and dropped after `ttl_secs` (or the configured default).
+///
+/// An existing temporary dataset of the same owner is replaced; other
+/// datasets are never overwritten.
+pub fn register(
+    catalog: &Catalog,
+    config: &TemporaryConfig,
+    name: &str,
+    job_id: u64,
+    source: ResultSource,
+    ttl_secs: Option<u64>,
+    owner: &str,
+) -> Result<Dataset, String> {
+    if !catalog::valid_name(name) {
+        return Err(format!(
+            "invalid dataset name {}: use letters, digits, '_' and '-'",
+            name
+        ));
+    }
+    let ttl = ttl_secs.unwrap_or(config.default_ttl_secs);
+    if ttl == 0 || ttl > config.max_ttl_secs {
+        return Err(format!(
+            "ttl_secs must be between 1 and {}",
+            config.max_ttl_secs
+        ));
+    }
+    let existing = catalog.get(name);
+    if let Some(existing) = &existing {
+        if existing.expires_at.is_none() {
+            return Err(format!(
+                "dataset {} already exists and is not temporary",
+                name
+            ));
+        }
+        if existing.owner != owner {
+            return Err(format!(
+                "access denied: temporary dataset {} belongs to another user",
+                name
+            ));
+        }
+    }
+    let dir = config.dir.join(name);
+    materialize(&dir, source).map_err(|e| format!("failed to store result: {}", e))?;
+    if existing.is_some() {
+        // Start again at version 1; the files of earlier versions are gone.
+        catalog.remove(name)?;
+    }
+    let spec = DatasetSpec {
+        name: name.to_string(),
+        location: dir.to_string_lossy().to_string(),
+        format: DatasetFormat::Ipc,
+        description: Some(format!("result of job {}", job_id)),
+        tags: vec![TEMPORARY_TAG.to_string()],
+        ..Default::default()
+    };
+    if let Err(e) = catalog.register(spec, owner) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(e);
+    }
+    catalog.set_expiry(name, Some(catalog::now_secs() + ttl))?;
+    catalog
+        .get(name)
+        .ok_or_else(|| format!("unknown dataset {}", name))
+}
+
+/// Drop temporary datasets past their expiry along with their files,
+/// returning their names.
+pub fn drop_expired(catalog: &Catalog, config: &TemporaryConfig) -> Vec<String> {
+    let now = catalog::now_secs();
+    let mut dropped = Vec::new();
+    for dataset in catalog.list() {
+        if dataset.expires_at.is_none_or(|t| t > now) {
+            continue;
+        }
+        if let Err(e) = catalog.remove(&dataset.name) {
+            tracing::warn!(dataset = %dataset.name, "failed to drop temporary dataset: {}", e);
+            continue;
+        }
+        match fs::remove_dir_all(config.dir.join(&dataset.name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                tracing::warn!(dataset = %dataset.name, "failed to remove files: {}", e)
+            }
+            _ => {}
+        }
+        dropped.push(dataset.name);
+    }
+    dropped
+}
+
+/// Drop expired temporary datasets every minute.
+pub fn spawn_sweep(catalog: Arc<Catalog>, config: TemporaryConfig) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            let catalog = catalog.clone();
+            let config = config.clone();
+            let Ok(dropped) =
+                tokio::task::spawn_blocking(move || drop_expired(&catalog, &config)).await
+            else {
+                continue;
+            };
+            for name in &dropped {
+                tracing::info!(dataset = %name, "dropped expired temporary dataset");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ResultStore;
+    use crate::utils::{prepare_output, OutputConfig};
+    use polars::prelude::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn results_are_registered_until_they_expire() {
+        let dir = tempdir().unwrap();
+        let config = TemporaryConfig {
+            dir: dir.path().join("temporary"),
+            ..Default::default()
+        };
+        let catalog = Catalog::in_memory();
+        let df = df!["id" => [1, 2, 3]].unwrap();
+        let out = prepare_output(&ResultStore::default(), &df, &OutputConfig::default()).unwrap();
+        let source = ResultSource::Inline(out.bytes.unwrap());
+
+        let dataset = register(&catalog, &config, "stage1", 7, source, None, "alice").unwrap();
+        assert_eq!(dataset.format, DatasetFormat::Ipc);
+        assert_eq!(dataset.versions[0].files.len(), 1);
+        assert!(dataset.expires_at.is_some());
+
+        let again = ResultSource::Files(vec![dataset.versions[0].files[0].path.clone()]);
+        assert!(register(&catalog, &config, "stage1", 8, again, None, "bob").is_err());
+
+        assert!(drop_expired(&catalog, &config).is_empty());
+        catalog.set_expiry("stage1", Some(0)).unwrap();
+        assert_eq!(drop_expired(&catalog, &config), vec!["stage1"]);
+        assert!(catalog.get("stage1").is_none());
+        assert!(!config.dir.join("stage1").exists());
+    }
+}
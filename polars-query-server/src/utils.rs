@@ -1,46 +1,283 @@
 use polars::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::{self, Cursor};
 
-/// Compressed bytes or path to saved Feather file.
+use crate::storage::ResultStore;
+
+/// Limits controlling how a result is returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Largest compressed size returned inline in the response.
+    pub inline_limit: usize,
+    /// Largest uncompressed IPC size written as a single file. Bigger outputs
+    /// are split into several part files of roughly this size.
+    pub part_size: usize,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            inline_limit: 1_000_000,
+            part_size: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Serialization of a job's result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Arrow IPC file (Feather), split into part files when large.
+    #[default]
+    Ipc,
+    /// Arrow IPC stream, as read by `pl.read_ipc_stream`.
+    Arrow,
+    /// A JSON array with one object per row.
+    Json,
+    Csv,
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "ipc" | "feather" => OutputFormat::Ipc,
+            "arrow" => OutputFormat::Arrow,
+            "json" => OutputFormat::Json,
+            "csv" => OutputFormat::Csv,
+            "parquet" => OutputFormat::Parquet,
+            _ => return None,
+        })
+    }
+
+    /// The first format named by the media types of an `Accept` header.
+    /// `application/json` is not one, as it asks for the job's JSON response.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept.split(',').find_map(|media| {
+            Some(match media.split(';').next()?.trim() {
+                "application/vnd.apache.arrow.file" => OutputFormat::Ipc,
+                "application/vnd.apache.arrow.stream" => OutputFormat::Arrow,
+                "text/csv" => OutputFormat::Csv,
+                "application/vnd.apache.parquet" => OutputFormat::Parquet,
+                _ => return None,
+            })
+        })
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            OutputFormat::Ipc => "application/vnd.apache.arrow.file",
+            OutputFormat::Arrow => "application/vnd.apache.arrow.stream",
+            OutputFormat::Json => "application/json",
+            OutputFormat::Csv => "text/csv",
+            OutputFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Ipc => "feather",
+            OutputFormat::Arrow => "arrows",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+
+    /// The format of a file written with `extension`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "arrows" => Some(OutputFormat::Arrow),
+            _ => Self::from_name(extension),
+        }
+    }
+}
+
+/// One file of a multi-part output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputPart {
+    pub path: String,
+    pub rows: usize,
+    pub size: u64,
+}
+
+/// Compressed bytes, path to saved file, or a list of part files.
+#[derive(Clone)]
 pub struct PreparedOutput {
     pub bytes: Option<Vec<u8>>, // zstd compressed
     pub path: Option<String>,
+    /// Part files in row order when the output was split.
+    pub parts: Option<Vec<OutputPart>>,
+    /// Set when `path` points at a file another job already produced.
+    pub reused: bool,
+    pub format: OutputFormat,
+    /// Rows of the result, when known.
+    pub rows: Option<u64>,
 }
 
-/// Compress a DataFrame using zstd after writing as IPC (Feather).
-fn compress_df(df: &DataFrame) -> io::Result<Vec<u8>> {
+/// Increment of a SplitMix64 generator's state.
+pub(crate) const SPLITMIX_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Output of a SplitMix64 generator whose state just became `state`.
+pub(crate) fn splitmix64(state: u64) -> u64 {
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Map 64 random bits to a uniform value in `[0, 1)`.
+pub(crate) fn unit_f64(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Serialize a DataFrame as IPC (Feather).
+fn encode_ipc(df: &DataFrame) -> io::Result<Vec<u8>> {
     let mut buf = Vec::new();
     let mut df = df.clone();
     IpcWriter::new(&mut buf)
         .finish(&mut df)
         .map_err(|e| io::Error::other(e.to_string()))?;
-    zstd::encode_all(Cursor::new(buf), 0)
+    Ok(buf)
 }
 
-/// Save a DataFrame to the given path in Feather format.
-fn save_df(path: &str, df: &DataFrame) -> io::Result<()> {
-    let file = File::create(path)?;
+/// Serialize a DataFrame in the Arrow IPC streaming format, as read by
+/// `pl.read_ipc_stream` and `pyarrow.ipc.open_stream`.
+pub fn encode_ipc_stream(df: &DataFrame) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
     let mut df = df.clone();
-    IpcWriter::new(file)
+    IpcStreamWriter::new(&mut buf)
         .finish(&mut df)
-        .map_err(|e| io::Error::other(e.to_string()))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(buf)
+}
+
+/// Serialize a DataFrame as `format`.
+fn encode(df: &DataFrame, format: OutputFormat) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut df = df.clone();
+    let written = match format {
+        OutputFormat::Ipc => IpcWriter::new(&mut buf).finish(&mut df),
+        OutputFormat::Arrow => IpcStreamWriter::new(&mut buf).finish(&mut df),
+        OutputFormat::Json => JsonWriter::new(&mut buf)
+            .with_json_format(JsonFormat::Json)
+            .finish(&mut df),
+        OutputFormat::Csv => CsvWriter::new(&mut buf).finish(&mut df),
+        OutputFormat::Parquet => ParquetWriter::new(&mut buf).finish(&mut df).map(|_| ()),
+    };
+    written.map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(buf)
 }
 
-/// Prepare output either inline (<1MB) or as file on disk.
-pub fn prepare_output(id: u64, df: &DataFrame) -> io::Result<PreparedOutput> {
-    let compressed = compress_df(df)?;
-    if compressed.len() <= 1_000_000 {
+/// Read a Feather file written by [`prepare_output_as`].
+pub fn read_feather(path: &str) -> Result<DataFrame, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open result {}: {}", path, e))?;
+    IpcReader::new(file).finish().map_err(|e| e.to_string())
+}
+
+/// Read an output prepared by [`prepare_output_as`] back into a DataFrame,
+/// whether it was returned inline, as one file or as part files.
+pub fn read_output(
+    bytes: Option<&[u8]>,
+    path: Option<&str>,
+    parts: &[OutputPart],
+) -> Result<DataFrame, String> {
+    if let Some(bytes) = bytes {
+        let ipc = zstd::decode_all(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        return IpcReader::new(Cursor::new(ipc))
+            .finish()
+            .map_err(|e| e.to_string());
+    }
+    if let Some(path) = path {
+        return read_feather(path);
+    }
+    let mut parts = parts.iter();
+    let mut df = read_feather(&parts.next().ok_or("no output to read")?.path)?;
+    for part in parts {
+        df.vstack_mut(&read_feather(&part.path)?)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(df)
+}
+
+/// The serialized result of an inline or single-file output, or `None` for
+/// one split into part files.
+pub fn output_bytes(output: &PreparedOutput) -> io::Result<Option<Vec<u8>>> {
+    if let Some(bytes) = &output.bytes {
+        return zstd::decode_all(Cursor::new(bytes)).map(Some);
+    }
+    output.path.as_ref().map(std::fs::read).transpose()
+}
+
+/// Write `df` as `n_parts` row-contiguous Feather files.
+fn write_parts(store: &ResultStore, df: &DataFrame, n_parts: usize) -> io::Result<Vec<OutputPart>> {
+    let rows_per_part = df.height().div_ceil(n_parts).max(1);
+    let mut parts = Vec::with_capacity(n_parts);
+    let mut offset = 0;
+    while offset < df.height() {
+        let chunk = df.slice(offset as i64, rows_per_part);
+        let stored = store.put(&encode_ipc(&chunk)?, "feather")?;
+        parts.push(OutputPart {
+            path: stored.path,
+            rows: chunk.height(),
+            size: stored.size,
+        });
+        offset += rows_per_part;
+    }
+    Ok(parts)
+}
+
+/// Prepare output inline when it compresses below `config.inline_limit`,
+/// otherwise as one or more content-addressed files in `store`.
+#[cfg(test)]
+pub fn prepare_output(
+    store: &ResultStore,
+    df: &DataFrame,
+    config: &OutputConfig,
+) -> io::Result<PreparedOutput> {
+    prepare_output_as(store, df, config, OutputFormat::Ipc)
+}
+
+/// Like [`prepare_output`], serializing `df` as `format`. Only IPC outputs
+/// are split into part files.
+pub fn prepare_output_as(
+    store: &ResultStore,
+    df: &DataFrame,
+    config: &OutputConfig,
+    format: OutputFormat,
+) -> io::Result<PreparedOutput> {
+    let encoded = encode(df, format)?;
+    let compressed = zstd::encode_all(Cursor::new(&encoded), 0)?;
+    if compressed.len() <= config.inline_limit {
         Ok(PreparedOutput {
             bytes: Some(compressed),
             path: None,
+            parts: None,
+            reused: false,
+            format,
+            rows: Some(df.height() as u64),
+        })
+    } else if format == OutputFormat::Ipc && encoded.len() > config.part_size && df.height() > 1 {
+        let n_parts = encoded.len().div_ceil(config.part_size.max(1));
+        Ok(PreparedOutput {
+            bytes: None,
+            path: None,
+            parts: Some(write_parts(store, df, n_parts)?),
+            reused: false,
+            format,
+            rows: Some(df.height() as u64),
         })
     } else {
-        let path = format!("output_{}.feather", id);
-        save_df(&path, df)?;
+        let stored = store.put(&encoded, format.extension())?;
         Ok(PreparedOutput {
             bytes: None,
-            path: Some(path),
+            path: Some(stored.path),
+            parts: None,
+            reused: stored.reused,
+            format,
+            rows: Some(df.height() as u64),
         })
     }
 }
@@ -49,24 +286,88 @@ pub fn prepare_output(id: u64, df: &DataFrame) -> io::Result<PreparedOutput> {
 mod tests {
     use super::*;
     use std::fs;
+    use tempfile::tempdir;
 
     #[test]
     fn small_dataframe_inline() {
+        let store = ResultStore::default();
         let df = df!["val" => [1, 2, 3]].unwrap();
-        let out = prepare_output(1, &df).unwrap();
+        let out = prepare_output(&store, &df, &OutputConfig::default()).unwrap();
         assert!(out.bytes.is_some());
         assert!(out.path.is_none());
+        let back = read_output(out.bytes.as_deref(), None, &[]).unwrap();
+        assert!(back.frame_equal(&df));
+    }
+
+    #[test]
+    fn outputs_in_requested_format() {
+        let store = ResultStore::default();
+        let config = OutputConfig::default();
+        let df = df!["id" => [1, 2], "name" => ["a", "b"]].unwrap();
+        let decoded = |format| {
+            let out = prepare_output_as(&store, &df, &config, format).unwrap();
+            assert_eq!(out.format, format);
+            zstd::decode_all(Cursor::new(out.bytes.unwrap())).unwrap()
+        };
+        assert_eq!(decoded(OutputFormat::Csv), b"id,name\n1,a\n2,b\n");
+        let json: serde_json::Value = serde_json::from_slice(&decoded(OutputFormat::Json)).unwrap();
+        assert_eq!(json[1]["name"], "b");
+        let parquet = ParquetReader::new(Cursor::new(decoded(OutputFormat::Parquet)))
+            .finish()
+            .unwrap();
+        assert!(parquet.frame_equal(&df));
+
+        assert_eq!(
+            OutputFormat::from_accept("application/json, text/csv;q=0.9"),
+            Some(OutputFormat::Csv)
+        );
+        assert_eq!(OutputFormat::from_accept("application/json"), None);
+
+        let out = prepare_output_as(&store, &df, &config, OutputFormat::Csv).unwrap();
+        assert_eq!(output_bytes(&out).unwrap().unwrap(), b"id,name\n1,a\n2,b\n");
+    }
+
+    #[test]
+    fn stream_round_trips() {
+        let df = df!["val" => [1, 2, 3], "name" => ["a", "b", "c"]].unwrap();
+        let bytes = encode_ipc_stream(&df).unwrap();
+        let back = IpcStreamReader::new(Cursor::new(bytes)).finish().unwrap();
+        assert!(back.frame_equal(&df));
     }
 
     #[test]
     fn large_dataframe_as_file() {
+        let dir = tempdir().unwrap();
+        let store = ResultStore::new(dir.path());
+        let config = OutputConfig::default();
         let data: Vec<i32> = (0..1_000_000).collect();
         let df = df!["val" => &data].unwrap();
-        let out = prepare_output(2, &df).unwrap();
+        let out = prepare_output(&store, &df, &config).unwrap();
         assert!(out.bytes.is_none());
         assert!(out.path.is_some());
         let path = out.path.unwrap();
         assert!(fs::metadata(&path).is_ok());
-        fs::remove_file(path).unwrap();
+
+        let again = prepare_output(&store, &df, &config).unwrap();
+        assert_eq!(again.path.as_deref(), Some(path.as_str()));
+        assert!(again.reused);
+    }
+
+    #[test]
+    fn very_large_dataframe_split_into_parts() {
+        let dir = tempdir().unwrap();
+        let store = ResultStore::new(dir.path());
+        let config = OutputConfig {
+            inline_limit: 0,
+            part_size: 1_000_000,
+        };
+        let data: Vec<i32> = (0..1_000_000).collect();
+        let df = df!["val" => &data].unwrap();
+        let out = prepare_output(&store, &df, &config).unwrap();
+        assert!(out.path.is_none());
+        let parts = out.parts.unwrap();
+        assert!(parts.len() > 1);
+        assert_eq!(parts.iter().map(|p| p.rows).sum::<usize>(), 1_000_000);
+        assert!(parts.iter().all(|p| fs::metadata(&p.path).is_ok()));
     }
 }
@@ -0,0 +1,453 @@
+//! Materialized views: named queries whose results are written to parquet
+//! and registered in the catalog as datasets.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::catalog::{self, Catalog, DatasetFormat, DatasetSpec};
+use crate::cron::Schedule;
+use crate::executor::{self, ExecContext};
+use crate::lineage::{self, Source};
+use crate::parser;
+
+/// Definition and refresh state of a materialized view.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewDefinition {
+    pub query: String,
+    /// Inputs of the last successful refresh.
+    #[serde(default)]
+    pub sources: Vec<Source>,
+    /// Unix seconds of the last successful refresh.
+    pub refreshed_at: Option<u64>,
+    /// Error of the last refresh, if it failed.
+    pub last_error: Option<String>,
+    /// Cron expression the view is refreshed on.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Refresh automatically whenever a source changes.
+    #[serde(default)]
+    pub refresh_on_change: bool,
+    /// Unix seconds of the last refresh attempt, successful or not.
+    #[serde(default)]
+    pub last_attempt_at: Option<u64>,
+    /// Unix seconds of the next scheduled refresh.
+    #[serde(default)]
+    pub next_refresh_at: Option<u64>,
+    /// Failed refreshes since the last success.
+    #[serde(default)]
+    pub consecutive_failures: u32,
+}
+
+/// Request to define (or redefine) a view.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewSpec {
+    pub name: String,
+    pub query: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Cron expression to refresh the view on, e.g. `0 * * * *`.
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub refresh_on_change: bool,
+}
+
+/// A view together with its current staleness.
+#[derive(Debug, Clone, Serialize)]
+pub struct ViewStatus {
+    pub name: String,
+    pub query: String,
+    pub version: u64,
+    pub refreshed_at: Option<u64>,
+    /// `true` when any input changed since the last refresh.
+    pub stale: bool,
+    /// Inputs that changed since the last refresh.
+    pub changed_sources: Vec<String>,
+    pub last_error: Option<String>,
+    pub schedule: Option<String>,
+    pub refresh_on_change: bool,
+    pub last_attempt_at: Option<u64>,
+    pub next_refresh_at: Option<u64>,
+    pub consecutive_failures: u32,
+}
+
+impl ViewStatus {
+    /// `true` when the last refresh failed.
+    pub fn failing(&self) -> bool {
+        self.consecutive_failures > 0
+    }
+}
+
+/// Defines, refreshes and inspects materialized views.
+pub struct Views {
+    catalog: Arc<Catalog>,
+    exec: ExecContext,
+    dir: PathBuf,
+}
+
+impl Views {
+    /// Views materialized into `dir`, with queries run in `exec`.
+    pub fn new(catalog: Arc<Catalog>, exec: ExecContext, dir: impl Into<PathBuf>) -> Self {
+        Views {
+            catalog,
+            exec,
+            dir: dir.into(),
+        }
+    }
+
+    /// Define a view and materialize it immediately.
+    pub fn define(&self, spec: ViewSpec, owner: &str) -> Result<ViewStatus, String> {
+        if !catalog::valid_name(&spec.name) {
+            return Err(format!(
+                "invalid view name {}: use letters, digits, '_' and '-'",
+                spec.name
+            ));
+        }
+        if let Some(existing) = self.catalog.get(&spec.name) {
+            if existing.view.is_none() {
+                return Err(format!("dataset {} exists and is not a view", spec.name));
+            }
+        }
+        parser::parse_query(&spec.query)?;
+        if let Some(expr) = &spec.schedule {
+            Schedule::parse(expr)?;
+        }
+        let view = ViewDefinition {
+            query: spec.query,
+            sources: Vec::new(),
+            refreshed_at: None,
+            last_error: None,
+            schedule: spec.schedule,
+            refresh_on_change: spec.refresh_on_change,
+            last_attempt_at: None,
+            next_refresh_at: None,
+            consecutive_failures: 0,
+        };
+        self.materialize(&spec.name, view, owner, spec.description, spec.tags)?;
+        self.status(&spec.name)
+    }
+
+    /// Re-run the view's query and register the result as a new version.
+    ///
+    /// On failure the previous materialization stays in place and the error
+    /// is recorded on the view.
+    pub fn refresh(&self, name: &str) -> Result<ViewStatus, String> {
+        let dataset = self
+            .catalog
+            .get(name)
+            .ok_or_else(|| format!("unknown view {}", name))?;
+        let view = dataset
+            .view
+            .clone()
+            .ok_or_else(|| format!("dataset {} is not a view", name))?;
+        if let Err(e) = self.materialize(
+            name,
+            view.clone(),
+            &dataset.owner,
+            dataset.description,
+            dataset.tags,
+        ) {
+            tracing::error!(view = name, "materialized view refresh failed: {}", e);
+            let now = catalog::now_secs();
+            let failed = ViewDefinition {
+                last_error: Some(e.clone()),
+                last_attempt_at: Some(now),
+                next_refresh_at: next_run(view.schedule.as_deref(), now),
+                consecutive_failures: view.consecutive_failures + 1,
+                ..view
+            };
+            self.catalog.set_view(name, failed)?;
+            return Err(e);
+        }
+        self.status(name)
+    }
+
+    /// Run `view`'s query, store the result as the next version of `name` and
+    /// record the successful refresh on the view.
+    fn materialize(
+        &self,
+        name: &str,
+        view: ViewDefinition,
+        owner: &str,
+        description: Option<String>,
+        tags: Vec<String>,
+    ) -> Result<(), String> {
+        let sources = lineage::snapshot(&self.exec, &view.query)?;
+        let exec = ExecContext {
+            user: Some(owner.to_string()),
+            ..self.exec.clone()
+        };
+        let mut df = executor::execute_plan_with(&view.query, &exec).map_err(|e| e.to_string())?;
+
+        let version = self.catalog.get(name).map_or(0, |d| d.version) + 1;
+        let dir = self.dir.join(name);
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let path = dir.join(format!("v{}.parquet", version));
+        let tmp = dir.join(format!("v{}.parquet.tmp", version));
+        let written = File::create(&tmp)
+            .map_err(|e| e.to_string())
+            .and_then(|f| {
+                ParquetWriter::new(f)
+                    .finish(&mut df)
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|_| fs::rename(&tmp, &path).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        self.catalog.register(
+            DatasetSpec {
+                name: name.to_string(),
+                location: path.to_string_lossy().to_string(),
+                format: DatasetFormat::Parquet,
+                description,
+                tags,
+                schema_mode: None,
+                partition_by: None,
+            },
+            owner,
+        )?;
+        let now = catalog::now_secs();
+        self.catalog.set_view(
+            name,
+            ViewDefinition {
+                sources,
+                refreshed_at: Some(now),
+                last_error: None,
+                last_attempt_at: Some(now),
+                next_refresh_at: next_run(view.schedule.as_deref(), now),
+                consecutive_failures: 0,
+                ..view
+            },
+        )
+    }
+
+    /// Current status of the view `name`.
+    pub fn status(&self, name: &str) -> Result<ViewStatus, String> {
+        let dataset = self
+            .catalog
+            .get(name)
+            .ok_or_else(|| format!("unknown view {}", name))?;
+        let view = dataset
+            .view
+            .ok_or_else(|| format!("dataset {} is not a view", name))?;
+        let changed_sources = lineage::changed_sources(&self.catalog, &view.query, &view.sources);
+        Ok(ViewStatus {
+            name: dataset.name,
+            query: view.query,
+            version: dataset.version,
+            refreshed_at: view.refreshed_at,
+            stale: !changed_sources.is_empty(),
+            changed_sources,
+            last_error: view.last_error,
+            schedule: view.schedule,
+            refresh_on_change: view.refresh_on_change,
+            last_attempt_at: view.last_attempt_at,
+            next_refresh_at: view.next_refresh_at,
+            consecutive_failures: view.consecutive_failures,
+        })
+    }
+
+    /// Status of every view, ordered by name.
+    pub fn list(&self) -> Vec<ViewStatus> {
+        self.catalog
+            .list()
+            .into_iter()
+            .filter(|d| d.view.is_some())
+            .filter_map(|d| self.status(&d.name).ok())
+            .collect()
+    }
+}
+
+/// Whether `status` is due for an automatic refresh at `now`.
+///
+/// Change-triggered refreshes back off exponentially (in multiples of
+/// `interval`) while the view keeps failing.
+fn due(status: &ViewStatus, now: u64, interval: u64) -> bool {
+    if status.next_refresh_at.is_some_and(|t| t <= now) {
+        return true;
+    }
+    if !status.refresh_on_change || !status.stale {
+        return false;
+    }
+    let backoff = interval.saturating_mul(1 << status.consecutive_failures.min(6));
+    status
+        .last_attempt_at
+        .is_none_or(|t| status.consecutive_failures == 0 || t + backoff <= now)
+}
+
+impl Views {
+    /// Refresh every view that is due at `now`, returning the names refreshed
+    /// (successfully or not).
+    pub fn refresh_due(&self, now: u64, interval: u64) -> Vec<String> {
+        let due: Vec<String> = self
+            .list()
+            .into_iter()
+            .filter(|status| due(status, now, interval))
+            .map(|status| status.name)
+            .collect();
+        for name in &due {
+            if let Ok(status) = self.refresh(name) {
+                info!(view = %name, version = status.version, "refreshed materialized view");
+            }
+        }
+        due
+    }
+
+    /// Spawn a task checking every `interval` for views due a refresh.
+    pub fn spawn_refresh_loop(self: Arc<Self>, interval: std::time::Duration) {
+        let secs = interval.as_secs().max(1);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let views = self.clone();
+                let now = catalog::now_secs();
+                let _ = tokio::task::spawn_blocking(move || views.refresh_due(now, secs)).await;
+            }
+        });
+    }
+}
+
+fn next_run(schedule: Option<&str>, after: u64) -> Option<u64> {
+    schedule
+        .and_then(|expr| Schedule::parse(expr).ok())
+        .and_then(|s| s.next_after_secs(after))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn view_is_materialized_and_goes_stale() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("people.parquet");
+        let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+        ParquetWriter::new(File::create(&source).unwrap())
+            .finish(&mut df)
+            .unwrap();
+
+        let catalog = Arc::new(Catalog::in_memory());
+        catalog
+            .register(
+                DatasetSpec {
+                    name: "people".into(),
+                    location: source.to_string_lossy().to_string(),
+                    format: DatasetFormat::Parquet,
+                    description: None,
+                    tags: vec![],
+                    schema_mode: None,
+                    partition_by: None,
+                },
+                "alice",
+            )
+            .unwrap();
+        let exec = ExecContext {
+            catalog: Some(catalog.clone()),
+            ..Default::default()
+        };
+        let views = Views::new(catalog.clone(), exec.clone(), dir.path().join("views"));
+
+        let status = views
+            .define(
+                ViewSpec {
+                    name: "adults".into(),
+                    query: "df = pl.read_table(\"people\")\ndf = df.filter(pl.col(\"age\") > 30)"
+                        .into(),
+                    description: None,
+                    tags: vec![],
+                    schedule: None,
+                    refresh_on_change: true,
+                },
+                "alice",
+            )
+            .unwrap();
+        assert_eq!(status.version, 1);
+        assert!(!status.stale);
+
+        let out = executor::execute_plan_with("df = pl.read_table(\"adults\")", &exec).unwrap();
+        assert_eq!(out.height(), 1);
+
+        // A new version of the source makes the view stale until refreshed.
+        let extra = dir.path().join("more.parquet");
+        ParquetWriter::new(File::create(&extra).unwrap())
+            .finish(&mut df)
+            .unwrap();
+        catalog
+            .register(
+                DatasetSpec {
+                    name: "people".into(),
+                    location: dir.path().to_string_lossy().to_string(),
+                    format: DatasetFormat::Parquet,
+                    description: None,
+                    tags: vec![],
+                    schema_mode: None,
+                    partition_by: None,
+                },
+                "alice",
+            )
+            .unwrap();
+        let status = views.status("adults").unwrap();
+        assert!(status.stale);
+        assert_eq!(status.changed_sources, vec!["people".to_string()]);
+
+        // Refreshed automatically because the view refreshes on change.
+        assert_eq!(views.refresh_due(catalog::now_secs(), 30), vec!["adults"]);
+        let status = views.status("adults").unwrap();
+        assert!(!status.stale);
+        assert_eq!(status.version, 2);
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let views = Views::new(
+            Arc::new(Catalog::in_memory()),
+            ExecContext::default(),
+            "views",
+        );
+        let spec = ViewSpec {
+            name: "../escape".into(),
+            query: "df = pl.read_parquet(\"x.parquet\")".into(),
+            description: None,
+            tags: vec![],
+            schedule: None,
+            refresh_on_change: false,
+        };
+        assert!(views.define(spec, "alice").is_err());
+    }
+
+    #[test]
+    fn failing_change_refresh_backs_off() {
+        let status = ViewStatus {
+            name: "v".into(),
+            query: String::new(),
+            version: 1,
+            refreshed_at: Some(0),
+            stale: true,
+            changed_sources: vec!["t".into()],
+            last_error: Some("boom".into()),
+            schedule: None,
+            refresh_on_change: true,
+            last_attempt_at: Some(1000),
+            next_refresh_at: None,
+            consecutive_failures: 2,
+        };
+        assert!(!due(&status, 1100, 30));
+        assert!(due(&status, 1120, 30));
+        let scheduled = ViewStatus {
+            refresh_on_change: false,
+            next_refresh_at: Some(2000),
+            ..status
+        };
+        assert!(!due(&scheduled, 1999, 30));
+        assert!(due(&scheduled, 2000, 30));
+    }
+}
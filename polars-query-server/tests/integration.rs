@@ -1,6 +1,5 @@
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
-use hyper;
 use tower::ServiceExt;
 
 use polars::prelude::ParquetWriter;
@@ -8,12 +7,201 @@ use polars::prelude::*;
 use std::fs::File;
 use tempfile::NamedTempFile;
 
-use polars_query_server::{api::app, api::AppState, scheduler::Scheduler};
+use polars_query_server::{Config, QueryServer};
+
+/// The HTTP API of a server built from `config`, without background tasks.
+fn app(config: Config) -> axum::Router {
+    QueryServer::builder()
+        .with_config(config)
+        .with_background_tasks(false)
+        .build()
+        .unwrap()
+        .router()
+}
+
+#[test]
+fn invalid_configuration_exits_with_an_error() {
+    let output = assert_cmd::Command::cargo_bin("rdata-server")
+        .unwrap()
+        .env("RDATA__SCHEDULER__MAX_CONCURRENCY", "0")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("invalid configuration: scheduler.max_concurrency must be at least 1"),
+        "{}",
+        stderr
+    );
+}
+
+#[tokio::test]
+async fn effective_config_is_served_without_secrets() {
+    let mut config = Config::default();
+    config.server.port = 8123;
+    config.scheduler.admin_token = Some("s3cret".into());
+    let app = app(config);
+
+    let response = app
+        .clone()
+        .oneshot(Request::get("/config").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .oneshot(
+            Request::get("/config")
+                .header("x-admin-token", "s3cret")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["server"]["port"], 8123);
+    assert_eq!(v["scheduler"]["admin_token"], "<redacted>");
+}
+
+#[tokio::test]
+async fn sql_queries_run_through_the_scheduler() {
+    let app = app(Config::from_env());
+    let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let query = format!(
+        "SELECT name FROM \"{}\" WHERE age > 30",
+        file.path().to_str().unwrap()
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-sql?wait=true")
+                .body(Body::from(query.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(v["error"].is_null());
+    assert_eq!(v["status"], "completed");
+
+    let body = serde_json::json!({ "query": query, "dialect": "sql" });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .oneshot(
+            Request::post("/run-sql")
+                .body(Body::from("SELECT name FROM people ORDER BY age DESC"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["kind"], "invalid_query");
+}
+
+#[tokio::test]
+async fn datasets_are_read_by_alias_and_deleted() {
+    let mut config = Config::default();
+    config.catalog.path = None;
+    let app = app(config);
+
+    let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let spec = serde_json::json!({
+        "name": "people",
+        "location": file.path().to_str().unwrap(),
+    });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/datasets")
+                .header("content-type", "application/json")
+                .body(Body::from(spec.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // `read_dataset` reads the same rows as `read_table`.
+    for read in ["read_dataset", "read_table"] {
+        let query = format!(
+            "df = pl.{}(\"people\")\ndf = df.filter(pl.col(\"age\") > 30)",
+            read
+        );
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/run-query?wait=true")
+                    .header("accept", "text/csv")
+                    .body(Body::from(query))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&bytes[..], b"name,age\nb,40\n");
+    }
+
+    let delete = |name: &str| {
+        Request::delete(format!("/datasets/{}", name))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(delete("people")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // The data stays where it was; only the catalog entry is removed.
+    assert!(file.path().exists());
+
+    for name in ["people", "unknown"] {
+        let response = app.clone().oneshot(delete(name)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(v["error"].as_str().unwrap().contains(name));
+    }
+
+    let response = app
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .body(Body::from("df = pl.read_dataset(\"people\")"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(v["error"].to_string().contains("unknown dataset"));
+}
 
 #[tokio::test]
 async fn post_query_returns_data() {
-    let scheduler = Scheduler::new();
-    let app = app(AppState { scheduler });
+    let app = app(Config::from_env());
 
     // prepare parquet file
     let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
@@ -28,12 +216,874 @@ async fn post_query_returns_data() {
     );
 
     let response = app
+        .clone()
+        .oneshot(Request::post("/run-query").body(Body::from(query)).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let job_id = v["job_id"].as_u64().unwrap();
+    assert!(v.get("output").is_none());
+    assert!(v["limits"]["max_concurrency"].as_u64().unwrap() >= 1);
+
+    let mut job = serde_json::Value::Null;
+    for _ in 0..100 {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/jobs/{}", job_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        job = serde_json::from_slice(&bytes).unwrap();
+        if job["status"] == "completed" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["status"], "completed");
+    assert!(job["output"].is_string());
+
+    let response = app
+        .clone()
+        .oneshot(Request::get("/jobs/999999").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains("rdata_jobs_submitted_total 1\n"));
+    assert!(text.contains("rdata_jobs_finished_total{status=\"completed\"} 1\n"));
+    assert!(text.contains("rdata_job_duration_seconds_count 1\n"));
+}
+
+#[tokio::test]
+async fn registered_dataset_is_listed() {
+    let mut config = Config::default();
+    config.catalog.path = None;
+    let app = app(config);
+
+    let mut df = df!["name" => ["a"], "age" => [20]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let spec = serde_json::json!({
+        "name": "people",
+        "location": file.path().to_str().unwrap(),
+        "description": "test people",
+        "tags": ["demo"],
+    });
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/datasets")
+                .header("content-type", "application/json")
+                .header("x-user-id", "alice")
+                .body(Body::from(spec.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get("/datasets?tag=demo")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["datasets"][0]["name"], "people");
+    assert_eq!(v["datasets"][0]["owner"], "alice");
+    assert_eq!(v["datasets"][0]["schema"][1]["name"], "age");
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .body(Body::from("df = pl.read_dataset(\"people\")"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(v["error"].is_null());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::delete("/datasets/people")
+                .header("x-user-id", "alice")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(
+            Request::get("/datasets/people")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn csv_upload_is_ingested() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.data.ingest_dir = dir.path().to_path_buf();
+    let app = app(config);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/ingest?dataset=people&tags=demo")
+                .header("content-type", "text/csv")
+                .body(Body::from("name,age\na,20\nb,40\n"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let query = "df = pl.read_table(\"people\")\ndf = df.filter(pl.col(\"age\") > 30)";
+    let response = app
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .body(Body::from(query))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(v["error"].is_null());
+}
+
+#[tokio::test]
+async fn ndjson_stream_is_appended() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.data.ingest_dir = dir.path().to_path_buf();
+    config.data.streaming.max_batch_rows = 2;
+    let app = app(config);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/datasets/events/append")
+                .header("content-type", "application/x-ndjson")
+                .body(Body::from("{\"id\": 1}\n{\"id\": 2}\n"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["rows"], 2);
+    assert_eq!(v["flushed"][0]["version"], 1);
+
+    let query = "df = pl.read_table(\"events\")";
+    let response = app
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .body(Body::from(query))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(v["error"].is_null());
+}
+
+#[tokio::test]
+async fn query_without_grant_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.access.enabled = true;
+    config.access.audit_log = Some(dir.path().join("audit.log"));
+    config.access.trusted_proxy_token = Some("proxy".into());
+    let app = app(config);
+
+    let mut df = df!["name" => ["a"], "age" => [20]].unwrap();
+    let file = dir.path().join("people.parquet");
+    ParquetWriter::new(File::create(&file).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let spec = serde_json::json!({ "name": "people", "location": file.to_str().unwrap() });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/datasets")
+                .header("content-type", "application/json")
+                .header("x-user-id", "alice")
+                .header("x-proxy-token", "proxy")
+                .body(Body::from(spec.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let query = "df = pl.read_table(\"people\")";
+    let response = app
+        .oneshot(
+            Request::post("/run-query")
+                .header("x-user-id", "bob")
+                .header("x-proxy-token", "proxy")
+                .body(Body::from(query))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "rejected");
+    assert_eq!(v["error"]["kind"], "access_denied");
+    assert_eq!(v["error"]["step"], "authorize");
+    assert!(v["error"]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("access denied"));
+}
+
+#[tokio::test]
+async fn failed_query_returns_structured_error() {
+    let app = app(Config::from_env());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query")
+                .body(Body::from("df = df.explode()"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["kind"], "invalid_query");
+
+    let response = app
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .body(Body::from("df = pl.read_parquet(\"missing.parquet\")"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["status"], "failed");
+    assert!(v["output"].is_null());
+    assert_eq!(v["error"]["kind"], "execution_failed");
+    assert_eq!(v["error"]["step"], "execute");
+    assert_eq!(v["error"]["job_id"], v["job_id"]);
+}
+
+#[tokio::test]
+async fn arrow_query_returns_stream() {
+    let app = app(Config::from_env());
+
+    let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let query = format!(
+        "df = pl.read_parquet(\"{}\")\ndf = df.filter(pl.col(\"age\") > 30)",
+        file.path().to_str().unwrap()
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query/arrow")
+                .body(Body::from(query))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/vnd.apache.arrow.stream"
+    );
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let out = IpcStreamReader::new(std::io::Cursor::new(bytes.to_vec()))
+        .finish()
+        .unwrap();
+    assert_eq!(out.height(), 1);
+
+    let response = app
+        .oneshot(
+            Request::post("/run-query/arrow")
+                .body(Body::from("df = df.explode()"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["kind"], "invalid_query");
+}
+
+#[tokio::test]
+async fn dry_run_returns_the_plan_without_running_it() {
+    let app = app(Config::from_env());
+
+    let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let query = format!(
+        "df = pl.read_parquet(\"{}\")\ndf = df.filter(pl.col(\"age\") > 30)",
+        file.path().to_str().unwrap()
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query?dry_run=true")
+                .body(Body::from(query))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["dry_run"], true);
+    assert!(v.get("job_id").is_none());
+    assert!(!v["plan"]["nodes"].as_array().unwrap().is_empty());
+    assert!(v["plan"]["optimized_plan"]
+        .as_str()
+        .unwrap()
+        .contains("col(\"age\")"));
+
+    // Nothing was enqueued.
+    let response = app
+        .clone()
+        .oneshot(Request::get("/jobs").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["total"], 0);
+    let response = app
+        .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains("rdata_jobs_submitted_total 0\n"));
+}
+
+#[tokio::test]
+async fn query_result_in_requested_format() {
+    let app = app(Config::from_env());
+
+    let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let query = format!(
+        "df = pl.read_parquet(\"{}\")\ndf = df.filter(pl.col(\"age\") > 30)",
+        file.path().to_str().unwrap()
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .header("accept", "text/csv")
+                .body(Body::from(query.clone()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/csv");
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&bytes[..], b"name,age\nb,40\n");
+
+    let request = serde_json::json!({ "query": query, "format": "json" });
+    let response = app
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .header("content-type", "application/json")
+                .body(Body::from(request.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["format"], "json");
+    assert!(v["output"].is_string());
+}
+
+#[tokio::test]
+async fn health_and_readiness_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.storage.output_dir = dir.path().to_path_buf();
+    let app = app(config.clone());
+
+    let response = app
+        .clone()
+        .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(Request::get("/readyz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let ready: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(ready["ready"], true);
+    assert_eq!(ready["checks"][1]["detail"], "0 of 100 jobs queued");
+
+    // Results can no longer be written.
+    drop(dir);
+    std::fs::write(&config.storage.output_dir, b"").unwrap();
+    let response = app
+        .oneshot(Request::get("/readyz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    std::fs::remove_file(&config.storage.output_dir).unwrap();
+}
+
+#[tokio::test]
+async fn stored_result_is_downloaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.storage.output_dir = dir.path().join("results");
+    config.storage.output.inline_limit = 0;
+    let app = app(config.clone());
+    assert!(config.storage.output_dir.is_dir());
+
+    let mut df = df!["name" => ["a", "b"], "age" => [20, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let query = format!(
+        "df = pl.read_parquet(\"{}\")",
+        file.path().to_str().unwrap()
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/run-query?wait=true")
+                .body(Body::from(query))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let url = v["output"].as_str().unwrap().to_string();
+    assert_eq!(url, format!("/jobs/{}/download", v["job_id"]));
+
+    let response = app
+        .clone()
+        .oneshot(Request::get(&url).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/vnd.apache.arrow.file"
+    );
+    let len: usize = response.headers()["content-length"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(bytes.len(), len);
+    let downloaded = IpcReader::new(std::io::Cursor::new(bytes.to_vec()))
+        .finish()
+        .unwrap();
+    assert!(downloaded.frame_equal(&df));
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get(&url)
+                .header("range", "bytes=2-5")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers()["content-range"],
+        format!("bytes 2-5/{}", len).as_str()
+    );
+    let part = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&part[..], &bytes[2..6]);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get(&url)
+                .header("range", format!("bytes={}-", len))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let response = app
+        .oneshot(
+            Request::get(format!("{}?part=1", url))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn submissions_beyond_the_rate_limit_get_429() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.csv"), "x\n1\n").unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.storage.output_dir = dir.path().join("output");
+    config.scheduler.rate_limit.per_client = Some(0.1);
+    let app = app(config);
+    let query = format!(
+        "df = pl.read_csv(\"{}\")",
+        dir.path().join("a.csv").display()
+    );
+    let submit = |user: &str| {
+        Request::post("/run-query")
+            .header("x-user-id", user)
+            .body(Body::from(query.clone()))
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(submit("alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = app.clone().oneshot(submit("alice")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "10");
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["kind"], "rate_limited");
+
+    let response = app.oneshot(submit("bob")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn dataset_and_file_schemas_are_described() {
+    let mut config = Config::default();
+    config.catalog.path = None;
+    let app = app(config);
+    let mut df = df!["name" => ["a", "b", "c"], "age" => [20, 30, 40]].unwrap();
+    let file = NamedTempFile::new().unwrap();
+    ParquetWriter::new(File::create(file.path()).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let path = file.path().to_str().unwrap();
+    let spec = serde_json::json!({ "name": "people", "location": path });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/datasets")
+                .header("content-type", "application/json")
+                .body(Body::from(spec.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let body = serde_json::json!({ "path": path, "format": "parquet" });
+    for request in [
+        Request::get("/datasets/people/schema")
+            .body(Body::empty())
+            .unwrap(),
+        Request::post("/schema")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+    ] {
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(v["columns"][0]["name"], "name");
+        assert_eq!(v["columns"][1]["name"], "age");
+        assert_eq!(v["estimated_rows"], 3);
+        assert!(v["bytes"].as_u64().unwrap() > 0);
+    }
+
+    let response = app
+        .oneshot(
+            Request::get("/datasets/missing/schema")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn job_events_are_streamed_until_it_finishes() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.csv"), "x\n1\n2\n").unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.storage.output_dir = dir.path().join("output");
+    let app = app(config);
+    let query = format!(
+        "df = pl.read_csv(\"{}\")",
+        dir.path().join("a.csv").display()
+    );
+
+    let response = app
+        .clone()
         .oneshot(Request::post("/run-query").body(Body::from(query)).unwrap())
         .await
         .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let id = v["job_id"].as_u64().unwrap();
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get(format!("/jobs/{}/events", id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
     assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    // The stream ends with the job, so the whole body can be read.
+    let body = tokio::time::timeout(
+        std::time::Duration::from_secs(30),
+        hyper::body::to_bytes(response.into_body()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let events: Vec<&str> = body
+        .lines()
+        .filter_map(|l| l.strip_prefix("event:"))
+        .collect();
+    assert_eq!(events.last(), Some(&"completed"), "{}", body);
+    let last: serde_json::Value = serde_json::from_str(
+        body.lines()
+            .rev()
+            .find_map(|l| l.strip_prefix("data:"))
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(last["job_id"], id);
+    assert_eq!(last["status"], "completed");
+
+    let response = app
+        .oneshot(
+            Request::get("/jobs/999/events")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn files_outside_the_sandbox_get_403() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("data");
+    std::fs::create_dir_all(data.join("sales/year=2024")).unwrap();
+    std::fs::write(data.join("a.csv"), "x\n1\n").unwrap();
+    std::fs::write(dir.path().join("secret.csv"), "x\n2\n").unwrap();
+    let mut df = df!["x" => [1]].unwrap();
+    for path in [
+        data.join("sales/year=2024/a.parquet"),
+        dir.path().join("secret.parquet"),
+    ] {
+        ParquetWriter::new(File::create(path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+    }
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.data.allowed_roots = vec![data.clone()];
+    config.data.ingest_dir = dir.path().join("ingest");
+    let app = app(config);
+    let secret = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+    let post = |uri: &str, body: serde_json::Value| {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let query = format!("df = pl.read_csv(\"{}\")", secret("secret.csv"));
+    let response = app
+        .clone()
+        .oneshot(Request::post("/run-query").body(Body::from(query)).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(v["error"]["kind"], "access_denied");
+
+    let refused = [
+        post(
+            "/ingest",
+            serde_json::json!({"dataset": "a", "source": secret("secret.csv")}),
+        ),
+        post(
+            "/ingest",
+            serde_json::json!({"dataset": "a", "source": "http://127.0.0.1:1/a.csv"}),
+        ),
+        post(
+            "/datasets",
+            serde_json::json!({"name": "secret", "location": secret("secret.parquet")}),
+        ),
+    ];
+    for request in refused {
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    let source = data.join("a.csv").to_string_lossy().to_string();
+    let request = post(
+        "/ingest",
+        serde_json::json!({"dataset": "a", "source": source}),
+    );
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let location = data.join("sales").to_string_lossy().to_string();
+    let spec = serde_json::json!({"name": "sales", "location": location, "partition_by": "year"});
+    let response = app.clone().oneshot(post("/datasets", spec)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let request = post(
+        "/datasets/sales/partitions/2025",
+        serde_json::json!({"source": secret("secret.parquet")}),
+    );
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!data.join("sales/year=2025").exists());
+}
+
+#[tokio::test]
+async fn dataset_metadata_and_lineage_need_access() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.catalog.path = None;
+    config.access.enabled = true;
+    config.access.trusted_proxy_token = Some("proxy".into());
+    config.storage.output_dir = dir.path().join("out");
+    config.storage.output.inline_limit = 0;
+    let app = app(config);
+    let as_user = |user: &str, request: axum::http::request::Builder| {
+        request
+            .header("x-user-id", user)
+            .header("x-proxy-token", "proxy")
+    };
+
+    let mut df = df!["name" => ["a"], "age" => [20]].unwrap();
+    let file = dir.path().join("people.parquet");
+    ParquetWriter::new(File::create(&file).unwrap())
+        .finish(&mut df)
+        .unwrap();
+    let spec = serde_json::json!({ "name": "people", "location": file.to_str().unwrap() });
+    let request = as_user("alice", Request::post("/datasets"))
+        .header("content-type", "application/json")
+        .body(Body::from(spec.to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let request = as_user("alice", Request::post("/run-query?wait=true"))
+        .body(Body::from("df = pl.read_table(\"people\")"))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
     let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
     let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
-    assert!(v.get("job_id").is_some());
-    assert!(v.get("output").is_some());
+    assert_eq!(v["status"], "completed");
+    let job_lineage = format!("/lineage/jobs/{}", v["job_id"]);
+
+    for uri in [
+        "/datasets/people/grants",
+        "/datasets/people/policies",
+        "/datasets/people/checks",
+        "/lineage/datasets/people",
+        job_lineage.as_str(),
+    ] {
+        let request = as_user("bob", Request::get(uri))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+    }
+    for uri in ["/datasets/people/grants", job_lineage.as_str()] {
+        let request = as_user("alice", Request::get(uri))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+    }
 }